@@ -18,12 +18,23 @@ struct Args {
 
     /// Which register allocation algorithm to use.
     algorithm: CliAlgorithm,
+
+    /// Run copy propagation over the result of register allocation.
+    #[clap(long)]
+    copy_propagation: bool,
+
+    /// Limit the steps taken by the allocation loop of ion and
+    /// linear-scan.
+    #[clap(long)]
+    fuel: Option<usize>,
 }
 
 #[derive(Clone, Copy, Debug, clap::ValueEnum)]
 enum CliAlgorithm {
     Ion,
     Fastalloc,
+    LinearScan,
+    IonWithFallback,
 }
 
 impl From<CliAlgorithm> for Algorithm {
@@ -31,6 +42,8 @@ impl From<CliAlgorithm> for Algorithm {
         match cli_algo {
             CliAlgorithm::Ion => Algorithm::Ion,
             CliAlgorithm::Fastalloc => Algorithm::Fastalloc,
+            CliAlgorithm::LinearScan => Algorithm::LinearScan,
+            CliAlgorithm::IonWithFallback => Algorithm::IonWithFallback,
         }
     }
 }
@@ -50,7 +63,11 @@ fn main() {
     let options = RegallocOptions {
         verbose_log: true,
         validate_ssa: true,
+        validate_function: true,
         algorithm: args.algorithm.into(),
+        copy_propagation: args.copy_propagation,
+        run_checker: false,
+        fuel: args.fuel,
     };
     let output = match regalloc2::run(&function, function.machine_env(), &options) {
         Ok(output) => output,
@@ -107,6 +124,9 @@ fn print_output(func: &SerializableFunction, output: &Output) {
                 InstOrEdit::Edit(Edit::Move { from, to }) => {
                     println!("    edit: move {to} <- {from}");
                 }
+                InstOrEdit::Edit(Edit::Remat { vreg, to }) => {
+                    println!("    edit: remat {to} <- {vreg}");
+                }
             }
         }
     }
@@ -37,6 +37,10 @@ pub struct CFGInfo {
     /// indices. Otherwise, it will be approximate, but should still
     /// be usable for heuristic purposes.
     pub approx_loop_depth: Vec<u32>,
+    /// For each block, how often is it expected to run? This is the
+    /// client's `Function::block_frequency` if given, or else an
+    /// estimate from `approx_loop_depth`.
+    pub block_frequency: Vec<f32>,
 }
 
 impl CFGInfo {
@@ -146,6 +150,15 @@ impl CFGInfo {
             }
         }
 
+        let block_frequency = self.block_frequency.cleared();
+        for block in 0..nb {
+            let frequency = f.block_frequency(Block::new(block)).unwrap_or_else(|| {
+                let depth = core::cmp::min(10, approx_loop_depth[block]);
+                (0..depth).fold(1.0, |a, _| a * 4.0)
+            });
+            block_frequency.push(frequency);
+        }
+
         Ok(())
     }
 
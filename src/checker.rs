@@ -67,6 +67,13 @@
 //!
 //!       A' = A[alloc_d → A\[alloc_s\]]
 //!
+//!   - `Edit::Remat` inserted by RA:      [ alloc_d := remat V_i ]
+//!
+//!       A' = A[alloc_d → { V_i }]
+//!
+//!     The value is recomputed by re-executing V_i's def, so other
+//!     allocs holding V_i remain valid.
+//!
 //!   - statement in pre-regalloc function [ V_i := op V_j, V_k, ... ]
 //!     with allocated form                [ A_i := op A_j, A_k, ... ]
 //!
@@ -92,14 +99,27 @@
 //! check that the allocation for each op arg (input/use) contains the
 //! symbol corresponding to the original virtual register specified
 //! for this arg.
+//!
+//! Along the way, each instruction is also checked against the rest of
+//! the function and the machine: no def may be in a register that its
+//! instruction clobbers, every spillslot must be within
+//! `Output::num_spillslots` and aligned for its class, and a scratch
+//! register may only be read where it was written since the last
+//! instruction.
+//!
+//! The same solution is used to check `Output::debug_locations`: at
+//! each program point in its range, a debug location's allocation must
+//! hold a vreg that its label was requested for in
+//! `Function::debug_value_labels`.
 
 #![allow(dead_code)]
 
 use crate::{
     Allocation, AllocationKind, Block, Edit, Function, FxHashMap, FxHashSet, Inst, InstOrEdit,
-    InstPosition, MachineEnv, Operand, OperandConstraint, OperandKind, OperandPos, Output, PReg,
-    PRegSet, VReg,
+    InstPosition, MachineEnv, Operand, OperandConstraint, OperandGroup, OperandKind, OperandPos,
+    Output, PReg, PRegSet, ProgPoint, RegClass, VReg,
 };
+use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::{format, vec};
 use core::default::Default;
@@ -125,17 +145,20 @@ pub enum CheckerError {
         inst: Inst,
         op: Operand,
         alloc: Allocation,
+        trace: String,
     },
     ConflictedValueInAllocation {
         inst: Inst,
         op: Operand,
         alloc: Allocation,
+        trace: String,
     },
     IncorrectValuesInAllocation {
         inst: Inst,
         op: Operand,
         alloc: Allocation,
         actual: FxHashSet<VReg>,
+        trace: String,
     },
     ConstraintViolated {
         inst: Inst,
@@ -158,23 +181,399 @@ pub enum CheckerError {
         alloc: Allocation,
         expected_alloc: Allocation,
     },
+    AllocationIsNotReuseEither {
+        inst: Inst,
+        op: Operand,
+        alloc: Allocation,
+        expected_allocs: [Allocation; 2],
+    },
     AllocationIsNotStack {
         inst: Inst,
         op: Operand,
         alloc: Allocation,
     },
+    AllocationIsNotWideReg {
+        inst: Inst,
+        op: Operand,
+        alloc: Allocation,
+    },
+    AllocationIsNotFixedStack {
+        inst: Inst,
+        op: Operand,
+        alloc: Allocation,
+    },
     StackToStackMove {
         into: Allocation,
         from: Allocation,
     },
+    NonRematerializableVReg {
+        into: Allocation,
+        vreg: VReg,
+    },
     AllocationOutsideLimit {
         inst: Inst,
         op: Operand,
         alloc: Allocation,
         range: Range<usize>,
     },
+    AllocationNotInRegSet {
+        inst: Inst,
+        op: Operand,
+        alloc: Allocation,
+    },
+    AllocationSharedWithUse {
+        inst: Inst,
+        op: Operand,
+        alloc: Allocation,
+        use_op: Operand,
+    },
+    /// `op`, a def, is in a register that `inst` clobbers.
+    AllocationIsClobbered {
+        inst: Inst,
+        op: Operand,
+        alloc: Allocation,
+        clobber: PReg,
+    },
+    /// The value of a use was lost from an allocation that
+    /// `clobber_inst` clobbered while the value was live across it.
+    ClobberedValueInAllocation {
+        inst: Inst,
+        op: Operand,
+        alloc: Allocation,
+        clobber_inst: Inst,
+        trace: String,
+    },
+    SpillSlotOutOfRange {
+        point: ProgPoint,
+        alloc: Allocation,
+        num_spillslots: usize,
+    },
+    /// A stack allocation of a multi-slot class that isn't aligned to
+    /// its `size` slots.
+    MisalignedSpillSlot {
+        point: ProgPoint,
+        alloc: Allocation,
+        class: RegClass,
+        size: usize,
+    },
+    /// A read of a scratch register at `point` whose value was written
+    /// before the last instruction, which may have used it.
+    ScratchRegisterAcrossInstruction {
+        point: ProgPoint,
+        alloc: Allocation,
+    },
+    DebugLocationNotRequested {
+        label: u32,
+        from: ProgPoint,
+        to: ProgPoint,
+        alloc: Allocation,
+    },
+    /// `actual` is `None` if the value in `alloc` is unknown.
+    IncorrectDebugLocation {
+        label: u32,
+        point: ProgPoint,
+        alloc: Allocation,
+        vreg: VReg,
+        actual: Option<FxHashSet<VReg>>,
+        trace: String,
+    },
+}
+
+impl CheckerErrors {
+    /// The errors, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &CheckerError> {
+        self.errors.iter()
+    }
+
+    /// The number of errors; never zero.
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+}
+
+impl IntoIterator for CheckerErrors {
+    type Item = CheckerError;
+    type IntoIter = alloc::vec::IntoIter<CheckerError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a CheckerErrors {
+    type Item = &'a CheckerError;
+    type IntoIter = core::slice::Iter<'a, CheckerError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.iter()
+    }
 }
 
+/// One error per line, each followed by its trace, if any.
+impl core::fmt::Display for CheckerErrors {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for CheckerErrors {}
+
+impl CheckerError {
+    /// For an error about the value in an allocation, a reconstruction
+    /// of how it got there, as text with one line per step.
+    ///
+    /// This follows one path through the blocks leading to the error,
+    /// along which the expected vreg is missing from the allocation. It
+    /// lists the moves that carried the value into the allocation and
+    /// the instruction that defined it or overwrote it, or else says
+    /// that nothing was ever put in it; block params are followed back
+    /// to the branch args they came from.
+    pub fn trace(&self) -> Option<&str> {
+        match self {
+            CheckerError::UnknownValueInAllocation { trace, .. }
+            | CheckerError::ConflictedValueInAllocation { trace, .. }
+            | CheckerError::IncorrectValuesInAllocation { trace, .. }
+            | CheckerError::ClobberedValueInAllocation { trace, .. }
+            | CheckerError::IncorrectDebugLocation { trace, .. } => Some(trace),
+            _ => None,
+        }
+    }
+}
+
+/// Names the instruction, operand and allocation, then says what is
+/// wrong; errors about the value in an allocation are followed by their
+/// trace, indented, on the next lines.
+impl core::fmt::Display for CheckerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let at = |f: &mut core::fmt::Formatter, inst: &Inst, op, alloc| {
+            write!(f, "inst{}: operand ({}) in {}: ", inst.index(), op, alloc)
+        };
+        match self {
+            CheckerError::MissingAllocation { inst, op } => {
+                write!(
+                    f,
+                    "inst{}: operand ({}) has no allocation",
+                    inst.index(),
+                    op
+                )
+            }
+            CheckerError::UnknownValueInAllocation {
+                inst, op, alloc, ..
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "expected {}, but the value is unknown", op.vreg())
+            }
+            CheckerError::ConflictedValueInAllocation {
+                inst, op, alloc, ..
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "expected {}, but the value is conflicted", op.vreg())
+            }
+            CheckerError::IncorrectValuesInAllocation {
+                inst,
+                op,
+                alloc,
+                actual,
+                ..
+            } => {
+                at(f, inst, op, alloc)?;
+                let mut actual: Vec<_> = actual.iter().collect();
+                actual.sort_unstable();
+                if actual.is_empty() {
+                    write!(f, "expected {}, but it holds no vreg", op.vreg())
+                } else {
+                    write!(f, "expected {}, but it holds ", op.vreg())?;
+                    for (i, vreg) in actual.iter().enumerate() {
+                        let sep = if i == 0 { "" } else { ", " };
+                        write!(f, "{}{}", sep, vreg)?;
+                    }
+                    Ok(())
+                }
+            }
+            CheckerError::ConstraintViolated { inst, op, alloc } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "violates the constraint")
+            }
+            CheckerError::AllocationIsNotReg { inst, op, alloc } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "not a register")
+            }
+            CheckerError::AllocationIsNotFixedReg { inst, op, alloc } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "not the fixed register")
+            }
+            CheckerError::AllocationIsNotReuse {
+                inst,
+                op,
+                alloc,
+                expected_alloc,
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "expected the reused input's {}", expected_alloc)
+            }
+            CheckerError::AllocationIsNotReuseEither {
+                inst,
+                op,
+                alloc,
+                expected_allocs,
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(
+                    f,
+                    "expected the reused inputs' {} or {}",
+                    expected_allocs[0], expected_allocs[1]
+                )
+            }
+            CheckerError::AllocationIsNotStack { inst, op, alloc } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "not on the stack")
+            }
+            CheckerError::AllocationIsNotWideReg { inst, op, alloc } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "not a wide register")
+            }
+            CheckerError::AllocationIsNotFixedStack { inst, op, alloc } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "not the fixed stack location")
+            }
+            CheckerError::StackToStackMove { into, from } => {
+                write!(
+                    f,
+                    "move from {} to {} is between two stack locations",
+                    from, into
+                )
+            }
+            CheckerError::NonRematerializableVReg { into, vreg } => write!(
+                f,
+                "remat of {} into {}, which is not rematerializable",
+                vreg, into
+            ),
+            CheckerError::AllocationOutsideLimit {
+                inst,
+                op,
+                alloc,
+                range,
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "outside the limit {}..{}", range.start, range.end)
+            }
+            CheckerError::AllocationNotInRegSet { inst, op, alloc } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "not in the register set")
+            }
+            CheckerError::AllocationSharedWithUse {
+                inst,
+                op,
+                alloc,
+                use_op,
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "shared with ({}) outside its operand groups", use_op)
+            }
+            CheckerError::AllocationIsClobbered {
+                inst,
+                op,
+                alloc,
+                clobber,
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(f, "the instruction clobbers {}", clobber)
+            }
+            CheckerError::ClobberedValueInAllocation {
+                inst,
+                op,
+                alloc,
+                clobber_inst,
+                ..
+            } => {
+                at(f, inst, op, alloc)?;
+                write!(
+                    f,
+                    "expected {}, but inst{} clobbered it",
+                    op.vreg(),
+                    clobber_inst.index()
+                )
+            }
+            CheckerError::SpillSlotOutOfRange {
+                point,
+                alloc,
+                num_spillslots,
+            } => write!(
+                f,
+                "{} at {:?} is outside the {} spillslots",
+                alloc, point, num_spillslots
+            ),
+            CheckerError::MisalignedSpillSlot {
+                point,
+                alloc,
+                class,
+                size,
+            } => write!(
+                f,
+                "{} at {:?} is not aligned to the {} slots of class {:?}",
+                alloc, point, size, class
+            ),
+            CheckerError::ScratchRegisterAcrossInstruction { point, alloc } => write!(
+                f,
+                "scratch register {} read at {:?} was written before an instruction",
+                alloc, point
+            ),
+            CheckerError::DebugLocationNotRequested {
+                label,
+                from,
+                to,
+                alloc,
+            } => write!(
+                f,
+                "debug location of label {} in {} from {:?} to {:?} was not requested",
+                label, alloc, from, to
+            ),
+            CheckerError::IncorrectDebugLocation {
+                label,
+                point,
+                alloc,
+                vreg,
+                actual,
+                ..
+            } => {
+                write!(
+                    f,
+                    "debug location of label {} in {} at {:?}: expected {}, ",
+                    label, alloc, point, vreg
+                )?;
+                match actual {
+                    None => write!(f, "but the value is unknown"),
+                    Some(actual) if actual.is_empty() => write!(f, "but it holds no vreg"),
+                    Some(actual) => {
+                        let mut actual: Vec<_> = actual.iter().collect();
+                        actual.sort_unstable();
+                        write!(f, "but it holds ")?;
+                        for (i, vreg) in actual.iter().enumerate() {
+                            let sep = if i == 0 { "" } else { ", " };
+                            write!(f, "{}{}", sep, vreg)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        }?;
+        if let Some(trace) = self.trace() {
+            for line in trace.lines() {
+                write!(f, "\n    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for CheckerError {}
+
 /// Abstract state for an allocation.
 ///
 /// Equivalent to a set of virtual register names, with the
@@ -362,6 +761,27 @@ impl CheckerState {
         }
     }
 
+    /// Remove the values of all registers that share a register unit
+    /// with `alloc` (but not `alloc` itself): writing to `alloc`
+    /// overwrites (part of) them.
+    fn remove_overlapping_values(&mut self, alloc: Allocation, machine_env: &MachineEnv) {
+        let preg = match alloc.as_reg() {
+            Some(preg) if !machine_env.reg_units.is_empty() => preg,
+            _ => return,
+        };
+        match self {
+            CheckerState::Top => {
+                panic!("Cannot remove values on Top state");
+            }
+            CheckerState::Allocations(allocs) => {
+                allocs.retain(|other, _| match other.as_reg() {
+                    Some(other) => other == preg || !machine_env.regs_overlap(preg, other),
+                    None => true,
+                });
+            }
+        }
+    }
+
     fn initial() -> Self {
         CheckerState::Allocations(FxHashMap::default())
     }
@@ -446,7 +866,12 @@ impl CheckerState {
         if op.kind() == OperandKind::Use && op.as_fixed_nonallocatable().is_none() {
             match val {
                 CheckerValue::Universe => {
-                    return Err(CheckerError::UnknownValueInAllocation { inst, op, alloc });
+                    return Err(CheckerError::UnknownValueInAllocation {
+                        inst,
+                        op,
+                        alloc,
+                        trace: String::new(),
+                    });
                 }
                 CheckerValue::VRegs(vregs) if !vregs.contains(&op.vreg()) => {
                     return Err(CheckerError::IncorrectValuesInAllocation {
@@ -454,6 +879,7 @@ impl CheckerState {
                         op,
                         alloc,
                         actual: vregs.clone(),
+                        trace: String::new(),
                     });
                 }
                 _ => {}
@@ -480,18 +906,62 @@ impl CheckerState {
                 inst,
                 ref operands,
                 ref allocs,
-                ..
+                ref clobbers,
             } => {
                 // Skip Use-checks at the After point if there are any
                 // reused inputs: the Def which reuses the input
                 // happens early.
-                let has_reused_input = operands
-                    .iter()
-                    .any(|op| matches!(op.constraint(), OperandConstraint::Reuse(_)));
+                let has_reused_input = operands.iter().any(|op| {
+                    matches!(
+                        op.constraint(),
+                        OperandConstraint::Reuse(_) | OperandConstraint::ReuseEither(..)
+                    )
+                });
                 if has_reused_input && pos == InstPosition::After {
                     return Ok(());
                 }
 
+                // Check that defs don't share a register with uses
+                // outside their operand groups.
+                let groups = checker.f.inst_operand_groups(inst);
+                if pos == InstPosition::Before && !groups.is_empty() {
+                    for def in 0..operands.len() {
+                        for use_idx in 0..operands.len() {
+                            if allocs[def] == allocs[use_idx]
+                                && OperandGroup::separates(groups, operands, def, use_idx)
+                            {
+                                return Err(CheckerError::AllocationSharedWithUse {
+                                    inst,
+                                    op: operands[def],
+                                    alloc: allocs[def],
+                                    use_op: operands[use_idx],
+                                });
+                            }
+                        }
+                    }
+                }
+
+                // Check that no def is in a clobbered register, as
+                // the clobber would destroy its value.
+                if pos == InstPosition::Before {
+                    for (op, alloc) in operands.iter().zip(allocs.iter()) {
+                        if op.kind() != OperandKind::Def {
+                            continue;
+                        }
+                        if let Some(&clobber) = clobbers
+                            .iter()
+                            .find(|&&clobber| checker.overwrites(Allocation::reg(clobber), *alloc))
+                        {
+                            return Err(CheckerError::AllocationIsClobbered {
+                                inst,
+                                op: *op,
+                                alloc: *alloc,
+                                clobber,
+                            });
+                        }
+                    }
+                }
+
                 // For each operand, check (i) that the allocation
                 // contains the expected vreg, and (ii) that it meets
                 // the requirements of the OperandConstraint.
@@ -522,13 +992,18 @@ impl CheckerState {
                     if let Some(reg) = alloc.as_reg() {
                         checker.stack_pregs.contains(reg)
                     } else {
-                        alloc.is_stack()
+                        alloc.is_stack() || alloc.is_fixed_stack()
                     }
                 };
                 if is_stack(into) && is_stack(from) {
                     return Err(CheckerError::StackToStackMove { into, from });
                 }
             }
+            &CheckerInst::Remat { into, vreg } => {
+                if !checker.f.is_rematerializable(vreg) {
+                    return Err(CheckerError::NonRematerializableVReg { into, vreg });
+                }
+            }
             &CheckerInst::ParallelMove { .. } => {
                 // This doesn't need verification; we just update
                 // according to the move semantics in the step
@@ -539,7 +1014,7 @@ impl CheckerState {
     }
 
     /// Update according to instruction.
-    fn update(&mut self, checkinst: &CheckerInst) {
+    fn update<'a, F: Function>(&mut self, checkinst: &CheckerInst, checker: &Checker<'a, F>) {
         self.become_defined();
 
         match checkinst {
@@ -560,6 +1035,13 @@ impl CheckerState {
                     );
                     self.set_value(into, val);
                 }
+                self.remove_overlapping_values(into, checker.machine_env);
+            }
+            &CheckerInst::Remat { into, vreg } => {
+                // Like a def, but without invalidating other copies of
+                // the vreg: they still hold the same value.
+                self.set_value(into, CheckerValue::from_reg(vreg));
+                self.remove_overlapping_values(into, checker.machine_env);
             }
             &CheckerInst::ParallelMove { ref moves } => {
                 // First, build map of actions for each vreg in an
@@ -614,9 +1096,11 @@ impl CheckerState {
                     }
                     self.remove_vreg(op.vreg());
                     self.set_value(*alloc, CheckerValue::from_reg(op.vreg()));
+                    self.remove_overlapping_values(*alloc, checker.machine_env);
                 }
                 for clobber in clobbers {
                     self.remove_value(&Allocation::reg(*clobber));
+                    self.remove_overlapping_values(Allocation::reg(*clobber), checker.machine_env);
                 }
             }
         }
@@ -663,6 +1147,11 @@ impl CheckerState {
                     return Err(CheckerError::AllocationIsNotFixedReg { inst, op, alloc });
                 }
             }
+            OperandConstraint::FixedStack(loc) => {
+                if alloc != Allocation::fixed_stack(loc) {
+                    return Err(CheckerError::AllocationIsNotFixedStack { inst, op, alloc });
+                }
+            }
             OperandConstraint::Reuse(idx) => {
                 if alloc.kind() != AllocationKind::Reg {
                     return Err(CheckerError::AllocationIsNotReg { inst, op, alloc });
@@ -676,6 +1165,27 @@ impl CheckerState {
                     });
                 }
             }
+            OperandConstraint::ReuseEither(a, b) => {
+                if alloc.kind() != AllocationKind::Reg {
+                    return Err(CheckerError::AllocationIsNotReg { inst, op, alloc });
+                }
+                if alloc != allocs[a] && alloc != allocs[b] {
+                    return Err(CheckerError::AllocationIsNotReuseEither {
+                        inst,
+                        op,
+                        alloc,
+                        expected_allocs: [allocs[a], allocs[b]],
+                    });
+                }
+            }
+            OperandConstraint::WideReg => {
+                if let Some(preg) = alloc.as_reg() {
+                    if checker.wide_pregs.contains(preg) {
+                        return Ok(());
+                    }
+                }
+                return Err(CheckerError::AllocationIsNotWideReg { inst, op, alloc });
+            }
             OperandConstraint::Limit(max) => {
                 if let Some(preg) = alloc.as_reg() {
                     if preg.hw_enc() >= max {
@@ -688,6 +1198,14 @@ impl CheckerState {
                     }
                 }
             }
+            OperandConstraint::RegSet(i) => {
+                if let Some(preg) = alloc.as_reg() {
+                    if checker.machine_env.reg_sets[i].contains(preg) {
+                        return Ok(());
+                    }
+                }
+                return Err(CheckerError::AllocationNotInRegSet { inst, op, alloc });
+            }
         }
         Ok(())
     }
@@ -700,6 +1218,9 @@ pub(crate) enum CheckerInst {
     /// spillslots).
     Move { into: Allocation, from: Allocation },
 
+    /// A recomputation of a rematerializable vreg into an allocation.
+    Remat { into: Allocation, vreg: VReg },
+
     /// A parallel move in the original program. Simultaneously moves
     /// from all source vregs to all corresponding dest vregs,
     /// permitting overlap in the src and dest sets and doing all
@@ -725,25 +1246,32 @@ pub struct Checker<'a, F: Function> {
     f: &'a F,
     bb_in: FxHashMap<Block, CheckerState>,
     bb_insts: FxHashMap<Block, Vec<CheckerInst>>,
+    /// The program point of each of `bb_insts`: the edit's, or before
+    /// the instruction for an `Op`.
+    bb_points: FxHashMap<Block, Vec<ProgPoint>>,
     edge_insts: FxHashMap<(Block, Block), Vec<CheckerInst>>,
+    debug_locations: Vec<(u32, ProgPoint, ProgPoint, Allocation)>,
+    num_spillslots: usize,
     machine_env: &'a MachineEnv,
     stack_pregs: PRegSet,
+    wide_pregs: PRegSet,
 }
 
 impl<'a, F: Function> Checker<'a, F> {
     /// Create a new checker for the given function, initializing CFG
-    /// info immediately.  The client should call the `add_*()`
-    /// methods to add abstract instructions to each BB before
-    /// invoking `run()` to check for errors.
+    /// info immediately.  The client should call `prepare()` with the
+    /// allocation results before invoking `run()` to check for errors.
     pub fn new(f: &'a F, machine_env: &'a MachineEnv) -> Checker<'a, F> {
         let mut bb_in = FxHashMap::default();
         let mut bb_insts = FxHashMap::default();
+        let mut bb_points = FxHashMap::default();
         let mut edge_insts = FxHashMap::default();
 
         for block in 0..f.num_blocks() {
             let block = Block::new(block);
             bb_in.insert(block, Default::default());
             bb_insts.insert(block, vec![]);
+            bb_points.insert(block, vec![]);
             for &succ in f.block_succs(block) {
                 edge_insts.insert((block, succ), vec![]);
             }
@@ -760,30 +1288,48 @@ impl<'a, F: Function> Checker<'a, F> {
             f,
             bb_in,
             bb_insts,
+            bb_points,
             edge_insts,
+            debug_locations: vec![],
+            num_spillslots: 0,
             machine_env,
             stack_pregs,
+            wide_pregs: machine_env.wide_regs(),
         }
     }
 
     /// Build the list of checker instructions based on the given func
-    /// and allocation results.
+    /// and allocation results. Everything needed is in `out` itself,
+    /// whatever features the allocator was built with.
     pub fn prepare(&mut self, out: &Output) {
         trace!("checker: out = {:?}", out);
         let mut last_inst = None;
+        // The edits are visited in order, so their positions can be
+        // found by walking `out.edits` alongside.
+        let mut edits = out.edits.iter();
         for block in 0..self.f.num_blocks() {
             let block = Block::new(block);
             for inst_or_edit in out.block_insts_and_edits(self.f, block) {
-                match inst_or_edit {
+                let point = match inst_or_edit {
                     InstOrEdit::Inst(inst) => {
                         debug_assert!(last_inst.is_none() || inst > last_inst.unwrap());
                         last_inst = Some(inst);
                         self.handle_inst(block, inst, out);
+                        ProgPoint::before(inst)
                     }
-                    InstOrEdit::Edit(edit) => self.handle_edit(block, edit),
-                }
+                    InstOrEdit::Edit(edit) => {
+                        self.handle_edit(block, edit);
+                        edits
+                            .find(|(_, e)| core::ptr::eq(e, edit))
+                            .expect("edits are visited in order")
+                            .0
+                    }
+                };
+                self.bb_points.get_mut(&block).unwrap().push(point);
             }
         }
+        self.debug_locations = out.debug_locations.clone();
+        self.num_spillslots = out.num_spillslots;
     }
 
     /// For each original instruction, create an `Op`.
@@ -836,6 +1382,12 @@ impl<'a, F: Function> Checker<'a, F> {
                     .unwrap()
                     .push(CheckerInst::Move { into: to, from });
             }
+            &Edit::Remat { vreg, to } => {
+                self.bb_insts
+                    .get_mut(&block)
+                    .unwrap()
+                    .push(CheckerInst::Remat { into: to, vreg });
+            }
         }
     }
 
@@ -863,14 +1415,14 @@ impl<'a, F: Function> Checker<'a, F> {
             let mut state = self.bb_in.get(&block).cloned().unwrap();
             trace!("analyze: block {} has state {:?}", block.index(), state);
             for inst in self.bb_insts.get(&block).unwrap() {
-                state.update(inst);
+                state.update(inst, self);
                 trace!("analyze: inst {:?} -> state {:?}", inst, state);
             }
 
             for &succ in self.f.block_succs(block) {
                 let mut new_state = state.clone();
                 for edge_inst in self.edge_insts.get(&(block, succ)).unwrap() {
-                    new_state.update(edge_inst);
+                    new_state.update(edge_inst, self);
                     trace!(
                         "analyze: succ {:?}: inst {:?} -> state {:?}",
                         succ,
@@ -914,18 +1466,31 @@ impl<'a, F: Function> Checker<'a, F> {
         let mut errors = vec![];
         for (block, input) in &self.bb_in {
             let mut state = input.clone();
-            for inst in self.bb_insts.get(block).unwrap() {
-                if let Err(e) = state.check(InstPosition::Before, inst, self) {
+            let points = self.bb_points.get(block).unwrap();
+            // The scratch registers written since the last instruction.
+            let mut scratch = PRegSet::empty();
+            for (i, inst) in self.bb_insts.get(block).unwrap().iter().enumerate() {
+                if let Err(mut e) = state.check(InstPosition::Before, inst, self) {
+                    self.add_trace(&mut e, *block, i);
                     trace!("Checker error: {:?}", e);
                     errors.push(e);
                 }
-                state.update(inst);
-                if let Err(e) = state.check(InstPosition::After, inst, self) {
+                for e in self
+                    .check_spillslots(&state, points[i], inst)
+                    .chain(self.check_scratch(&mut scratch, points[i], inst))
+                {
+                    trace!("Checker error: {:?}", e);
+                    errors.push(e);
+                }
+                state.update(inst, self);
+                if let Err(mut e) = state.check(InstPosition::After, inst, self) {
+                    self.add_trace(&mut e, *block, i + 1);
                     trace!("Checker error: {:?}", e);
                     errors.push(e);
                 }
             }
         }
+        self.find_debug_location_errors(&mut errors);
 
         if errors.is_empty() {
             Ok(())
@@ -934,6 +1499,530 @@ impl<'a, F: Function> Checker<'a, F> {
         }
     }
 
+    /// Check that the stack allocations of `checkinst`, at `point`, are
+    /// within `Output::num_spillslots` and, for a value of a class with
+    /// multi-slot spillslots, aligned to the slot count. The class of
+    /// a moved value is that of the vregs it holds in `state`, if any.
+    fn check_spillslots<'b>(
+        &'b self,
+        state: &CheckerState,
+        point: ProgPoint,
+        checkinst: &CheckerInst,
+    ) -> impl Iterator<Item = CheckerError> + 'b {
+        let stack_allocs: SmallVec<[(ProgPoint, Allocation, Option<RegClass>); 4]> = match checkinst
+        {
+            CheckerInst::Op {
+                inst,
+                operands,
+                allocs,
+                ..
+            } => operands
+                .iter()
+                .zip(allocs.iter())
+                .map(|(op, &alloc)| {
+                    let point = match op.pos() {
+                        OperandPos::Early => ProgPoint::before(*inst),
+                        OperandPos::Late => ProgPoint::after(*inst),
+                    };
+                    (point, alloc, Some(op.class()))
+                })
+                .collect(),
+            &CheckerInst::Move { into, from } => {
+                let class = state
+                    .get_value(&from)
+                    .and_then(|value| value.vregs())
+                    .and_then(|vregs| vregs.iter().next())
+                    .map(|vreg| vreg.class());
+                smallvec![(point, into, class), (point, from, class)]
+            }
+            &CheckerInst::Remat { into, vreg } => smallvec![(point, into, Some(vreg.class()))],
+            CheckerInst::ParallelMove { .. } => smallvec![],
+        };
+        stack_allocs
+            .into_iter()
+            .filter_map(move |(point, alloc, class)| self.check_spillslot(point, alloc, class))
+    }
+
+    /// Check a single stack allocation: see `check_spillslots`.
+    fn check_spillslot(
+        &self,
+        point: ProgPoint,
+        alloc: Allocation,
+        class: Option<RegClass>,
+    ) -> Option<CheckerError> {
+        let slot = alloc.as_stack()?.index();
+        let size = class.map_or(1, |class| self.f.spillslot_size(class));
+        // The slot after the last one the allocation covers.
+        let end = if self.f.multi_spillslot_named_by_last_slot() {
+            slot + 1
+        } else {
+            slot + size
+        };
+        if end > self.num_spillslots {
+            Some(CheckerError::SpillSlotOutOfRange {
+                point,
+                alloc,
+                num_spillslots: self.num_spillslots,
+            })
+        } else if end < size || end % size != 0 {
+            Some(CheckerError::MisalignedSpillSlot {
+                point,
+                alloc,
+                class: class.unwrap(),
+                size,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Check that `checkinst`, at `point`, reads a scratch register of
+    /// `MachineEnv::scratch_by_class` only if it was written since the
+    /// last instruction, tracking those written in `scratch`: the client
+    /// may use the scratch registers within each instruction.
+    fn check_scratch(
+        &self,
+        scratch: &mut PRegSet,
+        point: ProgPoint,
+        checkinst: &CheckerInst,
+    ) -> Option<CheckerError> {
+        let scratch_reg = |alloc: Allocation| {
+            let reg = alloc.as_reg()?;
+            self.machine_env
+                .scratch_by_class
+                .iter()
+                .flatten()
+                .find(|&&scratch| self.machine_env.regs_overlap(scratch, reg))
+                .copied()
+        };
+        let mut error = None;
+        let mut read = |alloc: Allocation, scratch: &PRegSet| {
+            if let Some(reg) = scratch_reg(alloc) {
+                if !scratch.contains(reg) && error.is_none() {
+                    error = Some(CheckerError::ScratchRegisterAcrossInstruction { point, alloc });
+                }
+            }
+        };
+        match *checkinst {
+            CheckerInst::Move { into, from } => {
+                read(from, scratch);
+                if let Some(reg) = scratch_reg(into) {
+                    scratch.add(reg);
+                }
+            }
+            CheckerInst::Remat { into, .. } => {
+                if let Some(reg) = scratch_reg(into) {
+                    scratch.add(reg);
+                }
+            }
+            CheckerInst::Op {
+                ref operands,
+                ref allocs,
+                ..
+            } => {
+                for (op, &alloc) in operands.iter().zip(allocs.iter()) {
+                    if op.kind() == OperandKind::Use && op.as_fixed_nonallocatable().is_none() {
+                        read(alloc, scratch);
+                    }
+                }
+                *scratch = PRegSet::empty();
+                for (op, &alloc) in operands.iter().zip(allocs.iter()) {
+                    if op.kind() == OperandKind::Def {
+                        if let Some(reg) = scratch_reg(alloc) {
+                            scratch.add(reg);
+                        }
+                    }
+                }
+            }
+            CheckerInst::ParallelMove { .. } => {}
+        }
+        error
+    }
+
+    /// Check each of `Output::debug_locations` at every program point
+    /// in its range, reporting each wrong one once.
+    fn find_debug_location_errors(&self, errors: &mut Vec<CheckerError>) {
+        // The vregs that each location's label was requested for, with
+        // their ranges, where those contain the location's.
+        let mut labels: FxHashMap<u32, Vec<(VReg, ProgPoint, ProgPoint)>> = FxHashMap::default();
+        for &(vreg, from, to, label) in self.f.debug_value_labels() {
+            labels.entry(label).or_default().push((
+                vreg,
+                ProgPoint::before(from),
+                ProgPoint::before(to),
+            ));
+        }
+        let mut reported = FxHashSet::default();
+        let mut requested = vec![];
+        for (i, &(label, from, to, alloc)) in self.debug_locations.iter().enumerate() {
+            let vregs: Vec<_> = labels
+                .get(&label)
+                .into_iter()
+                .flatten()
+                .filter(|&&(_, label_from, label_to)| label_from <= from && to <= label_to)
+                .cloned()
+                .collect();
+            if vregs.is_empty() {
+                let e = CheckerError::DebugLocationNotRequested {
+                    label,
+                    from,
+                    to,
+                    alloc,
+                };
+                trace!("Checker error: {:?}", e);
+                errors.push(e);
+                reported.insert(i);
+            }
+            // The vregs of a label may differ in class, so only the
+            // bounds of a stack location are checked.
+            if let Some(e) = self.check_spillslot(from, alloc, None) {
+                trace!("Checker error: {:?}", e);
+                errors.push(e);
+            }
+            requested.push(vregs);
+        }
+
+        for (block, input) in &self.bb_in {
+            if *input == CheckerState::Top && *block != self.f.entry_block() {
+                // Unreachable: the locations can say anything here.
+                continue;
+            }
+            let range = self.f.block_insns(*block);
+            let locations: Vec<usize> = (0..self.debug_locations.len())
+                .filter(|&i| {
+                    let (_, from, to, _) = self.debug_locations[i];
+                    from <= ProgPoint::after(range.last()) && ProgPoint::before(range.first()) < to
+                })
+                .collect();
+            if locations.is_empty() {
+                continue;
+            }
+            let insts = self.bb_insts.get(block).unwrap();
+            let points = self.bb_points.get(block).unwrap();
+            let mut state = input.clone();
+            let mut i = 0;
+            // The moves before a block's last instruction include those
+            // for the edges to its successors, whose values belong to
+            // the successors' locations: a location may lose its value
+            // to them once it held it there, so the point after that
+            // instruction isn't checked where there are successors.
+            let has_succs = !self.f.block_succs(*block).is_empty();
+            for point in range
+                .iter()
+                .flat_map(|inst| [ProgPoint::before(inst), ProgPoint::after(inst)])
+                .filter(|&point| !has_succs || point != ProgPoint::after(range.last()))
+            {
+                // A location only needs to hold its value at some time
+                // during the moves at a point, as those are what put the
+                // value into it at the start of its range and can take
+                // it out at the end.
+                let mut missing: Vec<usize> = locations
+                    .iter()
+                    .cloned()
+                    .filter(|&l| {
+                        let (_, from, to, _) = self.debug_locations[l];
+                        from <= point
+                            && point < to
+                            && !reported.contains(&l)
+                            && !self.holds_debug_value(&state, l, &requested[l], None)
+                    })
+                    .collect();
+                while i < insts.len()
+                    && points[i] == point
+                    && !matches!(insts[i], CheckerInst::Op { .. })
+                {
+                    state.update(&insts[i], self);
+                    i += 1;
+                    missing.retain(|&l| !self.holds_debug_value(&state, l, &requested[l], None));
+                }
+                let op = match insts.get(i) {
+                    Some(op @ CheckerInst::Op { .. }) if point.pos() == InstPosition::Before => {
+                        Some(op)
+                    }
+                    _ => None,
+                };
+                missing.retain(|&l| !self.holds_debug_value(&state, l, &requested[l], op));
+
+                for l in missing {
+                    let (label, _, _, alloc) = self.debug_locations[l];
+                    let (vreg, ..) = requested[l]
+                        .iter()
+                        .find(|&&(_, from, to)| from <= point && point < to)
+                        .unwrap_or(&requested[l][0]);
+                    let actual = match state.get_value(&alloc) {
+                        Some(CheckerValue::VRegs(vregs)) => Some(vregs.clone()),
+                        _ => None,
+                    };
+                    let e = CheckerError::IncorrectDebugLocation {
+                        label,
+                        point,
+                        alloc,
+                        vreg: *vreg,
+                        actual,
+                        trace: self.value_trace(*block, i, alloc, *vreg).0,
+                    };
+                    trace!("Checker error: {:?}", e);
+                    errors.push(e);
+                    reported.insert(l);
+                }
+                if let Some(op) = op {
+                    state.update(op, self);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Whether the allocation of debug location `l` holds one of the
+    /// `requested` vregs for its label in `state`. The early defs of
+    /// `op`, the instruction at the point checked, count as written.
+    fn holds_debug_value(
+        &self,
+        state: &CheckerState,
+        l: usize,
+        requested: &[(VReg, ProgPoint, ProgPoint)],
+        op: Option<&CheckerInst>,
+    ) -> bool {
+        let (_, _, _, alloc) = self.debug_locations[l];
+        let early_def = |vreg| match op {
+            Some(CheckerInst::Op {
+                operands, allocs, ..
+            }) => operands.iter().zip(allocs).any(|(op, &def_alloc)| {
+                op.kind() == OperandKind::Def
+                    && op.pos() == OperandPos::Early
+                    && op.vreg() == vreg
+                    && def_alloc == alloc
+            }),
+            _ => false,
+        };
+        requested.iter().any(|&(vreg, ..)| {
+            early_def(vreg)
+                || match state.get_value(&alloc) {
+                    Some(CheckerValue::VRegs(vregs)) => vregs.contains(&vreg),
+                    _ => false,
+                }
+        })
+    }
+
+    /// Fill in the trace of an error about the value in an allocation
+    /// that was found before the `end`th checker instruction of `block`.
+    /// A use whose value was clobbered becomes a
+    /// `ClobberedValueInAllocation` error.
+    fn add_trace(&self, error: &mut CheckerError, block: Block, end: usize) {
+        let clobbered_by = match error {
+            CheckerError::UnknownValueInAllocation {
+                op, alloc, trace, ..
+            }
+            | CheckerError::ConflictedValueInAllocation {
+                op, alloc, trace, ..
+            }
+            | CheckerError::IncorrectValuesInAllocation {
+                op, alloc, trace, ..
+            } => {
+                let (value_trace, clobbered_by) = self.value_trace(block, end, *alloc, op.vreg());
+                *trace = value_trace;
+                clobbered_by
+            }
+            _ => None,
+        };
+        let clobbered = match (&mut *error, clobbered_by) {
+            (
+                CheckerError::UnknownValueInAllocation {
+                    inst,
+                    op,
+                    alloc,
+                    trace,
+                }
+                | CheckerError::IncorrectValuesInAllocation {
+                    inst,
+                    op,
+                    alloc,
+                    trace,
+                    ..
+                },
+                Some(clobber_inst),
+            ) => CheckerError::ClobberedValueInAllocation {
+                inst: *inst,
+                op: *op,
+                alloc: *alloc,
+                clobber_inst,
+                trace: core::mem::take(trace),
+            },
+            _ => return,
+        };
+        *error = clobbered;
+    }
+
+    /// Whether writing to `write` overwrites (part of) `alloc`.
+    fn overwrites(&self, write: Allocation, alloc: Allocation) -> bool {
+        match (write.as_reg(), alloc.as_reg()) {
+            (Some(a), Some(b)) => self.machine_env.regs_overlap(a, b),
+            _ => write == alloc,
+        }
+    }
+
+    /// The state at the end of the edge from `pred` to `succ`, and the
+    /// vreg on the `pred` side of the edge that becomes `vreg` on the
+    /// `succ` side; `None` if `pred` is never reached.
+    fn edge_out_state(&self, pred: Block, succ: Block, vreg: VReg) -> Option<(CheckerState, VReg)> {
+        let mut state = self.bb_in.get(&pred).unwrap().clone();
+        if state == CheckerState::Top {
+            return None;
+        }
+        let mut pred_vreg = vreg;
+        for inst in self.bb_insts.get(&pred).unwrap() {
+            state.update(inst, self);
+        }
+        for edge_inst in self.edge_insts.get(&(pred, succ)).unwrap() {
+            if let CheckerInst::ParallelMove { moves } = edge_inst {
+                if let Some(&(_, src)) = moves.iter().find(|&&(dest, _)| dest == vreg) {
+                    pred_vreg = src;
+                }
+            }
+            state.update(edge_inst, self);
+        }
+        Some((state, pred_vreg))
+    }
+
+    /// Reconstruct how `alloc` came to not hold `vreg` before the
+    /// `end`th checker instruction of `block`: see
+    /// [`CheckerError::trace`].
+    ///
+    /// This walks backwards from the error, to the last write of the
+    /// allocation or the last def of the vreg; moves are followed to
+    /// their source. At the start of a block, it continues in a
+    /// predecessor at whose end the allocation doesn't hold the vreg
+    /// either, which must exist as the state at the start of the
+    /// block is the meet of those at the end of its predecessors.
+    ///
+    /// Also returns the instruction that clobbered the allocation while
+    /// it still held the vreg, if that is how the value was lost.
+    fn value_trace(
+        &self,
+        mut block: Block,
+        mut end: usize,
+        mut alloc: Allocation,
+        mut vreg: VReg,
+    ) -> (String, Option<Inst>) {
+        // The steps, last first.
+        let mut steps: Vec<String> = vec![];
+        let mut visited = FxHashSet::default();
+        let mut clobbered_by = None;
+        loop {
+            let mut found = false;
+            let insts = self.bb_insts.get(&block).unwrap();
+            for (i, checkinst) in insts[..end].iter().enumerate().rev() {
+                match *checkinst {
+                    CheckerInst::Move { into, from } if into == alloc => {
+                        steps.push(format!("  move {} -> {}", from, into));
+                        alloc = from;
+                    }
+                    CheckerInst::Move { into, .. } if self.overwrites(into, alloc) => {
+                        steps.push(format!("  move into {} overwrites {}", into, alloc));
+                        found = true;
+                    }
+                    CheckerInst::Remat { into, vreg: remat } if self.overwrites(into, alloc) => {
+                        steps.push(format!("  remat {} into {}", remat, into));
+                        found = true;
+                    }
+                    CheckerInst::Op {
+                        inst,
+                        ref operands,
+                        ref allocs,
+                        ref clobbers,
+                    } => {
+                        // Clobbers happen after the defs.
+                        let clobber = clobbers
+                            .iter()
+                            .find(|&&clobber| self.overwrites(Allocation::reg(clobber), alloc));
+                        let def = operands
+                            .iter()
+                            .zip(allocs.iter())
+                            .find(|&(op, &def_alloc)| {
+                                op.kind() == OperandKind::Def
+                                    && (self.overwrites(def_alloc, alloc) || op.vreg() == vreg)
+                            });
+                        if let Some(clobber) = clobber {
+                            steps.push(format!("  inst{}: clobbers {}", inst.index(), clobber));
+                            found = true;
+                            let mut state = self.bb_in.get(&block).unwrap().clone();
+                            for checkinst in &insts[..i] {
+                                state.update(checkinst, self);
+                            }
+                            let held = state
+                                .get_value(&alloc)
+                                .and_then(|value| value.vregs())
+                                .map_or(false, |vregs| vregs.contains(&vreg));
+                            if held && !operands.iter().any(|op| op.vreg() == vreg) {
+                                clobbered_by = Some(inst);
+                            }
+                        } else if let Some((op, &def_alloc)) = def {
+                            let mut step = format!(
+                                "  inst{}: def {} into {}",
+                                inst.index(),
+                                op.vreg(),
+                                def_alloc
+                            );
+                            if !self.overwrites(def_alloc, alloc) {
+                                step += &format!(", so {} no longer holds it", alloc);
+                            }
+                            steps.push(step);
+                            found = true;
+                        }
+                    }
+                    _ => {}
+                }
+                if found {
+                    break;
+                }
+            }
+            steps.push(format!("block{}:", block.index()));
+            if found {
+                break;
+            }
+
+            if !visited.insert((block, alloc, vreg)) {
+                steps.push(String::from("  (and so on around a loop)"));
+                break;
+            }
+            let pred = self.f.block_preds(block).iter().find_map(|&pred| {
+                let (state, pred_vreg) = self.edge_out_state(pred, block, vreg)?;
+                let holds = state
+                    .get_value(&alloc)
+                    .and_then(|value| value.vregs())
+                    .map_or(false, |vregs| vregs.contains(&pred_vreg));
+                (!holds).then(|| (pred, pred_vreg))
+            });
+            match pred {
+                Some((pred, pred_vreg)) => {
+                    if pred_vreg != vreg {
+                        steps.push(format!(
+                            "  block param {} := {} on the edge from block{}",
+                            vreg,
+                            pred_vreg,
+                            pred.index()
+                        ));
+                    }
+                    block = pred;
+                    end = self.bb_insts.get(&block).unwrap().len();
+                    vreg = pred_vreg;
+                }
+                None => {
+                    let header = steps.pop().unwrap();
+                    if block == self.f.entry_block() {
+                        steps.push(format!("  {} holds no value on entry", alloc));
+                    } else {
+                        steps.push(format!("  {} holds no value here", alloc));
+                    }
+                    steps.push(header);
+                    break;
+                }
+            }
+        }
+        steps.reverse();
+        (steps.join("\n"), clobbered_by)
+    }
+
     /// Find any errors, returning `Err(CheckerErrors)` with all errors found
     /// or `Ok(())` otherwise.
     pub fn run(mut self) -> Result<(), CheckerErrors> {
@@ -978,11 +2067,14 @@ impl<'a, F: Function> Checker<'a, F> {
                     &CheckerInst::Move { from, into } => {
                         trace!("    {} -> {}", from, into);
                     }
+                    &CheckerInst::Remat { into, vreg } => {
+                        trace!("    remat {} -> {}", vreg, into);
+                    }
                     &CheckerInst::ParallelMove { .. } => {
                         panic!("unexpected parallel_move in body (non-edge)")
                     }
                 }
-                state.update(inst);
+                state.update(inst, &self);
                 print_state(&state);
             }
 
@@ -1000,7 +2092,7 @@ impl<'a, F: Function> Checker<'a, F> {
                         }
                         _ => panic!("unexpected edge_inst: not a parallel move"),
                     }
-                    state.update(edge_inst);
+                    state.update(edge_inst, &self);
                     print_state(&state);
                 }
             }
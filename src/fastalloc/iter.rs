@@ -45,6 +45,33 @@ impl<'a> Operands<'a> {
     pub fn early(&self) -> impl Iterator<Item = (usize, Operand)> + 'a {
         self.matches(|op| op.pos() == OperandPos::Early)
     }
+
+    /// Whether `op` must be allocated to a register in a register set,
+    /// either directly or by reusing an input that must be.
+    fn in_reg_set(operands: &[Operand], op: Operand) -> bool {
+        match op.constraint() {
+            OperandConstraint::RegSet(_) => true,
+            OperandConstraint::Reuse(idx) => {
+                matches!(operands[idx].constraint(), OperandConstraint::RegSet(_))
+            }
+            _ => false,
+        }
+    }
+
+    /// The operands at `pos`, with those in a register set first.
+    fn by_reg_set(&self, pos: OperandPos) -> impl Iterator<Item = (usize, Operand)> + 'a {
+        let operands = self.0;
+        self.matches(move |op| op.pos() == pos && Self::in_reg_set(operands, op))
+            .chain(self.matches(move |op| op.pos() == pos && !Self::in_reg_set(operands, op)))
+    }
+
+    pub fn late_by_reg_set(&self) -> impl Iterator<Item = (usize, Operand)> + 'a {
+        self.by_reg_set(OperandPos::Late)
+    }
+
+    pub fn early_by_reg_set(&self) -> impl Iterator<Item = (usize, Operand)> + 'a {
+        self.by_reg_set(OperandPos::Early)
+    }
 }
 
 impl<'a> core::ops::Index<usize> for Operands<'a> {
@@ -14,7 +14,7 @@ pub struct Lru {
     /// The index of a node is the `address` from the perspective of the linked list.
     pub data: Vec<LruNode>,
     /// Index of the most recently used register.
-    pub head: u8,
+    pub head: u16,
     /// Class of registers in the cache.
     pub regclass: RegClass,
 }
@@ -22,9 +22,9 @@ pub struct Lru {
 #[derive(Clone, Copy, Debug)]
 pub struct LruNode {
     /// The previous physical register in the list.
-    pub prev: u8,
+    pub prev: u16,
     /// The next physical register in the list.
-    pub next: u8,
+    pub next: u16,
 }
 
 impl Lru {
@@ -32,8 +32,8 @@ impl Lru {
         let regs = regs.into_iter().collect::<Vec<_>>();
         let mut data = vec![
             LruNode {
-                prev: u8::MAX,
-                next: u8::MAX
+                prev: u16::MAX,
+                next: u16::MAX
             };
             PReg::MAX + 1
         ];
@@ -44,14 +44,14 @@ impl Lru {
                 regs[i.checked_sub(1).unwrap_or(no_of_regs - 1)],
                 regs[if i >= no_of_regs - 1 { 0 } else { i + 1 }],
             );
-            data[reg.hw_enc()].prev = prev_reg.hw_enc() as u8;
-            data[reg.hw_enc()].next = next_reg.hw_enc() as u8;
+            data[reg.hw_enc()].prev = prev_reg.hw_enc() as u16;
+            data[reg.hw_enc()].next = next_reg.hw_enc() as u16;
         }
         Self {
             head: if regs.is_empty() {
-                u8::MAX
+                u16::MAX
             } else {
-                regs[0].hw_enc() as u8
+                regs[0].hw_enc() as u16
             },
             data,
             regclass,
@@ -68,7 +68,7 @@ impl Lru {
         );
         trace!("About to poke {:?} in {:?} LRU", preg, self.regclass);
         let prev_newest = self.head;
-        let hw_enc = preg.hw_enc() as u8;
+        let hw_enc = preg.hw_enc() as u16;
         if hw_enc == prev_newest {
             return;
         }
@@ -144,14 +144,14 @@ impl Lru {
         );
         self.data[iprev].next = self.data[hw_enc].next;
         self.data[inext].prev = self.data[hw_enc].prev;
-        self.data[hw_enc].prev = u8::MAX;
-        self.data[hw_enc].next = u8::MAX;
+        self.data[hw_enc].prev = u16::MAX;
+        self.data[hw_enc].next = u16::MAX;
         if hw_enc == self.head as usize {
             if hw_enc == inext {
                 // There are no regs in the LRU
-                self.head = u8::MAX;
+                self.head = u16::MAX;
             } else {
-                self.head = inext as u8;
+                self.head = inext as u16;
             }
         }
         trace!("Removed p{hw_enc} from {:?} LRU", self.regclass);
@@ -161,7 +161,7 @@ impl Lru {
     }
 
     /// Insert node `i` before node `j` in the list.
-    fn insert_before(&mut self, i: u8, j: u8) {
+    fn insert_before(&mut self, i: u16, j: u16) {
         trace!(
             "Before inserting: {:?} LRU. head: {:?}, Actual data: {:?}",
             self.regclass,
@@ -180,7 +180,7 @@ impl Lru {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.head == u8::MAX
+        self.head == u16::MAX
     }
 
     // Using this to debug.
@@ -191,7 +191,7 @@ impl Lru {
             self.head,
             self.data
         );
-        if self.head != u8::MAX {
+        if self.head != u16::MAX {
             let mut node = self.data[self.head as usize].next;
             let mut seen = FxHashSet::default();
             while node != self.head {
@@ -206,17 +206,17 @@ impl Lru {
                 node = self.data[node as usize].next;
             }
             for i in 0..self.data.len() {
-                if self.data[i].prev == u8::MAX && self.data[i].next == u8::MAX {
+                if self.data[i].prev == u16::MAX && self.data[i].next == u16::MAX {
                     // Removed
                     continue;
                 }
-                if self.data[i].prev == u8::MAX || self.data[i].next == u8::MAX {
+                if self.data[i].prev == u16::MAX || self.data[i].next == u16::MAX {
                     panic!(
                         "Invalid LRU. p{} next or previous is an invalid value, but not both",
                         i
                     );
                 }
-                if self.data[self.data[i].prev as usize].next != i as u8 {
+                if self.data[self.data[i].prev as usize].next != i as u16 {
                     panic!(
                         "Invalid LRU. p{i} prev is p{:?}, but p{:?} next is {:?}",
                         self.data[i].prev,
@@ -224,7 +224,7 @@ impl Lru {
                         self.data[self.data[i].prev as usize].next
                     );
                 }
-                if self.data[self.data[i].next as usize].prev != i as u8 {
+                if self.data[self.data[i].next as usize].prev != i as u16 {
                     panic!(
                         "Invalid LRU. p{i} next is p{:?}, but p{:?} prev is p{:?}",
                         self.data[i].next,
@@ -240,7 +240,7 @@ impl Lru {
 impl fmt::Debug for Lru {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use alloc::format;
-        let data_str = if self.head == u8::MAX {
+        let data_str = if self.head == u16::MAX {
             format!("<empty>")
         } else {
             let mut data_str = format!("p{}", self.head);
@@ -270,7 +270,16 @@ impl fmt::Debug for Lru {
 
 #[derive(Clone)]
 pub struct PartedByRegClass<T> {
-    pub items: [T; 3],
+    pub items: [T; RegClass::COUNT],
+}
+
+impl<T> PartedByRegClass<T> {
+    /// Builds the per-class items by calling `f` for each class.
+    pub fn from_fn(mut f: impl FnMut(RegClass) -> T) -> Self {
+        Self {
+            items: core::array::from_fn(|i| f(RegClass::from_index(i))),
+        }
+    }
 }
 
 impl<T: Copy> Copy for PartedByRegClass<T> {}
@@ -295,18 +304,12 @@ impl<T: PartialEq> PartialEq for PartedByRegClass<T> {
     }
 }
 
-/// Least-recently-used caches for register classes Int, Float, and Vector, respectively.
+/// Least-recently-used caches for each register class.
 pub type Lrus = PartedByRegClass<Lru>;
 
 impl Lrus {
-    pub fn new(int_regs: &PRegSet, float_regs: &PRegSet, vec_regs: &PRegSet) -> Self {
-        Self {
-            items: [
-                Lru::new(RegClass::Int, int_regs),
-                Lru::new(RegClass::Float, float_regs),
-                Lru::new(RegClass::Vector, vec_regs),
-            ],
-        }
+    pub fn new(regs: &[PRegSet; RegClass::COUNT]) -> Self {
+        Self::from_fn(|class| Lru::new(class, &regs[class.index()]))
     }
 }
 
@@ -314,20 +317,20 @@ use core::fmt::{Debug, Display};
 
 impl<T: Display> Display for PartedByRegClass<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{{ int: {}, float: {}, vector: {} }}",
-            self.items[0], self.items[1], self.items[2]
-        )
+        write!(f, "{{ ")?;
+        for class in RegClass::ALL {
+            write!(f, "{:?}: {}, ", class, self[class])?;
+        }
+        write!(f, "}}")
     }
 }
 
 impl<T: Debug> Debug for PartedByRegClass<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{{ int: {:?}, float: {:?}, vector: {:?} }}",
-            self.items[0], self.items[1], self.items[2]
-        )
+        write!(f, "{{ ")?;
+        for class in RegClass::ALL {
+            write!(f, "{:?}: {:?}, ", class, self[class])?;
+        }
+        write!(f, "}}")
     }
 }
@@ -1,9 +1,9 @@
 use crate::moves::{MoveAndScratchResolver, ParallelMoves};
-use crate::{cfg::CFGInfo, ion::Stats, Allocation, RegAllocError};
+use crate::{cfg::CFGInfo, ion::Stats, Algorithm, Allocation, InvalidOperand, RegAllocError};
 use crate::{ssa::validate_ssa, Edit, Function, MachineEnv, Output, ProgPoint};
 use crate::{
-    AllocationKind, Block, FxHashMap, Inst, InstPosition, Operand, OperandConstraint, OperandKind,
-    OperandPos, PReg, PRegSet, RegClass, SpillSlot, VReg,
+    AllocationKind, Block, FxHashMap, FxHashSet, Inst, InstPosition, Operand, OperandConstraint,
+    OperandGroup, OperandKind, OperandPos, PReg, PRegSet, RegClass, SpillSlot, VReg,
 };
 use alloc::format;
 use alloc::{vec, vec::Vec};
@@ -132,13 +132,14 @@ pub struct State<'a, F: Function> {
     /// with index `i`.
     vreg_in_preg: Vec<VReg>,
     stack: Stack<'a, F>,
-    /// Least-recently-used caches for register classes Int, Float, and Vector, respectively.
+    /// Least-recently-used caches for each register class.
     lrus: Lrus,
 }
 
 impl<'a, F: Function> State<'a, F> {
     fn is_stack(&self, alloc: Allocation) -> bool {
         alloc.is_stack()
+            || alloc.is_fixed_stack()
             || (alloc.is_reg() && self.fixed_stack_slots.contains(alloc.as_reg().unwrap()))
     }
 
@@ -149,6 +150,47 @@ impl<'a, F: Function> State<'a, F> {
         self.vreg_spillslots[vreg.vreg()]
     }
 
+    /// The error for running out of registers of `class` at `pos` of
+    /// `inst`, while trying to find one for `vreg` if there is one.
+    fn too_many_live_regs(
+        &self,
+        inst: Inst,
+        pos: InstPosition,
+        class: RegClass,
+        vreg: Option<VReg>,
+    ) -> RegAllocError {
+        let mut vregs: Vec<VReg> = vreg.into_iter().collect();
+        for &live in self.vreg_in_preg.iter() {
+            if live != VReg::invalid() && live.class() == class && !vregs.contains(&live) {
+                vregs.push(live);
+            }
+        }
+        let mut fixed = Vec::new();
+        for op in self.func.inst_operands(inst) {
+            if let OperandConstraint::FixedReg(preg) = op.constraint() {
+                if preg.class() == class && !fixed.contains(&preg) {
+                    fixed.push(preg);
+                }
+            }
+        }
+        RegAllocError::TooManyLiveRegs {
+            point: ProgPoint::new(inst, pos),
+            class,
+            vregs,
+            fixed,
+        }
+    }
+
+    /// The error for running out of registers for operand `op` of
+    /// `inst`.
+    fn too_many_live_regs_for(&self, inst: Inst, op: Operand) -> RegAllocError {
+        let pos = match op.pos() {
+            OperandPos::Early => InstPosition::Before,
+            OperandPos::Late => InstPosition::After,
+        };
+        self.too_many_live_regs(inst, pos, op.class(), Some(op.vreg()))
+    }
+
     fn evict_vreg_in_preg(
         &mut self,
         inst: Inst,
@@ -193,7 +235,7 @@ impl<'a, F: Function> State<'a, F> {
             Ok(())
         } else {
             trace!("Can't get a scratch register for {class:?}");
-            Err(RegAllocError::TooManyLiveRegs)
+            Err(self.too_many_live_regs(inst, pos, class, None))
         }
     }
 
@@ -429,6 +471,11 @@ pub struct Env<'a, F: Function> {
     vreg_to_live_inst_range: Vec<(ProgPoint, ProgPoint, Allocation)>,
 
     fixed_stack_slots: PRegSet,
+    /// Wide registers and their units. Wide registers are never
+    /// allocated, but clobbering one clobbers all of its units.
+    reg_units: &'a [(PReg, PRegSet)],
+    /// The register sets that `RegSet` constraints index into.
+    reg_sets: &'a [PRegSet],
 
     // Output.
     allocs: Allocs,
@@ -439,31 +486,16 @@ pub struct Env<'a, F: Function> {
 
 impl<'a, F: Function> Env<'a, F> {
     fn new(func: &'a F, env: &'a MachineEnv) -> Self {
-        let mut regs = [
-            env.preferred_regs_by_class[RegClass::Int as usize].clone(),
-            env.preferred_regs_by_class[RegClass::Float as usize].clone(),
-            env.preferred_regs_by_class[RegClass::Vector as usize].clone(),
-        ];
-        regs[0].union_from(env.non_preferred_regs_by_class[RegClass::Int as usize]);
-        regs[1].union_from(env.non_preferred_regs_by_class[RegClass::Float as usize]);
-        regs[2].union_from(env.non_preferred_regs_by_class[RegClass::Vector as usize]);
-        let allocatable_regs = PRegSet::from(env);
-        let num_available_pregs: PartedByRegClass<i16> = PartedByRegClass {
-            items: [
-                (env.preferred_regs_by_class[RegClass::Int as usize].len()
-                    + env.non_preferred_regs_by_class[RegClass::Int as usize].len())
-                .try_into()
-                .unwrap(),
-                (env.preferred_regs_by_class[RegClass::Float as usize].len()
-                    + env.non_preferred_regs_by_class[RegClass::Float as usize].len())
-                .try_into()
-                .unwrap(),
-                (env.preferred_regs_by_class[RegClass::Vector as usize].len()
-                    + env.non_preferred_regs_by_class[RegClass::Vector as usize].len())
-                .try_into()
-                .unwrap(),
-            ],
-        };
+        // Wide registers are not supported yet, so only their units
+        // are allocatable.
+        let narrow_regs = env.wide_regs().invert();
+        let regs: [PRegSet; RegClass::COUNT] = core::array::from_fn(|class| {
+            (env.preferred_regs_by_class[class] | env.non_preferred_regs_by_class[class])
+                & narrow_regs
+        });
+        let allocatable_regs = PRegSet::from(env) & narrow_regs;
+        let num_available_pregs: PartedByRegClass<i16> =
+            PartedByRegClass::from_fn(|class| regs[class.index()].len().try_into().unwrap());
         let init_available_pregs = {
             let mut regs = allocatable_regs;
             for preg in env.fixed_stack_slots.iter() {
@@ -472,11 +504,7 @@ impl<'a, F: Function> Env<'a, F> {
             regs
         };
         let dedicated_scratch_regs = PartedByRegClass {
-            items: [
-                env.scratch_by_class[0],
-                env.scratch_by_class[1],
-                env.scratch_by_class[2],
-            ],
+            items: env.scratch_by_class,
         };
         trace!("{:#?}", env);
         let (allocs, max_operand_len) = Allocs::new(func);
@@ -486,6 +514,8 @@ impl<'a, F: Function> Env<'a, F> {
             allocatable_regs,
             live_vregs: VRegSet::with_capacity(func.num_vregs()),
             fixed_stack_slots,
+            reg_units: &env.reg_units,
+            reg_sets: &env.reg_sets,
             vreg_to_live_inst_range: vec![
                 (
                     ProgPoint::invalid(),
@@ -494,21 +524,23 @@ impl<'a, F: Function> Env<'a, F> {
                 );
                 func.num_vregs()
             ],
-            preferred_victim: PartedByRegClass {
-                items: [
-                    regs[0].max_preg().unwrap_or(PReg::invalid()),
-                    regs[1].max_preg().unwrap_or(PReg::invalid()),
-                    regs[2].max_preg().unwrap_or(PReg::invalid()),
-                ],
-            },
+            preferred_victim: PartedByRegClass::from_fn(|class| {
+                regs[class.index()].max_preg().unwrap_or(PReg::invalid())
+            }),
             reused_input_to_reuse_op: vec![usize::MAX; max_operand_len as usize],
             init_available_pregs,
             init_num_available_pregs: num_available_pregs.clone(),
             num_any_reg_ops: PartedByExclusiveOperandPos {
                 items: [
-                    PartedByRegClass { items: [0; 3] },
-                    PartedByRegClass { items: [0; 3] },
-                    PartedByRegClass { items: [0; 3] },
+                    PartedByRegClass {
+                        items: [0; RegClass::COUNT],
+                    },
+                    PartedByRegClass {
+                        items: [0; RegClass::COUNT],
+                    },
+                    PartedByRegClass {
+                        items: [0; RegClass::COUNT],
+                    },
                 ],
             },
             allocs,
@@ -530,7 +562,7 @@ impl<'a, F: Function> Env<'a, F> {
                 available_pregs: PartedByOperandPos {
                     items: [init_available_pregs, init_available_pregs],
                 },
-                lrus: Lrus::new(&regs[0], &regs[1], &regs[2]),
+                lrus: Lrus::new(&regs),
                 vreg_in_preg: vec![VReg::invalid(); PReg::NUM_INDEX],
                 stack: Stack::new(func),
                 vreg_allocs: vec![Allocation::none(); func.num_vregs()],
@@ -553,13 +585,16 @@ impl<'a, F: Function> Env<'a, F> {
         debug_assert_eq!(
             self.num_any_reg_ops,
             PartedByExclusiveOperandPos {
-                items: [PartedByRegClass { items: [0; 3] }; 3]
+                items: [PartedByRegClass {
+                    items: [0; RegClass::COUNT]
+                }; 3]
             }
         );
     }
 
     fn reserve_reg_for_operand(
         &mut self,
+        inst: Inst,
         op: Operand,
         op_idx: usize,
         preg: PReg,
@@ -571,13 +606,13 @@ impl<'a, F: Function> Env<'a, F> {
             (OperandPos::Early, OperandKind::Use) => {
                 if op.as_fixed_nonallocatable().is_none() && !early_avail_pregs.contains(preg) {
                     trace!("fixed {preg} for {op} isn't available");
-                    return Err(RegAllocError::TooManyLiveRegs);
+                    return Err(self.too_many_live_regs_for(inst, op));
                 }
                 self.available_pregs[OperandPos::Early].remove(preg);
                 if self.reused_input_to_reuse_op[op_idx] != usize::MAX {
                     if op.as_fixed_nonallocatable().is_none() && !late_avail_pregs.contains(preg) {
                         trace!("fixed {preg} for {op} isn't available");
-                        return Err(RegAllocError::TooManyLiveRegs);
+                        return Err(self.too_many_live_regs_for(inst, op));
                     }
                     self.available_pregs[OperandPos::Late].remove(preg);
                 }
@@ -585,7 +620,7 @@ impl<'a, F: Function> Env<'a, F> {
             (OperandPos::Late, OperandKind::Def) => {
                 if op.as_fixed_nonallocatable().is_none() && !late_avail_pregs.contains(preg) {
                     trace!("fixed {preg} for {op} isn't available");
-                    return Err(RegAllocError::TooManyLiveRegs);
+                    return Err(self.too_many_live_regs_for(inst, op));
                 }
                 self.available_pregs[OperandPos::Late].remove(preg);
             }
@@ -594,7 +629,7 @@ impl<'a, F: Function> Env<'a, F> {
                     && (!early_avail_pregs.contains(preg) || !late_avail_pregs.contains(preg))
                 {
                     trace!("fixed {preg} for {op} isn't available");
-                    return Err(RegAllocError::TooManyLiveRegs);
+                    return Err(self.too_many_live_regs_for(inst, op));
                 }
                 self.available_pregs[OperandPos::Early].remove(preg);
                 self.available_pregs[OperandPos::Late].remove(preg);
@@ -603,6 +638,18 @@ impl<'a, F: Function> Env<'a, F> {
         Ok(())
     }
 
+    /// Returns the registers clobbered by `inst`, including the units of
+    /// any clobbered wide register.
+    fn inst_clobbers(&self, inst: Inst) -> PRegSet {
+        let mut clobbers = self.func.inst_clobbers(inst);
+        for &(wide, units) in self.reg_units {
+            if clobbers.contains(wide) {
+                clobbers.union_from(units);
+            }
+        }
+        clobbers
+    }
+
     fn allocd_within_constraint(&self, op: Operand, inst: Inst) -> bool {
         let alloc = self.vreg_allocs[op.vreg().vreg()];
         match op.constraint() {
@@ -634,7 +681,7 @@ impl<'a, F: Function> Env<'a, F> {
                             // clobber. For example:
                             // use v0 (fixed: p0), late use v0
                             // If p0 is a clobber, then v0 shouldn't be allocated to it.
-                            (op.pos() != OperandPos::Late || !self.func.inst_clobbers(inst).contains(preg))
+                            (op.pos() != OperandPos::Late || !self.inst_clobbers(inst).contains(preg))
                     } else {
                         true
                     }
@@ -642,16 +689,21 @@ impl<'a, F: Function> Env<'a, F> {
                     !alloc.is_none()
                 }
             }
-            OperandConstraint::Reg => {
+            OperandConstraint::Reg | OperandConstraint::RegSet(_) => {
                 if self.is_stack(alloc) {
                     return false;
                 }
                 if let Some(preg) = alloc.as_reg() {
+                    if let OperandConstraint::RegSet(i) = op.constraint() {
+                        if !self.reg_sets[i].contains(preg) {
+                            return false;
+                        }
+                    }
                     if !self.available_pregs[op.pos()].contains(preg) {
                         trace!("The vreg in {preg}: {}", self.vreg_in_preg[preg.index()]);
                         self.vreg_in_preg[preg.index()] == op.vreg()
                             && (op.pos() != OperandPos::Late
-                                || !self.func.inst_clobbers(inst).contains(preg))
+                                || !self.inst_clobbers(inst).contains(preg))
                     } else {
                         true
                     }
@@ -662,13 +714,14 @@ impl<'a, F: Function> Env<'a, F> {
             // It is possible for an operand to have a fixed register constraint to
             // a clobber.
             OperandConstraint::FixedReg(preg) => alloc.is_reg() && alloc.as_reg().unwrap() == preg,
-            OperandConstraint::Reuse(_) => {
+            OperandConstraint::FixedStack(loc) => alloc == Allocation::fixed_stack(loc),
+            OperandConstraint::Reuse(_) | OperandConstraint::ReuseEither(..) => {
                 unreachable!()
             }
 
             OperandConstraint::Stack => self.is_stack(alloc),
-            OperandConstraint::Limit(_) => {
-                todo!("limit constraints are not yet supported in fastalloc")
+            OperandConstraint::Limit(_) | OperandConstraint::WideReg => {
+                unreachable!("rejected by `unsupported_operand`")
             }
         }
     }
@@ -681,7 +734,7 @@ impl<'a, F: Function> Env<'a, F> {
                 let preg = alloc.as_reg().unwrap();
                 self.vreg_in_preg[preg.index()] = VReg::invalid();
             }
-            AllocationKind::Stack => (),
+            AllocationKind::Stack | AllocationKind::FixedStack => (),
             AllocationKind::None => unreachable!("Attempting to free an unallocated operand!"),
         }
         self.vreg_allocs[vreg.vreg()] = Allocation::none();
@@ -693,7 +746,7 @@ impl<'a, F: Function> Env<'a, F> {
         );
     }
 
-    fn select_suitable_reg_in_lru(&self, op: Operand) -> Result<PReg, RegAllocError> {
+    fn select_suitable_reg_in_lru(&self, inst: Inst, op: Operand) -> Result<PReg, RegAllocError> {
         let draw_from = match (op.pos(), op.kind()) {
             // No need to consider reuse constraints because they are
             // handled elsewhere
@@ -702,16 +755,31 @@ impl<'a, F: Function> Env<'a, F> {
             }
             _ => self.available_pregs[op.pos()],
         };
+        let draw_from = match op.constraint() {
+            OperandConstraint::RegSet(i) => draw_from & self.reg_sets[i],
+            _ => draw_from,
+        };
         if draw_from.is_empty(op.class()) {
             trace!("No registers available for {op} in selection");
-            return Err(RegAllocError::TooManyLiveRegs);
+            return Err(self.too_many_live_regs_for(inst, op));
+        }
+        // Prefer the client's hint over the least recently used register,
+        // unless that would evict another vreg.
+        if let Some(hint) = self.func.vreg_hint(op.vreg()) {
+            if hint.class() == op.class()
+                && self.allocatable_regs.contains(hint)
+                && draw_from.contains(hint)
+                && self.vreg_in_preg[hint.index()] == VReg::invalid()
+            {
+                return Ok(hint);
+            }
         }
         let Some(preg) = self.lrus[op.class()].last(draw_from) else {
             trace!(
                 "Failed to find an available {:?} register in the LRU for operand {op}",
                 op.class()
             );
-            return Err(RegAllocError::TooManyLiveRegs);
+            return Err(self.too_many_live_regs_for(inst, op));
         };
         Ok(preg)
     }
@@ -723,11 +791,9 @@ impl<'a, F: Function> Env<'a, F> {
         op: Operand,
     ) -> Result<Allocation, RegAllocError> {
         trace!("available regs: {}", self.available_pregs);
-        trace!("Int LRU: {:?}", self.lrus[RegClass::Int]);
-        trace!("Float LRU: {:?}", self.lrus[RegClass::Float]);
-        trace!("Vector LRU: {:?}", self.lrus[RegClass::Vector]);
+        trace!("LRUs: {:?}", self.lrus);
         trace!("");
-        let preg = self.select_suitable_reg_in_lru(op)?;
+        let preg = self.select_suitable_reg_in_lru(inst, op)?;
         if self.vreg_in_preg[preg.index()] != VReg::invalid() {
             self.evict_vreg_in_preg(inst, preg, InstPosition::After)?;
         }
@@ -772,14 +838,14 @@ impl<'a, F: Function> Env<'a, F> {
                 } else {
                     match self.alloc_reg_for_operand(inst, op) {
                         Ok(alloc) => alloc,
-                        Err(RegAllocError::TooManyLiveRegs) => {
+                        Err(RegAllocError::TooManyLiveRegs { .. }) => {
                             Allocation::stack(self.get_spillslot(op.vreg()))
                         }
                         Err(err) => return Err(err),
                     }
                 }
             }
-            OperandConstraint::Reg => {
+            OperandConstraint::Reg | OperandConstraint::RegSet(_) => {
                 let alloc = self.alloc_reg_for_operand(inst, op)?;
                 self.num_any_reg_ops[op.into()][op.class()] -= 1;
                 trace!(
@@ -794,14 +860,15 @@ impl<'a, F: Function> Env<'a, F> {
 
                 Allocation::reg(preg)
             }
-            OperandConstraint::Reuse(_) => {
+            OperandConstraint::Reuse(_) | OperandConstraint::ReuseEither(..) => {
                 // This is handled elsewhere.
                 unreachable!();
             }
 
             OperandConstraint::Stack => Allocation::stack(self.get_spillslot(op.vreg())),
-            OperandConstraint::Limit(_) => {
-                todo!("limit constraints are not yet supported in fastalloc")
+            OperandConstraint::FixedStack(loc) => Allocation::fixed_stack(loc),
+            OperandConstraint::Limit(_) | OperandConstraint::WideReg => {
+                unreachable!("rejected by `unsupported_operand`")
             }
         };
         self.allocs[(inst.index(), op_idx)] = new_alloc;
@@ -882,7 +949,10 @@ impl<'a, F: Function> Env<'a, F> {
         } else {
             trace!("{op} is already allocated within constraints");
             self.allocs[(inst.index(), op_idx)] = self.vreg_allocs[op.vreg().vreg()];
-            if op.constraint() == OperandConstraint::Reg {
+            if matches!(
+                op.constraint(),
+                OperandConstraint::Reg | OperandConstraint::RegSet(_)
+            ) {
                 self.num_any_reg_ops[op.into()][op.class()] -= 1;
                 trace!("{op} is already within constraint. Number of reg-only ops that need to be allocated now: {}", self.num_any_reg_ops[op.into()]);
             }
@@ -934,9 +1004,8 @@ impl<'a, F: Function> Env<'a, F> {
     fn process_branch(&mut self, block: Block, inst: Inst) -> Result<(), RegAllocError> {
         trace!("Processing branch instruction {inst:?} in block {block:?}");
 
-        let mut int_parallel_moves = ParallelMoves::new();
-        let mut float_parallel_moves = ParallelMoves::new();
-        let mut vec_parallel_moves = ParallelMoves::new();
+        let mut parallel_moves_by_class: [ParallelMoves<_>; RegClass::COUNT] =
+            core::array::from_fn(|_| ParallelMoves::new());
 
         for (succ_idx, succ) in self.func.block_succs(block).iter().enumerate() {
             for (pos, vreg) in self
@@ -980,11 +1049,7 @@ impl<'a, F: Function> Env<'a, F> {
                     )?;
                 }
                 self.vreg_allocs[vreg.vreg()] = vreg_spill;
-                let parallel_moves = match vreg.class() {
-                    RegClass::Int => &mut int_parallel_moves,
-                    RegClass::Float => &mut float_parallel_moves,
-                    RegClass::Vector => &mut vec_parallel_moves,
-                };
+                let parallel_moves = &mut parallel_moves_by_class[vreg.class().index()];
                 let from = Allocation::stack(self.vreg_spillslots[vreg.vreg()]);
                 let to = Allocation::stack(self.vreg_spillslots[succ_param_vreg.vreg()]);
                 trace!("Recording parallel move from {from} to {to}");
@@ -992,20 +1057,16 @@ impl<'a, F: Function> Env<'a, F> {
             }
         }
 
-        let resolved_int = int_parallel_moves.resolve();
-        let resolved_float = float_parallel_moves.resolve();
-        let resolved_vec = vec_parallel_moves.resolve();
         let mut scratch_regs = self.scratch_regs.clone();
         let mut num_spillslots = self.stack.num_spillslots;
         let mut avail_regs =
             self.available_pregs[OperandPos::Early] & self.available_pregs[OperandPos::Late];
 
         trace!("Resolving parallel moves");
-        for (resolved, class) in [
-            (resolved_int, RegClass::Int),
-            (resolved_float, RegClass::Float),
-            (resolved_vec, RegClass::Vector),
-        ] {
+        for (parallel_moves, class) in
+            IntoIterator::into_iter(parallel_moves_by_class).zip(RegClass::ALL)
+        {
+            let resolved = parallel_moves.resolve();
             let scratch_resolver = MoveAndScratchResolver {
                 find_free_reg: || {
                     if let Some(reg) = scratch_regs[class] {
@@ -1039,6 +1100,9 @@ impl<'a, F: Function> Env<'a, F> {
                 },
                 is_stack_alloc: |alloc| self.is_stack(alloc),
                 borrowed_scratch_reg: self.preferred_victim[class],
+                // Wide registers are never allocated by fastalloc.
+                borrowed_scratch_aliases: PRegSet::empty(),
+                borrowed_scratch_occupants: PRegSet::empty(),
             };
             let moves = scratch_resolver.compute(resolved);
             trace!("Resolved {class:?} parallel moves");
@@ -1136,17 +1200,86 @@ impl<'a, F: Function> Env<'a, F> {
                 Operand::new(op.vreg(), new_reused_input_constraint, op.kind(), op.pos());
             trace!("Allocating reused input {op} as {new_reused_input}");
             self.process_operand_allocation(inst, new_reused_input, op_idx)?;
+        } else if let OperandConstraint::FixedStack(loc) = op.constraint() {
+            // The vreg can live anywhere: it's copied into the stack
+            // location just before the instruction by the use moves
+            // in `alloc_inst`. It never lives in the location itself,
+            // as that may be needed by another vreg earlier.
+            let any_op = Operand::new(op.vreg(), OperandConstraint::Any, op.kind(), op.pos());
+            trace!("Allocating fixed-stack use {op} as {any_op}");
+            self.process_operand_allocation(inst, any_op, op_idx)?;
+            self.allocs[(inst.index(), op_idx)] = Allocation::fixed_stack(loc);
         } else {
             self.process_operand_allocation(inst, op, op_idx)?;
         }
         Ok(())
     }
 
+    /// Rewrites the operands of `inst` into a form the allocator
+    /// handles directly, if needed: a `ReuseEither` def becomes a plain
+    /// `Reuse` of the chosen input, and an early use that some def must
+    /// not share a register with (see `OperandGroup`) becomes a late
+    /// use, so it interferes with the defs.
+    ///
+    /// For a `ReuseEither` def, an input that isn't live after the
+    /// instruction is preferred, as its register can be taken over by
+    /// the def without a copy.
+    fn resolve_operands(&self, inst: Inst) -> Option<Vec<Operand>> {
+        let operands = self.func.inst_operands(inst);
+        let groups = self.func.inst_operand_groups(inst);
+        let reuse_either =
+            operands
+                .iter()
+                .enumerate()
+                .find_map(|(idx, op)| match op.constraint() {
+                    OperandConstraint::ReuseEither(a, b) => Some((idx, a, b)),
+                    _ => None,
+                });
+        if reuse_either.is_none() && groups.is_empty() {
+            return None;
+        }
+        let mut resolved = operands.to_vec();
+        let mut reused_idx = operands.iter().find_map(|op| match op.constraint() {
+            OperandConstraint::Reuse(i) => Some(i),
+            _ => None,
+        });
+        if let Some((def_idx, a, b)) = reuse_either {
+            let live_after = |idx: usize| self.live_vregs.contains(operands[idx].vreg());
+            let chosen = if live_after(a) && !live_after(b) {
+                b
+            } else {
+                a
+            };
+            let def = operands[def_idx];
+            trace!("Resolving {def} to reuse input {chosen}");
+            resolved[def_idx] = Operand::new(
+                def.vreg(),
+                OperandConstraint::Reuse(chosen),
+                def.kind(),
+                def.pos(),
+            );
+            reused_idx = Some(chosen);
+        }
+        for (idx, op) in operands.iter().enumerate() {
+            if reused_idx != Some(idx) && OperandGroup::separates_use(groups, operands, idx) {
+                trace!("Allocating {op} as a late use, apart from the defs");
+                resolved[idx] =
+                    Operand::new(op.vreg(), op.constraint(), op.kind(), OperandPos::Late);
+            }
+        }
+        Some(resolved)
+    }
+
     fn alloc_inst(&mut self, block: Block, inst: Inst) -> Result<(), RegAllocError> {
         trace!("Allocating instruction {:?}", inst);
         self.reset_available_pregs_and_scratch_regs();
-        let operands = Operands::new(self.func.inst_operands(inst));
-        let clobbers = self.func.inst_clobbers(inst);
+        let resolved_operands = self.resolve_operands(inst);
+        let operands = Operands::new(
+            resolved_operands
+                .as_deref()
+                .unwrap_or_else(|| self.func.inst_operands(inst)),
+        );
+        let clobbers = self.inst_clobbers(inst);
         // Number of registers that can be used for reg-only operands
         // allocated to fixed-reg operands
         let mut num_fixed_regs_allocatable_clobbers = 0u16;
@@ -1155,7 +1288,10 @@ impl<'a, F: Function> Env<'a, F> {
             if let OperandConstraint::Reuse(reused_idx) = op.constraint() {
                 trace!("Initializing reused_input_to_reuse_op for {op}");
                 self.reused_input_to_reuse_op[reused_idx] = op_idx;
-                if operands.0[reused_idx].constraint() == OperandConstraint::Reg {
+                if matches!(
+                    operands.0[reused_idx].constraint(),
+                    OperandConstraint::Reg | OperandConstraint::RegSet(_)
+                ) {
                     trace!(
                         "Counting {op} as an any-reg op that needs a reg in phase {:?}",
                         ExclusiveOperandPos::Both
@@ -1169,7 +1305,10 @@ impl<'a, F: Function> Env<'a, F> {
                     );
                     self.num_any_reg_ops[ExclusiveOperandPos::EarlyOnly][op.class()] -= 1;
                 }
-            } else if op.constraint() == OperandConstraint::Reg {
+            } else if matches!(
+                op.constraint(),
+                OperandConstraint::Reg | OperandConstraint::RegSet(_)
+            ) {
                 trace!(
                     "Counting {op} as an any-reg op that needs a reg in phase {:?}",
                     Into::<ExclusiveOperandPos>::into(op)
@@ -1182,7 +1321,7 @@ impl<'a, F: Function> Env<'a, F> {
             let OperandConstraint::FixedReg(preg) = op.constraint() else {
                 unreachable!();
             };
-            self.reserve_reg_for_operand(op, op_idx, preg)?;
+            self.reserve_reg_for_operand(inst, op, op_idx, preg)?;
 
             if !seen.contains(preg) {
                 seen.add(preg);
@@ -1270,14 +1409,17 @@ impl<'a, F: Function> Env<'a, F> {
             self.available_pregs[OperandPos::Late]
         );
 
-        for (op_idx, op) in operands.late() {
+        // Operands restricted to a register set are allocated first
+        // within each phase, so that operands that can use any
+        // register don't take the only registers they can use.
+        for (op_idx, op) in operands.late_by_reg_set() {
             if op.kind() == OperandKind::Def {
                 self.alloc_def_op(op_idx, op, operands.0, block, inst)?;
             } else {
                 self.alloc_use(op_idx, op, inst)?;
             }
         }
-        for (op_idx, op) in operands.early() {
+        for (op_idx, op) in operands.early_by_reg_set() {
             trace!("Allocating use operand {op}");
             if op.kind() == OperandKind::Use {
                 self.alloc_use(op_idx, op, inst)?;
@@ -1303,6 +1445,15 @@ impl<'a, F: Function> Env<'a, F> {
                 )?;
             }
         }
+        for (op_idx, op) in operands.0.iter().enumerate() {
+            if op.as_fixed_nonallocatable().is_some() {
+                continue;
+            }
+            let hint = self.func.vreg_hint(op.vreg()).map(Allocation::reg);
+            if hint == Some(self.allocs[(inst.index(), op_idx)]) {
+                self.stats.hints_satisfied += 1;
+            }
+        }
         if self.func.is_branch(inst) {
             self.process_branch(block, inst)?;
         }
@@ -1487,9 +1638,7 @@ impl<'a, F: Function> Env<'a, F> {
             }
         }
         trace!("vreg_in_preg: {:?}", map);
-        trace!("Int LRU: {:?}", self.state.lrus[RegClass::Int]);
-        trace!("Float LRU: {:?}", self.state.lrus[RegClass::Float]);
-        trace!("Vector LRU: {:?}", self.state.lrus[RegClass::Vector]);
+        trace!("LRUs: {:?}", self.state.lrus);
     }
 
     fn log_post_inst_processing_state(&self, inst: Inst) {
@@ -1513,9 +1662,7 @@ impl<'a, F: Function> Env<'a, F> {
             }
         }
         trace!("vreg_in_preg: {:?}", v);
-        trace!("Int LRU: {:?}", self.state.lrus[RegClass::Int]);
-        trace!("Float LRU: {:?}", self.state.lrus[RegClass::Float]);
-        trace!("Vector LRU: {:?}", self.state.lrus[RegClass::Vector]);
+        trace!("LRUs: {:?}", self.state.lrus);
         trace!(
             "Number of any-reg early-only to allocate for: {}",
             self.num_any_reg_ops[ExclusiveOperandPos::EarlyOnly]
@@ -1543,14 +1690,144 @@ impl<'a, F: Function> Env<'a, F> {
 
     fn build_debug_info(&mut self) {
         trace!("Building debug location info");
-        for &(vreg, start, end, label) in self.func.debug_value_labels() {
-            let (point_start, point_end, alloc) = self.vreg_to_live_inst_range[vreg.vreg()];
-            if point_start.inst() <= start && end <= point_end.inst().next() {
-                self.debug_locations
-                    .push((label, point_start, point_end, alloc));
+        // The live range recorded during allocation runs from the def to
+        // the last use in instruction order, but the vreg may be dead in
+        // blocks in between, where its allocation can be reused (for a
+        // block param on a back edge, say). So find the blocks where
+        // each labelled vreg is live, and report its value within each.
+        let mut defs: FxHashMap<VReg, (Block, ProgPoint)> = FxHashMap::default();
+        let mut ends: FxHashMap<VReg, Vec<(Block, ProgPoint)>> = FxHashMap::default();
+        for &(vreg, ..) in self.func.debug_value_labels() {
+            ends.insert(vreg, vec![]);
+        }
+        for block in 0..self.func.num_blocks() {
+            let block = Block::new(block);
+            let insts = self.func.block_insns(block);
+            for &param in self.func.block_params(block) {
+                if ends.contains_key(&param) {
+                    defs.insert(param, (block, ProgPoint::before(insts.first())));
+                }
+            }
+            for inst in insts.iter() {
+                for op in self.func.inst_operands(inst) {
+                    let Some(ends) = ends.get_mut(&op.vreg()) else {
+                        continue;
+                    };
+                    let end = match (op.pos(), op.kind()) {
+                        (OperandPos::Late, OperandKind::Use) | (_, OperandKind::Def) => {
+                            ProgPoint::before(inst.next())
+                        }
+                        (OperandPos::Early, OperandKind::Use) => ProgPoint::after(inst),
+                    };
+                    if op.kind() == OperandKind::Def {
+                        defs.insert(op.vreg(), (block, ProgPoint::after(inst)));
+                    }
+                    ends.push((block, end));
+                }
+            }
+            let last = insts.last();
+            if self.func.is_branch(last) {
+                for succ_idx in 0..self.func.block_succs(block).len() {
+                    for arg in self.func.branch_blockparams(block, last, succ_idx) {
+                        if let Some(ends) = ends.get_mut(arg) {
+                            ends.push((block, ProgPoint::before(last)));
+                        }
+                    }
+                }
             }
         }
-        self.debug_locations.sort_by_key(|loc| loc.0);
+
+        // The range of each labelled vreg in each block where it's live.
+        let mut ranges: FxHashMap<VReg, Vec<(ProgPoint, ProgPoint)>> = FxHashMap::default();
+        for (&vreg, ends) in &ends {
+            let Some(&(def_block, def)) = defs.get(&vreg) else {
+                continue;
+            };
+            let mut live_in = FxHashSet::default();
+            let mut live_out = FxHashSet::default();
+            let mut stack: Vec<Block> = ends
+                .iter()
+                .map(|&(block, _)| block)
+                .filter(|&block| block != def_block && live_in.insert(block))
+                .collect();
+            while let Some(block) = stack.pop() {
+                for &pred in self.func.block_preds(block) {
+                    live_out.insert(pred);
+                    if pred != def_block && live_in.insert(pred) {
+                        stack.push(pred);
+                    }
+                }
+            }
+            let mut blocks: Vec<Block> = live_in.into_iter().collect();
+            blocks.push(def_block);
+            blocks.sort_unstable();
+            let vreg_ranges = blocks
+                .into_iter()
+                .map(|block| {
+                    let insts = self.func.block_insns(block);
+                    let from = if block == def_block {
+                        def
+                    } else {
+                        ProgPoint::before(insts.first())
+                    };
+                    let to = if live_out.contains(&block) {
+                        ProgPoint::before(insts.last().next())
+                    } else {
+                        ends.iter()
+                            .filter(|&&(b, _)| b == block)
+                            .map(|&(_, end)| end)
+                            .fold(from, core::cmp::max)
+                    };
+                    (from, to)
+                })
+                .filter(|&(from, to)| from < to)
+                .collect();
+            ranges.insert(vreg, vreg_ranges);
+        }
+
+        // Each location with the index of the request it is for.
+        let mut locations: Vec<(u32, ProgPoint, ProgPoint, Allocation, usize)> =
+            Vec::with_capacity(self.func.debug_value_labels().len());
+        for (request, &(vreg, start, end, label)) in
+            self.func.debug_value_labels().iter().enumerate()
+        {
+            let alloc = self.vreg_to_live_inst_range[vreg.vreg()].2;
+            if alloc.is_none() {
+                continue;
+            }
+            let (start, end) = (ProgPoint::before(start), ProgPoint::before(end));
+            // Keep within the requested range, outside of which another
+            // of the label's vregs may hold its value.
+            for &(from, to) in ranges.get(&vreg).into_iter().flatten() {
+                let from = core::cmp::max(from, start);
+                let to = core::cmp::min(to, end);
+                if from < to {
+                    locations.push((label, from, to, alloc, request));
+                }
+            }
+        }
+        // Join the ranges of a request in consecutive blocks, but not
+        // those of separate requests, as each location must be within
+        // one. The requested ranges of a label may overlap: keep each
+        // location's range disjoint from the next's.
+        locations.sort_by_key(|loc| (loc.0, loc.1));
+        self.debug_locations.clear();
+        let mut prev_request = None;
+        for (label, from, to, alloc, request) in locations {
+            match self.debug_locations.last_mut() {
+                Some(prev)
+                    if prev.0 == label && prev.2 == from && prev_request == Some(request) =>
+                {
+                    prev.2 = to;
+                    continue;
+                }
+                Some(prev) if prev.0 == label && from < prev.2 => prev.2 = from,
+                _ => {}
+            }
+            self.debug_locations.push((label, from, to, alloc));
+            prev_request = Some(request);
+        }
+        self.debug_locations.retain(|&(_, from, to, _)| from < to);
     }
 
     fn run(&mut self) -> Result<(), RegAllocError> {
@@ -1609,6 +1886,26 @@ fn log_output<'a, F: Function>(env: &Env<'a, F>) {
     trace!("Final edits: {:?}", env.state.edits);
 }
 
+/// Finds an operand with a constraint that fastalloc doesn't support,
+/// returning its instruction and index.
+pub(crate) fn unsupported_operand<F: Function>(
+    func: &F,
+    env: &MachineEnv,
+) -> Option<(Inst, usize)> {
+    let wide_regs = env.wide_regs();
+    (0..func.num_insts()).map(Inst::new).find_map(|inst| {
+        let i = func
+            .inst_operands(inst)
+            .iter()
+            .position(|op| match op.constraint() {
+                OperandConstraint::Limit(_) | OperandConstraint::WideReg => true,
+                OperandConstraint::FixedReg(preg) => wide_regs.contains(preg),
+                _ => false,
+            })?;
+        Some((inst, i))
+    })
+}
+
 pub fn run<F: Function>(
     func: &F,
     mach_env: &MachineEnv,
@@ -1619,6 +1916,14 @@ pub fn run<F: Function>(
         validate_ssa(func, &CFGInfo::new(func)?)?;
     }
 
+    if let Some((inst, i)) = unsupported_operand(func, mach_env) {
+        return Err(RegAllocError::Operand(
+            inst,
+            i,
+            InvalidOperand::Unsupported(Algorithm::Fastalloc),
+        ));
+    }
+
     if trace_enabled!() || verbose_log {
         log_function(func);
     }
@@ -1637,5 +1942,6 @@ pub fn run<F: Function>(
         num_spillslots: env.state.stack.num_spillslots as usize,
         debug_locations: env.debug_locations,
         stats: env.stats,
+        algorithm: Algorithm::Fastalloc,
     })
 }
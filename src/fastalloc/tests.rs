@@ -1,11 +1,14 @@
-use crate::OperandConstraint::{self, *};
-use crate::OperandKind::{self, *};
+use crate::checker::Checker;
+use crate::test_function::{op, p, BlockBuildInfo, RealFunction, RealInstKind};
+use crate::OperandConstraint::*;
+use crate::OperandKind::*;
 use crate::{
-    run, Algorithm, Allocation, Block, Function, Inst, InstRange, MachineEnv, Operand, OperandPos,
-    PReg, PRegSet, ProgPoint, RegClass, RegallocOptions, VReg,
+    peephole, run, validate_function, Algorithm, Allocation, Block, Edit, Inst, InvalidBlock,
+    MachineEnv, OperandGroup, Output, PReg, PRegSet, ProgPoint, RegAllocError, RegClass,
+    RegallocOptions, SpillSlot, StackLoc, VReg,
 };
+use alloc::string::ToString;
 use alloc::vec;
-use alloc::vec::Vec;
 
 #[test]
 fn test_debug_locations1() {
@@ -37,7 +40,7 @@ fn test_debug_locations1() {
         (v(2), i(2), i(4), 71),
         (v(3), i(3), i(4), 34),
     ];
-    let result = run(&f, &mach_env, &options).unwrap();
+    let mut result = run(&f, &mach_env, &options).unwrap();
     assert_eq!(
         result.debug_locations,
         vec![
@@ -67,6 +70,27 @@ fn test_debug_locations1() {
             ),
         ]
     );
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
+
+    // Label 70 is moved to v1's register, and label 99 was never requested.
+    result.debug_locations[2].3 = alloc(p(0));
+    result.debug_locations.push((
+        99,
+        ProgPoint::after(i(0)),
+        ProgPoint::before(i(1)),
+        alloc(p(0)),
+    ));
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    let errors = checker.run().unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "debug location of label 99 in p0i from progpoint0-post to progpoint1-pre was not requested\n\
+         debug location of label 70 in p0i at progpoint2-post: expected v2, but it holds v1\n    \
+         block0:\n      inst2: def v2 into p8i, so p0i no longer holds it"
+    );
 }
 
 #[test]
@@ -102,214 +126,428 @@ fn test_debug_locations2() {
     );
     assert_eq!(result.debug_locations[1].0, 23);
     assert_eq!(result.debug_locations[1].1, ProgPoint::after(i(2)));
-    assert_eq!(result.debug_locations[1].2, ProgPoint::after(i(4)));
+    assert_eq!(result.debug_locations[1].2, ProgPoint::before(i(3)));
     assert!(matches!(result.debug_locations[1].3.as_stack(), Some(_)));
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-impl RealFunction {
-    fn new(blocks: Vec<BlockBuildInfo>) -> Self {
-        assert!(blocks.len() <= 2, "Just for testing purposes");
-        let mut f = Self::default();
-        let mut max_vreg_num_seen = 0;
-        for block in blocks.iter() {
-            f.blocks.push(RealBlock {
-                params: vec![],
-                preds: vec![],
-                succs: vec![],
-            });
-            let start_inst_idx = f.insts.len();
-            for inst in block.insts.iter() {
-                f.insts.push(RealInst {
-                    inst: Inst::new(f.insts.len()),
-                    kind: RealInstKind::Normal,
-                });
-                let start_op_idx = f.operands.len();
-                for op in inst.iter() {
-                    max_vreg_num_seen = max_vreg_num_seen.max(op.vreg().vreg());
-                    f.operands.push(*op);
-                }
-                f.operand_ranges.push((start_op_idx, f.operands.len()));
-            }
-            if !block.insts.is_empty() {
-                f.insts.last_mut().unwrap().kind = RealInstKind::Ret;
-            }
-            f.inst_ranges.push((start_inst_idx, f.insts.len()));
-        }
-        f.num_vregs = max_vreg_num_seen + 1;
-        f
-    }
+#[test]
+fn test_debug_locations_across_blocks() {
+    // v0 is live from its def in block 0 to its use in block 1, so its
+    // location covers both blocks.
+    let mach_env = mach_env(2);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let mut f = RealFunction::new(vec![
+        BlockBuildInfo {
+            insts: vec![
+                /* 0. */ vec![op(Def, 0, Reg)],
+                /* 1. */ vec![op(Def, 1, Reg)],
+                /* 2. */ vec![],
+            ],
+        },
+        BlockBuildInfo {
+            insts: vec![
+                /* 3. */ vec![op(Use, 0, Reg), op(Use, 1, Reg)],
+                /* 4. */ vec![],
+            ],
+        },
+    ]);
+    f.insts[2].kind = RealInstKind::Branch(Block::new(1), vec![]);
+    f.blocks[0].succs = vec![Block::new(1)];
+    f.blocks[1].preds = vec![Block::new(0)];
+    f.debug_value_labels = vec![(v(0), i(1), i(4), 5)];
+    let result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(result.debug_locations.len(), 1);
+    let (label, from, to, alloc) = result.debug_locations[0];
+    assert_eq!(label, 5);
+    assert_eq!(from, ProgPoint::before(i(1)));
+    assert_eq!(to, ProgPoint::after(i(3)));
+    assert!(alloc.is_stack());
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-fn mach_env(no_of_regs: usize) -> MachineEnv {
-    MachineEnv {
-        preferred_regs_by_class: [
-            (0..no_of_regs)
-                .map(|no| PReg::new(no, RegClass::Int))
-                .collect(),
-            PRegSet::empty(),
-            PRegSet::empty(),
-        ],
-        non_preferred_regs_by_class: [PRegSet::empty(); 3],
-        scratch_by_class: [None, None, None],
-        fixed_stack_slots: vec![],
-    }
+#[test]
+fn test_debug_locations_in_single_inst_block() {
+    // Block 1 is only its return, where v0 is labelled.
+    let mach_env = mach_env(2);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let mut f = RealFunction::new(vec![
+        BlockBuildInfo {
+            insts: vec![
+                /* 0. */ vec![op(Def, 0, Reg)],
+                /* 1. */ vec![op(Def, 1, FixedReg(p(0)))],
+                /* 2. */ vec![],
+            ],
+        },
+        BlockBuildInfo {
+            insts: vec![/* 3. */ vec![op(Use, 0, FixedReg(p(1)))]],
+        },
+    ]);
+    f.insts[2].kind = RealInstKind::Branch(Block::new(1), vec![]);
+    f.blocks[0].succs = vec![Block::new(1)];
+    f.blocks[1].preds = vec![Block::new(0)];
+    f.debug_value_labels = vec![(v(0), i(3), i(4), 7)];
+    let mut result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(
+        result.debug_locations,
+        vec![(
+            7,
+            ProgPoint::before(i(3)),
+            ProgPoint::after(i(3)),
+            Allocation::stack(SpillSlot::new(0))
+        )]
+    );
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
+
+    // v1 is in p0 at the end of block 0.
+    result.debug_locations[0].3 = alloc(p(0));
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    let errors = checker.run().unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "debug location of label 7 in p0i at progpoint3-pre: expected v0, but it holds v1\n    \
+         block1:\n      p0i holds no value here"
+    );
 }
 
-fn op(kind: OperandKind, vreg_num: usize, constraint: OperandConstraint) -> Operand {
-    Operand::new(
-        VReg::new(vreg_num, RegClass::Int),
-        constraint,
-        kind,
-        match kind {
-            Use => OperandPos::Early,
-            Def => OperandPos::Late,
+#[test]
+fn test_validate_wide_block_param() {
+    // A wide value can't be passed to a narrow block param.
+    let mach_env = mach_env(2);
+    let mut f = RealFunction::new(vec![
+        BlockBuildInfo {
+            insts: vec![
+                /* 0. */ vec![op(Def, 0, WideReg)],
+                /* 1. */ vec![],
+            ],
+        },
+        BlockBuildInfo {
+            insts: vec![/* 2. */ vec![op(Use, 1, Reg)]],
         },
-    )
+    ]);
+    f.insts[1].kind = RealInstKind::Branch(Block::new(1), vec![v(0)]);
+    f.blocks[0].succs = vec![Block::new(1)];
+    f.blocks[1].preds = vec![Block::new(0)];
+    f.blocks[1].params = vec![v(1)];
+    assert!(matches!(
+        validate_function(&f, &mach_env, Algorithm::Ion),
+        Err(RegAllocError::BB(block, InvalidBlock::WideParam(param)))
+            if block == Block::new(1) && param == v(1)
+    ));
+    f.operands[0] = op(Def, 0, Reg);
+    assert!(validate_function(&f, &mach_env, Algorithm::Ion).is_ok());
 }
 
-fn alloc(preg: PReg) -> Allocation {
-    Allocation::reg(preg)
+#[test]
+fn test_fixed_stack_locations() {
+    let mach_env = mach_env(3);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, Reg)],
+            /* 1. */ vec![op(Def, 1, Reg)],
+            /* 2. */ vec![op(Def, 2, FixedStack(StackLoc::new(0)))],
+            /* 3. */
+            vec![
+                op(Use, 0, FixedStack(StackLoc::new(0))),
+                op(Use, 1, FixedStack(StackLoc::new(1))),
+                op(Use, 2, FixedStack(StackLoc::new(2))),
+                op(Use, 0, Reg),
+            ],
+            /* 4. */ vec![op(Use, 1, Reg), op(Use, 2, Reg)],
+        ],
+    }]);
+    let result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(
+        result.inst_allocs(i(2))[0],
+        Allocation::fixed_stack(StackLoc::new(0))
+    );
+    for loc in 0..3 {
+        assert_eq!(
+            result.inst_allocs(i(3))[loc],
+            Allocation::fixed_stack(StackLoc::new(loc))
+        );
+    }
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-fn v(vreg_num: usize) -> VReg {
-    VReg::new(vreg_num, RegClass::Int)
+#[test]
+fn test_reg_set_constraints() {
+    // Four values live in four registers, two of which must be in the
+    // set {p0, p3}: the reg operands must leave those to them.
+    let mut mach_env = mach_env(4);
+    mach_env.reg_sets = vec![PRegSet::empty().with(p(0)).with(p(3))];
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, Reg)],
+            /* 1. */ vec![op(Def, 1, Reg)],
+            /* 2. */ vec![op(Def, 2, Reg)],
+            /* 3. */ vec![op(Def, 3, Reg)],
+            /* 4. */
+            vec![
+                op(Use, 0, Reg),
+                op(Use, 1, Reg),
+                op(Use, 2, RegSet(0)),
+                op(Use, 3, RegSet(0)),
+            ],
+        ],
+    }]);
+    let result = run(&f, &mach_env, &options).unwrap();
+    let allocs = result.inst_allocs(i(4));
+    for a in &allocs[2..] {
+        assert!(mach_env.reg_sets[0].contains(a.as_reg().unwrap()));
+    }
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-fn i(inst: usize) -> Inst {
-    Inst::new(inst)
+#[test]
+fn test_vreg_hints() {
+    // Both values get their hinted registers rather than the least
+    // recently used ones.
+    let mach_env = mach_env(4);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let mut f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, Reg)],
+            /* 1. */ vec![op(Def, 1, Reg)],
+            /* 2. */ vec![op(Use, 0, Reg), op(Use, 1, Reg)],
+        ],
+    }]);
+    f.vreg_hints = vec![(0, p(2)), (1, p(1))];
+    let result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(result.inst_allocs(i(0)), [alloc(p(2))]);
+    assert_eq!(result.inst_allocs(i(1)), [alloc(p(1))]);
+    assert_eq!(result.inst_allocs(i(2)), [alloc(p(2)), alloc(p(1))]);
+    assert_eq!(result.stats.hints_satisfied, 4);
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-fn p(hw_enc: usize) -> PReg {
-    PReg::new(hw_enc, RegClass::Int)
+#[test]
+fn test_shared_vreg_hint() {
+    // Both values are hinted to p1 and live at once: only one of them
+    // gets it, and neither is spilled for it.
+    let mach_env = mach_env(4);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let mut f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, Reg)],
+            /* 1. */ vec![op(Def, 1, Reg)],
+            /* 2. */ vec![op(Use, 0, Reg)],
+            /* 3. */ vec![op(Use, 1, Reg)],
+            /* 4. */ vec![op(Use, 0, Reg)],
+            /* 5. */ vec![op(Use, 1, Reg)],
+        ],
+    }]);
+    let unhinted = run(&f, &mach_env, &options).unwrap();
+    f.vreg_hints = vec![(0, p(1)), (1, p(1))];
+    let result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(result.num_spillslots, unhinted.num_spillslots);
+    assert_eq!(result.edits.len(), unhinted.edits.len());
+    assert!(result.edits.is_empty());
+    assert!(result.stats.hints_satisfied > 0);
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-struct BlockBuildInfo {
-    insts: Vec<Vec<Operand>>,
+#[test]
+fn test_reuse_either() {
+    // v1 dies at inst 2 and v0 doesn't, so v2 takes over v1's register
+    // and no copy is needed for the tie.
+    let mach_env = mach_env(4);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, Reg)],
+            /* 1. */ vec![op(Def, 1, Reg)],
+            /* 2. */
+            vec![
+                op(Def, 2, ReuseEither(1, 2)),
+                op(Use, 0, Reg),
+                op(Use, 1, Reg),
+            ],
+            /* 3. */ vec![op(Use, 0, Reg), op(Use, 2, Reg)],
+        ],
+    }]);
+    let result = run(&f, &mach_env, &options).unwrap();
+    let allocs = result.inst_allocs(i(2));
+    assert_eq!(allocs[0], allocs[2]);
+    assert_ne!(allocs[0], allocs[1]);
+    assert!(result.edits.is_empty());
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-#[derive(Default)]
-struct RealFunction {
-    blocks: Vec<RealBlock>,
-    insts: Vec<RealInst>,
-    operands: Vec<Operand>,
-    operand_ranges: Vec<(usize, usize)>,
-    inst_ranges: Vec<(usize, usize)>,
-    num_vregs: usize,
-    debug_value_labels: Vec<(VReg, Inst, Inst, u32)>,
+#[test]
+fn test_operand_groups() {
+    // v2 may only share a register with v0, while v3 is not in a group
+    // and may share one with either input.
+    let mach_env = mach_env(3);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let mut f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, Reg)],
+            /* 1. */ vec![op(Def, 1, Reg)],
+            /* 2. */
+            vec![
+                op(Def, 2, Reg),
+                op(Def, 3, Reg),
+                op(Use, 0, Reg),
+                op(Use, 1, Reg),
+            ],
+            /* 3. */ vec![op(Use, 2, Reg), op(Use, 3, Reg)],
+        ],
+    }]);
+    f.operand_groups = vec![vec![], vec![], vec![OperandGroup::new(&[0], &[2])]];
+    let result = run(&f, &mach_env, &options).unwrap();
+    let allocs = result.inst_allocs(i(2));
+    assert_ne!(allocs[0], allocs[3]);
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-struct RealBlock {
-    params: Vec<VReg>,
-    preds: Vec<Block>,
-    succs: Vec<Block>,
+#[test]
+fn test_copy_propagation() {
+    // v1 is moved out of p0 after inst 1 and only read once more, at
+    // inst 2, which can read it from p0 instead.
+    let mach_env = mach_env(4);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, FixedReg(p(0)))],
+            /* 1. */ vec![op(Def, 1, FixedReg(p(0))), op(Use, 0, Reg)],
+            /* 2. */ vec![op(Use, 1, Reg), op(Use, 0, FixedReg(p(2)))],
+        ],
+    }]);
+    let result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(result.edits.len(), 2);
+    options.copy_propagation = true;
+    let result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(result.edits.len(), 1);
+    assert_eq!(result.stats.copy_prop_moves_removed, 1);
+    assert_eq!(result.inst_allocs(i(2)), [alloc(p(0)), alloc(p(2))]);
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
 }
 
-struct RealInst {
-    inst: Inst,
-    kind: RealInstKind,
+#[test]
+fn test_copy_propagation_round_trip() {
+    // v0 is moved from p0 to p1 and straight back: both moves go, and
+    // both are counted.
+    let mach_env = mach_env(2);
+    let f = RealFunction::new(vec![BlockBuildInfo {
+        insts: vec![
+            /* 0. */ vec![op(Def, 0, FixedReg(p(0)))],
+            /* 1. */ vec![op(Use, 0, FixedReg(p(0)))],
+        ],
+    }]);
+    let mut output = Output {
+        edits: vec![
+            (
+                ProgPoint::before(i(1)),
+                Edit::Move {
+                    from: alloc(p(0)),
+                    to: alloc(p(1)),
+                },
+            ),
+            (
+                ProgPoint::before(i(1)),
+                Edit::Move {
+                    from: alloc(p(1)),
+                    to: alloc(p(0)),
+                },
+            ),
+        ],
+        allocs: vec![alloc(p(0)), alloc(p(0))],
+        inst_alloc_offsets: vec![0, 1],
+        ..Output::default()
+    };
+    peephole::run(&f, &mach_env, &mut output);
+    assert!(output.edits.is_empty());
+    assert_eq!(output.stats.copy_prop_moves_removed, 2);
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&output);
+    checker.run().unwrap();
 }
 
-impl RealInst {
-    fn is_branch(&self) -> bool {
-        match self.kind {
-            RealInstKind::Branch(_, _) => true,
-            _ => false,
-        }
-    }
+#[cfg(feature = "large-pregs")]
+#[test]
+fn test_large_register_file() {
+    let num_regs = PReg::MAX + 1;
+    let mach_env = mach_env(num_regs);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    let mut insts: alloc::vec::Vec<_> = (0..num_regs - 1).map(|v| vec![op(Def, v, Reg)]).collect();
+    insts.push((0..num_regs - 1).map(|v| op(Use, v, Reg)).collect());
+    insts.push(vec![op(Use, 0, FixedReg(p(PReg::MAX)))]);
+    let f = RealFunction::new(vec![BlockBuildInfo { insts }]);
+    let result = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(result.num_spillslots, 0);
+    assert_eq!(result.inst_allocs(i(num_regs))[0], alloc(p(PReg::MAX)));
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&result);
+    checker.run().unwrap();
+}
 
-    fn is_ret(&self) -> bool {
-        match self.kind {
-            RealInstKind::Ret => true,
-            _ => false,
-        }
+fn mach_env(no_of_regs: usize) -> MachineEnv {
+    let mut preferred_regs_by_class = [PRegSet::empty(); RegClass::COUNT];
+    preferred_regs_by_class[RegClass::Int as usize] = (0..no_of_regs)
+        .map(|no| PReg::new(no, RegClass::Int))
+        .collect();
+    MachineEnv {
+        preferred_regs_by_class,
+        non_preferred_regs_by_class: [PRegSet::empty(); RegClass::COUNT],
+        scratch_by_class: [None; RegClass::COUNT],
+        fixed_stack_slots: vec![],
+        reg_units: vec![],
+        reg_sets: vec![],
     }
 }
 
-enum RealInstKind {
-    Normal,
-    Branch(Block, Vec<VReg>),
-    Ret,
+fn alloc(preg: PReg) -> Allocation {
+    Allocation::reg(preg)
 }
 
-impl Function for RealFunction {
-    fn num_insts(&self) -> usize {
-        self.insts.len()
-    }
-
-    fn num_blocks(&self) -> usize {
-        self.blocks.len()
-    }
-
-    fn block_insns(&self, block: crate::Block) -> crate::InstRange {
-        let (start, end) = self.inst_ranges[block.index()];
-        if start != end {
-            InstRange::new(
-                self.insts[start].inst,
-                Inst::new(self.insts[end - 1].inst.index() + 1),
-            )
-        } else {
-            InstRange::new(Inst::new(0), Inst::new(0))
-        }
-    }
-
-    fn allow_multiple_vreg_defs(&self) -> bool {
-        false
-    }
-
-    fn block_params(&self, block: crate::Block) -> &[VReg] {
-        &self.blocks[block.index()].params
-    }
-
-    fn block_preds(&self, block: crate::Block) -> &[crate::Block] {
-        &self.blocks[block.index()].preds
-    }
-
-    fn block_succs(&self, block: Block) -> &[Block] {
-        &self.blocks[block.index()].succs
-    }
-
-    fn debug_value_labels(&self) -> &[(VReg, Inst, Inst, u32)] {
-        &self.debug_value_labels
-    }
-
-    fn entry_block(&self) -> Block {
-        Block::new(0)
-    }
-
-    fn inst_clobbers(&self, _insn: Inst) -> crate::PRegSet {
-        PRegSet::empty()
-    }
-
-    fn inst_operands(&self, insn: Inst) -> &[Operand] {
-        let (start, end) = self.operand_ranges[insn.index()];
-        &self.operands[start..end]
-    }
-
-    fn is_branch(&self, insn: Inst) -> bool {
-        self.insts[insn.index()].is_branch()
-    }
-
-    fn is_ret(&self, insn: Inst) -> bool {
-        self.insts[insn.index()].is_ret()
-    }
-
-    fn multi_spillslot_named_by_last_slot(&self) -> bool {
-        false
-    }
-
-    fn num_vregs(&self) -> usize {
-        self.num_vregs
-    }
-
-    fn spillslot_size(&self, regclass: crate::RegClass) -> usize {
-        match regclass {
-            RegClass::Int => 2,
-            RegClass::Float => 4,
-            RegClass::Vector => 8,
-        }
-    }
+fn v(vreg_num: usize) -> VReg {
+    VReg::new(vreg_num, RegClass::Int)
+}
 
-    fn branch_blockparams(&self, _block: Block, _insn: Inst, _succ_idx: usize) -> &[VReg] {
-        &[]
-    }
+fn i(inst: usize) -> Inst {
+    Inst::new(inst)
 }
@@ -54,6 +54,10 @@ impl VRegSet {
         self.items[vreg_num].vreg = VReg::invalid();
     }
 
+    pub fn contains(&self, vreg: VReg) -> bool {
+        self.items[vreg.vreg()].vreg != VReg::invalid()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.items[self.head.index()].next == self.head
     }
@@ -128,6 +132,8 @@ mod tests {
         assert_eq!(iter.next(), Some(VREG(199, Float)));
         assert_eq!(iter.next(), Some(VREG(2000, Int)));
         assert_eq!(iter.next(), Some(VREG(10, Int)));
+        assert!(set.contains(VREG(73, Vector)));
+        assert!(!set.contains(VREG(23, Int)));
         assert!(!set.is_empty());
     }
 
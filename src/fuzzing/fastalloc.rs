@@ -1,6 +1,9 @@
 //! Fuzz the `fastalloc` register allocator.
 
-use crate::{checker, fastalloc, fuzzing::func};
+use crate::{
+    checker, fastalloc, fuzzing::func, peephole, run_incremental, validate_function, Algorithm,
+    Ctx, RegallocOptions,
+};
 use arbitrary::{Arbitrary, Result, Unstructured};
 
 /// `fastalloc`-specific options for generating functions.
@@ -8,8 +11,14 @@ const OPTIONS: func::Options = func::Options {
     reused_inputs: true,
     fixed_regs: true,
     fixed_nonallocatable: true,
+    fixed_stack: true,
+    reg_sets: true,
     clobbers: true,
     reftypes: false,
+    remat: false,
+    block_frequencies: false,
+    vreg_hints: true,
+    operand_groups: true,
     callsite_ish_constraints: true,
     ..func::Options::DEFAULT
 };
@@ -21,6 +30,8 @@ pub struct TestCase {
     func: func::Func,
     annotate: bool,
     check_ssa: bool,
+    copy_propagation: bool,
+    incremental: Option<(usize, usize)>,
 }
 
 impl Arbitrary<'_> for TestCase {
@@ -28,10 +39,18 @@ impl Arbitrary<'_> for TestCase {
         let func = func::Func::arbitrary_with_options(u, &OPTIONS)?;
         let annotate = bool::arbitrary(u)?;
         let check_ssa = bool::arbitrary(u)?;
+        let copy_propagation = bool::arbitrary(u)?;
+        let incremental = if bool::arbitrary(u)? {
+            Some((usize::arbitrary(u)?, u.int_in_range(1..=8)?))
+        } else {
+            None
+        };
         Ok(TestCase {
             func,
             annotate,
             check_ssa,
+            copy_propagation,
+            incremental,
         })
     }
 }
@@ -40,22 +59,57 @@ impl Arbitrary<'_> for TestCase {
 ///
 /// This also:
 /// - optionally creates annotations
-/// - optionally verifies the incoming SSA
-/// - runs the [`checker`].
+/// - optionally verifies the incoming SSA and the rest of the function
+/// - optionally runs copy propagation over the output
+/// - runs the [`checker`]
+/// - optionally changes a few instructions, re-allocates them with
+///   [`crate::run_incremental`] and runs the [`checker`] again.
 pub fn check(t: TestCase) {
     let TestCase {
         func,
         annotate,
         check_ssa,
+        copy_propagation,
+        incremental,
     } = &t;
     log::trace!("func:\n{func:?}");
 
     let env = func::machine_env();
-    let out = fastalloc::run(func, &env, *annotate, *check_ssa).expect("regalloc did not succeed");
+    if *check_ssa {
+        validate_function(func, &env, Algorithm::Fastalloc).expect("function is not valid");
+    }
+    let mut out =
+        fastalloc::run(func, &env, *annotate, *check_ssa).expect("regalloc did not succeed");
+    if *copy_propagation {
+        peephole::run(func, &env, &mut out);
+    }
 
     let mut checker = checker::Checker::new(func, &env);
     checker.prepare(&out);
     checker.run().expect("checker failed");
+
+    if let Some((start, len)) = *incremental {
+        let mut func = func.clone();
+        let changed = func.change_constraints(start, len);
+        let options = RegallocOptions {
+            verbose_log: *annotate,
+            validate_ssa: *check_ssa,
+            validate_function: *check_ssa,
+            algorithm: Algorithm::Fastalloc,
+            copy_propagation: *copy_propagation,
+            run_checker: false,
+            fuel: None,
+        };
+        let mut ctx = Ctx::default();
+        run_incremental(&func, &env, &options, &mut ctx, &out, &[changed])
+            .expect("incremental regalloc did not succeed");
+
+        let mut checker = checker::Checker::new(&func, &env);
+        checker.prepare(&ctx.output);
+        checker
+            .run()
+            .expect("checker failed after incremental regalloc");
+    }
 }
 
 #[test]
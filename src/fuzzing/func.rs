@@ -4,8 +4,9 @@
  */
 
 use crate::{
-    domtree, postorder, Allocation, Block, Function, Inst, InstRange, MachineEnv, Operand,
-    OperandConstraint, OperandKind, OperandPos, PReg, PRegSet, RegClass, VReg,
+    domtree, postorder, Allocation, Block, Function, FxHashSet, Inst, InstRange, MachineEnv,
+    Operand, OperandConstraint, OperandGroup, OperandKind, OperandPos, PReg, PRegSet, RegClass,
+    StackLoc, VReg,
 };
 
 use alloc::vec::Vec;
@@ -27,6 +28,7 @@ pub struct InstData {
     op: InstOpcode,
     operands: Vec<Operand>,
     clobbers: Vec<PReg>,
+    groups: Vec<OperandGroup>,
 }
 
 impl InstData {
@@ -35,6 +37,7 @@ impl InstData {
             op: InstOpcode::Branch,
             operands: vec![],
             clobbers: vec![],
+            groups: vec![],
         }
     }
     pub fn ret() -> InstData {
@@ -42,6 +45,7 @@ impl InstData {
             op: InstOpcode::Ret,
             operands: vec![],
             clobbers: vec![],
+            groups: vec![],
         }
     }
 }
@@ -56,6 +60,9 @@ pub struct Func {
     block_params_out: Vec<Vec<Vec<VReg>>>,
     num_vregs: usize,
     reftype_vregs: Vec<VReg>,
+    remat_vregs: Vec<VReg>,
+    block_frequencies: Vec<f32>,
+    vreg_hints: Vec<(VReg, PReg)>,
     debug_value_labels: Vec<(VReg, Inst, Inst, u32)>,
 }
 
@@ -109,6 +116,10 @@ impl Function for Func {
         &self.insts[insn.index()].operands[..]
     }
 
+    fn inst_operand_groups(&self, insn: Inst) -> &[OperandGroup] {
+        &self.insts[insn.index()].groups[..]
+    }
+
     fn inst_clobbers(&self, insn: Inst) -> PRegSet {
         let mut set = PRegSet::default();
         for &preg in &self.insts[insn.index()].clobbers {
@@ -117,16 +128,32 @@ impl Function for Func {
         set
     }
 
+    fn is_rematerializable(&self, vreg: VReg) -> bool {
+        self.remat_vregs.contains(&vreg)
+    }
+
+    fn block_frequency(&self, block: Block) -> Option<f32> {
+        self.block_frequencies.get(block.index()).copied()
+    }
+
+    fn vreg_hint(&self, vreg: VReg) -> Option<PReg> {
+        self.vreg_hints
+            .iter()
+            .find(|&&(v, _)| v == vreg)
+            .map(|&(_, preg)| preg)
+    }
+
     fn num_vregs(&self) -> usize {
         self.num_vregs
     }
 
     fn spillslot_size(&self, regclass: RegClass) -> usize {
         match regclass {
-            // Test the case where 2 classes share the same
-            RegClass::Int => 1,
+            // Test the case where 2 classes share the same size. Float
+            // values are too small for the wide registers of
+            // `machine_env`, so that class has none.
             RegClass::Float => 1,
-            RegClass::Vector => 2,
+            _ => 2,
         }
     }
 }
@@ -152,6 +179,9 @@ impl FuncBuilder {
                 blocks: vec![],
                 num_vregs: 0,
                 reftype_vregs: vec![],
+                remat_vregs: vec![],
+                block_frequencies: vec![],
+                vreg_hints: vec![],
                 debug_value_labels: vec![],
             },
             insts_per_block: vec![],
@@ -228,6 +258,94 @@ impl FuncBuilder {
         })
     }
 
+    /// Makes some vregs wide by converting their register operands to
+    /// `WideReg`. Vregs that are block parameters or arguments, or
+    /// that are tied to other vregs by a reuse or an operand group,
+    /// stay narrow, as do those of classes without wide registers.
+    fn add_arbitrary_wide_vregs(
+        &mut self,
+        u: &mut Unstructured<'_>,
+        env: &MachineEnv,
+    ) -> ArbitraryResult<()> {
+        let wide_regs = env.wide_regs();
+        let mut narrow = vec![false; self.f.num_vregs];
+        let block_args = self.f.block_params_out.iter().flatten().flatten();
+        for vreg in self.f.block_params_in.iter().flatten().chain(block_args) {
+            narrow[vreg.vreg()] = true;
+        }
+        for inst in self.insts_per_block.iter().flatten() {
+            let tied = !inst.groups.is_empty()
+                || inst.operands.iter().any(|op| {
+                    matches!(
+                        op.constraint(),
+                        OperandConstraint::Reuse(_) | OperandConstraint::ReuseEither(..)
+                    )
+                });
+            if tied {
+                for op in &inst.operands {
+                    if op.as_fixed_nonallocatable().is_none() {
+                        narrow[op.vreg().vreg()] = true;
+                    }
+                }
+            }
+        }
+
+        let mut wide: Vec<Option<bool>> = vec![None; self.f.num_vregs];
+        for inst in self.insts_per_block.iter_mut().flatten() {
+            // Keep wide values out of registers at instructions that
+            // clobber or fix any part of a wide register, and require
+            // at most two wide registers per instruction, so that the
+            // problem stays allocatable.
+            let fixed = inst.operands.iter().filter_map(|op| match op.constraint() {
+                OperandConstraint::FixedReg(preg) => Some(preg),
+                _ => None,
+            });
+            let blocked = inst.clobbers.iter().copied().chain(fixed).any(|preg| {
+                wide_regs
+                    .into_iter()
+                    .any(|wide| env.regs_overlap(preg, wide))
+            });
+            let mut num_wide = 0;
+            for op in &mut inst.operands {
+                if op.as_fixed_nonallocatable().is_some() {
+                    continue;
+                }
+                let is_wide = match wide[op.vreg().vreg()] {
+                    Some(is_wide) => is_wide,
+                    None => {
+                        let has_wide_regs =
+                            wide_regs.into_iter().any(|wide| wide.class() == op.class());
+                        let is_wide =
+                            !narrow[op.vreg().vreg()] && has_wide_regs && u.ratio(1, 4)?;
+                        wide[op.vreg().vreg()] = Some(is_wide);
+                        is_wide
+                    }
+                };
+                if !is_wide {
+                    continue;
+                }
+                let constraint = match op.constraint() {
+                    OperandConstraint::Reg
+                    | OperandConstraint::FixedReg(_)
+                    | OperandConstraint::RegSet(_)
+                    | OperandConstraint::Limit(_)
+                        if !blocked && num_wide < 2 =>
+                    {
+                        num_wide += 1;
+                        OperandConstraint::WideReg
+                    }
+                    OperandConstraint::Reg
+                    | OperandConstraint::FixedReg(_)
+                    | OperandConstraint::RegSet(_)
+                    | OperandConstraint::Limit(_) => OperandConstraint::Any,
+                    _ => continue,
+                };
+                *op = Operand::new(op.vreg(), constraint, op.kind(), op.pos());
+            }
+        }
+        Ok(())
+    }
+
     fn finalize(mut self) -> Func {
         for (blocknum, blockrange) in self.f.blocks.iter_mut().enumerate() {
             let begin_inst = self.f.insts.len();
@@ -244,7 +362,7 @@ impl FuncBuilder {
 
 impl Arbitrary<'_> for RegClass {
     fn arbitrary(u: &mut Unstructured) -> ArbitraryResult<Self> {
-        Ok(*u.choose(&[RegClass::Int, RegClass::Float, RegClass::Vector])?)
+        Ok(*u.choose(&RegClass::ALL)?)
     }
 }
 
@@ -290,24 +408,38 @@ fn convert_def_to_reuse(u: &mut Unstructured<'_>, operands: &mut [Operand]) -> A
     debug_assert_eq!(op.kind(), OperandKind::Def);
 
     let reused = u.int_in_range(1..=(operands.len() - 1))?;
-    Ok(if op.class() == operands[reused].class() {
-        // Replace the def with a reuse of an existing input.
-        operands[0] = Operand::new(
-            op.vreg(),
-            OperandConstraint::Reuse(reused),
-            op.kind(),
-            OperandPos::Late,
-        );
+    if op.class() != operands[reused].class() {
+        return Ok(());
+    }
+
+    // Sometimes let the allocator pick between two inputs, as for a
+    // commutative instruction.
+    let mut other = None;
+    if operands.len() > 2 && bool::arbitrary(u)? {
+        let i = u.int_in_range(1..=(operands.len() - 1))?;
+        if i != reused && op.class() == operands[i].class() {
+            other = Some(i);
+        }
+    }
+
+    // Replace the def with a reuse of an existing input.
+    let constraint = match other {
+        Some(other) => OperandConstraint::ReuseEither(reused, other),
+        None => OperandConstraint::Reuse(reused),
+    };
+    operands[0] = Operand::new(op.vreg(), constraint, op.kind(), OperandPos::Late);
 
-        // Make sure reused input is a register.
-        let op = operands[reused];
-        operands[reused] = Operand::new(
+    // Make sure reused inputs are registers.
+    for i in core::iter::once(reused).chain(other) {
+        let op = operands[i];
+        operands[i] = Operand::new(
             op.vreg(),
             OperandConstraint::Reg,
             op.kind(),
             OperandPos::Early,
         );
-    })
+    }
+    Ok(())
 }
 
 fn convert_op_to_fixed(
@@ -315,13 +447,20 @@ fn convert_op_to_fixed(
     op: &mut Operand,
     fixed_early: &mut Vec<PReg>,
     fixed_late: &mut Vec<PReg>,
+    env: &MachineEnv,
+    opts: &Options,
 ) -> ArbitraryResult<()> {
     let fixed_reg = PReg::new(u.int_in_range(0..=62)?, op.class());
+    if !opts.wide_regs && env.wide_regs().contains(fixed_reg) {
+        return Ok(());
+    }
+    // Two fixed registers conflict if they share a register unit.
+    let conflicts = |fixed: &Vec<PReg>| fixed.iter().any(|&r| env.regs_overlap(r, fixed_reg));
 
     if op.kind() == OperandKind::Def && op.pos() == OperandPos::Early {
         // Early-defs with fixed constraints conflict with
         // any other fixed uses of the same preg.
-        if fixed_late.contains(&fixed_reg) {
+        if conflicts(fixed_late) {
             return Ok(());
         }
     }
@@ -329,7 +468,7 @@ fn convert_op_to_fixed(
     if op.kind() == OperandKind::Use && op.pos() == OperandPos::Late {
         // Late-use with fixed constraints conflict with
         // any other fixed uses of the same preg.
-        if fixed_early.contains(&fixed_reg) {
+        if conflicts(fixed_early) {
             return Ok(());
         }
     }
@@ -339,7 +478,7 @@ fn convert_op_to_fixed(
         OperandPos::Early => fixed_early,
         OperandPos::Late => fixed_late,
     };
-    if fixed_list.contains(&fixed_reg) {
+    if conflicts(fixed_list) {
         return Ok(());
     }
 
@@ -354,9 +493,9 @@ fn convert_op_to_fixed(
     Ok(())
 }
 
-fn has_fixed_def_with(preg: PReg) -> impl Fn(&Operand) -> bool {
+fn has_fixed_def_with(env: &MachineEnv, preg: PReg) -> impl Fn(&Operand) -> bool + '_ {
     move |op| match (op.kind(), op.constraint()) {
-        (OperandKind::Def, OperandConstraint::FixedReg(fixed)) => fixed == preg,
+        (OperandKind::Def, OperandConstraint::FixedReg(fixed)) => env.regs_overlap(fixed, preg),
         _ => false,
     }
 }
@@ -366,9 +505,16 @@ pub struct Options {
     pub reused_inputs: bool,
     pub fixed_regs: bool,
     pub fixed_nonallocatable: bool,
+    pub fixed_stack: bool,
+    pub reg_sets: bool,
     pub clobbers: bool,
     pub reftypes: bool,
+    pub remat: bool,
+    pub block_frequencies: bool,
+    pub vreg_hints: bool,
+    pub operand_groups: bool,
     pub callsite_ish_constraints: bool,
+    pub wide_regs: bool,
     pub num_blocks: RangeInclusive<usize>,
     pub num_vregs_per_block: RangeInclusive<usize>,
     pub num_uses_per_inst: RangeInclusive<usize>,
@@ -382,9 +528,16 @@ impl Options {
         reused_inputs: false,
         fixed_regs: false,
         fixed_nonallocatable: false,
+        fixed_stack: false,
+        reg_sets: false,
         clobbers: false,
         reftypes: false,
+        remat: false,
+        block_frequencies: false,
+        vreg_hints: false,
+        operand_groups: false,
         callsite_ish_constraints: false,
+        wide_regs: false,
         num_blocks: 1..=100,
         num_vregs_per_block: 5..=15,
         num_uses_per_inst: 0..=10,
@@ -410,6 +563,7 @@ impl Func {
         //      already defined (via blockparam or inst) in this block,
         //      or one defined in a dominating block.
 
+        let env = machine_env();
         let mut builder = FuncBuilder::new();
         for _ in 0..u.int_in_range(opts.num_blocks.clone())? {
             builder.add_block();
@@ -447,6 +601,13 @@ impl Func {
         }
         builder.compute_doms();
 
+        if opts.block_frequencies && bool::arbitrary(u)? {
+            for _ in 0..num_blocks {
+                let frequency = f32::from(u.int_in_range(0..=100u8)?);
+                builder.f.block_frequencies.push(frequency);
+            }
+        }
+
         let alloc_vreg = |builder: &mut FuncBuilder, u: &mut Unstructured| {
             let vreg = VReg::new(builder.f.num_vregs, RegClass::arbitrary(u)?);
             builder.f.num_vregs += 1;
@@ -468,6 +629,12 @@ impl Func {
                 if bool::arbitrary(u)? {
                     builder.add_arbitrary_debug_labels(u, num_blocks, vreg)?;
                 }
+                if opts.vreg_hints && bool::arbitrary(u)? {
+                    // Also hint at fixed stack slots and non-allocatable
+                    // registers, which the allocator must ignore.
+                    let preg = PReg::new(u.int_in_range(0..=63)?, vreg.class());
+                    builder.f.vreg_hints.push((vreg, preg));
+                }
             }
             vregs_by_block.push(vregs.clone());
 
@@ -479,6 +646,9 @@ impl Func {
                     block_params[block].push(vreg);
                     max_block_params -= 1;
                 } else {
+                    if opts.remat && bool::arbitrary(u)? {
+                        builder.f.remat_vregs.push(vreg);
+                    }
                     vregs_to_be_defined.push(vreg);
                 }
             }
@@ -533,6 +703,7 @@ impl Func {
                 // Convert some of the operands to have special constraints:
                 // reuses, fixed, clobbers, etc.
                 let mut clobbers: Vec<PReg> = vec![];
+                let mut groups = vec![];
                 if operands.len() > 1 && opts.reused_inputs && bool::arbitrary(u)? {
                     convert_def_to_reuse(u, &mut operands)?;
                 } else if opts.fixed_regs && bool::arbitrary(u)? {
@@ -542,7 +713,7 @@ impl Func {
                         // Pick an operand and make it a fixed reg.
                         let i = u.int_in_range(0..=(operands.len() - 1))?;
                         let op = &mut operands[i];
-                        convert_op_to_fixed(u, op, &mut fixed_early, &mut fixed_late)?;
+                        convert_op_to_fixed(u, op, &mut fixed_early, &mut fixed_late, &env, opts)?;
                     }
 
                     if opts.callsite_ish_constraints && bool::arbitrary(u)? {
@@ -561,7 +732,7 @@ impl Func {
                         for _ in 0..u.int_in_range(opts.num_clobbers_per_inst.clone())? {
                             let reg = u.int_in_range(0..=30)?;
                             let preg = PReg::new(reg, RegClass::arbitrary(u)?);
-                            if operands.iter().any(has_fixed_def_with(preg)) {
+                            if operands.iter().any(has_fixed_def_with(&env, preg)) {
                                 continue;
                             }
                             clobbers.push(preg);
@@ -580,6 +751,66 @@ impl Func {
                         63,
                         RegClass::arbitrary(u)?,
                     )));
+                } else if opts.fixed_stack && bool::arbitrary(u)? {
+                    // Place some operands in stack locations, using the
+                    // operand index as the location so that no two
+                    // operands share one.
+                    for (i, op) in operands.iter_mut().enumerate() {
+                        if bool::arbitrary(u)? {
+                            *op = Operand::new(
+                                op.vreg(),
+                                OperandConstraint::FixedStack(StackLoc::new(i)),
+                                op.kind(),
+                                OperandPos::arbitrary(u)?,
+                            );
+                        }
+                    }
+                } else if opts.operand_groups && operands.len() > 1 && bool::arbitrary(u)? {
+                    // Add a second def, and let each def share a
+                    // register with only some of the uses.
+                    let num_uses = operands.len() - 1;
+                    let def = operands[0];
+                    operands[0] =
+                        Operand::new(def.vreg(), def.constraint(), def.kind(), OperandPos::Late);
+                    let vreg = alloc_vreg(&mut builder, u)?;
+                    operands.push(Operand::new(
+                        vreg,
+                        OperandConstraint::arbitrary(u)?,
+                        OperandKind::Def,
+                        OperandPos::Late,
+                    ));
+                    for def in [0, num_uses + 1] {
+                        let mut uses = vec![];
+                        for i in 1..=num_uses {
+                            if bool::arbitrary(u)? {
+                                uses.push(i);
+                            }
+                        }
+                        groups.push(OperandGroup::new(&[def], &uses));
+                    }
+                } else if opts.reg_sets && bool::arbitrary(u)? {
+                    // Restrict a few operands to one of the register sets
+                    // of their class. Each set has more registers than
+                    // the number of operands restricted here, so the
+                    // problem stays allocatable.
+                    let set = u.int_in_range(0..=REG_SETS_PER_CLASS - 1)?;
+                    for _ in 0..u.int_in_range(0..=4)? {
+                        let i = u.int_in_range(0..=(operands.len() - 1))?;
+                        let op = &mut operands[i];
+                        if matches!(
+                            op.constraint(),
+                            OperandConstraint::Any | OperandConstraint::Reg
+                        ) {
+                            *op = Operand::new(
+                                op.vreg(),
+                                OperandConstraint::RegSet(
+                                    op.class().index() * REG_SETS_PER_CLASS + set,
+                                ),
+                                op.kind(),
+                                op.pos(),
+                            );
+                        }
+                    }
                 }
 
                 builder.add_inst(
@@ -588,6 +819,7 @@ impl Func {
                         op: InstOpcode::Op,
                         operands,
                         clobbers,
+                        groups,
                     },
                 );
                 avail.push(vreg);
@@ -634,10 +866,54 @@ impl Func {
             }
         }
 
+        if opts.wide_regs {
+            builder.add_arbitrary_wide_vregs(u, &env)?;
+        }
+
         builder.f.debug_value_labels.sort_unstable();
 
         Ok(builder.finalize())
     }
+
+    /// Swaps `Reg` and `Any` constraints on `len` instructions from
+    /// `start` (except those with a reused input), as an edit to the
+    /// function after allocation. Returns the changed range.
+    ///
+    /// The `Any` operands of wide vregs stay as they are, as only a
+    /// few of them at a time can be in (wide) registers.
+    pub fn change_constraints(&mut self, start: usize, len: usize) -> InstRange {
+        let start = start % self.insts.len();
+        let end = (start + len).min(self.insts.len());
+        let wide_vregs: FxHashSet<VReg> = self
+            .insts
+            .iter()
+            .flat_map(|inst| &inst.operands)
+            .filter(|op| op.constraint() == OperandConstraint::WideReg)
+            .map(|op| op.vreg())
+            .collect();
+        for inst in &mut self.insts[start..end] {
+            let reuse = inst.operands.iter().any(|op| {
+                matches!(
+                    op.constraint(),
+                    OperandConstraint::Reuse(_) | OperandConstraint::ReuseEither(..)
+                )
+            });
+            if reuse {
+                continue;
+            }
+            for op in &mut inst.operands {
+                let constraint = match op.constraint() {
+                    OperandConstraint::Reg => OperandConstraint::Any,
+                    OperandConstraint::Any if !wide_vregs.contains(&op.vreg()) => {
+                        OperandConstraint::Reg
+                    }
+                    _ => continue,
+                };
+                *op = Operand::new(op.vreg(), constraint, op.kind(), op.pos());
+            }
+        }
+        InstRange::new(Inst::new(start), Inst::new(end))
+    }
 }
 
 impl core::fmt::Debug for Func {
@@ -685,6 +961,9 @@ impl core::fmt::Debug for Func {
                     self.insts[inst.index()].operands,
                     self.insts[inst.index()].clobbers
                 )?;
+                if !self.insts[inst.index()].groups.is_empty() {
+                    write!(f, "    groups: {:?}\n", self.insts[inst.index()].groups)?;
+                }
                 if let InstOpcode::Branch = self.insts[inst.index()].op {
                     write!(f, "    params: {}\n", params_out)?;
                 }
@@ -695,35 +974,55 @@ impl core::fmt::Debug for Func {
     }
 }
 
+/// The number of register sets of each class in [`machine_env`].
+const REG_SETS_PER_CLASS: usize = 2;
+
+/// The encodings of the wide registers of each class in
+/// [`machine_env`], taken from the end of the fixed stack slots.
+const WIDE_REGS: core::ops::Range<usize> = 60..63;
+
 pub fn machine_env() -> MachineEnv {
     fn regs(r: core::ops::Range<usize>, c: RegClass) -> PRegSet {
         r.map(|i| PReg::new(i, c)).collect()
     }
-    let preferred_regs_by_class = [
-        regs(0..24, RegClass::Int),
-        regs(0..24, RegClass::Float),
-        regs(0..24, RegClass::Vector),
-    ];
-    let non_preferred_regs_by_class = [
-        regs(24..32, RegClass::Int),
-        regs(24..32, RegClass::Float),
-        regs(24..32, RegClass::Vector),
-    ];
-    let scratch_by_class = [None, None, None];
+    let preferred_regs_by_class = RegClass::ALL.map(|class| regs(0..24, class));
+    let mut non_preferred_regs_by_class = RegClass::ALL.map(|class| regs(24..32, class));
+    let scratch_by_class = [None; RegClass::COUNT];
+    // Each wide register is made up of a pair of non-preferred
+    // registers, in every class but `Float`, which uses those
+    // encodings for more fixed stack slots instead.
+    let has_wide_regs = |class: RegClass| class != RegClass::Float;
     let fixed_stack_slots = (32..63)
-        .flat_map(|i| {
-            [
-                PReg::new(i, RegClass::Int),
-                PReg::new(i, RegClass::Float),
-                PReg::new(i, RegClass::Vector),
-            ]
+        .flat_map(|i| RegClass::ALL.map(|class| PReg::new(i, class)))
+        .filter(|preg| !WIDE_REGS.contains(&preg.hw_enc()) || !has_wide_regs(preg.class()))
+        .collect();
+    let reg_units: Vec<_> = RegClass::ALL
+        .iter()
+        .filter(|&&class| has_wide_regs(class))
+        .flat_map(|&class| {
+            WIDE_REGS.enumerate().map(move |(i, wide)| {
+                let units = regs(24 + 2 * i..26 + 2 * i, class);
+                (PReg::new(wide, class), units)
+            })
         })
         .collect();
+    for &(wide, _) in &reg_units {
+        non_preferred_regs_by_class[wide.class() as usize].add(wide);
+    }
+    // Register sets of each class, in class order: the first eight
+    // registers, and eight registers spanning the preferred and
+    // non-preferred ones.
+    let reg_sets = RegClass::ALL
+        .iter()
+        .flat_map(|&class| vec![regs(0..8, class), regs(20..28, class)])
+        .collect();
     // Register 63 is reserved for use as a fixed non-allocatable register.
     MachineEnv {
         preferred_regs_by_class,
         non_preferred_regs_by_class,
         scratch_by_class,
         fixed_stack_slots,
+        reg_units,
+        reg_sets,
     }
 }
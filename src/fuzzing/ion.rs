@@ -1,18 +1,34 @@
 //! Fuzz the `ion` register allocator.
 
-use crate::{checker, fuzzing::func, ion};
+use crate::{
+    checker, fuzzing::func, ion, peephole, run_incremental, run_with_ctx, validate_function,
+    Algorithm, RegallocOptions,
+};
 use arbitrary::{Arbitrary, Result, Unstructured};
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use std::thread_local;
 
+thread_local! {
+    /// How many incremental runs [`check`] made, and how many of them
+    /// fell back to re-allocating the whole function.
+    static INCREMENTAL: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
 /// `ion`-specific options for generating functions.
-const OPTIONS: func::Options = func::Options {
+pub(crate) const OPTIONS: func::Options = func::Options {
     reused_inputs: true,
     fixed_regs: true,
     fixed_nonallocatable: true,
+    fixed_stack: true,
+    reg_sets: true,
     clobbers: true,
     reftypes: true,
+    remat: true,
+    block_frequencies: true,
+    vreg_hints: true,
+    operand_groups: true,
     callsite_ish_constraints: true,
+    wide_regs: true,
     ..func::Options::DEFAULT
 };
 
@@ -23,6 +39,10 @@ pub struct TestCase {
     func: func::Func,
     annotate: bool,
     check_ssa: bool,
+    copy_propagation: bool,
+    fuel: Option<usize>,
+    fallback: bool,
+    incremental: Option<(usize, usize)>,
 }
 
 impl Arbitrary<'_> for TestCase {
@@ -30,10 +50,26 @@ impl Arbitrary<'_> for TestCase {
         let func = func::Func::arbitrary_with_options(u, &OPTIONS)?;
         let annotate = bool::arbitrary(u)?;
         let check_ssa = bool::arbitrary(u)?;
+        let copy_propagation = bool::arbitrary(u)?;
+        let fuel = if bool::arbitrary(u)? {
+            Some(u.int_in_range(0..=100)?)
+        } else {
+            None
+        };
+        let fallback = bool::arbitrary(u)?;
+        let incremental = if bool::arbitrary(u)? {
+            Some((usize::arbitrary(u)?, u.int_in_range(1..=8)?))
+        } else {
+            None
+        };
         Ok(TestCase {
             func,
             annotate,
             check_ssa,
+            copy_propagation,
+            fuel,
+            fallback,
+            incremental,
         })
     }
 }
@@ -42,13 +78,22 @@ impl Arbitrary<'_> for TestCase {
 ///
 /// This also:
 /// - optionally creates annotations
-/// - optionally verifies the incoming SSA
-/// - runs the [`checker`].
+/// - optionally verifies the incoming SSA and the rest of the function
+/// - optionally runs copy propagation over the output
+/// - optionally limits the allocator's fuel, and optionally falls back to
+///   `fastalloc` when it runs out
+/// - runs the [`checker`]
+/// - optionally changes a few instructions, re-allocates them with
+///   [`crate::run_incremental`] and runs the [`checker`] again.
 pub fn check(t: TestCase) {
     let TestCase {
         func,
         annotate,
         check_ssa,
+        copy_propagation,
+        fuel,
+        fallback,
+        incremental,
     } = &t;
     log::trace!("func:\n{func:?}");
 
@@ -59,12 +104,68 @@ pub fn check(t: TestCase) {
     }
 
     CTX.with(|ctx| {
-        ion::run(func, &env, &mut *ctx.borrow_mut(), *annotate, *check_ssa)
+        let options = RegallocOptions {
+            verbose_log: *annotate,
+            validate_ssa: *check_ssa,
+            validate_function: *check_ssa,
+            copy_propagation: *copy_propagation,
+            run_checker: false,
+            fuel: *fuel,
+            algorithm: if *fallback {
+                Algorithm::IonWithFallback
+            } else {
+                Algorithm::Ion
+            },
+        };
+        if *fallback {
+            run_with_ctx(func, &env, &options, &mut ctx.borrow_mut())
+                .expect("regalloc did not succeed");
+        } else {
+            if *check_ssa {
+                validate_function(func, &env, Algorithm::Ion).expect("function is not valid");
+            }
+            ion::run(
+                func,
+                &env,
+                &mut *ctx.borrow_mut(),
+                *annotate,
+                *check_ssa,
+                *fuel,
+            )
             .expect("regalloc did not succeed");
+            if *copy_propagation {
+                peephole::run(func, &env, &mut ctx.borrow_mut().output);
+            }
+        }
 
         let mut checker = checker::Checker::new(func, &env);
         checker.prepare(&ctx.borrow().output);
         checker.run().expect("checker failed");
+
+        if let Some((start, len)) = *incremental {
+            let previous = core::mem::take(&mut ctx.borrow_mut().output);
+            let mut func = func.clone();
+            let changed = func.change_constraints(start, len);
+            let mut ctx = ctx.borrow_mut();
+            run_incremental(&func, &env, &options, &mut ctx, &previous, &[changed])
+                .expect("incremental regalloc did not succeed");
+            INCREMENTAL.with(|counts| {
+                let (runs, fallbacks) = counts.get();
+                let counts_now = (runs + 1, fallbacks + ctx.output.stats.incremental_fallbacks);
+                counts.set(counts_now);
+                log::debug!(
+                    "incremental: {} of {} runs fell back",
+                    counts_now.1,
+                    counts_now.0
+                );
+            });
+
+            let mut checker = checker::Checker::new(&func, &env);
+            checker.prepare(&ctx.output);
+            checker
+                .run()
+                .expect("checker failed after incremental regalloc");
+        }
     });
 }
 
@@ -76,4 +177,15 @@ fn smoke() {
         Ok(())
     })
     .budget_ms(1_000);
+
+    // Falling back is correct but defeats the point of incremental
+    // allocation: it must stay the exception.
+    let (runs, fallbacks) = INCREMENTAL.with(Cell::get);
+    std::eprintln!("incremental: {fallbacks} of {runs} runs fell back");
+    assert!(
+        runs < 100 || fallbacks * 2 < runs,
+        "incremental allocation fell back in {} of {} runs",
+        fallbacks,
+        runs
+    );
 }
@@ -0,0 +1,73 @@
+//! Fuzz the linear-scan register allocator.
+
+use crate::{
+    checker,
+    fuzzing::{func, ion::OPTIONS},
+    ion, peephole,
+};
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// A convenience wrapper to generate a [`func::Func`] with the same
+/// options as for `ion`, which linear scan shares its constraint
+/// handling with.
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    func: func::Func,
+    annotate: bool,
+    check_ssa: bool,
+    copy_propagation: bool,
+}
+
+impl Arbitrary<'_> for TestCase {
+    fn arbitrary(u: &mut Unstructured) -> Result<TestCase> {
+        let func = func::Func::arbitrary_with_options(u, &OPTIONS)?;
+        let annotate = bool::arbitrary(u)?;
+        let check_ssa = bool::arbitrary(u)?;
+        let copy_propagation = bool::arbitrary(u)?;
+        Ok(TestCase {
+            func,
+            annotate,
+            check_ssa,
+            copy_propagation,
+        })
+    }
+}
+
+/// Test a single function with the linear-scan allocator.
+///
+/// This also:
+/// - optionally creates annotations
+/// - optionally verifies the incoming SSA
+/// - optionally runs copy propagation over the output
+/// - runs the [`checker`].
+pub fn check(t: TestCase) {
+    let TestCase {
+        func,
+        annotate,
+        check_ssa,
+        copy_propagation,
+    } = &t;
+    log::trace!("func:\n{func:?}");
+
+    let env = func::machine_env();
+    let mut ctx = ion::Ctx::default();
+    ion::linear_scan::run(func, &env, &mut ctx, *annotate, *check_ssa, None)
+        .expect("regalloc did not succeed");
+    if *copy_propagation {
+        peephole::run(func, &env, &mut ctx.output);
+    }
+
+    let mut checker = checker::Checker::new(func, &env);
+    checker.prepare(&ctx.output);
+    checker.run().expect("checker failed");
+}
+
+#[test]
+fn smoke() {
+    arbtest::arbtest(|u| {
+        let test_case = TestCase::arbitrary(u)?;
+        check(test_case);
+        Ok(())
+    })
+    .budget_ms(1_000);
+}
@@ -7,4 +7,5 @@ pub mod domtree;
 pub mod fastalloc;
 pub mod func;
 pub mod ion;
+pub mod linear_scan;
 pub mod moves;
@@ -1,7 +1,7 @@
 //! Fuzz the parallel-move resolver.
 
 use crate::moves::{MoveAndScratchResolver, ParallelMoves};
-use crate::{Allocation, PReg, RegClass, SpillSlot};
+use crate::{Allocation, PReg, PRegSet, RegClass, SpillSlot};
 use arbitrary::{Arbitrary, Result, Unstructured};
 use std::collections::{HashMap, HashSet};
 use std::{vec, vec::Vec};
@@ -92,6 +92,8 @@ pub fn check(t: TestCase) {
         get_stackslot,
         is_stack_alloc,
         borrowed_scratch_reg: preferred_victim,
+        borrowed_scratch_aliases: PRegSet::empty(),
+        borrowed_scratch_occupants: PRegSet::empty(),
     };
     let moves = scratch_resolver.compute(moves);
     log::trace!("resolved moves: {:?}", moves);
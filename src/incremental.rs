@@ -0,0 +1,1432 @@
+/*
+ * Released under the terms of the Apache 2.0 license with LLVM
+ * exception. See `LICENSE` for details.
+ */
+
+//! Incremental re-allocation (see `run_incremental`).
+//!
+//! The blocks containing changed instructions are grouped into
+//! regions: sets of changed blocks connected by CFG edges. Each region
+//! must be entered through a single header block, and is allocated on
+//! its own while the rest of the function keeps the previous output:
+//!
+//! - A backward analysis over the unchanged blocks finds which vregs
+//!   the unchanged code reads, and from where, after each edge out of
+//!   a region. It follows the checker's view of the output: moves copy
+//!   the vregs an allocation holds, and defs and clobbers overwrite
+//!   them.
+//! - A forward analysis finds where the unchanged code leaves each
+//!   vreg on entry to a region header. Each vreg live into the region
+//!   is taken from one of these locations, which in turn becomes
+//!   something the unchanged code must provide, so the two analyses
+//!   are repeated until the choice settles.
+//! - Each region is then allocated as a function of its own, made of
+//!   an entry block that defines the live-in vregs in their locations
+//!   and branches to the header, the region's blocks, and one block per
+//!   edge out of the region that returns, using each vreg read after
+//!   the edge in its location. The spillslots of the previous output
+//!   become fixed stack locations past those that the client uses, so
+//!   that the allocator only moves values into or out of them there.
+//! - The region's allocations and edits replace the previous ones. The
+//!   moves that the allocator inserts in the entry and exit blocks go
+//!   at the start of the block after the corresponding edge if it has
+//!   a single predecessor, and at the end of the block before it
+//!   otherwise.
+
+use crate::{
+    check_output, ion::Stats, run_with_ctx, validate_function, Algorithm, Allocation, Block,
+    CostModel, Ctx, Edit, Function, FxHashMap, FxHashSet, Inst, InstOrEdit, InstPosition,
+    InstRange, MachineEnv, Operand, OperandConstraint, OperandGroup, OperandKind, OperandPos,
+    Output, PReg, PRegSet, ProgPoint, RegAllocError, RegClass, RegallocOptions, SpillSlot,
+    StackLoc, VReg,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// How many times the choice of live-in locations may change before
+/// we give up on re-allocating the regions on their own.
+const MAX_ROUNDS: usize = 8;
+
+/// An allocation that must hold a vreg at some point.
+type Demand = (Allocation, VReg);
+
+/// The vregs that each allocation is known to hold at some point, as
+/// in the checker.
+type State = FxHashMap<Allocation, FxHashSet<VReg>>;
+
+/// A set of changed blocks connected by CFG edges.
+struct Region {
+    /// The blocks, in order.
+    blocks: Vec<Block>,
+    /// The only block entered from outside of the region.
+    header: Block,
+    /// The edges out of the region, as (block, successor index).
+    exits: Vec<(Block, usize)>,
+    /// What the unchanged code reads after each exit, in terms of the
+    /// vregs before the edge.
+    exit_demands: Vec<FxHashSet<Demand>>,
+    /// Where each vreg live into the header is taken from.
+    live_ins: FxHashSet<Demand>,
+}
+
+/// Where the moves of a region's entry or exit block go in the real
+/// function.
+#[derive(Clone, Copy, Debug)]
+enum Placement {
+    /// Before any other edit at the start of the block.
+    Start(Block),
+    /// After any other edit before the branch that ends the block.
+    End(Block),
+}
+
+struct Incremental<'a, F: Function> {
+    func: &'a F,
+    env: &'a MachineEnv,
+    previous: &'a Output,
+    /// The block of each instruction.
+    block_of: Vec<Block>,
+    /// The region of each changed block.
+    region_of: Vec<Option<usize>>,
+    regions: Vec<Region>,
+    /// Whether each unchanged block leads to a region header through
+    /// unchanged blocks, so that its state matters.
+    reaches_header: Vec<bool>,
+    /// Whether each unchanged block follows an edge out of a region
+    /// through unchanged blocks, so that its demands matter.
+    follows_exit: Vec<bool>,
+    /// The vregs that may be live into a region, which are the only
+    /// ones that the states on the way to the headers keep.
+    tracked: FxHashSet<VReg>,
+    /// The first fixed stack location that stands for a spillslot of
+    /// `previous`.
+    stack_base: usize,
+    /// The vregs named by a `WideReg` operand.
+    wide_vregs: FxHashSet<VReg>,
+}
+
+pub(crate) fn run<'a, F: Function>(
+    func: &F,
+    env: &MachineEnv,
+    options: &RegallocOptions,
+    ctx: &'a mut Ctx,
+    previous: &Output,
+    changed: &[InstRange],
+) -> Result<&'a Output, RegAllocError> {
+    if options.validate_function {
+        validate_function(func, env, options.algorithm)?;
+    }
+    // The regions and the fallback are derived from `func`, which is
+    // valid now: don't check them again. The checker runs once, over
+    // the final output.
+    let run_checker = options.run_checker;
+    let options = &RegallocOptions {
+        validate_function: false,
+        run_checker: false,
+        ..*options
+    };
+    let output = Incremental::new(func, env, previous, changed).and_then(|mut incremental| {
+        incremental.analyze()?;
+        incremental.reallocate(options, ctx)
+    });
+    match output {
+        Some(output) => ctx.output = output,
+        None => {
+            trace!("incremental: re-allocating the whole function");
+            run_with_ctx(func, env, options, ctx)?;
+            ctx.output.stats.incremental_fallbacks = 1;
+        }
+    }
+    if run_checker {
+        check_output(func, env, &ctx.output)?;
+    }
+    Ok(&ctx.output)
+}
+
+fn overlaps(env: &MachineEnv, a: Allocation, b: Allocation) -> bool {
+    match (a.as_reg(), b.as_reg()) {
+        (Some(a), Some(b)) => a != b && env.regs_overlap(a, b),
+        _ => false,
+    }
+}
+
+/// Removes the demands that overwriting `alloc` would not preserve.
+fn kill(env: &MachineEnv, demands: &mut FxHashSet<Demand>, alloc: Allocation) {
+    demands.retain(|&(a, _)| a != alloc && !overlaps(env, a, alloc));
+}
+
+/// Sets what `alloc` holds, forgetting about everything it overlaps.
+fn overwrite(env: &MachineEnv, state: &mut State, alloc: Allocation, vregs: FxHashSet<VReg>) {
+    state.retain(|&a, _| a != alloc && !overlaps(env, a, alloc));
+    if !vregs.is_empty() {
+        state.insert(alloc, vregs);
+    }
+}
+
+/// Meets `state` into `into`, returning whether `into` changed.
+fn meet(into: &mut Option<State>, state: State) -> bool {
+    match into {
+        None => {
+            *into = Some(state);
+            true
+        }
+        Some(into) => {
+            let before: usize = into.values().map(|vregs| vregs.len()).sum();
+            into.retain(|alloc, vregs| match state.get(alloc) {
+                Some(other) => {
+                    vregs.retain(|vreg| other.contains(vreg));
+                    !vregs.is_empty()
+                }
+                None => false,
+            });
+            into.values().map(|vregs| vregs.len()).sum::<usize>() != before
+        }
+    }
+}
+
+impl<'a, F: Function> Incremental<'a, F> {
+    /// Finds the regions, or returns `None` if they can't be
+    /// re-allocated on their own.
+    fn new(
+        func: &'a F,
+        env: &'a MachineEnv,
+        previous: &'a Output,
+        changed: &[InstRange],
+    ) -> Option<Self> {
+        if previous.inst_alloc_offsets.len() != func.num_insts() {
+            return None;
+        }
+        let mut block_of = vec![Block::invalid(); func.num_insts()];
+        for block in 0..func.num_blocks() {
+            let block = Block::new(block);
+            for inst in func.block_insns(block).iter() {
+                block_of[inst.index()] = block;
+            }
+        }
+
+        let mut region_of = vec![None; func.num_blocks()];
+        let mut changed_blocks = vec![];
+        for range in changed {
+            for inst in range.iter() {
+                let block = *block_of.get(inst.index())?;
+                if region_of[block.index()].is_none() {
+                    region_of[block.index()] = Some(usize::MAX);
+                    changed_blocks.push(block);
+                }
+            }
+        }
+        changed_blocks.sort_unstable();
+
+        // Group the changed blocks into regions, and find the header
+        // and exits of each.
+        let mut regions = vec![];
+        for &block in &changed_blocks {
+            if region_of[block.index()] != Some(usize::MAX) {
+                continue;
+            }
+            let index = regions.len();
+            let mut region = Region {
+                blocks: vec![],
+                header: Block::invalid(),
+                exits: vec![],
+                exit_demands: vec![],
+                live_ins: FxHashSet::default(),
+            };
+            region_of[block.index()] = Some(index);
+            let mut stack = vec![block];
+            while let Some(block) = stack.pop() {
+                region.blocks.push(block);
+                let succs = func.block_succs(block);
+                for &next in succs.iter().chain(func.block_preds(block)) {
+                    if region_of[next.index()] == Some(usize::MAX) {
+                        region_of[next.index()] = Some(index);
+                        stack.push(next);
+                    }
+                }
+            }
+            region.blocks.sort_unstable();
+            let mut headers = region.blocks.iter().filter(|&&block| {
+                block == func.entry_block()
+                    || func
+                        .block_preds(block)
+                        .iter()
+                        .any(|pred| region_of[pred.index()] != Some(index))
+            });
+            region.header = *headers.next()?;
+            if headers.next().is_some() {
+                return None;
+            }
+            if region.header == func.entry_block() && !func.block_params(region.header).is_empty() {
+                return None;
+            }
+            for &block in &region.blocks {
+                let last = func.block_insns(block).last();
+                for (succ_idx, &succ) in func.block_succs(block).iter().enumerate() {
+                    if !func.is_branch(last)
+                        || func.branch_blockparams(block, last, succ_idx).len()
+                            != func.block_params(succ).len()
+                    {
+                        return None;
+                    }
+                    if region_of[succ.index()] != Some(index) {
+                        region.exits.push((block, succ_idx));
+                    }
+                }
+            }
+            regions.push(region);
+        }
+
+        let mut reaches_header = vec![false; func.num_blocks()];
+        let mut stack: Vec<Block> = regions.iter().map(|region| region.header).collect();
+        while let Some(block) = stack.pop() {
+            for &pred in func.block_preds(block) {
+                if region_of[pred.index()].is_none() && !reaches_header[pred.index()] {
+                    reaches_header[pred.index()] = true;
+                    stack.push(pred);
+                }
+            }
+        }
+        let mut follows_exit = vec![false; func.num_blocks()];
+        let mut stack: Vec<Block> = regions
+            .iter()
+            .flat_map(|region| &region.exits)
+            .map(|&(block, succ_idx)| func.block_succs(block)[succ_idx])
+            .collect();
+        while let Some(block) = stack.pop() {
+            if follows_exit[block.index()] {
+                continue;
+            }
+            follows_exit[block.index()] = true;
+            for &succ in func.block_succs(block) {
+                if region_of[succ.index()].is_none() {
+                    stack.push(succ);
+                }
+            }
+        }
+
+        let mut stack_locs = 0;
+        let mut wide_vregs = FxHashSet::default();
+        let mut note = |alloc: Allocation| {
+            if let Some(loc) = alloc.as_fixed_stack() {
+                stack_locs = stack_locs.max(loc.index() + 1);
+            }
+        };
+        for inst in 0..func.num_insts() {
+            for op in func.inst_operands(Inst::new(inst)) {
+                match op.constraint() {
+                    OperandConstraint::FixedStack(loc) => note(Allocation::fixed_stack(loc)),
+                    OperandConstraint::WideReg => {
+                        wide_vregs.insert(op.vreg());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for &alloc in &previous.allocs {
+            note(alloc);
+        }
+        for (_, edit) in &previous.edits {
+            match *edit {
+                Edit::Move { from, to } => {
+                    note(from);
+                    note(to);
+                }
+                Edit::Remat { to, .. } => note(to),
+            }
+        }
+
+        Some(Incremental {
+            func,
+            env,
+            previous,
+            block_of,
+            region_of,
+            regions,
+            reaches_header,
+            follows_exit,
+            tracked: FxHashSet::default(),
+            stack_base: stack_locs,
+            wide_vregs,
+        })
+    }
+
+    fn is_changed(&self, block: Block) -> bool {
+        self.region_of[block.index()].is_some()
+    }
+
+    fn branch_args(&self, block: Block, succ_idx: usize) -> &'a [VReg] {
+        let last = self.func.block_insns(block).last();
+        self.func.branch_blockparams(block, last, succ_idx)
+    }
+
+    /// The operand constraint that places a vreg in `alloc` in a
+    /// region's allocation, if there is one.
+    fn constraint(&self, alloc: Allocation, class: RegClass) -> Option<OperandConstraint> {
+        if let Some(preg) = alloc.as_reg() {
+            let env = self.env;
+            let allocatable = env.preferred_regs_by_class[preg.class() as usize].contains(preg)
+                || env.non_preferred_regs_by_class[preg.class() as usize].contains(preg)
+                || env.fixed_stack_slots.contains(&preg);
+            if preg.class() != class || !allocatable {
+                return None;
+            }
+            Some(OperandConstraint::FixedReg(preg))
+        } else if let Some(slot) = alloc.as_stack() {
+            let loc = self.stack_base + slot.index();
+            (loc <= StackLoc::MAX).then(|| OperandConstraint::FixedStack(StackLoc::new(loc)))
+        } else {
+            alloc.as_fixed_stack().map(OperandConstraint::FixedStack)
+        }
+    }
+
+    /// The demands before the instructions and edits of an unchanged
+    /// block, given those after them.
+    fn demands_before(&self, block: Block, mut demands: FxHashSet<Demand>) -> FxHashSet<Demand> {
+        let env = self.env;
+        let steps: Vec<_> = self
+            .previous
+            .block_insts_and_edits(self.func, block)
+            .collect();
+        for step in steps.into_iter().rev() {
+            match step {
+                InstOrEdit::Edit(&Edit::Move { from, to }) => {
+                    demands = demands
+                        .into_iter()
+                        .filter(|&(alloc, _)| !overlaps(env, alloc, to))
+                        .map(|(alloc, vreg)| (if alloc == to { from } else { alloc }, vreg))
+                        .collect();
+                }
+                InstOrEdit::Edit(&Edit::Remat { to, .. }) => kill(env, &mut demands, to),
+                InstOrEdit::Inst(inst) => {
+                    let operands = self.func.inst_operands(inst);
+                    let allocs = self.previous.inst_allocs(inst);
+                    for (op, &alloc) in operands.iter().zip(allocs) {
+                        if op.kind() == OperandKind::Def {
+                            demands.retain(|&(_, vreg)| vreg != op.vreg());
+                            kill(env, &mut demands, alloc);
+                        }
+                    }
+                    for preg in self.func.inst_clobbers(inst) {
+                        kill(env, &mut demands, Allocation::reg(preg));
+                    }
+                    for (op, &alloc) in operands.iter().zip(allocs) {
+                        if op.kind() == OperandKind::Use && op.as_fixed_nonallocatable().is_none() {
+                            demands.insert((alloc, op.vreg()));
+                        }
+                    }
+                }
+            }
+        }
+        demands
+    }
+
+    /// The demands before the edge from `block` to its `succ_idx`th
+    /// successor, given those after it.
+    fn demands_before_edge(
+        &self,
+        block: Block,
+        succ_idx: usize,
+        demands: &FxHashSet<Demand>,
+    ) -> FxHashSet<Demand> {
+        let succ = self.func.block_succs(block)[succ_idx];
+        let params = self.func.block_params(succ);
+        let args = self.branch_args(block, succ_idx);
+        demands
+            .iter()
+            .map(
+                |&(alloc, vreg)| match params.iter().position(|&p| p == vreg) {
+                    Some(i) => (alloc, args[i]),
+                    None => (alloc, vreg),
+                },
+            )
+            .collect()
+    }
+
+    /// The state after the instructions and edits of an unchanged
+    /// block, given that before them.
+    fn state_after(&self, block: Block, state: &mut State) {
+        for step in self.previous.block_insts_and_edits(self.func, block) {
+            self.update(self.previous, step, state, &self.tracked);
+        }
+    }
+
+    /// The state after an instruction or edit of `output`, given that
+    /// before it, keeping only the `tracked` vregs.
+    fn update(
+        &self,
+        output: &Output,
+        step: InstOrEdit,
+        state: &mut State,
+        tracked: &FxHashSet<VReg>,
+    ) {
+        let env = self.env;
+        match step {
+            InstOrEdit::Edit(&Edit::Move { from, to }) => {
+                let vregs = state.get(&from).cloned().unwrap_or_default();
+                overwrite(env, state, to, vregs);
+            }
+            InstOrEdit::Edit(&Edit::Remat { vreg, to }) => {
+                let vregs = core::iter::once(vreg).filter(|v| tracked.contains(v));
+                overwrite(env, state, to, vregs.collect());
+            }
+            InstOrEdit::Inst(inst) => {
+                let operands = self.func.inst_operands(inst);
+                let allocs = output.inst_allocs(inst);
+                for (op, &alloc) in operands.iter().zip(allocs) {
+                    if op.kind() == OperandKind::Def {
+                        let vregs = core::iter::once(op.vreg()).filter(|v| tracked.contains(v));
+                        let vregs: FxHashSet<VReg> = vregs.collect();
+                        if !vregs.is_empty() {
+                            for vregs in state.values_mut() {
+                                vregs.remove(&op.vreg());
+                            }
+                        }
+                        overwrite(env, state, alloc, vregs);
+                    }
+                }
+                for preg in self.func.inst_clobbers(inst) {
+                    let alloc = Allocation::reg(preg);
+                    state.retain(|&a, _| a != alloc && !overlaps(env, a, alloc));
+                }
+            }
+        }
+    }
+
+    /// The state after the edge from `block` to its `succ_idx`th
+    /// successor, given that before it.
+    fn state_after_edge(&self, block: Block, succ_idx: usize, mut state: State) -> State {
+        let succ = self.func.block_succs(block)[succ_idx];
+        let params = self.func.block_params(succ);
+        let args = self.branch_args(block, succ_idx);
+        for vregs in state.values_mut() {
+            let copies: Vec<VReg> = params
+                .iter()
+                .zip(args)
+                .filter(|&(_, arg)| vregs.contains(arg))
+                .map(|(&param, _)| param)
+                .collect();
+            for param in params {
+                vregs.remove(param);
+            }
+            vregs.extend(copies);
+        }
+        state
+    }
+
+    /// Computes the state on entry to each region header, and to each
+    /// unchanged block that leads to one. If `exits` is set, each edge
+    /// out of a region provides exactly its demands; otherwise, such
+    /// edges are ignored.
+    fn entry_states(&self, exits: bool) -> Vec<Option<State>> {
+        let func = self.func;
+        let mut states = vec![None; func.num_blocks()];
+        states[func.entry_block().index()] = Some(State::default());
+        if exits {
+            for region in &self.regions {
+                for (&(block, succ_idx), demands) in region.exits.iter().zip(&region.exit_demands) {
+                    let mut state = State::default();
+                    for &(alloc, vreg) in demands {
+                        state.entry(alloc).or_default().insert(vreg);
+                    }
+                    let succ = func.block_succs(block)[succ_idx];
+                    if self.reaches_header[succ.index()] {
+                        let state = self.state_after_edge(block, succ_idx, state);
+                        meet(&mut states[succ.index()], state);
+                    }
+                }
+            }
+        }
+
+        let mut queue: Vec<Block> = (0..func.num_blocks())
+            .rev()
+            .map(Block::new)
+            .filter(|&block| self.reaches_header[block.index()])
+            .collect();
+        let mut queued: FxHashSet<Block> = queue.iter().copied().collect();
+        while let Some(block) = queue.pop() {
+            queued.remove(&block);
+            let Some(mut state) = states[block.index()].clone() else {
+                continue;
+            };
+            self.state_after(block, &mut state);
+            for (succ_idx, &succ) in func.block_succs(block).iter().enumerate() {
+                if !self.reaches_header[succ.index()] && !self.is_changed(succ) {
+                    continue;
+                }
+                let state = self.state_after_edge(block, succ_idx, state.clone());
+                if meet(&mut states[succ.index()], state)
+                    && !self.is_changed(succ)
+                    && queued.insert(succ)
+                {
+                    queue.push(succ);
+                }
+            }
+        }
+        states
+    }
+
+    /// Computes the demands on entry to each unchanged block that
+    /// follows an edge out of a region, given those of each region's
+    /// header.
+    fn entry_demands(&self) -> Vec<FxHashSet<Demand>> {
+        let func = self.func;
+        let mut demands = vec![FxHashSet::default(); func.num_blocks()];
+        for region in &self.regions {
+            demands[region.header.index()] = region.live_ins.clone();
+        }
+        let mut queue: Vec<Block> = (0..func.num_blocks())
+            .map(Block::new)
+            .filter(|&block| self.follows_exit[block.index()])
+            .collect();
+        let mut queued: FxHashSet<Block> = queue.iter().copied().collect();
+        while let Some(block) = queue.pop() {
+            queued.remove(&block);
+            let mut after = FxHashSet::default();
+            for (succ_idx, &succ) in func.block_succs(block).iter().enumerate() {
+                after.extend(self.demands_before_edge(block, succ_idx, &demands[succ.index()]));
+            }
+            let before = self.demands_before(block, after);
+            if before != demands[block.index()] {
+                demands[block.index()] = before;
+                for &pred in func.block_preds(block) {
+                    if self.follows_exit[pred.index()] && queued.insert(pred) {
+                        queue.push(pred);
+                    }
+                }
+            }
+        }
+        demands
+    }
+
+    /// Computes the state on entry to each block of `output` that the
+    /// entry block reaches.
+    fn output_entry_states(
+        &self,
+        output: &Output,
+        tracked: &FxHashSet<VReg>,
+    ) -> Vec<Option<State>> {
+        let func = self.func;
+        let mut states = vec![None; func.num_blocks()];
+        states[func.entry_block().index()] = Some(State::default());
+        let mut queue = vec![func.entry_block()];
+        let mut queued: FxHashSet<Block> = queue.iter().copied().collect();
+        while let Some(block) = queue.pop() {
+            queued.remove(&block);
+            let mut state = states[block.index()].clone().unwrap();
+            for step in output.block_insts_and_edits(func, block) {
+                self.update(output, step, &mut state, tracked);
+            }
+            for (succ_idx, &succ) in func.block_succs(block).iter().enumerate() {
+                let state = self.state_after_edge(block, succ_idx, state.clone());
+                if meet(&mut states[succ.index()], state) && queued.insert(succ) {
+                    queue.push(succ);
+                }
+            }
+        }
+        states
+    }
+
+    /// The parts of the previous debug locations that still hold their
+    /// values in `output`. As in the checker, a location holds at a
+    /// point if its allocation has, at some time during the edits
+    /// there, a vreg that its label was requested for at that point (or
+    /// is an early def of such a vreg there), and anywhere in
+    /// unreachable blocks.
+    fn debug_locations(&self, output: &Output) -> Vec<(u32, ProgPoint, ProgPoint, Allocation)> {
+        let func = self.func;
+        let locations = &self.previous.debug_locations;
+        if locations.is_empty() {
+            return vec![];
+        }
+        let mut labels: FxHashMap<u32, Vec<(VReg, ProgPoint, ProgPoint)>> = FxHashMap::default();
+        for &(vreg, from, to, label) in func.debug_value_labels() {
+            labels.entry(label).or_default().push((
+                vreg,
+                ProgPoint::before(from),
+                ProgPoint::before(to),
+            ));
+        }
+        // The vregs that each location may hold, and the locations
+        // that overlap each block.
+        let mut requested = vec![];
+        let mut by_block = vec![vec![]; func.num_blocks()];
+        for (l, &(label, from, to, _)) in locations.iter().enumerate() {
+            let vregs: Vec<(VReg, ProgPoint, ProgPoint)> = labels
+                .get(&label)
+                .into_iter()
+                .flatten()
+                .filter(|&&(_, label_from, label_to)| label_from < to && from < label_to)
+                .copied()
+                .collect();
+            if !vregs.is_empty() {
+                let mut inst = from.inst();
+                while inst.index() < func.num_insts() && ProgPoint::before(inst) < to {
+                    let block = self.block_of[inst.index()];
+                    by_block[block.index()].push(l);
+                    inst = func.block_insns(block).last().next();
+                }
+            }
+            requested.push(vregs);
+        }
+
+        let labelled = func.debug_value_labels().iter().map(|&(vreg, ..)| vreg);
+        let labelled: FxHashSet<VReg> = labelled.collect();
+        let states = self.output_entry_states(output, &labelled);
+        let requested_at = |l: usize, vreg: VReg, point: ProgPoint| {
+            requested[l]
+                .iter()
+                .any(|&(v, from, to)| v == vreg && from <= point && point < to)
+        };
+        let holds = |state: &Option<State>, l: usize, point: ProgPoint| {
+            let alloc = locations[l].3;
+            state.as_ref().map_or(true, |state| {
+                state.get(&alloc).map_or(false, |vregs| {
+                    vregs.iter().any(|&v| requested_at(l, v, point))
+                })
+            })
+        };
+        let mut points: Vec<(usize, ProgPoint)> = vec![];
+        for (block, block_locations) in by_block.iter().enumerate() {
+            if block_locations.is_empty() {
+                continue;
+            }
+            let insts = func.block_insns(Block::new(block));
+            let mut state = states[block].clone();
+            let mut edit = output
+                .edits
+                .partition_point(|&(pos, _)| pos < ProgPoint::before(insts.first()));
+            for inst in insts.iter() {
+                for point in [ProgPoint::before(inst), ProgPoint::after(inst)] {
+                    let mut held: Vec<bool> = block_locations
+                        .iter()
+                        .map(|&l| holds(&state, l, point))
+                        .collect();
+                    while edit < output.edits.len() && output.edits[edit].0 == point {
+                        if let Some(state) = &mut state {
+                            let step = InstOrEdit::Edit(&output.edits[edit].1);
+                            self.update(output, step, state, &labelled);
+                        }
+                        for (held, &l) in held.iter_mut().zip(block_locations) {
+                            *held |= holds(&state, l, point);
+                        }
+                        edit += 1;
+                    }
+                    if point.pos() == InstPosition::Before {
+                        let operands = func.inst_operands(inst);
+                        for (held, &l) in held.iter_mut().zip(block_locations) {
+                            *held |= operands.iter().zip(output.inst_allocs(inst)).any(
+                                |(op, &alloc)| {
+                                    op.kind() == OperandKind::Def
+                                        && op.pos() == OperandPos::Early
+                                        && alloc == locations[l].3
+                                        && requested_at(l, op.vreg(), point)
+                                },
+                            );
+                        }
+                        if let Some(state) = &mut state {
+                            self.update(output, InstOrEdit::Inst(inst), state, &labelled);
+                        }
+                    }
+                    for (&held, &l) in held.iter().zip(block_locations) {
+                        let (_, from, to, _) = locations[l];
+                        if held && from <= point && point < to {
+                            points.push((l, point));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Each kept range must lie within one requested range, so it
+        // is split where one of those starts or ends.
+        let boundary = |l: usize, point: ProgPoint| {
+            requested[l]
+                .iter()
+                .any(|&(_, from, to)| from == point || to == point)
+        };
+        points.sort_unstable();
+        let mut kept: Vec<(u32, ProgPoint, ProgPoint, Allocation)> = vec![];
+        let mut last = None;
+        for (l, point) in points {
+            let (label, _, _, alloc) = locations[l];
+            match kept.last_mut() {
+                Some((_, _, to, _)) if last == Some(l) && *to == point && !boundary(l, point) => {
+                    *to = point.next()
+                }
+                _ => kept.push((label, point, point.next(), alloc)),
+            }
+            last = Some(l);
+        }
+        kept
+    }
+
+    /// The vregs that a region may take from before its header, sorted.
+    fn live_in_vregs(&self, region: &Region) -> Vec<VReg> {
+        let func = self.func;
+        let mut used = FxHashSet::default();
+        let mut defined = FxHashSet::default();
+        for &block in &region.blocks {
+            defined.extend(func.block_params(block).iter().copied());
+            let insts = func.block_insns(block);
+            for inst in insts.iter() {
+                for op in func.inst_operands(inst) {
+                    if op.as_fixed_nonallocatable().is_some() {
+                        continue;
+                    }
+                    match op.kind() {
+                        OperandKind::Use => used.insert(op.vreg()),
+                        OperandKind::Def => defined.insert(op.vreg()),
+                    };
+                }
+            }
+            for (succ_idx, &succ) in func.block_succs(block).iter().enumerate() {
+                if self.region_of[succ.index()] == self.region_of[block.index()] {
+                    used.extend(self.branch_args(block, succ_idx).iter().copied());
+                }
+            }
+        }
+        for demands in &region.exit_demands {
+            used.extend(demands.iter().map(|&(_, vreg)| vreg));
+        }
+        let params = func.block_params(region.header);
+        let mut live_ins: Vec<VReg> = used
+            .into_iter()
+            .filter(|vreg| !defined.contains(vreg) || params.contains(vreg))
+            .collect();
+        live_ins.sort_unstable();
+        live_ins
+    }
+
+    /// Adds the vregs that the regions may take from before their
+    /// headers to `tracked`, along with those copied into them on the
+    /// way there. Returns whether there were new ones.
+    fn track_live_ins(&mut self) -> bool {
+        let func = self.func;
+        let mut stack: Vec<VReg> = self
+            .regions
+            .iter()
+            .flat_map(|region| self.live_in_vregs(region))
+            .filter(|vreg| !self.tracked.contains(vreg))
+            .collect();
+        if stack.is_empty() {
+            return false;
+        }
+        let mut copies: FxHashMap<VReg, Vec<VReg>> = FxHashMap::default();
+        for block in 0..func.num_blocks() {
+            let block = Block::new(block);
+            if !self.reaches_header[block.index()] {
+                continue;
+            }
+            for (succ_idx, &succ) in func.block_succs(block).iter().enumerate() {
+                let params = func.block_params(succ);
+                for (&param, &arg) in params.iter().zip(self.branch_args(block, succ_idx)) {
+                    copies.entry(param).or_default().push(arg);
+                }
+            }
+        }
+        while let Some(vreg) = stack.pop() {
+            if self.tracked.insert(vreg) {
+                stack.extend(copies.get(&vreg).into_iter().flatten().copied());
+            }
+        }
+        true
+    }
+
+    /// Picks where each vreg live into a region is taken from, given
+    /// the state on entry to its header.
+    fn choose_live_ins(&self, region: &Region, state: &State) -> Option<FxHashSet<Demand>> {
+        // Prefer registers, then spillslots, then the client's stack
+        // locations.
+        let mut allocs: Vec<Allocation> = state.keys().copied().collect();
+        allocs.sort_unstable_by_key(|&alloc| {
+            let rank = match alloc.as_reg() {
+                Some(preg) if self.env.fixed_stack_slots.contains(&preg) => 1,
+                Some(_) => 0,
+                None if alloc.is_stack() => 2,
+                None => 3,
+            };
+            (rank, alloc)
+        });
+        let mut chosen = FxHashSet::default();
+        let mut taken = FxHashSet::default();
+        for vreg in self.live_in_vregs(region) {
+            let alloc = allocs.iter().copied().find(|&alloc| {
+                !taken.contains(&alloc)
+                    && state[&alloc].contains(&vreg)
+                    && self.constraint(alloc, vreg.class()).is_some()
+            })?;
+            taken.insert(alloc);
+            chosen.insert((alloc, vreg));
+        }
+        Some(chosen)
+    }
+
+    /// Finds what each region must provide at its exits and where it
+    /// takes its live-in vregs from, or returns `None` if that isn't
+    /// possible.
+    fn analyze(&mut self) -> Option<()> {
+        let mut states = None;
+        let mut rounds = 0;
+        loop {
+            let demands = self.entry_demands();
+            for region in &mut self.regions {
+                region.exit_demands.clear();
+            }
+            for index in 0..self.regions.len() {
+                let exit_demands = self.regions[index]
+                    .exits
+                    .iter()
+                    .map(|&(block, succ_idx)| {
+                        let succ = self.func.block_succs(block)[succ_idx];
+                        self.demands_before_edge(block, succ_idx, &demands[succ.index()])
+                    })
+                    .collect();
+                self.regions[index].exit_demands = exit_demands;
+            }
+
+            // The states only need the vregs that may be live into a
+            // region, which depend on what the exits demand.
+            if self.track_live_ins() || states.is_none() {
+                states = Some(self.entry_states(false));
+            }
+            let states = states.as_ref().unwrap();
+            let mut settled = true;
+            for index in 0..self.regions.len() {
+                let region = &self.regions[index];
+                let state = states[region.header.index()].as_ref()?;
+                let live_ins = self.choose_live_ins(region, state)?;
+                if live_ins != region.live_ins {
+                    settled = false;
+                    self.regions[index].live_ins = live_ins;
+                }
+            }
+            if settled {
+                break;
+            }
+            rounds += 1;
+            if rounds == MAX_ROUNDS {
+                return None;
+            }
+        }
+
+        // Each exit must be able to name each demand as a use, and the
+        // live-in vregs must still be where we take them from once the
+        // exits provide no more than their demands.
+        for region in &self.regions {
+            for demands in &region.exit_demands {
+                let mut allocs = FxHashSet::default();
+                for &(alloc, vreg) in demands {
+                    if !allocs.insert(alloc) || self.constraint(alloc, vreg.class()).is_none() {
+                        return None;
+                    }
+                }
+            }
+        }
+        let states = self.entry_states(true);
+        for region in &self.regions {
+            let state = states[region.header.index()].as_ref()?;
+            for (alloc, vreg) in &region.live_ins {
+                if !state.get(alloc).map_or(false, |vregs| vregs.contains(vreg)) {
+                    return None;
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Where the moves on entry to or exit from a region go, for an
+    /// edge from `pred` to `succ`. Moves can only go at the end of
+    /// `pred` if its branch has nothing that they could overwrite.
+    fn placement(&self, pred: Block, succ: Block) -> Option<Placement> {
+        let func = self.func;
+        let preds = func.block_preds(succ).len() + usize::from(succ == func.entry_block());
+        if preds == 1 {
+            return Some(Placement::Start(succ));
+        }
+        let last = func.block_insns(pred).last();
+        (func.block_succs(pred).len() == 1
+            && func.inst_operands(last).is_empty()
+            && func.inst_clobbers(last) == PRegSet::empty())
+        .then(|| Placement::End(pred))
+    }
+
+    /// Allocates each region on its own and merges the results into
+    /// the previous output.
+    fn reallocate(&self, options: &RegallocOptions, ctx: &mut Ctx) -> Option<Output> {
+        let func = self.func;
+        let previous = self.previous;
+        let mut num_spillslots = previous.num_spillslots;
+        // The algorithm of the region runs, not of `previous`.
+        let mut algorithm = None;
+        let align = RegClass::ALL
+            .iter()
+            .filter(|&&class| {
+                self.env.preferred_regs_by_class[class as usize] != PRegSet::empty()
+                    || self.env.non_preferred_regs_by_class[class as usize] != PRegSet::empty()
+            })
+            .map(|&class| func.spillslot_size(class))
+            .max()
+            .unwrap_or(1);
+
+        let mut allocs: Vec<Option<Vec<Allocation>>> = vec![None; func.num_insts()];
+        let mut edits: Vec<(ProgPoint, u8, Edit)> = previous
+            .edits
+            .iter()
+            .filter(|(pos, _)| !self.is_changed(self.block_of[pos.inst().index()]))
+            .map(|(pos, edit)| (*pos, 1, edit.clone()))
+            .collect();
+
+        for region in &self.regions {
+            let entry_placements: Vec<Placement> = if func.block_preds(region.header).len()
+                + usize::from(region.header == func.entry_block())
+                == 1
+            {
+                vec![Placement::Start(region.header)]
+            } else {
+                func.block_preds(region.header)
+                    .iter()
+                    .filter(|&&pred| !self.is_changed(pred))
+                    .map(|&pred| self.placement(pred, region.header))
+                    .collect::<Option<_>>()?
+            };
+            let exit_placements: Vec<Placement> = region
+                .exits
+                .iter()
+                .map(|&(block, succ_idx)| self.placement(block, func.block_succs(block)[succ_idx]))
+                .collect::<Option<_>>()?;
+
+            let region_func = RegionFunc::new(self, region)?;
+            run_with_ctx(&region_func, self.env, options, ctx).ok()?;
+            let output = core::mem::take(&mut ctx.output);
+            if algorithm != Some(Algorithm::Fastalloc) {
+                algorithm = Some(output.algorithm);
+            }
+            trace!(
+                "incremental: region {:?} output {:?}",
+                region.blocks,
+                output
+            );
+
+            let base = (num_spillslots + align - 1) / align * align;
+            if output.num_spillslots > 0 {
+                num_spillslots = base + output.num_spillslots;
+            }
+            let map = |alloc: Allocation| {
+                if let Some(slot) = alloc.as_stack() {
+                    Allocation::stack(slot.plus(base))
+                } else if let Some(loc) = alloc
+                    .as_fixed_stack()
+                    .filter(|loc| loc.index() >= self.stack_base)
+                {
+                    Allocation::stack(SpillSlot::new(loc.index() - self.stack_base))
+                } else {
+                    alloc
+                }
+            };
+
+            for (i, kind) in region_func.insts.iter().enumerate() {
+                if let &RegionInst::Inst(inst) = kind {
+                    let region_allocs = output.inst_allocs(Inst::new(i));
+                    allocs[inst.index()] = Some(region_allocs.iter().map(|&a| map(a)).collect());
+                }
+            }
+            for (pos, edit) in &output.edits {
+                let edit = match *edit {
+                    Edit::Move { from, to } => Edit::Move {
+                        from: map(from),
+                        to: map(to),
+                    },
+                    Edit::Remat { vreg, to } => Edit::Remat { vreg, to: map(to) },
+                };
+                let placements = match region_func.insts[pos.inst().index()] {
+                    RegionInst::Inst(inst) => {
+                        edits.push((ProgPoint::new(inst, pos.pos()), 1, edit));
+                        continue;
+                    }
+                    RegionInst::Entry | RegionInst::EntryBranch => &entry_placements[..],
+                    RegionInst::Exit(exit) => &exit_placements[exit..exit + 1],
+                };
+                if placements.is_empty() {
+                    return None;
+                }
+                for &placement in placements {
+                    match placement {
+                        Placement::Start(block) => edits.push((
+                            ProgPoint::before(func.block_insns(block).first()),
+                            0,
+                            edit.clone(),
+                        )),
+                        Placement::End(block) => edits.push((
+                            ProgPoint::before(func.block_insns(block).last()),
+                            2,
+                            edit.clone(),
+                        )),
+                    }
+                }
+            }
+        }
+
+        let mut output = Output {
+            num_spillslots,
+            stats: Stats {
+                incremental_blocks: self.region_of.iter().filter(|r| r.is_some()).count(),
+                ..Stats::default()
+            },
+            algorithm: algorithm.unwrap_or(previous.algorithm),
+            ..Output::default()
+        };
+        for inst in 0..func.num_insts() {
+            let inst = Inst::new(inst);
+            output.inst_alloc_offsets.push(output.allocs.len() as u32);
+            match &allocs[inst.index()] {
+                Some(allocs) => output.allocs.extend_from_slice(allocs),
+                None => output.allocs.extend_from_slice(previous.inst_allocs(inst)),
+            }
+        }
+        edits.sort_by_key(|&(pos, order, _)| (pos, order));
+        output.edits = edits
+            .into_iter()
+            .map(|(pos, _, edit)| (pos, edit))
+            .collect();
+
+        output.debug_locations = self.debug_locations(&output);
+        Some(output)
+    }
+}
+
+/// An instruction of a region, as a function of its own.
+#[derive(Clone, Copy, Debug)]
+enum RegionInst {
+    /// Defines each live-in vreg.
+    Entry,
+    /// Branches from the entry block to the header.
+    EntryBranch,
+    /// An instruction of the real function.
+    Inst(Inst),
+    /// Returns from the block for the given exit, using what the
+    /// unchanged code reads after it.
+    Exit(usize),
+}
+
+/// A region as a function of its own. Its blocks are the entry block,
+/// the region's blocks in order, and a block for each exit.
+struct RegionFunc<'a, F: Function> {
+    func: &'a F,
+    insts: Vec<RegionInst>,
+    ranges: Vec<InstRange>,
+    succs: Vec<Vec<Block>>,
+    preds: Vec<Vec<Block>>,
+    /// The number of blocks of the real function in the region.
+    num_region_blocks: usize,
+    /// The vregs that the entry block passes for the header's
+    /// parameters.
+    entry_args: Vec<VReg>,
+    entry_defs: Vec<Operand>,
+    /// A `WideReg` use of each wide vreg that the entry block defines,
+    /// as a fixed def alone would not make it wide in the region.
+    entry_wide_uses: Vec<Operand>,
+    exit_uses: Vec<Vec<Operand>>,
+    /// Vregs passed to the block after an exit: rematerializing them
+    /// after the edge would not give the block parameter its value.
+    no_remat: FxHashSet<VReg>,
+    num_vregs: usize,
+    cost_model: RegionCostModel<'a>,
+}
+
+impl<'a, F: Function> RegionFunc<'a, F> {
+    fn new(incremental: &Incremental<'a, F>, region: &Region) -> Option<Self> {
+        let func = incremental.func;
+        let index_of = |block: Block| 1 + region.blocks.binary_search(&block).unwrap();
+        let num_region_blocks = region.blocks.len();
+
+        let mut insts = vec![RegionInst::Entry, RegionInst::EntryBranch];
+        let mut ranges = vec![InstRange::new(Inst::new(0), Inst::new(2))];
+        let mut succs = vec![vec![Block::new(index_of(region.header))]];
+        let mut preds = vec![vec![]];
+        let mut orig_blocks = vec![region.header];
+        let mut no_remat = FxHashSet::default();
+        for &block in &region.blocks {
+            let start = insts.len();
+            insts.extend(func.block_insns(block).iter().map(RegionInst::Inst));
+            ranges.push(InstRange::new(Inst::new(start), Inst::new(insts.len())));
+            orig_blocks.push(block);
+            succs.push(
+                func.block_succs(block)
+                    .iter()
+                    .enumerate()
+                    .map(|(succ_idx, &succ)| {
+                        match region.exits.iter().position(|&e| e == (block, succ_idx)) {
+                            Some(exit) => {
+                                no_remat.extend(incremental.branch_args(block, succ_idx));
+                                Block::new(1 + num_region_blocks + exit)
+                            }
+                            None => Block::new(index_of(succ)),
+                        }
+                    })
+                    .collect(),
+            );
+            let mut block_preds = vec![];
+            for &pred in func.block_preds(block) {
+                if incremental.region_of[pred.index()] == incremental.region_of[block.index()] {
+                    block_preds.push(Block::new(index_of(pred)));
+                } else if !block_preds.contains(&Block::new(0)) {
+                    block_preds.push(Block::new(0));
+                }
+            }
+            if block == func.entry_block() && !block_preds.contains(&Block::new(0)) {
+                block_preds.push(Block::new(0));
+            }
+            preds.push(block_preds);
+        }
+        for (exit, &(block, _)) in region.exits.iter().enumerate() {
+            let start = insts.len();
+            insts.push(RegionInst::Exit(exit));
+            ranges.push(InstRange::new(Inst::new(start), Inst::new(start + 1)));
+            succs.push(vec![]);
+            preds.push(vec![Block::new(index_of(block))]);
+            orig_blocks.push(block);
+        }
+
+        let mut num_vregs = func.num_vregs();
+        let mut entry_args = vec![];
+        for &param in func.block_params(region.header) {
+            if num_vregs >= VReg::MAX {
+                return None;
+            }
+            entry_args.push(VReg::new(num_vregs, param.class()));
+            num_vregs += 1;
+        }
+        let params = func.block_params(region.header);
+        let mut live_ins: Vec<Demand> = region.live_ins.iter().copied().collect();
+        live_ins.sort_unstable();
+        let mut entry_defs = vec![];
+        let mut entry_wide_uses = vec![];
+        for (i, &param) in params.iter().enumerate() {
+            if incremental.wide_vregs.contains(&param) {
+                entry_wide_uses.push(Operand::reg_wide_use(entry_args[i]));
+            }
+        }
+        for (alloc, vreg) in live_ins {
+            let vreg = match params.iter().position(|&p| p == vreg) {
+                Some(i) => entry_args[i],
+                None => {
+                    if incremental.wide_vregs.contains(&vreg) {
+                        entry_wide_uses.push(Operand::reg_wide_use(vreg));
+                    }
+                    vreg
+                }
+            };
+            let constraint = incremental.constraint(alloc, vreg.class())?;
+            entry_defs.push(Operand::new(
+                vreg,
+                constraint,
+                OperandKind::Def,
+                OperandPos::Late,
+            ));
+        }
+        for &arg in &entry_args {
+            if !entry_defs.iter().any(|op| op.vreg() == arg) {
+                entry_defs.push(Operand::any_def(arg));
+            }
+        }
+        let mut exit_uses = vec![];
+        for demands in &region.exit_demands {
+            let mut demands: Vec<Demand> = demands.iter().copied().collect();
+            demands.sort_unstable();
+            let mut uses = vec![];
+            for (alloc, vreg) in demands {
+                let constraint = incremental.constraint(alloc, vreg.class())?;
+                uses.push(Operand::new(
+                    vreg,
+                    constraint,
+                    OperandKind::Use,
+                    OperandPos::Early,
+                ));
+                if incremental.wide_vregs.contains(&vreg) {
+                    uses.push(Operand::reg_wide_use(vreg));
+                }
+            }
+            exit_uses.push(uses);
+        }
+
+        Some(RegionFunc {
+            func,
+            insts,
+            ranges,
+            succs,
+            preds,
+            num_region_blocks,
+            entry_args,
+            entry_defs,
+            entry_wide_uses,
+            exit_uses,
+            no_remat,
+            num_vregs,
+            cost_model: RegionCostModel {
+                inner: func.cost_model(),
+                orig_blocks,
+            },
+        })
+    }
+
+    /// The block of the real function that `block` stands for, if any.
+    fn region_block(&self, block: Block) -> Option<Block> {
+        (1..=self.num_region_blocks)
+            .contains(&block.index())
+            .then(|| self.cost_model.orig_blocks[block.index()])
+    }
+}
+
+impl<'a, F: Function> Function for RegionFunc<'a, F> {
+    fn num_insts(&self) -> usize {
+        self.insts.len()
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.ranges.len()
+    }
+
+    fn entry_block(&self) -> Block {
+        Block::new(0)
+    }
+
+    fn block_insns(&self, block: Block) -> InstRange {
+        self.ranges[block.index()]
+    }
+
+    fn block_succs(&self, block: Block) -> &[Block] {
+        &self.succs[block.index()]
+    }
+
+    fn block_preds(&self, block: Block) -> &[Block] {
+        &self.preds[block.index()]
+    }
+
+    fn block_params(&self, block: Block) -> &[VReg] {
+        match self.region_block(block) {
+            Some(block) => self.func.block_params(block),
+            None => &[],
+        }
+    }
+
+    fn is_ret(&self, insn: Inst) -> bool {
+        match self.insts[insn.index()] {
+            RegionInst::Inst(inst) => self.func.is_ret(inst),
+            RegionInst::Exit(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_branch(&self, insn: Inst) -> bool {
+        match self.insts[insn.index()] {
+            RegionInst::Inst(inst) => self.func.is_branch(inst),
+            RegionInst::EntryBranch => true,
+            _ => false,
+        }
+    }
+
+    fn branch_blockparams(&self, block: Block, insn: Inst, succ_idx: usize) -> &[VReg] {
+        match self.insts[insn.index()] {
+            RegionInst::EntryBranch => &self.entry_args,
+            RegionInst::Inst(inst)
+                if self
+                    .region_block(self.succs[block.index()][succ_idx])
+                    .is_some() =>
+            {
+                let block = self.cost_model.orig_blocks[block.index()];
+                self.func.branch_blockparams(block, inst, succ_idx)
+            }
+            _ => &[],
+        }
+    }
+
+    fn inst_operands(&self, insn: Inst) -> &[Operand] {
+        match self.insts[insn.index()] {
+            RegionInst::Entry => &self.entry_defs,
+            RegionInst::EntryBranch => &self.entry_wide_uses,
+            RegionInst::Inst(inst) => self.func.inst_operands(inst),
+            RegionInst::Exit(exit) => &self.exit_uses[exit],
+        }
+    }
+
+    fn inst_operand_groups(&self, insn: Inst) -> &[OperandGroup] {
+        match self.insts[insn.index()] {
+            RegionInst::Inst(inst) => self.func.inst_operand_groups(inst),
+            _ => &[],
+        }
+    }
+
+    fn inst_clobbers(&self, insn: Inst) -> PRegSet {
+        match self.insts[insn.index()] {
+            RegionInst::Inst(inst) => self.func.inst_clobbers(inst),
+            _ => PRegSet::empty(),
+        }
+    }
+
+    fn num_vregs(&self) -> usize {
+        self.num_vregs
+    }
+
+    fn spillslot_size(&self, regclass: RegClass) -> usize {
+        self.func.spillslot_size(regclass)
+    }
+
+    fn multi_spillslot_named_by_last_slot(&self) -> bool {
+        self.func.multi_spillslot_named_by_last_slot()
+    }
+
+    fn is_rematerializable(&self, vreg: VReg) -> bool {
+        vreg.vreg() < self.func.num_vregs()
+            && !self.no_remat.contains(&vreg)
+            && self.func.is_rematerializable(vreg)
+    }
+
+    fn vreg_hint(&self, vreg: VReg) -> Option<PReg> {
+        if vreg.vreg() < self.func.num_vregs() {
+            self.func.vreg_hint(vreg)
+        } else {
+            None
+        }
+    }
+
+    fn block_frequency(&self, block: Block) -> Option<f32> {
+        self.func
+            .block_frequency(self.cost_model.orig_blocks[block.index()])
+    }
+
+    fn cost_model(&self) -> &dyn CostModel {
+        &self.cost_model
+    }
+
+    fn allow_multiple_vreg_defs(&self) -> bool {
+        self.func.allow_multiple_vreg_defs()
+    }
+}
+
+/// The client's cost model, seen from a region: each block of the
+/// region stands for a block of the real function.
+struct RegionCostModel<'a> {
+    inner: &'a dyn CostModel,
+    orig_blocks: Vec<Block>,
+}
+
+impl CostModel for RegionCostModel<'_> {
+    fn operand_weight(
+        &self,
+        block: Block,
+        frequency: f32,
+        constraint: OperandConstraint,
+        is_def: bool,
+    ) -> f32 {
+        let block = self.orig_blocks[block.index()];
+        self.inner
+            .operand_weight(block, frequency, constraint, is_def)
+    }
+
+    fn range_priority(&self, from: ProgPoint, to: ProgPoint) -> u32 {
+        self.inner.range_priority(from, to)
+    }
+
+    fn remat_spill_weight(&self, weight: u32) -> u32 {
+        self.inner.remat_spill_weight(weight)
+    }
+}
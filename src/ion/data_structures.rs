@@ -18,8 +18,9 @@ use crate::index::ContainerComparator;
 use crate::indexset::IndexSet;
 use crate::Vec2;
 use crate::{
-    define_index, Allocation, Block, Bump, Edit, Function, FxHashMap, FxHashSet, MachineEnv,
-    Operand, Output, PReg, ProgPoint, RegClass, VReg,
+    define_index, Allocation, Block, Bump, Edit, Function, FxHashMap, FxHashSet, Inst, MachineEnv,
+    Operand, OperandConstraint, OperandKind, Output, PReg, PRegSet, ProgPoint, RegClass, StackLoc,
+    VReg,
 };
 use alloc::collections::BTreeMap;
 use alloc::collections::VecDeque;
@@ -203,7 +204,9 @@ pub struct LiveBundle {
     pub allocation: Allocation,
     pub prio: u32, // recomputed after every bulk update
     pub spill_weight_and_props: u32,
-    pub limit: Option<u8>,
+    pub limit: Option<u16>,
+    /// Whether any use in the bundle has a `RegSet` constraint.
+    pub has_reg_set: bool,
 }
 
 pub const BUNDLE_MAX_SPILL_WEIGHT: u32 = (1 << 28) - 1;
@@ -302,6 +305,13 @@ pub struct SpillSet {
     pub spill_bundle: LiveBundleIndex,
     pub required: bool,
     pub splits: u8,
+    /// Whether the vregs in this spillset must live in wide registers
+    /// (see `OperandConstraint::WideReg`).
+    pub wide: bool,
+    /// Whether this spillset holds a single vreg, which can be
+    /// rematerialized instead of reloaded (see
+    /// `Function::is_rematerializable`).
+    pub remat: bool,
 
     /// The aggregate [`CodeRange`] of all involved [`LiveRange`]s. The effect of this abstraction
     /// is that we attempt to allocate one spill slot for the extent of a bundle. For fragmented
@@ -318,12 +328,21 @@ pub struct VRegData {
     pub blockparam: Block,
     // We don't initially know the RegClass until we observe a use of the VReg.
     pub class: Option<RegClass>,
+    // Set when we observe a `WideReg` constraint on any mention of the VReg.
+    pub wide: bool,
 }
 
 #[derive(Clone, Debug)]
 pub struct PRegData {
     pub allocations: LiveRangeSet,
     pub is_stack: bool,
+    pub is_wide: bool,
+    /// The PRegs whose allocation maps must be checked and updated when
+    /// allocating this PReg: the PReg itself plus, for a wide register,
+    /// all of its register units.
+    pub units: PRegSet,
+    /// All other PRegs that share a register unit with this one.
+    pub aliases: PRegSet,
 }
 
 #[derive(Clone, Debug)]
@@ -345,6 +364,15 @@ pub enum FixedRegFixupLevel {
     Secondary,
 }
 
+#[derive(Clone, Debug)]
+pub struct FixedStackFixup {
+    pub inst: Inst,
+    pub slot: u16,
+    pub kind: OperandKind,
+    pub loc: StackLoc,
+    pub vreg: VRegIndex,
+}
+
 /// The field order is significant: these are sorted so that a
 /// scan over vregs, then blocks in each range, can scan in
 /// order through this (sorted) list and add allocs to the
@@ -416,6 +444,7 @@ impl LiveBundles {
             prio: 0,
             spill_weight_and_props: 0,
             limit: None,
+            has_reg_set: false,
         })
     }
 }
@@ -460,12 +489,24 @@ pub struct Ctx {
     pub(crate) pregs: Vec<PRegData>,
     pub(crate) allocation_queue: PrioQueue,
 
+    // Whether bundles are allocated by linear scan rather than by
+    // backtracking; see `linear_scan.rs`.
+    pub(crate) linear_scan: bool,
+
+    // Steps of the allocation loop left before it stops evicting and
+    // splitting normally; see `RegallocOptions::fuel`.
+    pub(crate) fuel: Option<usize>,
+
+    // Whether to stop allocating altogether when the fuel runs out,
+    // for `Algorithm::IonWithFallback`.
+    pub(crate) give_up_on_fuel: bool,
+
     pub(crate) spilled_bundles: Vec<LiveBundleIndex>,
     pub(crate) spillslots: Vec<SpillSlotData>,
-    pub(crate) slots_by_class: [SpillSlotList; 3],
+    pub(crate) slots_by_class: [SpillSlotList; RegClass::COUNT],
 
-    pub(crate) extra_spillslots_by_class: [SmallVec<[Allocation; 2]>; 3],
-    pub(crate) preferred_victim_by_class: [PReg; 3],
+    pub(crate) extra_spillslots_by_class: [SmallVec<[Allocation; 2]>; RegClass::COUNT],
+    pub(crate) preferred_victim_by_class: [PReg; RegClass::COUNT],
 
     // When multiple fixed-register constraints are present on a
     // single VReg at a single program point (this can happen for,
@@ -477,6 +518,18 @@ pub struct Ctx {
     // was to the appropriate PReg.
     pub(crate) multi_fixed_reg_fixups: Vec<MultiFixedRegFixup>,
 
+    // Operands constrained to a client-defined stack location are
+    // allocated as if they were unconstrained; when we produce the
+    // final edit-list, we copy the value into the location before a
+    // use or out of it after a def.
+    pub(crate) fixed_stack_fixups: Vec<FixedStackFixup>,
+
+    // For each instruction with a `ReuseEither` def, the index of the
+    // input whose register the def reuses. The choice is made once,
+    // when computing liveranges, and then treated like a plain
+    // `Reuse` constraint.
+    pub(crate) reuse_choices: FxHashMap<Inst, usize>,
+
     pub(crate) allocated_bundle_count: usize,
 
     // For debug output only: a list of textual annotations at every
@@ -555,6 +608,16 @@ impl<'a, F: Function> Env<'a, F> {
     pub fn is_vreg_used(&self, index: VRegIndex) -> bool {
         self.vregs[index].class.is_some()
     }
+
+    /// The index of the input whose register `op` reuses, if `op` is
+    /// a def at `inst` with a `Reuse` or `ReuseEither` constraint.
+    pub fn reused_input_index(&self, inst: Inst, op: Operand) -> Option<usize> {
+        match op.constraint() {
+            OperandConstraint::Reuse(idx) => Some(idx),
+            OperandConstraint::ReuseEither(..) => Some(self.reuse_choices[&inst]),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -704,10 +767,12 @@ pub struct InsertedMove {
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum InsertMovePrio {
+    FixedStackDef,
     InEdgeMoves,
     Regular,
     MultiFixedRegInitial,
     MultiFixedRegSecondary,
+    FixedStackUse,
     ReusedInput,
     OutEdgeMoves,
 }
@@ -799,6 +864,11 @@ impl Edits {
             self.edits.push((pos_prio, Edit::Move { from, to }));
         }
     }
+
+    #[inline(always)]
+    pub fn add_remat(&mut self, pos_prio: PosWithPrio, vreg: VReg, to: Allocation) {
+        self.edits.push((pos_prio, Edit::Remat { vreg, to }));
+    }
 }
 
 /// The fields in this struct are reversed in sort order so that the entire
@@ -850,6 +920,32 @@ pub struct Stats {
     pub blockparam_outs_count: usize,
     pub halfmoves_count: usize,
     pub edits_count: usize,
+    pub remat_count: usize,
+    /// Split points moved out of a hotter block to its entry.
+    pub splits_hoisted: usize,
+    /// The estimated dynamic cost of all edits: the sum of the
+    /// frequencies of the blocks they are in, rounded down.
+    pub edits_dynamic_cost: usize,
+    /// Operands of vregs with a `Function::vreg_hint` that were
+    /// allocated to the hinted register.
+    pub hints_satisfied: usize,
+    /// Moves left out because their destination already holds a copy
+    /// of their source, possibly made in a preceding block.
+    pub redundant_moves_elided: usize,
+    /// Moves removed by copy propagation (see
+    /// `RegallocOptions::copy_propagation`).
+    pub copy_prop_moves_removed: usize,
+    /// Blocks re-allocated by `run_incremental`.
+    pub incremental_blocks: usize,
+    /// Set to 1 when `run_incremental` had to allocate the whole
+    /// function again.
+    pub incremental_fallbacks: usize,
+    /// Set to 1 when the allocation loop ran out of
+    /// `RegallocOptions::fuel`.
+    pub fuel_exhausted: usize,
+    /// Bundles split into minimal bundles because there was no fuel
+    /// left to allocate them otherwise.
+    pub fuel_minimal_splits: usize,
 }
 
 // Helper function for generating sorting keys. The order of arguments is from
@@ -0,0 +1,554 @@
+/*
+ * Released under the terms of the Apache 2.0 license with LLVM
+ * exception. See `LICENSE` for details.
+ */
+
+//! Linear-scan allocation.
+//!
+//! This shares liveness, liveranges and bundle merging, spillslot
+//! allocation and move insertion (through `ParallelMoves`) with the
+//! backtracking allocator, but replaces its main loop with a classic
+//! linear scan over bundles, which are intervals with lifetime holes.
+//!
+//! Bundles are taken in order of their start. Those that got a
+//! register are kept in two lists: the *active* ones are live at the
+//! current position, and the *inactive* ones started before it but are
+//! in a lifetime hole there. Bundles move between the lists as the
+//! position advances, and leave them once they end. A register is free
+//! for the current bundle until the first point where the bundle
+//! intersects a fixed reservation, or an active or inactive bundle in
+//! an overlapping register. Then:
+//!
+//! - If a register is free for the whole bundle, the bundle gets it
+//!   (its hint if possible).
+//! - Otherwise, if a register is free beyond the bundle's first
+//!   register use, the bundle is split where the one that stays free
+//!   the longest stops being free, and the first half is queued again
+//!   with it as a hint.
+//! - Otherwise, the register whose occupants are next used the latest
+//!   is taken from them, if they are all used after the bundle's first
+//!   register use. Each of them is split where it first intersects the
+//!   bundle: the part before keeps the register, and the rest is
+//!   queued again. Minimal bundles, which can't be split, instead take
+//!   a register from bundles with a lower spill weight.
+//! - Otherwise, the bundle is split before its first register use, and
+//!   the part before that is spilled.
+//!
+//! Whatever is queued again starts no earlier than the current
+//! position, so the scan never goes back to reconsider a decision.
+//! Unlike the backtracking loop, which evicts whole bundles and
+//! allocates them again, this only ever takes the rest of a bundle's
+//! register away, at the cost of worse splits.
+
+use super::{
+    allocate, AllocRegResult, Ctx, Env, LiveBundleIndex, LiveRangeIndex, LiveRangeList,
+    LiveRangeListEntry, PRegIndex, RegTraversalIter, Requirement,
+};
+use crate::{Function, Inst, MachineEnv, OperandConstraint, PReg, ProgPoint, RegAllocError, VReg};
+use alloc::vec;
+use alloc::vec::Vec;
+use smallvec::{smallvec, SmallVec};
+
+impl<'a, F: Function> Env<'a, F> {
+    /// The key with which `bundle` is queued for allocation: its
+    /// priority, or with linear scan, how early it starts.
+    pub fn queue_prio(&self, bundle: LiveBundleIndex) -> usize {
+        if self.ctx.linear_scan {
+            let start = self.ctx.bundles[bundle].ranges[0].range.from;
+            (u32::MAX - start.to_index()) as usize
+        } else {
+            self.ctx.bundles[bundle].prio as usize
+        }
+    }
+
+    /// The main loop of linear scan, in place of `process_bundles`.
+    pub fn scan_bundles(&mut self) -> Result<(), RegAllocError> {
+        // Nothing is allocated yet, so the allocation maps hold only
+        // fixed reservations.
+        let fixed: Vec<Vec<LiveRangeListEntry>> = self
+            .ctx
+            .pregs
+            .iter()
+            .map(|preg| {
+                preg.allocations
+                    .btree
+                    .keys()
+                    .map(|key| LiveRangeListEntry {
+                        range: key.to_range(),
+                        index: LiveRangeIndex::invalid(),
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut scan = Scan {
+            pos: ProgPoint::before(Inst::new(0)),
+            active: vec![],
+            inactive: vec![],
+            fixed,
+        };
+        while let Some((bundle, hint)) = self.ctx.allocation_queue.pop() {
+            self.ctx.output.stats.process_bundle_count += 1;
+            let start = self.ctx.bundles[bundle].ranges[0].range.from;
+            let pos = ProgPoint::before(start.inst());
+            debug_assert!(pos >= scan.pos);
+            if pos > scan.pos {
+                scan.pos = pos;
+                self.advance_scan(&mut scan);
+            }
+            self.scan_bundle(&mut scan, bundle, hint)?;
+        }
+        self.ctx.output.stats.final_liverange_count = self.ranges.len();
+        self.ctx.output.stats.final_bundle_count = self.bundles.len();
+        self.ctx.output.stats.spill_bundle_count = self.spilled_bundles.len();
+        Ok(())
+    }
+
+    /// Drops the bundles that ended before `scan.pos` from the active
+    /// and inactive lists, and moves the others to the one they now
+    /// belong to.
+    fn advance_scan(&self, scan: &mut Scan) {
+        let pos = scan.pos;
+        let mut i = 0;
+        while i < scan.active.len() {
+            let bundle = scan.active[i];
+            if self.bundle_end(bundle) <= pos {
+                scan.active.swap_remove(i);
+            } else if !self.bundle_covers(bundle, pos) {
+                scan.inactive.push(scan.active.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        let mut i = 0;
+        while i < scan.inactive.len() {
+            let bundle = scan.inactive[i];
+            if self.bundle_end(bundle) <= pos {
+                scan.inactive.swap_remove(i);
+            } else if self.bundle_covers(bundle, pos) {
+                scan.active.push(scan.inactive.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn scan_bundle(
+        &mut self,
+        scan: &mut Scan,
+        bundle: LiveBundleIndex,
+        hint: PReg,
+    ) -> Result<(), RegAllocError> {
+        let spillset = self.bundles[bundle].spillset;
+        let class = self.ctx.spillsets[spillset].class;
+        let wide = self.ctx.spillsets[spillset].wide;
+        let mut hint = if hint != PReg::invalid() {
+            hint
+        } else {
+            self.ctx.spillsets[spillset].hint
+        };
+        if self.ctx.pregs[hint.index()].is_stack {
+            hint = PReg::invalid();
+        }
+        trace!(
+            "scan_bundle: bundle {bundle:?} hint {hint:?} at {:?}",
+            scan.pos
+        );
+
+        let req = match self.compute_requirement(bundle) {
+            Ok(req) => req,
+            Err(conflict) => {
+                trace!("conflict!: {:?}", conflict);
+                self.split_and_requeue_bundle(
+                    bundle,
+                    conflict.suggested_split_point(),
+                    hint,
+                    conflict.should_trim_edges_around_split(),
+                );
+                return Ok(());
+            }
+        };
+        let fixed_preg = match req {
+            Requirement::FixedReg(preg) | Requirement::FixedStack(preg) => Some(preg),
+            Requirement::Register | Requirement::Limit(..) | Requirement::RegSet(..) => None,
+            Requirement::Stack => {
+                self.ctx.spillsets[spillset].required = true;
+                return Ok(());
+            }
+            Requirement::Any => {
+                match self.get_or_create_spill_bundle(bundle, /* create_if_absent = */ false) {
+                    Some(spill) => {
+                        let empty_vec = LiveRangeList::new_in(self.ctx.bump());
+                        let mut list =
+                            core::mem::replace(&mut self.ctx.bundles[bundle].ranges, empty_vec);
+                        for entry in &list {
+                            self.ctx.ranges[entry.index].bundle = spill;
+                        }
+                        self.ctx.bundles[spill].ranges.extend(list.drain(..));
+                    }
+                    None => self.ctx.spilled_bundles.push(bundle),
+                }
+                return Ok(());
+            }
+        };
+
+        let start = self.bundles[bundle].ranges[0].range.from;
+        let end = self.bundle_end(bundle);
+        let first_use = self.next_reg_use(bundle, start).unwrap_or(start);
+        let out_of_fuel = self.burn_fuel();
+        let scan_offset = start.inst().index() + bundle.index();
+        let limit = self.bundles[bundle].limit;
+        let mut candidates: SmallVec<[PReg; 16]> = smallvec![];
+        for preg in RegTraversalIter::new(
+            self.env,
+            class,
+            fixed_preg,
+            hint.as_valid(),
+            scan_offset,
+            limit,
+        ) {
+            // Wide values need wide registers, and wide registers are
+            // reserved for them.
+            if fixed_preg.is_none() && self.ctx.pregs[preg.index()].is_wide != wide {
+                continue;
+            }
+            if let Requirement::RegSet(set) = req {
+                if !set.contains(preg) {
+                    continue;
+                }
+            }
+            candidates.push(preg);
+        }
+
+        // Find the register that is free the longest.
+        let mut free: Option<(ProgPoint, PReg)> = None;
+        for &preg in &candidates {
+            self.ctx.output.stats.process_bundle_reg_probes_any += 1;
+            let free_until = self.free_until(scan, bundle, preg);
+            trace!(" -> {preg:?} is free until {free_until:?}");
+            match free_until {
+                None => {
+                    self.assign_bundle(scan, bundle, preg);
+                    return Ok(());
+                }
+                Some(point) if free.map_or(true, |(best, _)| point > best) => {
+                    free = Some((point, preg));
+                }
+                Some(_) => {}
+            }
+        }
+        let minimal = self.minimal_bundle(bundle);
+        if !minimal && out_of_fuel {
+            trace!(" -> out of fuel; splitting into minimal bundles");
+            self.ctx.output.stats.fuel_minimal_splits += 1;
+            self.split_into_minimal_bundles(bundle, hint);
+            return Ok(());
+        }
+        if let Some((free_until, preg)) = free {
+            if free_until > first_use {
+                trace!(" -> splitting where {preg:?} stops being free");
+                self.split_and_requeue_bundle(bundle, free_until, preg, true);
+                return Ok(());
+            }
+        }
+
+        // No register is free at the first use: take one away from the
+        // bundles in it, if they may be evicted, preferring those used
+        // the latest.
+        let weight = self.bundle_spill_weight(bundle);
+        let mut blocked: Option<(u32, PReg)> = None;
+        'pregs: for &preg in &candidates {
+            let units = self.ctx.pregs[preg.index()].units;
+            if let Some(point) = self.fixed_conflict(scan, bundle, preg) {
+                if ProgPoint::before(point.inst()) <= first_use {
+                    continue;
+                }
+            }
+            // When the bundles in `preg` are next used; never is latest.
+            let mut next_use = u32::MAX;
+            for &other in scan.active.iter().chain(&scan.inactive) {
+                let Some(point) = self.occupant_conflict(bundle, other, units) else {
+                    continue;
+                };
+                let split_at = core::cmp::max(scan.pos, ProgPoint::before(point.inst()));
+                let other_use = self.next_reg_use(other, core::cmp::max(split_at, start));
+                let evictable = if minimal {
+                    self.bundle_spill_weight(other) < weight
+                } else {
+                    !self.minimal_bundle(other) && other_use.map_or(true, |u| u > first_use)
+                };
+                if !evictable {
+                    continue 'pregs;
+                }
+                if let Some(point) = other_use {
+                    next_use = core::cmp::min(next_use, point.to_index());
+                }
+            }
+            if blocked.map_or(true, |(latest, _)| next_use > latest) {
+                blocked = Some((next_use, preg));
+            }
+        }
+        let Some((_, preg)) = blocked else {
+            if !minimal {
+                trace!(" -> nothing to evict; spilling up to the first use");
+                let split_at = core::cmp::max(start, ProgPoint::before(first_use.inst()));
+                self.split_and_requeue_bundle(bundle, split_at, hint, true);
+                return Ok(());
+            }
+            return Err(self.too_many_live_regs(scan, bundle, &candidates));
+        };
+
+        trace!(" -> evicting the bundles in {preg:?}");
+        self.ctx.output.stats.evict_bundle_event += 1;
+        let units = self.ctx.pregs[preg.index()].units;
+        let evicted: Vec<(LiveBundleIndex, ProgPoint)> = scan
+            .active
+            .iter()
+            .chain(&scan.inactive)
+            .filter_map(|&other| {
+                let point = self.occupant_conflict(bundle, other, units)?;
+                let split_at = core::cmp::max(scan.pos, ProgPoint::before(point.inst()));
+                Some((other, split_at))
+            })
+            .collect();
+        for (other, split_at) in evicted {
+            self.evict_rest_of_bundle(scan, other, split_at);
+            self.ctx.output.stats.evict_bundle_count += 1;
+        }
+        match self.fixed_conflict(scan, bundle, preg) {
+            Some(point) => {
+                trace!(" -> splitting before the fixed reservation at {point:?}");
+                self.split_and_requeue_bundle(bundle, point, preg, true);
+            }
+            None => {
+                debug_assert!(end > start);
+                self.assign_bundle(scan, bundle, preg);
+            }
+        }
+        Ok(())
+    }
+
+    /// The first point where `bundle` intersects anything in the
+    /// allocation maps of `preg`'s units, or `None` if it doesn't.
+    fn free_until(&self, scan: &Scan, bundle: LiveBundleIndex, preg: PReg) -> Option<ProgPoint> {
+        let units = self.ctx.pregs[preg.index()].units;
+        let occupied = scan
+            .active
+            .iter()
+            .chain(&scan.inactive)
+            .filter_map(|&other| self.occupant_conflict(bundle, other, units));
+        self.fixed_conflict(scan, bundle, preg)
+            .into_iter()
+            .chain(occupied)
+            .min()
+    }
+
+    /// The first point where `bundle` intersects a fixed reservation of
+    /// any of `preg`'s units.
+    fn fixed_conflict(
+        &self,
+        scan: &Scan,
+        bundle: LiveBundleIndex,
+        preg: PReg,
+    ) -> Option<ProgPoint> {
+        let ranges = &self.ctx.bundles[bundle].ranges;
+        self.ctx.pregs[preg.index()]
+            .units
+            .into_iter()
+            .filter_map(|unit| first_intersection(&scan.fixed[unit.index()], ranges))
+            .min()
+    }
+
+    /// The first point where `bundle` intersects `other`, an allocated
+    /// bundle, if `other`'s register shares any of `units`.
+    fn occupant_conflict(
+        &self,
+        bundle: LiveBundleIndex,
+        other: LiveBundleIndex,
+        units: crate::PRegSet,
+    ) -> Option<ProgPoint> {
+        let preg = self.ctx.bundles[other].allocation.as_reg()?;
+        if (self.ctx.pregs[preg.index()].units & units).is_empty_set() {
+            return None;
+        }
+        first_intersection(
+            &self.ctx.bundles[other].ranges,
+            &self.ctx.bundles[bundle].ranges,
+        )
+    }
+
+    /// The first use of `bundle` at or after `from` that needs more than
+    /// any location.
+    fn next_reg_use(&self, bundle: LiveBundleIndex, from: ProgPoint) -> Option<ProgPoint> {
+        let ranges = &self.ctx.bundles[bundle].ranges;
+        let first = ranges.partition_point(|entry| entry.range.to <= from);
+        ranges[first..]
+            .iter()
+            .flat_map(|entry| &self.ctx.ranges[entry.index].uses)
+            .find(|u| u.pos >= from && u.operand.constraint() != OperandConstraint::Any)
+            .map(|u| u.pos)
+    }
+
+    fn bundle_end(&self, bundle: LiveBundleIndex) -> ProgPoint {
+        self.ctx.bundles[bundle].ranges.last().unwrap().range.to
+    }
+
+    fn bundle_covers(&self, bundle: LiveBundleIndex, pos: ProgPoint) -> bool {
+        let ranges = &self.ctx.bundles[bundle].ranges;
+        let i = ranges.partition_point(|entry| entry.range.to <= pos);
+        ranges.get(i).map_or(false, |entry| entry.range.from <= pos)
+    }
+
+    /// Allocates `preg`, which must be free for all of it, to `bundle`.
+    fn assign_bundle(&mut self, scan: &mut Scan, bundle: LiveBundleIndex, preg: PReg) {
+        let mut conflicts = core::mem::take(&mut self.ctx.scratch_conflicts);
+        let result = self.try_to_allocate_bundle_to_reg(
+            bundle,
+            PRegIndex::new(preg.index()),
+            None,
+            &mut conflicts,
+        );
+        assert!(
+            matches!(result, AllocRegResult::Allocated(_)),
+            "linear scan missed a conflict in {:?}",
+            preg
+        );
+        self.ctx.scratch_conflicts = conflicts;
+        self.ctx.output.stats.process_bundle_reg_success_any += 1;
+        self.ctx.spillsets[self.ctx.bundles[bundle].spillset].hint = preg;
+        if self.bundle_covers(bundle, scan.pos) {
+            scan.active.push(bundle);
+        } else {
+            scan.inactive.push(bundle);
+        }
+    }
+
+    /// Takes the register of `bundle`, an active or inactive bundle,
+    /// away from `split_at` on, and queues the rest of the bundle
+    /// again.
+    fn evict_rest_of_bundle(
+        &mut self,
+        scan: &mut Scan,
+        bundle: LiveBundleIndex,
+        split_at: ProgPoint,
+    ) {
+        let preg = self.ctx.bundles[bundle].allocation.as_reg().unwrap();
+        trace!(" -> evicting {bundle:?} in {preg:?} from {split_at:?} on");
+        for unit in self.ctx.pregs[preg.index()].units {
+            for entry in &self.ctx.bundles[bundle].ranges {
+                self.ctx.pregs[unit.index()]
+                    .allocations
+                    .btree
+                    .remove(&super::LiveRangeKey::from_range(&entry.range));
+            }
+        }
+        self.ctx.bundles[bundle].allocation = crate::Allocation::none();
+        let start = self.ctx.bundles[bundle].ranges[0].range.from;
+        let rest = if start >= split_at {
+            scan.active.retain(|&b| b != bundle);
+            scan.inactive.retain(|&b| b != bundle);
+            bundle
+        } else {
+            let rest = self.split_bundle(bundle, split_at);
+            self.recompute_bundle_properties(bundle);
+            let mut conflicts = core::mem::take(&mut self.ctx.scratch_conflicts);
+            let result = self.try_to_allocate_bundle_to_reg(
+                bundle,
+                PRegIndex::new(preg.index()),
+                None,
+                &mut conflicts,
+            );
+            debug_assert!(matches!(result, AllocRegResult::Allocated(_)));
+            self.ctx.scratch_conflicts = conflicts;
+            rest
+        };
+        self.recompute_bundle_properties(rest);
+        let prio = self.queue_prio(rest);
+        self.ctx
+            .allocation_queue
+            .insert(rest, prio, PReg::invalid());
+    }
+
+    fn too_many_live_regs(
+        &self,
+        scan: &Scan,
+        bundle: LiveBundleIndex,
+        candidates: &[PReg],
+    ) -> RegAllocError {
+        let first = self.ctx.bundles[bundle].ranges[0].index;
+        let mut vregs: Vec<VReg> = vec![self.vreg(self.ctx.ranges[first].vreg)];
+        let mut fixed = vec![];
+        for &preg in candidates {
+            if self.fixed_conflict(scan, bundle, preg).is_some() {
+                fixed.push(preg);
+            }
+            let units = self.ctx.pregs[preg.index()].units;
+            for &other in scan.active.iter().chain(&scan.inactive) {
+                if self.occupant_conflict(bundle, other, units).is_some() {
+                    let lr = self.ctx.bundles[other].ranges[0].index;
+                    let vreg = self.vreg(self.ctx.ranges[lr].vreg);
+                    if !vregs.contains(&vreg) {
+                        vregs.push(vreg);
+                    }
+                }
+            }
+        }
+        RegAllocError::TooManyLiveRegs {
+            point: self.ctx.bundles[bundle].ranges[0].range.from,
+            class: self.ctx.spillsets[self.ctx.bundles[bundle].spillset].class,
+            vregs,
+            fixed,
+        }
+    }
+}
+
+/// The state of the scan in `Env::scan_bundles`.
+struct Scan {
+    /// The start of the instruction of the bundle being allocated.
+    pos: ProgPoint,
+    /// Allocated bundles that are live at `pos`.
+    active: Vec<LiveBundleIndex>,
+    /// Allocated bundles that started before `pos` but are in a
+    /// lifetime hole there.
+    inactive: Vec<LiveBundleIndex>,
+    /// The fixed reservations of each register.
+    fixed: Vec<Vec<LiveRangeListEntry>>,
+}
+
+/// The first point where two sorted lists of ranges intersect.
+fn first_intersection(a: &[LiveRangeListEntry], b: &[LiveRangeListEntry]) -> Option<ProgPoint> {
+    let b_start = b.first()?.range.from;
+    let mut i = a.partition_point(|entry| entry.range.to <= b_start);
+    let mut j = 0;
+    while i < a.len() && j < b.len() {
+        let (x, y) = (a[i].range, b[j].range);
+        if x.overlaps(&y) {
+            return Some(core::cmp::max(x.from, y.from));
+        }
+        if x.to <= y.from {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    None
+}
+
+pub fn run<F: Function>(
+    func: &F,
+    mach_env: &MachineEnv,
+    ctx: &mut Ctx,
+    enable_annotations: bool,
+    enable_ssa_checker: bool,
+    fuel: Option<usize>,
+) -> Result<(), RegAllocError> {
+    ctx.linear_scan = true;
+    ctx.give_up_on_fuel = false;
+    allocate(
+        func,
+        mach_env,
+        ctx,
+        enable_annotations,
+        enable_ssa_checker,
+        fuel,
+    )
+}
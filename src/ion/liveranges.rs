@@ -18,11 +18,11 @@ use super::{
 };
 use crate::indexset::IndexSet;
 use crate::ion::data_structures::{
-    BlockparamIn, BlockparamOut, FixedRegFixupLevel, MultiFixedRegFixup,
+    BlockparamIn, BlockparamOut, FixedRegFixupLevel, FixedStackFixup, MultiFixedRegFixup,
 };
 use crate::{
-    Allocation, Block, Function, Inst, InstPosition, Operand, OperandConstraint, OperandKind,
-    OperandPos, PReg, ProgPoint, RegAllocError, VReg, VecExt,
+    Allocation, Block, Function, Inst, InstPosition, Operand, OperandConstraint, OperandGroup,
+    OperandKind, OperandPos, PReg, PRegSet, ProgPoint, RegAllocError, VReg, VecExt,
 };
 use core::convert::TryFrom;
 use core::usize;
@@ -32,25 +32,6 @@ use smallvec::{smallvec, SmallVec};
 #[derive(Clone, Copy, Debug)]
 pub struct SpillWeight(f32);
 
-#[inline(always)]
-pub fn spill_weight_from_constraint(
-    constraint: OperandConstraint,
-    loop_depth: usize,
-    is_def: bool,
-) -> SpillWeight {
-    // A bonus of 1000 for one loop level, 4000 for two loop levels,
-    // 16000 for three loop levels, etc. Avoids exponentiation.
-    let loop_depth = core::cmp::min(10, loop_depth);
-    let hot_bonus: f32 = (0..loop_depth).fold(1000.0, |a, _| a * 4.0);
-    let def_bonus: f32 = if is_def { 2000.0 } else { 0.0 };
-    let constraint_bonus: f32 = match constraint {
-        OperandConstraint::Any => 1000.0,
-        OperandConstraint::Reg | OperandConstraint::FixedReg(_) => 2000.0,
-        _ => 0.0,
-    };
-    SpillWeight(hot_bonus + def_bonus + constraint_bonus)
-}
-
 impl SpillWeight {
     /// Convert a floating-point weight to a u16 that can be compactly
     /// stored in a `Use`. We simply take the top 16 bits of the f32; this
@@ -96,8 +77,8 @@ impl core::ops::Add<SpillWeight> for SpillWeight {
     }
 }
 
-fn slot_idx(i: usize) -> Result<u16, RegAllocError> {
-    u16::try_from(i).map_err(|_| RegAllocError::TooManyOperands)
+fn slot_idx(inst: Inst, i: usize) -> Result<u16, RegAllocError> {
+    u16::try_from(i).map_err(|_| RegAllocError::TooManyOperands(inst))
 }
 
 impl<'a, F: Function> Env<'a, F> {
@@ -108,16 +89,49 @@ impl<'a, F: Function> Env<'a, F> {
             PRegData {
                 allocations: LiveRangeSet::new(),
                 is_stack: false,
+                is_wide: false,
+                units: PRegSet::empty(),
+                aliases: PRegSet::empty(),
             },
         );
         for &preg in &self.env.fixed_stack_slots {
             self.pregs[preg.index()].is_stack = true;
         }
+        // Compute register units and aliases from the env.
+        let mut overlapping = PRegSet::empty();
+        for (i, preg) in self.ctx.pregs.iter_mut().enumerate() {
+            preg.is_wide = false;
+            preg.units = PRegSet::empty().with(PReg::from_index(i));
+            preg.aliases = PRegSet::empty();
+        }
+        for &(wide, units) in &self.env.reg_units {
+            self.ctx.pregs[wide.index()].is_wide = true;
+            self.ctx.pregs[wide.index()].units.union_from(units);
+            overlapping.add(wide);
+            overlapping.union_from(units);
+        }
+        for a in overlapping {
+            for b in overlapping {
+                if a != b
+                    && !(self.pregs[a.index()].units & self.pregs[b.index()].units).is_empty_set()
+                {
+                    self.ctx.pregs[a.index()].aliases.add(b);
+                }
+            }
+        }
+        let wide_regs = self.env.wide_regs();
         for class in 0..self.preferred_victim_by_class.len() {
-            self.preferred_victim_by_class[class] = self.env.non_preferred_regs_by_class[class]
-                .max_preg()
-                .or(self.env.preferred_regs_by_class[class].max_preg())
-                .unwrap_or(PReg::invalid());
+            // The victim of a class with wide registers may be asked to
+            // carry wide values, so it must be wide too.
+            let has_wide = wide_regs
+                .into_iter()
+                .any(|preg| preg.class() as usize == class);
+            let candidates = |regs: PRegSet| if has_wide { regs & wide_regs } else { regs };
+            self.preferred_victim_by_class[class] =
+                candidates(self.env.non_preferred_regs_by_class[class])
+                    .max_preg()
+                    .or(candidates(self.env.preferred_regs_by_class[class]).max_preg())
+                    .unwrap_or(PReg::invalid());
         }
         // Create VRegs from the vreg count.
         for idx in 0..self.func.num_vregs() {
@@ -129,6 +143,7 @@ impl<'a, F: Function> Env<'a, F> {
                     blockparam: Block::invalid(),
                     // We'll learn the RegClass as we scan the code.
                     class: None,
+                    wide: false,
                 },
             );
         }
@@ -212,16 +227,28 @@ impl<'a, F: Function> Env<'a, F> {
         }
     }
 
+    /// Returns the spill weight of an operand of `inst` with
+    /// `constraint`, according to the function's cost model.
+    pub fn spill_weight_at(
+        &self,
+        inst: Inst,
+        constraint: OperandConstraint,
+        is_def: bool,
+    ) -> SpillWeight {
+        let block = self.cfginfo.insn_block[inst.index()];
+        let frequency = self.cfginfo.block_frequency[block.index()];
+        SpillWeight(
+            self.func
+                .cost_model()
+                .operand_weight(block, frequency, constraint, is_def),
+        )
+    }
+
     pub fn insert_use_into_liverange(&mut self, into: LiveRangeIndex, mut u: Use) {
         let operand = u.operand;
         let constraint = operand.constraint();
-        let block = self.cfginfo.insn_block[u.pos.inst().index()];
-        let loop_depth = self.cfginfo.approx_loop_depth[block.index()] as usize;
-        let weight = spill_weight_from_constraint(
-            constraint,
-            loop_depth,
-            operand.kind() != OperandKind::Use,
-        );
+        let weight =
+            self.spill_weight_at(u.pos.inst(), constraint, operand.kind() != OperandKind::Use);
         u.weight = weight.to_bits();
 
         trace!(
@@ -262,11 +289,27 @@ impl<'a, F: Function> Env<'a, F> {
     pub fn add_liverange_to_preg(&mut self, range: CodeRange, reg: PReg) {
         trace!("adding liverange to preg: {:?} to {}", range, reg);
         let preg_idx = PRegIndex::new(reg.index());
-        let res = self.pregs[preg_idx.index()]
-            .allocations
-            .btree
-            .insert(LiveRangeKey::from_range(&range), LiveRangeIndex::invalid());
-        debug_assert!(res.is_none());
+        let units = self.pregs[preg_idx.index()].units;
+        if units.len() == 1 {
+            let res = self.pregs[preg_idx.index()]
+                .allocations
+                .btree
+                .insert(LiveRangeKey::from_range(&range), LiveRangeIndex::invalid());
+            debug_assert!(res.is_none());
+            return;
+        }
+        // Reservations of overlapping registers may overlap in a shared
+        // unit; coalesce them so that the BTree keys stay disjoint.
+        for unit in units {
+            let btree = &mut self.ctx.pregs[unit.index()].allocations.btree;
+            let mut key = LiveRangeKey::from_range(&range);
+            while let Some((existing, lr)) = btree.remove_entry(&key) {
+                debug_assert!(!lr.is_valid());
+                key.from = core::cmp::min(key.from, existing.from);
+                key.to = core::cmp::max(key.to, existing.to);
+            }
+            btree.insert(key, LiveRangeIndex::invalid());
+        }
     }
 
     pub fn is_live_in(&mut self, block: Block, vreg: VRegIndex) -> bool {
@@ -330,6 +373,9 @@ impl<'a, F: Function> Env<'a, F> {
                                 }
                             }
                             self.observe_vreg_class(op.vreg());
+                            if op.constraint() == OperandConstraint::WideReg {
+                                self.vregs[op.vreg()].wide = true;
+                            }
                         }
                     }
                 }
@@ -353,12 +399,11 @@ impl<'a, F: Function> Env<'a, F> {
         }
 
         // Check that there are no liveins to the entry block.
-        if !self.liveins[self.func.entry_block().index()].is_empty() {
-            trace!(
-                "non-empty liveins to entry block: {:?}",
-                self.liveins[self.func.entry_block().index()]
-            );
-            return Err(RegAllocError::EntryLivein);
+        let entry_liveins = &self.liveins[self.func.entry_block().index()];
+        if let Some(vreg) = entry_liveins.iter().next() {
+            trace!("non-empty liveins to entry block: {:?}", entry_liveins);
+            let class = self.vregs[VRegIndex::new(vreg)].class.unwrap();
+            return Err(RegAllocError::EntryLivein(VReg::new(vreg, class)));
         }
 
         self.ctx.scratch_workqueue = workqueue;
@@ -459,14 +504,33 @@ impl<'a, F: Function> Env<'a, F> {
                 // proper interference wrt other inputs. We note the
                 // *vreg* that is reused, not the index.
                 let mut reused_input = None;
+                let mut reused_slot = None;
                 for op in self.func.inst_operands(inst) {
-                    if let OperandConstraint::Reuse(i) = op.constraint() {
-                        debug_assert!(self.func.inst_operands(inst)[i]
-                            .as_fixed_nonallocatable()
-                            .is_none());
-                        reused_input = Some(self.func.inst_operands(inst)[i].vreg());
-                        break;
-                    }
+                    let i = match op.constraint() {
+                        OperandConstraint::Reuse(i) => i,
+                        OperandConstraint::ReuseEither(a, b) => {
+                            // Prefer an input that dies here: its
+                            // register is free to be overwritten, so
+                            // the tie doesn't force a copy.
+                            let operands = self.func.inst_operands(inst);
+                            let i = if live.get(operands[a].vreg().vreg())
+                                && !live.get(operands[b].vreg().vreg())
+                            {
+                                b
+                            } else {
+                                a
+                            };
+                            self.reuse_choices.insert(inst, i);
+                            i
+                        }
+                        _ => continue,
+                    };
+                    debug_assert!(self.func.inst_operands(inst)[i]
+                        .as_fixed_nonallocatable()
+                        .is_none());
+                    reused_input = Some(self.func.inst_operands(inst)[i].vreg());
+                    reused_slot = Some(i);
+                    break;
                 }
 
                 // Preprocess defs and uses. Specifically, if there
@@ -498,6 +562,36 @@ impl<'a, F: Function> Env<'a, F> {
                     if operand.as_fixed_nonallocatable().is_some() {
                         continue;
                     }
+                    // Operands in a client-defined stack location are
+                    // allocated without a constraint, and a fixup copies
+                    // the value into the location just before the
+                    // instruction, or out of it just after. Only the
+                    // Before point (for uses) or the After point (for
+                    // defs) needs the value in its own allocation, so
+                    // the position is rewritten accordingly.
+                    if let OperandConstraint::FixedStack(loc) = operand.constraint() {
+                        self.fixed_stack_fixups.push(FixedStackFixup {
+                            inst,
+                            slot: slot_idx(inst, i)?,
+                            kind: operand.kind(),
+                            loc,
+                            vreg: VRegIndex::new(operand.vreg().vreg()),
+                        });
+                        let pos = match operand.kind() {
+                            OperandKind::Use => OperandPos::Early,
+                            OperandKind::Def => OperandPos::Late,
+                        };
+                        operand_rewrites.insert(
+                            i,
+                            Operand::new(
+                                operand.vreg(),
+                                OperandConstraint::Any,
+                                operand.kind(),
+                                pos,
+                            ),
+                        );
+                        continue;
+                    }
                     if let OperandConstraint::FixedReg(preg) = operand.constraint() {
                         match (operand.pos(), operand.kind()) {
                             (OperandPos::Early, OperandKind::Use)
@@ -538,8 +632,8 @@ impl<'a, F: Function> Env<'a, F> {
                                     let pos = ProgPoint::before(inst);
                                     self.multi_fixed_reg_fixups.push(MultiFixedRegFixup {
                                         pos,
-                                        from_slot: slot_idx(i)?,
-                                        to_slot: slot_idx(i)?,
+                                        from_slot: slot_idx(inst, i)?,
+                                        to_slot: slot_idx(inst, i)?,
                                         to_preg: PRegIndex::new(preg.index()),
                                         vreg: VRegIndex::new(operand.vreg().vreg()),
                                         level: FixedRegFixupLevel::Initial,
@@ -569,6 +663,16 @@ impl<'a, F: Function> Env<'a, F> {
                     }
                 }
 
+                // Early uses that some def must not share a register
+                // with (see `OperandGroup`) are treated like the inputs
+                // other than a reused one: they are kept live until
+                // the After point, so they interfere with the defs.
+                let groups = self.func.inst_operand_groups(inst);
+                let operands = self.func.inst_operands(inst);
+                let separated = |i: usize| {
+                    reused_slot != Some(i) && OperandGroup::separates_use(groups, operands, i)
+                };
+
                 // Process defs and uses.
                 for &cur_pos in &[InstPosition::After, InstPosition::Before] {
                     for i in 0..self.func.inst_operands(inst).len() {
@@ -588,9 +692,18 @@ impl<'a, F: Function> Env<'a, F> {
                             // account for the interference between
                             // the other inputs and the
                             // input-that-is-reused/output.
+                            //
+                            // With register units, the same goes for
+                            // other uses of the reused vreg: the copy
+                            // into the output's register must not
+                            // clobber part of the input's register
+                            // while they still read it.
                             (OperandKind::Use, OperandPos::Early)
-                                if reused_input.is_some()
-                                    && reused_input.unwrap() != operand.vreg() =>
+                                if (reused_input.is_some()
+                                    && (reused_input.unwrap() != operand.vreg()
+                                        || (reused_slot != Some(i)
+                                            && !self.env.reg_units.is_empty())))
+                                    || separated(i) =>
                             {
                                 ProgPoint::after(inst)
                             }
@@ -645,7 +758,7 @@ impl<'a, F: Function> Env<'a, F> {
                                 // Create the use in the LiveRange.
                                 self.insert_use_into_liverange(
                                     lr,
-                                    Use::new(operand, pos, slot_idx(i)?),
+                                    Use::new(operand, pos, slot_idx(inst, i)?),
                                 );
                                 // If def (not mod), this reg is now dead,
                                 // scanning backward; make it so.
@@ -691,7 +804,7 @@ impl<'a, F: Function> Env<'a, F> {
 
                                 self.insert_use_into_liverange(
                                     lr,
-                                    Use::new(operand, pos, slot_idx(i)?),
+                                    Use::new(operand, pos, slot_idx(inst, i)?),
                                 );
 
                                 // Add to live-set.
@@ -806,13 +919,17 @@ impl<'a, F: Function> Env<'a, F> {
                     let mut first_stack_slot = None;
                     let mut min_limit = usize::MAX;
                     let mut max_fixed_reg = usize::MIN;
+                    let mut reg_set: Option<PRegSet> = None;
                     for u in uses.iter() {
                         match u.operand.constraint() {
-                            OperandConstraint::Any => {
+                            OperandConstraint::Any | OperandConstraint::FixedStack(_) => {
                                 first_reg_slot.get_or_insert(u.slot);
                                 first_stack_slot.get_or_insert(u.slot);
                             }
-                            OperandConstraint::Reg | OperandConstraint::Reuse(_) => {
+                            OperandConstraint::Reg
+                            | OperandConstraint::WideReg
+                            | OperandConstraint::Reuse(_)
+                            | OperandConstraint::ReuseEither(..) => {
                                 first_reg_slot.get_or_insert(u.slot);
                                 requires_reg = true;
                             }
@@ -821,6 +938,12 @@ impl<'a, F: Function> Env<'a, F> {
                                 min_limit = min_limit.min(max);
                                 requires_reg = true;
                             }
+                            OperandConstraint::RegSet(i) => {
+                                first_reg_slot.get_or_insert(u.slot);
+                                let set = self.env.reg_sets[i];
+                                reg_set = Some(reg_set.map_or(set, |prev| prev & set));
+                                requires_reg = true;
+                            }
                             OperandConstraint::FixedReg(preg) => {
                                 max_fixed_reg = max_fixed_reg.max(preg.hw_enc());
                                 if self.ctx.pregs[preg.index()].is_stack {
@@ -843,6 +966,12 @@ impl<'a, F: Function> Env<'a, F> {
                     if num_fixed_reg + num_fixed_stack <= 1
                         && !(requires_reg && num_fixed_stack != 0)
                         && max_fixed_reg < min_limit
+                        && reg_set.map_or(true, |set| {
+                            uses.iter().all(|u| match u.operand.constraint() {
+                                OperandConstraint::FixedReg(preg) => set.contains(preg),
+                                _ => true,
+                            })
+                        })
                     {
                         continue;
                     }
@@ -877,6 +1006,7 @@ impl<'a, F: Function> Env<'a, F> {
                             if !(requires_reg && self.ctx.pregs[preg.index()].is_stack)
                                 && *first_preg.get_or_insert(preg) == preg
                                 && preg.hw_enc() < min_limit
+                                && reg_set.map_or(true, |set| set.contains(preg))
                             {
                                 continue;
                             }
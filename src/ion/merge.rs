@@ -16,7 +16,7 @@ use crate::ion::data_structures::{
     BlockparamOut, CodeRange, Env, LiveBundleIndex, LiveRangeList, SpillSet, SpillSlotIndex,
     VRegIndex,
 };
-use crate::{Function, Inst, OperandConstraint, OperandKind, PReg, ProgPoint};
+use crate::{Function, Inst, OperandConstraint, OperandKind, PReg, PRegSet, ProgPoint};
 use alloc::format;
 use core::convert::TryFrom;
 
@@ -37,6 +37,9 @@ impl<'a, F: Function> Env<'a, F> {
                 None => self.bundles[to].limit = Some(theirs),
             }
         }
+        if self.bundles[from].has_reg_set {
+            self.bundles[to].has_reg_set = true;
+        }
     }
 
     pub fn merge_bundles(&mut self, from: LiveBundleIndex, to: LiveBundleIndex) -> bool {
@@ -58,6 +61,14 @@ impl<'a, F: Function> Env<'a, F> {
             return false;
         }
 
+        // Wide and narrow values cannot share a register.
+        if self.spillsets[self.bundles[from].spillset].wide
+            != self.spillsets[self.bundles[to].spillset].wide
+        {
+            trace!(" -> mismatching register widths");
+            return false;
+        }
+
         // If either bundle is already assigned (due to a pinned vreg), don't merge.
         if self.bundles[from].allocation.is_some() || self.bundles[to].allocation.is_some() {
             trace!("one of the bundles is already assigned (pinned)");
@@ -131,9 +142,11 @@ impl<'a, F: Function> Env<'a, F> {
         if self.bundles[from].cached_stack()
             || self.bundles[from].cached_fixed()
             || self.bundles[from].limit.is_some()
+            || self.bundles[from].has_reg_set
             || self.bundles[to].cached_stack()
             || self.bundles[to].cached_fixed()
             || self.bundles[to].limit.is_some()
+            || self.bundles[to].has_reg_set
         {
             if self.merge_bundle_requirements(from, to).is_err() {
                 trace!(" -> conflicting requirements; aborting merge");
@@ -247,8 +260,11 @@ impl<'a, F: Function> Env<'a, F> {
         if self.bundles[from].spillset != self.bundles[to].spillset {
             // Widen the range for the target spillset to include the one being merged in.
             let from_range = self.spillsets[self.bundles[from].spillset].range;
-            let to_range = &mut self.ctx.spillsets[self.ctx.bundles[to].spillset].range;
-            *to_range = to_range.join(from_range);
+            let to_spillset = &mut self.ctx.spillsets[self.ctx.bundles[to].spillset];
+            to_spillset.range = to_spillset.range.join(from_range);
+            // The spillset now holds the values of several vregs, but
+            // rematerialization only recreates one of them.
+            to_spillset.remat = false;
         }
 
         self.merge_bundle_properties(from, to);
@@ -259,6 +275,7 @@ impl<'a, F: Function> Env<'a, F> {
     pub fn merge_vreg_bundles(&mut self) {
         // Create a bundle for every vreg, initially.
         trace!("merge_vreg_bundles: creating vreg bundles");
+        let allocatable = PRegSet::from(self.env);
         for vreg in 0..self.vregs.len() {
             let vreg = VRegIndex::new(vreg);
             if self.vregs[vreg].ranges.is_empty() {
@@ -283,7 +300,8 @@ impl<'a, F: Function> Env<'a, F> {
             let mut fixed = false;
             let mut fixed_def = false;
             let mut stack = false;
-            let mut limit: Option<u8> = None;
+            let mut limit: Option<u16> = None;
+            let mut has_reg_set = false;
             for entry in &self.bundles[bundle].ranges {
                 for u in &self.ranges[entry.index].uses {
                     use OperandConstraint::*;
@@ -296,14 +314,15 @@ impl<'a, F: Function> Env<'a, F> {
                         }
                         Stack => stack = true,
                         Limit(current) => {
-                            let current = u8::try_from(current)
-                                .expect("the current limit is too large to fit in a u8");
+                            let current = u16::try_from(current)
+                                .expect("the current limit is too large to fit in a u16");
                             match limit {
                                 Some(prev) => limit = Some(prev.min(current)),
                                 None => limit = Some(current),
                             }
                         }
-                        Any | Reg | Reuse(_) => {
+                        RegSet(_) => has_reg_set = true,
+                        Any | Reg | WideReg | Reuse(_) | ReuseEither(..) | FixedStack(_) => {
                             continue;
                         }
                     }
@@ -322,16 +341,28 @@ impl<'a, F: Function> Env<'a, F> {
                 self.bundles[bundle].set_cached_stack();
             }
             self.bundles[bundle].limit = limit;
+            self.bundles[bundle].has_reg_set = has_reg_set;
 
             // Create a spillslot for this bundle.
             let reg = self.vreg(vreg);
+            let wide = self.vregs[vreg].wide;
+            let remat = self.func.is_rematerializable(reg);
+            // Seed the spillset's hint with the client's, so that it is
+            // the first register tried for this bundle.
+            let hint = self
+                .func
+                .vreg_hint(reg)
+                .filter(|&preg| preg.class() == reg.class() && allocatable.contains(preg))
+                .unwrap_or(PReg::invalid());
             let ssidx = self.spillsets.push(SpillSet {
                 slot: SpillSlotIndex::invalid(),
                 required: false,
                 class: reg.class(),
-                hint: PReg::invalid(),
+                hint,
                 spill_bundle: LiveBundleIndex::invalid(),
                 splits: 0,
+                wide,
+                remat,
                 range,
             });
             self.bundles[bundle].spillset = ssidx;
@@ -343,7 +374,7 @@ impl<'a, F: Function> Env<'a, F> {
             // Attempt to merge Reuse-constraint operand outputs with the
             // corresponding inputs.
             for op in self.func.inst_operands(inst) {
-                if let OperandConstraint::Reuse(reuse_idx) = op.constraint() {
+                if let Some(reuse_idx) = self.reused_input_index(inst, *op) {
                     let src_vreg = op.vreg();
                     let dst_vreg = self.func.inst_operands(inst)[reuse_idx].vreg();
 
@@ -387,25 +418,27 @@ impl<'a, F: Function> Env<'a, F> {
     }
 
     pub fn compute_bundle_prio(&self, bundle: LiveBundleIndex) -> u32 {
-        // The priority is simply the total "length" -- the number of
-        // instructions covered by all LiveRanges.
-        let mut total = 0;
+        // The priority is the total over all LiveRanges given by the
+        // cost model; by default, the number of instructions covered.
+        let cost_model = self.func.cost_model();
+        let mut total = 0u32;
         for entry in &self.bundles[bundle].ranges {
-            total += entry.range.len() as u32;
+            total =
+                total.saturating_add(cost_model.range_priority(entry.range.from, entry.range.to));
         }
         trace!(" -> prio {total}");
         total
     }
 
-    pub fn compute_bundle_limit(&self, bundle: LiveBundleIndex) -> Option<u8> {
-        let mut limit: Option<u8> = None;
+    pub fn compute_bundle_limit(&self, bundle: LiveBundleIndex) -> Option<u16> {
+        let mut limit: Option<u16> = None;
         for entry in &self.bundles[bundle].ranges {
             for u in &self.ranges[entry.index].uses {
                 use OperandConstraint::*;
                 match u.operand.constraint() {
                     Limit(current) => {
-                        let current = u8::try_from(current)
-                            .expect("the current limit is too large to fit in a u8");
+                        let current = u16::try_from(current)
+                            .expect("the current limit is too large to fit in a u16");
                         match limit {
                             Some(prev) => limit = Some(prev.min(current)),
                             None => limit = Some(current),
@@ -414,7 +447,8 @@ impl<'a, F: Function> Env<'a, F> {
                     FixedReg(_) | Stack => {
                         break;
                     }
-                    Any | Reg | Reuse(_) => {
+                    Any | Reg | WideReg | Reuse(_) | ReuseEither(..) | FixedStack(_)
+                    | RegSet(_) => {
                         continue;
                     }
                 }
@@ -423,6 +457,15 @@ impl<'a, F: Function> Env<'a, F> {
         limit
     }
 
+    pub fn compute_bundle_has_reg_set(&self, bundle: LiveBundleIndex) -> bool {
+        self.bundles[bundle].ranges.iter().any(|entry| {
+            self.ranges[entry.index]
+                .uses
+                .iter()
+                .any(|u| matches!(u.operand.constraint(), OperandConstraint::RegSet(_)))
+        })
+    }
+
     pub fn queue_bundles(&mut self) {
         for bundle in 0..self.bundles.len() {
             trace!("enqueueing bundle{}", bundle);
@@ -432,7 +475,7 @@ impl<'a, F: Function> Env<'a, F> {
                 continue;
             }
             self.recompute_bundle_properties(bundle);
-            let prio = self.bundles[bundle].prio as usize;
+            let prio = self.queue_prio(bundle);
             self.allocation_queue.insert(bundle, prio, PReg::invalid());
         }
         self.output.stats.merged_bundle_count = self.allocation_queue.heap.len();
@@ -14,7 +14,7 @@
 //! its design.
 
 use crate::ssa::validate_ssa;
-use crate::{Function, MachineEnv, PReg, RegAllocError, RegClass, VecExt};
+use crate::{Algorithm, Function, MachineEnv, PReg, RegAllocError, RegClass, VecExt};
 pub(crate) mod data_structures;
 pub use data_structures::Ctx;
 pub use data_structures::Stats;
@@ -32,9 +32,13 @@ pub(crate) mod process;
 use process::*;
 use smallvec::smallvec;
 pub(crate) mod dump;
+pub(crate) mod linear_scan;
 pub(crate) mod moves;
 pub(crate) mod spill;
 
+#[cfg(test)]
+mod tests;
+
 impl<'a, F: Function> Env<'a, F> {
     pub(crate) fn new(func: &'a F, env: &'a MachineEnv, ctx: &'a mut Ctx) -> Self {
         let ninstrs = func.num_insts();
@@ -61,8 +65,10 @@ impl<'a, F: Function> Env<'a, F> {
             }));
         ctx.slots_by_class = core::array::from_fn(|_| SpillSlotList::default());
         ctx.extra_spillslots_by_class = core::array::from_fn(|_| smallvec![]);
-        ctx.preferred_victim_by_class = [PReg::invalid(); 3];
+        ctx.preferred_victim_by_class = [PReg::invalid(); RegClass::COUNT];
         ctx.multi_fixed_reg_fixups.clear();
+        ctx.fixed_stack_fixups.clear();
+        ctx.reuse_choices.clear();
         ctx.allocated_bundle_count = 0;
         ctx.debug_annotations.clear();
         ctx.scratch_bump
@@ -76,6 +82,11 @@ impl<'a, F: Function> Env<'a, F> {
         ctx.output.debug_locations.clear();
         ctx.output.edits.clear();
         ctx.output.stats = Stats::default();
+        ctx.output.algorithm = if ctx.linear_scan {
+            Algorithm::LinearScan
+        } else {
+            Algorithm::Ion
+        };
 
         Self { func, env, ctx }
     }
@@ -94,7 +105,14 @@ impl<'a, F: Function> Env<'a, F> {
     }
 
     pub(crate) fn run(&mut self) -> Result<Edits, RegAllocError> {
-        self.process_bundles()?;
+        if self.ctx.linear_scan {
+            self.scan_bundles()?;
+        } else {
+            self.process_bundles()?;
+        }
+        if self.ctx.give_up_on_fuel && self.ctx.output.stats.fuel_exhausted == 1 {
+            return Ok(Edits::with_capacity(0));
+        }
         self.try_allocating_regs_for_spilled_bundles();
         self.allocate_spillslots();
         if trace_enabled!() {
@@ -111,7 +129,53 @@ pub fn run<F: Function>(
     ctx: &mut Ctx,
     enable_annotations: bool,
     enable_ssa_checker: bool,
+    fuel: Option<usize>,
+) -> Result<(), RegAllocError> {
+    ctx.linear_scan = false;
+    ctx.give_up_on_fuel = false;
+    allocate(
+        func,
+        mach_env,
+        ctx,
+        enable_annotations,
+        enable_ssa_checker,
+        fuel,
+    )
+}
+
+/// Like `run`, but stops as soon as `fuel` runs out instead of
+/// finishing with minimal bundles, in which case it returns `false`
+/// and leaves `ctx.output` unusable.
+pub fn run_or_give_up<F: Function>(
+    func: &F,
+    mach_env: &MachineEnv,
+    ctx: &mut Ctx,
+    enable_annotations: bool,
+    enable_ssa_checker: bool,
+    fuel: Option<usize>,
+) -> Result<bool, RegAllocError> {
+    ctx.linear_scan = false;
+    ctx.give_up_on_fuel = true;
+    allocate(
+        func,
+        mach_env,
+        ctx,
+        enable_annotations,
+        enable_ssa_checker,
+        fuel,
+    )?;
+    Ok(ctx.output.stats.fuel_exhausted == 0)
+}
+
+fn allocate<F: Function>(
+    func: &F,
+    mach_env: &MachineEnv,
+    ctx: &mut Ctx,
+    enable_annotations: bool,
+    enable_ssa_checker: bool,
+    fuel: Option<usize>,
 ) -> Result<(), RegAllocError> {
+    ctx.fuel = fuel;
     ctx.cfginfo.init(func, &mut ctx.cfginfo_ctx)?;
 
     if enable_ssa_checker {
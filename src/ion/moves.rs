@@ -26,7 +26,7 @@ use crate::ion::reg_traversal::RegTraversalIter;
 use crate::moves::{MoveAndScratchResolver, ParallelMoves};
 use crate::{
     Allocation, Block, Edit, Function, FxHashMap, Inst, InstPosition, OperandConstraint,
-    OperandKind, OperandPos, PReg, ProgPoint, RegClass, SpillSlot,
+    OperandKind, OperandPos, PReg, PRegSet, ProgPoint, RegClass, SpillSlot, VReg,
 };
 use alloc::format;
 use alloc::vec::Vec;
@@ -34,6 +34,88 @@ use hashbrown::hash_map::Entry;
 use smallvec::{smallvec, SmallVec};
 
 impl<'a, F: Function> Env<'a, F> {
+    /// Returns the registers that share a register unit with `alloc`,
+    /// excluding `alloc` itself.
+    pub fn alloc_aliases(&self, alloc: Allocation) -> PRegSet {
+        match alloc.as_reg() {
+            Some(preg) => self.ctx.pregs[preg.index()].aliases,
+            None => PRegSet::empty(),
+        }
+    }
+
+    /// Returns whether a move from `src` to `dst` brings a value from
+    /// the stack into a real register, and so could be replaced by
+    /// rematerialization.
+    fn is_reload(&self, src: Allocation, dst: Allocation) -> bool {
+        let is_stack = |alloc: Allocation| match alloc.as_reg() {
+            Some(preg) => self.ctx.pregs[preg.index()].is_stack,
+            None => alloc.is_stack() || alloc.is_fixed_stack(),
+        };
+        is_stack(src) && dst.is_reg() && !is_stack(dst)
+    }
+
+    /// Returns whether `vreg` can be rematerialized rather than
+    /// reloaded from its spillslot, which must hold no other vreg.
+    fn can_remat(&self, vreg: VReg) -> bool {
+        let Some(entry) = self.vregs[vreg].ranges.first() else {
+            return false;
+        };
+        let bundle = self.ranges[entry.index].bundle;
+        self.spillsets[self.bundles[bundle].spillset].remat
+    }
+
+    /// Finds a register to borrow as a last-resort scratch register
+    /// at `key`, for a class with wide registers, along with the
+    /// registers that hold values in parts of it. Prefers one whose
+    /// live value, if any, occupies all of its register units, so that
+    /// saving and restoring it around the borrow also restores
+    /// anything that overlaps it; otherwise, the occupants have to be
+    /// saved and restored along with it. Registers in `move_regs`, read
+    /// or written by the moves at `key`, count as occupants even where
+    /// the allocation maps don't have them, as edge moves are outside
+    /// the liveranges they connect.
+    fn find_scratch_victim(
+        &self,
+        class: RegClass,
+        wide: bool,
+        key: LiveRangeKey,
+        move_regs: PRegSet,
+    ) -> (PReg, PRegSet) {
+        let occupants = |preg: PReg| {
+            let mut occupants = self.ctx.pregs[preg.index()].aliases & move_regs;
+            for unit in self.ctx.pregs[preg.index()].units {
+                if let Some(lr) = self.ctx.pregs[unit.index()].allocations.btree.get(&key) {
+                    // A fixed reservation doesn't tell which register
+                    // holds the value.
+                    if !lr.is_valid() {
+                        return None;
+                    }
+                    let bundle = self.ctx.ranges[*lr].bundle;
+                    match self.ctx.bundles[bundle].allocation.as_reg() {
+                        Some(reg) if reg != preg => occupants.add(reg),
+                        _ => {}
+                    }
+                }
+            }
+            Some(occupants)
+        };
+        let mut fallback = None;
+        for preg in RegTraversalIter::new(self.env, class, None, None, 0, None) {
+            if wide && !self.ctx.pregs[preg.index()].is_wide {
+                continue;
+            }
+            match occupants(preg) {
+                Some(occupants) if occupants.is_empty_set() => return (preg, occupants),
+                Some(occupants) if fallback.is_none() => fallback = Some((preg, occupants)),
+                _ => {}
+            }
+        }
+        fallback.unwrap_or((
+            self.preferred_victim_by_class[class as usize],
+            PRegSet::empty(),
+        ))
+    }
+
     pub fn is_start_of_block(&self, pos: ProgPoint) -> bool {
         let block = self.ctx.cfginfo.insn_block[pos.inst().index()];
         pos == self.ctx.cfginfo.block_entry[block.index()]
@@ -65,11 +147,15 @@ impl<'a, F: Function> Env<'a, F> {
             bundledata.allocation
         } else {
             trace!(" -> spillset {:?}", bundledata.spillset);
-            trace!(
-                " -> spill slot {:?}",
-                self.ctx.spillsets[bundledata.spillset].slot
-            );
-            self.ctx.spillslots[self.ctx.spillsets[bundledata.spillset].slot.index()].alloc
+            let spillset = &self.ctx.spillsets[bundledata.spillset];
+            trace!(" -> spill slot {:?}", spillset.slot);
+            if !spillset.slot.is_valid() {
+                // Spilled without a slot (see `can_spill_without_slot`):
+                // the value is nowhere until it is rematerialized.
+                debug_assert!(spillset.remat);
+                return Allocation::none();
+            }
+            self.ctx.spillslots[spillset.slot.index()].alloc
         }
     }
 
@@ -168,7 +254,10 @@ impl<'a, F: Function> Env<'a, F> {
         //   `from` must have only one out-edge; moves go at tail of
         //   `from` just before last Branch/Ret.
         // - Otherwise, there must be at most one in-edge to `to`,
-        //   and moves go at start of `to`.
+        //   and moves go at start of `to`, unless `from` also has
+        //   only one out-edge, runs less often than `to`, and ends
+        //   in a branch that reads and writes no registers; then
+        //   they go at the tail of `from`.
         #[inline(always)]
         fn choose_move_location<'a, F: Function>(
             env: &Env<'a, F>,
@@ -181,8 +270,12 @@ impl<'a, F: Function> Env<'a, F> {
             let to_is_entry = env.func.entry_block() == to;
             let from_outs = env.func.block_succs(from).len() + if from_is_ret { 1 } else { 0 };
             let to_ins = env.func.block_preds(to).len() + if to_is_entry { 1 } else { 0 };
+            let from_is_colder = env.cfginfo.block_frequency[from.index()]
+                < env.cfginfo.block_frequency[to.index()]
+                && env.func.inst_operands(from_last_insn).is_empty()
+                && env.func.inst_clobbers(from_last_insn).is_empty_set();
 
-            if to_ins > 1 && from_outs <= 1 {
+            if (to_ins > 1 || from_is_colder) && from_outs <= 1 {
                 (
                     // N.B.: though semantically the edge moves happen
                     // after the branch, we must insert them before
@@ -284,6 +377,7 @@ impl<'a, F: Function> Env<'a, F> {
             }
 
             inter_block_sources.clear();
+            let hint = self.func.vreg_hint(self.vreg(vreg)).map(Allocation::reg);
 
             // For each range in each vreg, insert moves or
             // half-moves.  We also scan over `blockparam_ins` and
@@ -301,8 +395,6 @@ impl<'a, F: Function> Env<'a, F> {
                     range,
                     alloc,
                 );
-                debug_assert!(alloc != Allocation::none());
-
                 if self.annotations_enabled {
                     self.annotate(
                         range.from,
@@ -350,8 +442,6 @@ impl<'a, F: Function> Env<'a, F> {
                 // instruction).
                 if let Some(prev) = prev.is_valid() {
                     let prev_alloc = self.get_alloc_for_range(prev.index);
-                    debug_assert!(prev_alloc != Allocation::none());
-
                     if prev.range.to >= range.from
                         && (prev.range.to > range.from || !self.is_start_of_block(range.from))
                         && !self.ranges[entry.index].has_flag(LiveRangeFlag::StartsAtDef)
@@ -568,7 +658,12 @@ impl<'a, F: Function> Env<'a, F> {
                     let slot = usedata.slot;
                     let operand = usedata.operand;
                     self.set_alloc(inst, slot as usize, alloc);
-                    if let OperandConstraint::Reuse(_) = operand.constraint() {
+                    if hint == Some(alloc) {
+                        self.output.stats.hints_satisfied += 1;
+                    }
+                    if let OperandConstraint::Reuse(_) | OperandConstraint::ReuseEither(..) =
+                        operand.constraint()
+                    {
                         reuse_input_insts.push(inst);
                     }
                 }
@@ -576,7 +671,8 @@ impl<'a, F: Function> Env<'a, F> {
                 // Scan debug-labels on this vreg that overlap with
                 // this range, producing a debug-info output record
                 // giving the allocation location for each label.
-                if !debug_labels.is_empty() {
+                // (A value spilled without a slot has no location.)
+                if !debug_labels.is_empty() && alloc != Allocation::none() {
                     // Do a binary search to find the start of any
                     // labels for this vreg. Recall that we require
                     // debug-label requests to be sorted by vreg as a
@@ -684,6 +780,38 @@ impl<'a, F: Function> Env<'a, F> {
             );
         }
 
+        // Handle client-defined stack locations by copying into them
+        // before uses and out of them after defs.
+        for fixup in core::mem::replace(&mut self.fixed_stack_fixups, vec![]) {
+            let alloc = self.get_alloc(fixup.inst, fixup.slot as usize);
+            let loc = Allocation::fixed_stack(fixup.loc);
+            trace!(
+                "fixed-stack constraint at {:?} ({:?}) between {} and {} for v{}",
+                fixup.inst,
+                fixup.kind,
+                alloc,
+                loc,
+                fixup.vreg.index(),
+            );
+            match fixup.kind {
+                OperandKind::Use => inserted_moves.push(
+                    ProgPoint::before(fixup.inst),
+                    InsertMovePrio::FixedStackUse,
+                    alloc,
+                    loc,
+                    self.vreg(fixup.vreg),
+                ),
+                OperandKind::Def => inserted_moves.push(
+                    ProgPoint::after(fixup.inst),
+                    InsertMovePrio::FixedStackDef,
+                    loc,
+                    alloc,
+                    self.vreg(fixup.vreg),
+                ),
+            }
+            self.set_alloc(fixup.inst, fixup.slot as usize, loc);
+        }
+
         // Handle outputs that reuse inputs: copy beforehand, then set
         // input's alloc to output's.
         //
@@ -732,7 +860,7 @@ impl<'a, F: Function> Env<'a, F> {
             let mut input_reused: SmallVec<[usize; 4]> = smallvec![];
             for output_idx in 0..self.func.inst_operands(inst).len() {
                 let operand = self.func.inst_operands(inst)[output_idx];
-                if let OperandConstraint::Reuse(input_idx) = operand.constraint() {
+                if let Some(input_idx) = self.reused_input_index(inst, operand) {
                     debug_assert!(!input_reused.contains(&input_idx));
                     debug_assert_eq!(operand.pos(), OperandPos::Late);
                     input_reused.push(input_idx);
@@ -792,9 +920,17 @@ impl<'a, F: Function> Env<'a, F> {
             from: ProgPoint,
             to: ProgPoint,
         ) {
-            // If we cross a block boundary, clear and return.
-            if this.cfginfo.insn_block[from.inst().index()]
-                != this.cfginfo.insn_block[to.inst().index()]
+            // If we cross a block boundary, the state still holds at
+            // `to` only if we got there along single-predecessor edges,
+            // i.e. if every block entered on the way has the block just
+            // before it as its only predecessor. Otherwise clear and
+            // return. (We propagate across splits but not joins.)
+            let from_block = this.cfginfo.insn_block[from.inst().index()];
+            let to_block = this.cfginfo.insn_block[to.inst().index()];
+            if from_block != to_block
+                && !(from_block.index() + 1..=to_block.index()).all(|block| {
+                    this.func.block_preds(Block::new(block)) == [Block::new(block - 1)]
+                })
             {
                 redundant_moves.clear();
                 return;
@@ -816,19 +952,30 @@ impl<'a, F: Function> Env<'a, F> {
                     match op.kind() {
                         OperandKind::Def => {
                             let alloc = this.get_alloc(inst, i);
-                            redundant_moves.clear_alloc(alloc);
+                            redundant_moves
+                                .clear_alloc_and_aliases(alloc, this.alloc_aliases(alloc));
+                        }
+                        // A client-defined stack location may be
+                        // overwritten by the instruction that reads it
+                        // (e.g. by a callee that owns its stack args).
+                        OperandKind::Use => {
+                            let alloc = this.get_alloc(inst, i);
+                            if alloc.is_fixed_stack() {
+                                redundant_moves.clear_alloc(alloc);
+                            }
                         }
-                        _ => {}
                     }
                 }
                 for reg in this.func.inst_clobbers(inst) {
-                    redundant_moves.clear_alloc(Allocation::reg(reg));
+                    let alloc = Allocation::reg(reg);
+                    redundant_moves.clear_alloc_and_aliases(alloc, this.alloc_aliases(alloc));
                 }
                 // The dedicated scratch registers may be clobbered by any
                 // instruction.
                 for reg in this.env.scratch_by_class {
                     if let Some(reg) = reg {
-                        redundant_moves.clear_alloc(Allocation::reg(reg));
+                        let alloc = Allocation::reg(reg);
+                        redundant_moves.clear_alloc_and_aliases(alloc, this.alloc_aliases(alloc));
                     }
                 }
             }
@@ -854,29 +1001,30 @@ impl<'a, F: Function> Env<'a, F> {
             // moves between the classes are impossible. (We could
             // enhance ParallelMoves to understand register classes, but
             // this seems simpler.)
-            let mut int_moves: SmallVec<[InsertedMove; 8]> = smallvec![];
-            let mut float_moves: SmallVec<[InsertedMove; 8]> = smallvec![];
-            let mut vec_moves: SmallVec<[InsertedMove; 8]> = smallvec![];
+            let mut moves_by_class: [SmallVec<[InsertedMove; 8]>; RegClass::COUNT] =
+                Default::default();
 
             for m in moves {
-                match m.to_vreg.class() {
-                    RegClass::Int => {
-                        int_moves.push(m.clone());
-                    }
-                    RegClass::Float => {
-                        float_moves.push(m.clone());
-                    }
-                    RegClass::Vector => {
-                        vec_moves.push(m.clone());
-                    }
-                }
+                moves_by_class[m.to_vreg.class().index()].push(m.clone());
             }
 
-            for &(regclass, moves) in &[
-                (RegClass::Int, &int_moves),
-                (RegClass::Float, &float_moves),
-                (RegClass::Vector, &vec_moves),
-            ] {
+            for regclass in RegClass::ALL {
+                let moves = &moves_by_class[regclass.index()];
+                // Wide values can only be carried by wide registers, and
+                // registers that merely overlap a move's source or
+                // destination cannot be used as scratch.
+                let wide = moves.iter().any(|m| self.vregs[m.to_vreg].wide);
+                let overlaps_move = |preg: PReg| {
+                    let alloc = Allocation::reg(preg);
+                    let aliases = self.ctx.pregs[preg.index()].aliases;
+                    moves.iter().any(|m| {
+                        m.from_alloc == alloc
+                            || m.to_alloc == alloc
+                            || m.from_alloc.as_reg().map_or(false, |r| aliases.contains(r))
+                            || m.to_alloc.as_reg().map_or(false, |r| aliases.contains(r))
+                    })
+                };
+
                 // All moves in `moves` semantically happen in
                 // parallel. Let's resolve these to a sequence of moves
                 // that can be done one at a time.
@@ -886,17 +1034,55 @@ impl<'a, F: Function> Env<'a, F> {
                     pos_prio.pos,
                     pos_prio.prio
                 );
+                // `ParallelMoves` requires each source and destination to
+                // be either identical or disjoint. A move whose destination
+                // partially overlaps some source is instead routed through
+                // a temporary stackslot, written in parallel with the other
+                // moves and read back once they are all done.
+                let mut stackslot_idx = 0;
+                let mut deferred_moves: SmallVec<[(Allocation, Allocation, Option<VReg>); 2]> =
+                    smallvec![];
+                // A value spilled without a slot is not stored, and is
+                // rematerialized rather than reloaded once the other
+                // moves have read what its register held.
+                let mut remats: SmallVec<[(VReg, Allocation); 2]> = smallvec![];
                 for m in moves {
                     trace!(" {} -> {}", m.from_alloc, m.to_alloc);
-                    parallel_moves.add(m.from_alloc, m.to_alloc, Some(m.to_vreg));
+                    if m.to_alloc.is_none() {
+                        continue;
+                    }
+                    if m.from_alloc.is_none() {
+                        debug_assert!(m
+                            .to_alloc
+                            .as_reg()
+                            .map_or(false, |preg| !self.pregs[preg.index()].is_stack));
+                        remats.push((m.to_vreg, m.to_alloc));
+                        continue;
+                    }
+                    let aliases = self.alloc_aliases(m.to_alloc);
+                    if !aliases.is_empty_set()
+                        && moves
+                            .iter()
+                            .any(|m| m.from_alloc.as_reg().map_or(false, |r| aliases.contains(r)))
+                    {
+                        let tmp = Allocation::stack(SpillSlot::new(SpillSlot::MAX - stackslot_idx));
+                        stackslot_idx += 1;
+                        trace!("  -> partially overlaps a source; going through {}", tmp);
+                        parallel_moves.add(m.from_alloc, tmp, Some(m.to_vreg));
+                        deferred_moves.push((tmp, m.to_alloc, Some(m.to_vreg)));
+                    } else {
+                        parallel_moves.add(m.from_alloc, m.to_alloc, Some(m.to_vreg));
+                    }
                 }
 
-                let resolved = parallel_moves.resolve();
+                let mut resolved = parallel_moves.resolve();
+                resolved.extend(deferred_moves);
                 let mut scratch_iter = RegTraversalIter::new(
                     self.env, regclass, None, None, 0,
                     None, // We assume there is no limit on the set of registers available for moves.
                 );
-                let mut dedicated_scratch = self.env.scratch_by_class[regclass as usize];
+                let mut dedicated_scratch = self.env.scratch_by_class[regclass as usize]
+                    .filter(|reg| !wide || self.ctx.pregs[reg.index()].is_wide);
                 let key = LiveRangeKey::from_range(&CodeRange {
                     from: pos_prio.pos,
                     to: pos_prio.pos.next(),
@@ -908,16 +1094,17 @@ impl<'a, F: Function> Env<'a, F> {
                         return Some(Allocation::reg(reg));
                     }
                     while let Some(preg) = scratch_iter.next() {
-                        if !self.pregs[preg.index()]
-                            .allocations
-                            .btree
-                            .contains_key(&key)
-                        {
+                        if wide && !self.pregs[preg.index()].is_wide {
+                            continue;
+                        }
+                        if self.pregs[preg.index()].units.into_iter().all(|unit| {
+                            !self.pregs[unit.index()]
+                                .allocations
+                                .btree
+                                .contains_key(&key)
+                        }) {
                             let alloc = Allocation::reg(preg);
-                            if moves
-                                .iter()
-                                .any(|m| m.from_alloc == alloc || m.to_alloc == alloc)
-                            {
+                            if overlaps_move(preg) {
                                 // Skip pregs used by moves in this
                                 // parallel move set, even if not
                                 // marked used at progpoint: edge move
@@ -931,7 +1118,6 @@ impl<'a, F: Function> Env<'a, F> {
                     }
                     None
                 };
-                let mut stackslot_idx = 0;
                 let get_stackslot = || {
                     let idx = stackslot_idx;
                     stackslot_idx += 1;
@@ -945,16 +1131,33 @@ impl<'a, F: Function> Env<'a, F> {
                     if let Some(preg) = alloc.as_reg() {
                         self.pregs[preg.index()].is_stack
                     } else {
-                        alloc.is_stack()
+                        alloc.is_stack() || alloc.is_fixed_stack()
+                    }
+                };
+                let (preferred_victim, occupants) = if self.env.reg_units.is_empty() {
+                    (
+                        self.preferred_victim_by_class[regclass as usize],
+                        PRegSet::empty(),
+                    )
+                } else {
+                    let mut move_regs = PRegSet::empty();
+                    for m in moves {
+                        for alloc in [m.from_alloc, m.to_alloc] {
+                            if let Some(preg) = alloc.as_reg() {
+                                move_regs.add(preg);
+                            }
+                        }
                     }
+                    self.find_scratch_victim(regclass, wide, key, move_regs)
                 };
-                let preferred_victim = self.preferred_victim_by_class[regclass as usize];
 
                 let scratch_resolver = MoveAndScratchResolver {
                     find_free_reg,
                     get_stackslot,
                     is_stack_alloc,
                     borrowed_scratch_reg: preferred_victim,
+                    borrowed_scratch_aliases: self.pregs[preferred_victim.index()].aliases,
+                    borrowed_scratch_occupants: occupants,
                 };
 
                 let resolved = scratch_resolver.compute(resolved);
@@ -978,11 +1181,35 @@ impl<'a, F: Function> Env<'a, F> {
                     trace!("  resolved: {} -> {} ({:?})", src, dst, to_vreg);
                     let action = redundant_moves.process_move(src, dst, to_vreg);
                     if !action.elide {
-                        edits.add(pos_prio, src, dst);
+                        for alias in self.alloc_aliases(dst) {
+                            redundant_moves.clear_alloc(Allocation::reg(alias));
+                        }
+                        match to_vreg {
+                            Some(vreg)
+                                if self.is_reload(src, dst)
+                                    && !self.vregs[vreg].blockparam.is_valid()
+                                    && self.can_remat(vreg) =>
+                            {
+                                trace!("    -> rematerialized");
+                                self.output.stats.remat_count += 1;
+                                edits.add_remat(pos_prio, vreg, dst);
+                                // `dst` now holds a fresh value of `vreg`
+                                // only, not a copy of everything `src` holds.
+                                redundant_moves.clear_alloc(dst);
+                            }
+                            _ => edits.add(pos_prio, src, dst),
+                        }
                     } else {
                         trace!("    -> redundant move elided");
+                        self.output.stats.redundant_moves_elided += 1;
                     }
                 }
+                for (vreg, dst) in remats {
+                    trace!("  remat: {} -> {}", vreg, dst);
+                    self.output.stats.remat_count += 1;
+                    edits.add_remat(pos_prio, vreg, dst);
+                    redundant_moves.clear_alloc_and_aliases(dst, self.alloc_aliases(dst));
+                }
             }
         }
 
@@ -992,6 +1219,14 @@ impl<'a, F: Function> Env<'a, F> {
         // key.
         edits.sort();
         self.output.stats.edits_count = edits.len();
+        let dynamic_cost: f64 = edits
+            .iter()
+            .map(|(pos_prio, _)| {
+                let block = self.cfginfo.insn_block[pos_prio.pos.inst().index()];
+                f64::from(self.cfginfo.block_frequency[block.index()])
+            })
+            .sum();
+        self.output.stats.edits_dynamic_cost = dynamic_cost as usize;
 
         // Add debug annotations.
         if self.annotations_enabled {
@@ -1000,6 +1235,9 @@ impl<'a, F: Function> Env<'a, F> {
                     &Edit::Move { from, to } => {
                         self.annotate(pos_prio.pos, format!("move {} -> {}", from, to));
                     }
+                    &Edit::Remat { vreg, to } => {
+                        self.annotate(pos_prio.pos, format!("remat {} -> {}", vreg, to));
+                    }
                 }
             }
         }
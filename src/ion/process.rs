@@ -13,9 +13,9 @@
 //! Main allocation loop that processes bundles.
 
 use super::{
-    spill_weight_from_constraint, Env, LiveBundleIndex, LiveBundleVec, LiveRangeFlag,
-    LiveRangeIndex, LiveRangeKey, LiveRangeList, LiveRangeListEntry, PRegIndex, RegTraversalIter,
-    Requirement, SpillWeight, UseList, VRegIndex,
+    Env, LiveBundleIndex, LiveBundleVec, LiveRangeFlag, LiveRangeIndex, LiveRangeKey,
+    LiveRangeList, LiveRangeListEntry, PRegIndex, RegTraversalIter, Requirement, SpillWeight,
+    UseList, VRegIndex,
 };
 use crate::{
     ion::data_structures::{
@@ -26,6 +26,7 @@ use crate::{
     Allocation, Function, Inst, InstPosition, OperandConstraint, OperandKind, PReg, ProgPoint,
     RegAllocError,
 };
+use alloc::vec;
 use core::fmt::Debug;
 use smallvec::{smallvec, SmallVec};
 
@@ -42,6 +43,10 @@ impl<'a, F: Function> Env<'a, F> {
         while let Some((bundle, hint)) = self.ctx.allocation_queue.pop() {
             self.ctx.output.stats.process_bundle_count += 1;
             self.process_bundle(bundle, hint)?;
+            if self.ctx.give_up_on_fuel && self.ctx.output.stats.fuel_exhausted == 1 {
+                trace!("out of fuel; giving up");
+                return Ok(());
+            }
         }
         self.ctx.output.stats.final_liverange_count = self.ranges.len();
         self.ctx.output.stats.final_bundle_count = self.bundles.len();
@@ -64,7 +69,11 @@ impl<'a, F: Function> Env<'a, F> {
         conflicts.clear();
         self.ctx.conflict_set.clear();
         let mut max_conflict_weight = 0;
-        // Traverse the BTreeMap in order by requesting the whole
+        // A wide register conflicts with anything allocated to any of its
+        // register units, so we scan (and below, fill) the allocation maps
+        // of all of them.
+        //
+        // For each, traverse the BTreeMap in order by requesting the whole
         // range spanned by the bundle and iterating over that
         // concurrently with our ranges. Because our ranges are in
         // order, and the BTreeMap is as well, this allows us to have
@@ -77,114 +86,117 @@ impl<'a, F: Function> Env<'a, F> {
         // *overlap*, so we are checking whether the BTree contains
         // any preg range that *overlaps* with range `range`, not
         // literally the range `range`.
-        let bundle_ranges = &self.ctx.bundles[bundle].ranges;
-        let from_key = LiveRangeKey::from_range(&CodeRange {
-            from: bundle_ranges.first().unwrap().range.from,
-            to: bundle_ranges.first().unwrap().range.from,
-        });
-        let mut preg_range_iter = self.ctx.pregs[reg.index()]
-            .allocations
-            .btree
-            .range(from_key..)
-            .peekable();
-        trace!(
-            "alloc map for {:?} in range {:?}..: {:?}",
-            reg,
-            from_key,
-            self.ctx.pregs[reg.index()].allocations.btree
-        );
+        let units = self.ctx.pregs[reg.index()].units;
         let mut first_conflict: Option<ProgPoint> = None;
+        for unit in units {
+            let bundle_ranges = &self.ctx.bundles[bundle].ranges;
+            let from_key = LiveRangeKey::from_range(&CodeRange {
+                from: bundle_ranges.first().unwrap().range.from,
+                to: bundle_ranges.first().unwrap().range.from,
+            });
+            let mut preg_range_iter = self.ctx.pregs[unit.index()]
+                .allocations
+                .btree
+                .range(from_key..)
+                .peekable();
+            trace!(
+                "alloc map for {:?} in range {:?}..: {:?}",
+                unit,
+                from_key,
+                self.ctx.pregs[unit.index()].allocations.btree
+            );
 
-        'ranges: for entry in bundle_ranges {
-            trace!(" -> range LR {:?}: {:?}", entry.index, entry.range);
-            let key = LiveRangeKey::from_range(&entry.range);
+            'ranges: for entry in bundle_ranges {
+                trace!(" -> range LR {:?}: {:?}", entry.index, entry.range);
+                let key = LiveRangeKey::from_range(&entry.range);
 
-            let mut skips = 0;
-            'alloc: loop {
-                trace!("  -> PReg range {:?}", preg_range_iter.peek());
+                let mut skips = 0;
+                'alloc: loop {
+                    trace!("  -> PReg range {:?}", preg_range_iter.peek());
 
-                // Advance our BTree traversal until it is >= this bundle
-                // range (i.e., skip PReg allocations in the BTree that
-                // are completely before this bundle range).
+                    // Advance our BTree traversal until it is >= this bundle
+                    // range (i.e., skip PReg allocations in the BTree that
+                    // are completely before this bundle range).
 
-                if preg_range_iter.peek().is_some() && *preg_range_iter.peek().unwrap().0 < key {
-                    trace!(
-                        "Skipping PReg range {:?}",
-                        preg_range_iter.peek().unwrap().0
-                    );
-                    preg_range_iter.next();
-                    skips += 1;
-                    if skips >= 16 {
-                        let from_pos = entry.range.from;
-                        let from_key = LiveRangeKey::from_range(&CodeRange {
-                            from: from_pos,
-                            to: from_pos,
-                        });
-                        preg_range_iter = self.ctx.pregs[reg.index()]
-                            .allocations
-                            .btree
-                            .range(from_key..)
-                            .peekable();
-                        skips = 0;
+                    if preg_range_iter.peek().is_some() && *preg_range_iter.peek().unwrap().0 < key
+                    {
+                        trace!(
+                            "Skipping PReg range {:?}",
+                            preg_range_iter.peek().unwrap().0
+                        );
+                        preg_range_iter.next();
+                        skips += 1;
+                        if skips >= 16 {
+                            let from_pos = entry.range.from;
+                            let from_key = LiveRangeKey::from_range(&CodeRange {
+                                from: from_pos,
+                                to: from_pos,
+                            });
+                            preg_range_iter = self.ctx.pregs[unit.index()]
+                                .allocations
+                                .btree
+                                .range(from_key..)
+                                .peekable();
+                            skips = 0;
+                        }
+                        continue 'alloc;
                     }
-                    continue 'alloc;
-                }
-                skips = 0;
-
-                // If there are no more PReg allocations, we're done!
-                if preg_range_iter.peek().is_none() {
-                    trace!(" -> no more PReg allocations; so no conflict possible!");
-                    break 'ranges;
-                }
+                    skips = 0;
 
-                // If the current PReg range is beyond this range, there is no conflict; continue.
-                if *preg_range_iter.peek().unwrap().0 > key {
-                    trace!(
-                        " -> next PReg allocation is at {:?}; moving to next VReg range",
-                        preg_range_iter.peek().unwrap().0
-                    );
-                    break 'alloc;
-                }
+                    // If there are no more PReg allocations, we're done!
+                    if preg_range_iter.peek().is_none() {
+                        trace!(" -> no more PReg allocations; so no conflict possible!");
+                        break 'ranges;
+                    }
 
-                // Otherwise, there is a conflict.
-                let preg_key = *preg_range_iter.peek().unwrap().0;
-                debug_assert_eq!(preg_key, key); // Assert that this range overlaps.
-                let preg_range = preg_range_iter.next().unwrap().1;
-
-                trace!(" -> btree contains range {:?} that overlaps", preg_range);
-                if preg_range.is_valid() {
-                    trace!("   -> from vreg {:?}", self.ctx.ranges[*preg_range].vreg);
-                    // range from an allocated bundle: find the bundle and add to
-                    // conflicts list.
-                    let conflict_bundle = self.ctx.ranges[*preg_range].bundle;
-                    trace!("   -> conflict bundle {:?}", conflict_bundle);
-                    if self.ctx.conflict_set.insert(conflict_bundle) {
-                        conflicts.push(conflict_bundle);
-                        max_conflict_weight = core::cmp::max(
-                            max_conflict_weight,
-                            self.ctx.bundles[conflict_bundle].cached_spill_weight(),
+                    // If the current PReg range is beyond this range, there is no conflict; continue.
+                    if *preg_range_iter.peek().unwrap().0 > key {
+                        trace!(
+                            " -> next PReg allocation is at {:?}; moving to next VReg range",
+                            preg_range_iter.peek().unwrap().0
                         );
-                        if max_allowable_cost.is_some()
-                            && max_conflict_weight > max_allowable_cost.unwrap()
-                        {
-                            trace!("   -> reached high cost, retrying early");
-                            return AllocRegResult::ConflictHighCost;
-                        }
+                        break 'alloc;
                     }
 
-                    if first_conflict.is_none() {
-                        first_conflict = Some(ProgPoint::from_index(core::cmp::max(
-                            preg_key.from,
-                            key.from,
-                        )));
+                    // Otherwise, there is a conflict.
+                    let preg_key = *preg_range_iter.peek().unwrap().0;
+                    debug_assert_eq!(preg_key, key); // Assert that this range overlaps.
+                    let preg_range = preg_range_iter.next().unwrap().1;
+
+                    trace!(" -> btree contains range {:?} that overlaps", preg_range);
+                    if preg_range.is_valid() {
+                        trace!("   -> from vreg {:?}", self.ctx.ranges[*preg_range].vreg);
+                        // range from an allocated bundle: find the bundle and add to
+                        // conflicts list.
+                        let conflict_bundle = self.ctx.ranges[*preg_range].bundle;
+                        trace!("   -> conflict bundle {:?}", conflict_bundle);
+                        if self.ctx.conflict_set.insert(conflict_bundle) {
+                            conflicts.push(conflict_bundle);
+                            max_conflict_weight = core::cmp::max(
+                                max_conflict_weight,
+                                self.ctx.bundles[conflict_bundle].cached_spill_weight(),
+                            );
+                            if max_allowable_cost.is_some()
+                                && max_conflict_weight > max_allowable_cost.unwrap()
+                            {
+                                trace!("   -> reached high cost, retrying early");
+                                return AllocRegResult::ConflictHighCost;
+                            }
+                        }
+
+                        let conflict_point =
+                            ProgPoint::from_index(core::cmp::max(preg_key.from, key.from));
+                        if first_conflict.map_or(true, |first| conflict_point < first) {
+                            first_conflict = Some(conflict_point);
+                        }
+                    } else {
+                        trace!("   -> conflict with fixed reservation");
+                        // range from a direct use of the PReg (due to clobber).
+                        return AllocRegResult::ConflictWithFixed(
+                            max_conflict_weight,
+                            ProgPoint::from_index(preg_key.from),
+                        );
                     }
-                } else {
-                    trace!("   -> conflict with fixed reservation");
-                    // range from a direct use of the PReg (due to clobber).
-                    return AllocRegResult::ConflictWithFixed(
-                        max_conflict_weight,
-                        ProgPoint::from_index(preg_key.from),
-                    );
                 }
             }
         }
@@ -197,15 +209,17 @@ impl<'a, F: Function> Env<'a, F> {
         let preg = PReg::from_index(reg.index());
         trace!("  -> bundle {:?} assigned to preg {:?}", bundle, preg);
         self.ctx.bundles[bundle].allocation = Allocation::reg(preg);
-        for entry in &self.ctx.bundles[bundle].ranges {
-            let key = LiveRangeKey::from_range(&entry.range);
-            let res = self.ctx.pregs[reg.index()]
-                .allocations
-                .btree
-                .insert(key, entry.index);
-
-            // We disallow LR overlap within bundles, so this should never be possible.
-            debug_assert!(res.is_none());
+        for unit in units {
+            for entry in &self.ctx.bundles[bundle].ranges {
+                let key = LiveRangeKey::from_range(&entry.range);
+                let res = self.ctx.pregs[unit.index()]
+                    .allocations
+                    .btree
+                    .insert(key, entry.index);
+
+                // We disallow LR overlap within bundles, so this should never be possible.
+                debug_assert!(res.is_none());
+            }
         }
 
         AllocRegResult::Allocated(Allocation::reg(preg))
@@ -227,20 +241,38 @@ impl<'a, F: Function> Env<'a, F> {
                 return;
             }
         };
-        let preg_idx = PRegIndex::new(preg.index());
         self.ctx.bundles[bundle].allocation = Allocation::none();
-        for entry in &self.ctx.bundles[bundle].ranges {
-            trace!(" -> removing LR {:?} from reg {:?}", entry.index, preg_idx);
-            self.ctx.pregs[preg_idx.index()]
-                .allocations
-                .btree
-                .remove(&LiveRangeKey::from_range(&entry.range));
+        for unit in self.ctx.pregs[preg.index()].units {
+            let preg_idx = PRegIndex::new(unit.index());
+            for entry in &self.ctx.bundles[bundle].ranges {
+                trace!(" -> removing LR {:?} from reg {:?}", entry.index, preg_idx);
+                self.ctx.pregs[preg_idx.index()]
+                    .allocations
+                    .btree
+                    .remove(&LiveRangeKey::from_range(&entry.range));
+            }
         }
-        let prio = self.ctx.bundles[bundle].prio;
+        let prio = self.queue_prio(bundle);
         trace!(" -> prio {}; back into queue", prio);
         self.ctx
             .allocation_queue
-            .insert(bundle, prio as usize, PReg::invalid());
+            .insert(bundle, prio, PReg::invalid());
+    }
+
+    /// Uses up one step of `RegallocOptions::fuel`, and returns
+    /// whether there was none left.
+    pub fn burn_fuel(&mut self) -> bool {
+        match &mut self.ctx.fuel {
+            Some(0) => {
+                self.ctx.output.stats.fuel_exhausted = 1;
+                true
+            }
+            Some(fuel) => {
+                *fuel -= 1;
+                false
+            }
+            None => false,
+        }
     }
 
     pub fn bundle_spill_weight(&self, bundle: LiveBundleIndex) -> u32 {
@@ -269,6 +301,7 @@ impl<'a, F: Function> Env<'a, F> {
         let mut fixed = false;
         let mut fixed_def = false;
         let mut stack = false;
+        let mut reg_set_len: Option<u32> = None;
         let bundledata = &self.ctx.bundles[bundle];
         let num_ranges = bundledata.ranges.len();
         let first_range = bundledata.ranges[0].index;
@@ -276,6 +309,7 @@ impl<'a, F: Function> Env<'a, F> {
 
         self.ctx.bundles[bundle].prio = self.compute_bundle_prio(bundle);
         self.ctx.bundles[bundle].limit = self.compute_bundle_limit(bundle);
+        self.ctx.bundles[bundle].has_reg_set = self.compute_bundle_has_reg_set(bundle);
 
         if first_range_data.vreg.is_invalid() {
             trace!("  -> no vreg; minimal and fixed");
@@ -296,6 +330,10 @@ impl<'a, F: Function> Env<'a, F> {
                     trace!("  -> stack operand at {:?}: {:?}", u.pos, u.operand);
                     stack = true;
                 }
+                if let OperandConstraint::RegSet(i) = u.operand.constraint() {
+                    let len = self.env.reg_sets[i].len();
+                    reg_set_len = Some(reg_set_len.map_or(len, |prev| prev.min(len)));
+                }
                 if stack && fixed {
                     break;
                 }
@@ -329,6 +367,9 @@ impl<'a, F: Function> Env<'a, F> {
             } else if let Some(limit) = self.ctx.bundles[bundle].limit {
                 trace!("  -> limited({limit}) and minimal");
                 MINIMAL_LIMITED_BUNDLE_SPILL_WEIGHT - u32::from(limit)
+            } else if let Some(len) = reg_set_len {
+                trace!("  -> reg set of {len} and minimal");
+                MINIMAL_LIMITED_BUNDLE_SPILL_WEIGHT - len
             } else {
                 trace!("  -> non-fixed and minimal");
                 MINIMAL_BUNDLE_SPILL_WEIGHT
@@ -345,7 +386,10 @@ impl<'a, F: Function> Env<'a, F> {
             }
 
             if self.ctx.bundles[bundle].prio > 0 {
-                let final_weight = (total.to_f32() as u32) / self.ctx.bundles[bundle].prio;
+                let mut final_weight = (total.to_f32() as u32) / self.ctx.bundles[bundle].prio;
+                if self.ctx.spillsets[self.ctx.bundles[bundle].spillset].remat {
+                    final_weight = self.func.cost_model().remat_spill_weight(final_weight);
+                }
                 trace!(
                     " -> dividing by prio {}; final weight {}",
                     self.ctx.bundles[bundle].prio,
@@ -514,133 +558,26 @@ impl<'a, F: Function> Env<'a, F> {
 
         debug_assert!(split_at > bundle_start && split_at < bundle_end);
 
-        // We need to find which LRs fall on each side of the split,
-        // which LR we need to split down the middle, then update the
-        // current bundle, create a new one, and (re)-queue both.
-
-        trace!(" -> LRs: {:?}", self.ctx.bundles[bundle].ranges);
-
-        let mut last_lr_in_old_bundle_idx = 0; // last LR-list index in old bundle
-        let mut first_lr_in_new_bundle_idx = 0; // first LR-list index in new bundle
-        for (i, entry) in self.ctx.bundles[bundle].ranges.iter().enumerate() {
-            if split_at > entry.range.from {
-                last_lr_in_old_bundle_idx = i;
-                first_lr_in_new_bundle_idx = i;
-            }
-            if split_at < entry.range.to {
-                first_lr_in_new_bundle_idx = i;
-
-                // When the bundle contains a fixed constraint, we advance the split point to right
-                // before the first instruction with a fixed use present.
-                if self.ctx.bundles[bundle].cached_fixed() {
-                    for u in &self.ctx.ranges[entry.index].uses {
-                        if u.pos < split_at {
-                            continue;
-                        }
-
-                        if matches!(u.operand.constraint(), OperandConstraint::FixedReg { .. }) {
-                            split_at = ProgPoint::before(u.pos.inst());
-
-                            if split_at > entry.range.from {
-                                last_lr_in_old_bundle_idx = i;
-                            }
-
-                            trace!(" -> advancing split point to {split_at:?}");
-
-                            trim_ends_into_spill_bundle = false;
-
-                            break;
-                        }
-                    }
-                }
-
-                break;
-            }
-        }
-
-        trace!(
-            " -> last LR in old bundle: LR {:?}",
-            self.ctx.bundles[bundle].ranges[last_lr_in_old_bundle_idx]
-        );
-        trace!(
-            " -> first LR in new bundle: LR {:?}",
-            self.ctx.bundles[bundle].ranges[first_lr_in_new_bundle_idx]
-        );
-
-        // Take the sublist of LRs that will go in the new bundle.
-        let mut new_lr_list: LiveRangeList = LiveRangeList::new_in(self.ctx.bump());
-        new_lr_list.extend(
-            self.ctx.bundles[bundle]
+        // When the bundle contains a fixed constraint, we advance the split point to right
+        // before the first instruction with a fixed use present.
+        if self.ctx.bundles[bundle].cached_fixed() {
+            let entry = self.ctx.bundles[bundle]
                 .ranges
                 .iter()
-                .cloned()
-                .skip(first_lr_in_new_bundle_idx),
-        );
-        self.ctx.bundles[bundle]
-            .ranges
-            .truncate(last_lr_in_old_bundle_idx + 1);
-        self.ctx.bundles[bundle].ranges.shrink_to_fit();
-
-        // If the first entry in `new_lr_list` is a LR that is split
-        // down the middle, replace it with a new LR and chop off the
-        // end of the same LR in the original list.
-        if split_at > new_lr_list[0].range.from {
-            debug_assert_eq!(last_lr_in_old_bundle_idx, first_lr_in_new_bundle_idx);
-            let orig_lr = new_lr_list[0].index;
-            let new_lr = self.ctx.ranges.add(
-                CodeRange {
-                    from: split_at,
-                    to: new_lr_list[0].range.to,
-                },
-                self.ctx.bump(),
-            );
-            self.ctx.ranges[new_lr].vreg = self.ranges[orig_lr].vreg;
-            trace!(" -> splitting LR {:?} into {:?}", orig_lr, new_lr);
-            let first_use = self.ctx.ranges[orig_lr]
-                .uses
-                .iter()
-                .position(|u| u.pos >= split_at)
-                .unwrap_or(self.ctx.ranges[orig_lr].uses.len());
-            let mut rest_uses = UseList::new_in(self.ctx.bump());
-            rest_uses.extend(
-                self.ctx.ranges[orig_lr]
-                    .uses
-                    .iter()
-                    .cloned()
-                    .skip(first_use),
-            );
-            self.ctx.ranges[new_lr].uses = rest_uses;
-            self.ctx.ranges[orig_lr].uses.truncate(first_use);
-            self.ctx.ranges[orig_lr].uses.shrink_to_fit();
-            self.recompute_range_properties(orig_lr);
-            self.recompute_range_properties(new_lr);
-            new_lr_list[0].index = new_lr;
-            new_lr_list[0].range = self.ctx.ranges[new_lr].range;
-            self.ctx.ranges[orig_lr].range.to = split_at;
-            self.ctx.bundles[bundle].ranges[last_lr_in_old_bundle_idx].range =
-                self.ctx.ranges[orig_lr].range;
-
-            // Perform a lazy split in the VReg data. We just
-            // append the new LR and its range; we will sort by
-            // start of range, and fix up range ends, once when we
-            // iterate over the VReg's ranges after allocation
-            // completes (this is the only time when order
-            // matters).
-            self.ctx.vregs[self.ctx.ranges[new_lr].vreg]
-                .ranges
-                .push(LiveRangeListEntry {
-                    range: self.ctx.ranges[new_lr].range,
-                    index: new_lr,
-                });
+                .find(|entry| split_at < entry.range.to)
+                .unwrap();
+            let fixed_use = self.ctx.ranges[entry.index].uses.iter().find(|u| {
+                u.pos >= split_at
+                    && matches!(u.operand.constraint(), OperandConstraint::FixedReg { .. })
+            });
+            if let Some(u) = fixed_use {
+                split_at = ProgPoint::before(u.pos.inst());
+                trace!(" -> advancing split point to {split_at:?}");
+                trim_ends_into_spill_bundle = false;
+            }
         }
 
-        let new_bundle = self.ctx.bundles.add(self.ctx.bump());
-        trace!(" -> creating new bundle {:?}", new_bundle);
-        self.ctx.bundles[new_bundle].spillset = spillset;
-        for entry in &new_lr_list {
-            self.ctx.ranges[entry.index].bundle = new_bundle;
-        }
-        self.ctx.bundles[new_bundle].ranges = new_lr_list;
+        let new_bundle = self.split_bundle(bundle, split_at);
 
         if trim_ends_into_spill_bundle {
             // Finally, handle moving LRs to the spill bundle when
@@ -689,6 +626,7 @@ impl<'a, F: Function> Env<'a, F> {
                         index: empty_lr,
                     });
                     self.ctx.ranges[empty_lr].bundle = spill;
+                    self.ctx.ranges[empty_lr].vreg = vreg;
                     self.ctx.vregs[vreg].ranges.push(LiveRangeListEntry {
                         range,
                         index: empty_lr,
@@ -754,6 +692,7 @@ impl<'a, F: Function> Env<'a, F> {
                         index: empty_lr,
                     });
                     self.ctx.ranges[empty_lr].bundle = spill;
+                    self.ctx.ranges[empty_lr].vreg = vreg;
                     self.ctx.vregs[vreg].ranges.push(LiveRangeListEntry {
                         range,
                         index: empty_lr,
@@ -776,18 +715,131 @@ impl<'a, F: Function> Env<'a, F> {
 
         if self.ctx.bundles[bundle].ranges.len() > 0 {
             self.recompute_bundle_properties(bundle);
-            let prio = self.ctx.bundles[bundle].prio;
-            self.ctx
-                .allocation_queue
-                .insert(bundle, prio as usize, hint);
+            let prio = self.queue_prio(bundle);
+            self.ctx.allocation_queue.insert(bundle, prio, hint);
         }
         if self.ctx.bundles[new_bundle].ranges.len() > 0 {
             self.recompute_bundle_properties(new_bundle);
-            let prio = self.ctx.bundles[new_bundle].prio;
-            self.ctx
-                .allocation_queue
-                .insert(new_bundle, prio as usize, hint);
+            let prio = self.queue_prio(new_bundle);
+            self.ctx.allocation_queue.insert(new_bundle, prio, hint);
+        }
+    }
+
+    /// Splits `bundle` at `split_at`, which must be strictly within it,
+    /// moving everything from `split_at` on into a new bundle in the
+    /// same spillset, which is returned. Neither bundle is requeued,
+    /// and their properties are not recomputed.
+    pub fn split_bundle(
+        &mut self,
+        bundle: LiveBundleIndex,
+        split_at: ProgPoint,
+    ) -> LiveBundleIndex {
+        let spillset = self.ctx.bundles[bundle].spillset;
+
+        // We need to find which LRs fall on each side of the split,
+        // which LR we need to split down the middle, then update the
+        // current bundle and create a new one.
+
+        trace!(" -> LRs: {:?}", self.ctx.bundles[bundle].ranges);
+
+        let mut last_lr_in_old_bundle_idx = 0; // last LR-list index in old bundle
+        let mut first_lr_in_new_bundle_idx = 0; // first LR-list index in new bundle
+        for (i, entry) in self.ctx.bundles[bundle].ranges.iter().enumerate() {
+            if split_at > entry.range.from {
+                last_lr_in_old_bundle_idx = i;
+                first_lr_in_new_bundle_idx = i;
+            }
+            if split_at < entry.range.to {
+                first_lr_in_new_bundle_idx = i;
+                break;
+            }
+        }
+
+        trace!(
+            " -> last LR in old bundle: LR {:?}",
+            self.ctx.bundles[bundle].ranges[last_lr_in_old_bundle_idx]
+        );
+        trace!(
+            " -> first LR in new bundle: LR {:?}",
+            self.ctx.bundles[bundle].ranges[first_lr_in_new_bundle_idx]
+        );
+
+        // Take the sublist of LRs that will go in the new bundle.
+        let mut new_lr_list: LiveRangeList = LiveRangeList::new_in(self.ctx.bump());
+        new_lr_list.extend(
+            self.ctx.bundles[bundle]
+                .ranges
+                .iter()
+                .cloned()
+                .skip(first_lr_in_new_bundle_idx),
+        );
+        self.ctx.bundles[bundle]
+            .ranges
+            .truncate(last_lr_in_old_bundle_idx + 1);
+        self.ctx.bundles[bundle].ranges.shrink_to_fit();
+
+        // If the first entry in `new_lr_list` is a LR that is split
+        // down the middle, replace it with a new LR and chop off the
+        // end of the same LR in the original list.
+        if split_at > new_lr_list[0].range.from {
+            debug_assert_eq!(last_lr_in_old_bundle_idx, first_lr_in_new_bundle_idx);
+            let orig_lr = new_lr_list[0].index;
+            let new_lr = self.ctx.ranges.add(
+                CodeRange {
+                    from: split_at,
+                    to: new_lr_list[0].range.to,
+                },
+                self.ctx.bump(),
+            );
+            self.ctx.ranges[new_lr].vreg = self.ranges[orig_lr].vreg;
+            trace!(" -> splitting LR {:?} into {:?}", orig_lr, new_lr);
+            let first_use = self.ctx.ranges[orig_lr]
+                .uses
+                .iter()
+                .position(|u| u.pos >= split_at)
+                .unwrap_or(self.ctx.ranges[orig_lr].uses.len());
+            let mut rest_uses = UseList::new_in(self.ctx.bump());
+            rest_uses.extend(
+                self.ctx.ranges[orig_lr]
+                    .uses
+                    .iter()
+                    .cloned()
+                    .skip(first_use),
+            );
+            self.ctx.ranges[new_lr].uses = rest_uses;
+            self.ctx.ranges[orig_lr].uses.truncate(first_use);
+            self.ctx.ranges[orig_lr].uses.shrink_to_fit();
+            self.recompute_range_properties(orig_lr);
+            self.recompute_range_properties(new_lr);
+            new_lr_list[0].index = new_lr;
+            new_lr_list[0].range = self.ctx.ranges[new_lr].range;
+            self.ctx.ranges[orig_lr].range.to = split_at;
+            self.ctx.bundles[bundle].ranges[last_lr_in_old_bundle_idx].range =
+                self.ctx.ranges[orig_lr].range;
+
+            // Perform a lazy split in the VReg data. We just
+            // append the new LR and its range; we will sort by
+            // start of range, and fix up range ends, once when we
+            // iterate over the VReg's ranges after allocation
+            // completes (this is the only time when order
+            // matters).
+            self.ctx.vregs[self.ctx.ranges[new_lr].vreg]
+                .ranges
+                .push(LiveRangeListEntry {
+                    range: self.ctx.ranges[new_lr].range,
+                    index: new_lr,
+                });
+        }
+
+        let new_bundle = self.ctx.bundles.add(self.ctx.bump());
+        trace!(" -> creating new bundle {:?}", new_bundle);
+        self.ctx.bundles[new_bundle].spillset = spillset;
+        for entry in &new_lr_list {
+            self.ctx.ranges[entry.index].bundle = new_bundle;
         }
+        self.ctx.bundles[new_bundle].ranges = new_lr_list;
+
+        new_bundle
     }
 
     /// Splits the given bundle into minimal bundles per Use, falling
@@ -961,10 +1013,8 @@ impl<'a, F: Function> Env<'a, F> {
         for bundle in new_bundles {
             if self.ctx.bundles[bundle].ranges.len() > 0 {
                 self.recompute_bundle_properties(bundle);
-                let prio = self.ctx.bundles[bundle].prio;
-                self.ctx
-                    .allocation_queue
-                    .insert(bundle, prio as usize, hint);
+                let prio = self.queue_prio(bundle);
+                self.ctx.allocation_queue.insert(bundle, prio, hint);
             }
         }
     }
@@ -975,6 +1025,7 @@ impl<'a, F: Function> Env<'a, F> {
         hint: PReg,
     ) -> Result<(), RegAllocError> {
         let class = self.ctx.spillsets[self.bundles[bundle].spillset].class;
+        let wide = self.ctx.spillsets[self.bundles[bundle].spillset].wide;
 
         // Grab a hint from either the queue or our spillset, if any.
         let mut hint = if hint != PReg::invalid() {
@@ -1037,11 +1088,12 @@ impl<'a, F: Function> Env<'a, F> {
         'outer: loop {
             attempts += 1;
             trace!("attempt {}, req {:?}", attempts, req);
+            let out_of_fuel = self.burn_fuel();
             debug_assert!(attempts < 100 * self.func.num_insts());
 
             let fixed_preg = match req {
                 Requirement::FixedReg(preg) | Requirement::FixedStack(preg) => Some(preg),
-                Requirement::Register | Requirement::Limit(..) => None,
+                Requirement::Register | Requirement::Limit(..) | Requirement::RegSet(..) => None,
                 Requirement::Stack => {
                     // If we must be on the stack, mark our spillset
                     // as required immediately.
@@ -1077,7 +1129,7 @@ impl<'a, F: Function> Env<'a, F> {
                 + bundle.index();
 
             self.ctx.output.stats.process_bundle_reg_probe_start_any += 1;
-            let limit = self.bundles[bundle].limit.map(|l| l as usize);
+            let limit = self.bundles[bundle].limit;
             for preg in RegTraversalIter::new(
                 self.env,
                 class,
@@ -1086,6 +1138,16 @@ impl<'a, F: Function> Env<'a, F> {
                 scan_offset,
                 limit,
             ) {
+                // Wide values need wide registers, and wide registers
+                // are reserved for them.
+                if fixed_preg.is_none() && self.ctx.pregs[preg.index()].is_wide != wide {
+                    continue;
+                }
+                if let Requirement::RegSet(set) = req {
+                    if !set.contains(preg) {
+                        continue;
+                    }
+                }
                 self.ctx.output.stats.process_bundle_reg_probes_any += 1;
                 let preg_idx = PRegIndex::new(preg.index());
                 trace!("trying preg {:?}", preg_idx);
@@ -1128,16 +1190,13 @@ impl<'a, F: Function> Env<'a, F> {
                             lowest_cost_evict_conflict_set.extend(bundles);
                         }
 
-                        let loop_depth =
-                            self.ctx.cfginfo.approx_loop_depth[self.ctx.cfginfo.insn_block
-                                [first_conflict_point.inst().index()]
-                            .index()];
-                        let move_cost = spill_weight_from_constraint(
-                            OperandConstraint::Reg,
-                            loop_depth as usize,
-                            /* is_def = */ true,
-                        )
-                        .to_int();
+                        let move_cost = self
+                            .spill_weight_at(
+                                first_conflict_point.inst(),
+                                OperandConstraint::Reg,
+                                /* is_def = */ true,
+                            )
+                            .to_int();
                         if lowest_cost_split_conflict_cost.is_none()
                             || (conflict_cost + move_cost)
                                 < lowest_cost_split_conflict_cost.unwrap()
@@ -1150,14 +1209,13 @@ impl<'a, F: Function> Env<'a, F> {
                     AllocRegResult::ConflictWithFixed(max_cost, point) => {
                         trace!(" -> conflict with fixed alloc; cost of other bundles up to point is {}, conflict at {:?}", max_cost, point);
 
-                        let loop_depth = self.ctx.cfginfo.approx_loop_depth
-                            [self.ctx.cfginfo.insn_block[point.inst().index()].index()];
-                        let move_cost = spill_weight_from_constraint(
-                            OperandConstraint::Reg,
-                            loop_depth as usize,
-                            /* is_def = */ true,
-                        )
-                        .to_int();
+                        let move_cost = self
+                            .spill_weight_at(
+                                point.inst(),
+                                OperandConstraint::Reg,
+                                /* is_def = */ true,
+                            )
+                            .to_int();
 
                         if lowest_cost_split_conflict_cost.is_none()
                             || (max_cost + move_cost) < lowest_cost_split_conflict_cost.unwrap()
@@ -1211,44 +1269,64 @@ impl<'a, F: Function> Env<'a, F> {
                     || lowest_cost_evict_conflict_cost.is_none()
                     || lowest_cost_evict_conflict_cost.unwrap() >= our_spill_weight)
             {
-                if matches!(req, Requirement::Register | Requirement::Limit(_)) {
+                if matches!(
+                    req,
+                    Requirement::Register | Requirement::Limit(_) | Requirement::RegSet(_)
+                ) {
                     // Check if this is a too-many-live-registers situation.
                     let range = self.ctx.bundles[bundle].ranges[0].range;
                     trace!("checking for too many live regs");
                     let mut min_bundles_assigned = 0;
                     let mut fixed_assigned = 0;
                     let mut total_regs = 0;
+                    let mut vregs =
+                        vec![self.vreg(self.ranges[self.bundles[bundle].ranges[0].index].vreg)];
+                    let mut fixed = vec![];
                     for preg in self.env.preferred_regs_by_class[class as u8 as usize]
                         .into_iter()
                         .chain(self.env.non_preferred_regs_by_class[class as u8 as usize])
                     {
                         trace!(" -> PR {:?}", preg);
+                        if self.ctx.pregs[preg.index()].is_wide != wide {
+                            continue;
+                        }
+                        if let Requirement::RegSet(set) = req {
+                            if !set.contains(preg) {
+                                continue;
+                            }
+                        }
                         let start = LiveRangeKey::from_range(&CodeRange {
                             from: range.from.prev(),
                             to: range.from.prev(),
                         });
-                        for (key, lr) in self.ctx.pregs[preg.index()]
-                            .allocations
-                            .btree
-                            .range(start..)
-                        {
-                            let preg_range = key.to_range();
-                            if preg_range.to <= range.from {
-                                continue;
-                            }
-                            if preg_range.from >= range.to {
-                                break;
-                            }
-                            if lr.is_valid() {
-                                if self.minimal_bundle(self.ranges[*lr].bundle) {
-                                    trace!("  -> min bundle {:?}", lr);
-                                    min_bundles_assigned += 1;
+                        for unit in self.ctx.pregs[preg.index()].units {
+                            for (key, lr) in self.ctx.pregs[unit.index()]
+                                .allocations
+                                .btree
+                                .range(start..)
+                            {
+                                let preg_range = key.to_range();
+                                if preg_range.to <= range.from {
+                                    continue;
+                                }
+                                if preg_range.from >= range.to {
+                                    break;
+                                }
+                                if lr.is_valid() {
+                                    if self.minimal_bundle(self.ranges[*lr].bundle) {
+                                        trace!("  -> min bundle {:?}", lr);
+                                        min_bundles_assigned += 1;
+                                        vregs.push(self.vreg(self.ranges[*lr].vreg));
+                                    } else {
+                                        trace!("  -> non-min bundle {:?}", lr);
+                                    }
                                 } else {
-                                    trace!("  -> non-min bundle {:?}", lr);
+                                    trace!("  -> fixed bundle");
+                                    fixed_assigned += 1;
+                                    if !fixed.contains(&preg) {
+                                        fixed.push(preg);
+                                    }
                                 }
-                            } else {
-                                trace!("  -> fixed bundle");
-                                fixed_assigned += 1;
                             }
                         }
 
@@ -1269,7 +1347,12 @@ impl<'a, F: Function> Env<'a, F> {
                         min_bundles_assigned
                     );
                     if min_bundles_assigned + fixed_assigned >= total_regs {
-                        return Err(RegAllocError::TooManyLiveRegs);
+                        return Err(RegAllocError::TooManyLiveRegs {
+                            point: range.from,
+                            class,
+                            vregs,
+                            fixed,
+                        });
                     }
                 }
 
@@ -1289,6 +1372,16 @@ impl<'a, F: Function> Env<'a, F> {
             // wins, and the other splits.
             //
             // Note that we don't split if the bundle is minimal.
+            //
+            // Once we are out of fuel, a bundle that isn't minimal is
+            // split into minimal bundles right away: this never evicts,
+            // and leaves only minimal bundles to allocate.
+            if !self.minimal_bundle(bundle) && out_of_fuel {
+                trace!(" -> out of fuel; splitting into minimal bundles");
+                self.ctx.output.stats.fuel_minimal_splits += 1;
+                self.split_into_minimal_bundles(bundle, hint);
+                break 'outer;
+            }
             if !self.minimal_bundle(bundle)
                 && (attempts >= 2
                     || lowest_cost_evict_conflict_cost.is_none()
@@ -1303,20 +1396,21 @@ impl<'a, F: Function> Env<'a, F> {
                     core::cmp::max(lowest_cost_split_conflict_point, bundle_start);
                 let requeue_with_reg = lowest_cost_split_conflict_reg;
 
-                // Adjust `split_at_point` if it is within a deeper loop
-                // than the bundle start -- hoist it to just before the
-                // first loop header it encounters.
-                let bundle_start_depth = self.ctx.cfginfo.approx_loop_depth
+                // Adjust `split_at_point` if it is within a hotter block
+                // than the bundle start (e.g. a deeper loop) -- hoist it
+                // to just before the first hotter block it encounters.
+                let bundle_start_freq = self.ctx.cfginfo.block_frequency
                     [self.ctx.cfginfo.insn_block[bundle_start.inst().index()].index()];
-                let split_at_depth = self.ctx.cfginfo.approx_loop_depth
+                let split_at_freq = self.ctx.cfginfo.block_frequency
                     [self.ctx.cfginfo.insn_block[split_at_point.inst().index()].index()];
-                if split_at_depth > bundle_start_depth {
+                if split_at_freq > bundle_start_freq {
                     for block in (self.ctx.cfginfo.insn_block[bundle_start.inst().index()].index()
                         + 1)
                         ..=self.ctx.cfginfo.insn_block[split_at_point.inst().index()].index()
                     {
-                        if self.ctx.cfginfo.approx_loop_depth[block] > bundle_start_depth {
+                        if self.ctx.cfginfo.block_frequency[block] > bundle_start_freq {
                             split_at_point = self.ctx.cfginfo.block_entry[block];
+                            self.ctx.output.stats.splits_hoisted += 1;
                             break;
                         }
                     }
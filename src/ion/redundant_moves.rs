@@ -1,6 +1,6 @@
 //! Redundant-move elimination.
 
-use crate::{Allocation, FxHashMap, VReg};
+use crate::{Allocation, FxHashMap, PRegSet, VReg};
 use smallvec::{smallvec, SmallVec};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -126,4 +126,13 @@ impl RedundantMoveEliminator {
         }
         self.allocs.remove(&alloc);
     }
+
+    /// Clear `alloc` as well as the registers in `aliases`, which share
+    /// a register unit with it and so are also overwritten.
+    pub fn clear_alloc_and_aliases(&mut self, alloc: Allocation, aliases: PRegSet) {
+        self.clear_alloc(alloc);
+        for preg in aliases {
+            self.clear_alloc(Allocation::reg(preg));
+        }
+    }
 }
@@ -47,7 +47,7 @@ pub struct RegTraversalIter {
     hint: Option<PReg>,
     preferred: Cursor,
     non_preferred: Cursor,
-    limit: Option<usize>,
+    limit: Option<u16>,
 }
 
 impl RegTraversalIter {
@@ -57,7 +57,7 @@ impl RegTraversalIter {
         fixed: Option<PReg>,
         hint: Option<PReg>,
         offset: usize,
-        limit: Option<usize>,
+        limit: Option<u16>,
     ) -> Self {
         debug_assert!(fixed != Some(PReg::invalid()));
         debug_assert!(hint != Some(PReg::invalid()));
@@ -89,20 +89,22 @@ impl core::iter::Iterator for RegTraversalIter {
 
         if self.use_hint {
             self.use_hint = false;
-            if self.hint.unwrap().hw_enc() < self.limit.unwrap_or(usize::MAX) {
+            if self.hint.unwrap().hw_enc() < self.limit.map_or(usize::MAX, usize::from) {
                 return self.hint;
             }
         }
 
         while let Some(reg) = self.preferred.next() {
-            if Some(reg) == self.hint || reg.hw_enc() >= self.limit.unwrap_or(usize::MAX) {
+            if Some(reg) == self.hint || reg.hw_enc() >= self.limit.map_or(usize::MAX, usize::from)
+            {
                 continue; // Try again; we already tried the hint or we are outside of the register range limit.
             }
             return Some(reg);
         }
 
         while let Some(reg) = self.non_preferred.next() {
-            if Some(reg) == self.hint || reg.hw_enc() >= self.limit.unwrap_or(usize::MAX) {
+            if Some(reg) == self.hint || reg.hw_enc() >= self.limit.map_or(usize::MAX, usize::from)
+            {
                 continue; // Try again; we already tried the hint or we are outside of the register range limit.
             }
             return Some(reg);
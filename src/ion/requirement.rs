@@ -13,7 +13,7 @@
 //! Requirements computation.
 
 use super::{Env, LiveBundleIndex};
-use crate::{Function, Inst, Operand, OperandConstraint, PReg, ProgPoint};
+use crate::{Function, Inst, Operand, OperandConstraint, PReg, PRegSet, ProgPoint, RegClass};
 
 pub struct RequirementConflict;
 
@@ -62,9 +62,22 @@ pub enum Requirement {
     Register,
     FixedReg(PReg),
     Limit(usize),
+    RegSet(PRegSet),
     Stack,
     FixedStack(PReg),
 }
+
+/// The registers of every class whose `hw_enc` is below `limit`.
+fn limit_mask(limit: usize) -> PRegSet {
+    let mut mask = PRegSet::empty();
+    for class in RegClass::ALL {
+        for hw_enc in 0..limit.min(PReg::MAX + 1) {
+            mask.add(PReg::new(hw_enc, class));
+        }
+    }
+    mask
+}
+
 impl Requirement {
     #[inline(always)]
     pub fn merge(self, other: Requirement) -> Result<Requirement, RequirementConflict> {
@@ -84,6 +97,15 @@ impl Requirement {
             (Limit(a), FixedReg(b)) | (FixedReg(b), Limit(a)) if usize::from(a) > b.hw_enc() => {
                 Ok(FixedReg(b))
             }
+            // Intersect a `RegSet` with any other register requirement.
+            (RegSet(a), RegSet(b)) if !(a & b).is_empty_set() => Ok(RegSet(a & b)),
+            (RegSet(a), Register) | (Register, RegSet(a)) => Ok(RegSet(a)),
+            (RegSet(a), Limit(b)) | (Limit(b), RegSet(a))
+                if !(a & limit_mask(b)).is_empty_set() =>
+            {
+                Ok(RegSet(a & limit_mask(b)))
+            }
+            (RegSet(a), FixedReg(b)) | (FixedReg(b), RegSet(a)) if a.contains(b) => Ok(FixedReg(b)),
             // Constrain `Register|Stack` to `Fixed{Reg|Stack}`.
             (Register, FixedReg(preg)) | (FixedReg(preg), Register) => Ok(FixedReg(preg)),
             (Stack, FixedStack(preg)) | (FixedStack(preg), Stack) => Ok(FixedStack(preg)),
@@ -96,7 +118,10 @@ impl Requirement {
     pub fn is_stack(self) -> bool {
         match self {
             Requirement::Stack | Requirement::FixedStack(..) => true,
-            Requirement::Register | Requirement::FixedReg(..) | Requirement::Limit(..) => false,
+            Requirement::Register
+            | Requirement::FixedReg(..)
+            | Requirement::Limit(..)
+            | Requirement::RegSet(..) => false,
             Requirement::Any => false,
         }
     }
@@ -104,7 +129,10 @@ impl Requirement {
     #[inline(always)]
     pub fn is_reg(self) -> bool {
         match self {
-            Requirement::Register | Requirement::FixedReg(..) | Requirement::Limit(..) => true,
+            Requirement::Register
+            | Requirement::FixedReg(..)
+            | Requirement::Limit(..)
+            | Requirement::RegSet(..) => true,
             Requirement::Stack | Requirement::FixedStack(..) => false,
             Requirement::Any => false,
         }
@@ -122,10 +150,16 @@ impl<'a, F: Function> Env<'a, F> {
                     Requirement::FixedReg(preg)
                 }
             }
-            OperandConstraint::Reg | OperandConstraint::Reuse(_) => Requirement::Register,
+            OperandConstraint::Reg
+            | OperandConstraint::WideReg
+            | OperandConstraint::Reuse(_)
+            | OperandConstraint::ReuseEither(..) => Requirement::Register,
             OperandConstraint::Limit(max) => Requirement::Limit(max),
+            OperandConstraint::RegSet(index) => Requirement::RegSet(self.env.reg_sets[index]),
             OperandConstraint::Stack => Requirement::Stack,
-            OperandConstraint::Any => Requirement::Any,
+            // The value is copied into its stack location around the
+            // instruction (see `FixedStackFixup`), so it can live anywhere.
+            OperandConstraint::Any | OperandConstraint::FixedStack(_) => Requirement::Any,
         }
     }
 
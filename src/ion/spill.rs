@@ -13,10 +13,10 @@
 //! Spillslot allocation.
 
 use super::{
-    AllocRegResult, Env, LiveRangeKey, PRegIndex, RegTraversalIter, SpillSetIndex, SpillSlotData,
-    SpillSlotIndex,
+    AllocRegResult, Env, LiveBundleIndex, LiveRangeKey, PRegIndex, RegTraversalIter, Requirement,
+    SpillSetIndex, SpillSlotData, SpillSlotIndex,
 };
-use crate::{Allocation, Function, SpillSlot};
+use crate::{Allocation, Function, OperandConstraint, SpillSlot};
 
 impl<'a, F: Function> Env<'a, F> {
     pub fn try_allocating_regs_for_spilled_bundles(&mut self) {
@@ -30,6 +30,7 @@ impl<'a, F: Function> Env<'a, F> {
             }
 
             let class = self.ctx.spillsets[self.ctx.bundles[bundle].spillset].class;
+            let wide = self.ctx.spillsets[self.ctx.bundles[bundle].spillset].wide;
             let hint = self.ctx.spillsets[self.ctx.bundles[bundle].spillset]
                 .hint
                 .as_valid();
@@ -42,8 +43,22 @@ impl<'a, F: Function> Env<'a, F> {
 
             let mut success = false;
             self.ctx.output.stats.spill_bundle_reg_probes += 1;
-            let limit = self.bundles[bundle].limit.map(|l| l as usize);
+            let limit = self.bundles[bundle].limit;
+            let reg_set = if self.bundles[bundle].has_reg_set {
+                match self.compute_requirement(bundle) {
+                    Ok(Requirement::RegSet(set)) => Some(set),
+                    _ => None,
+                }
+            } else {
+                None
+            };
             for preg in RegTraversalIter::new(self.env, class, None, hint, bundle.index(), limit) {
+                if self.ctx.pregs[preg.index()].is_wide != wide {
+                    continue;
+                }
+                if reg_set.map_or(false, |set| !set.contains(preg)) {
+                    continue;
+                }
                 trace!("trying bundle {:?} to preg {:?}", bundle, preg);
                 let preg_idx = PRegIndex::new(preg.index());
                 if let AllocRegResult::Allocated(_) =
@@ -56,6 +71,10 @@ impl<'a, F: Function> Env<'a, F> {
             }
 
             if !success {
+                if self.can_spill_without_slot(bundle) {
+                    trace!("spilling bundle {:?}: rematerialized, no slot", bundle);
+                    continue;
+                }
                 trace!(
                     "spilling bundle {:?}: marking spillset {:?} as required",
                     bundle,
@@ -67,6 +86,54 @@ impl<'a, F: Function> Env<'a, F> {
         self.ctx.scratch_conflicts = scratch;
     }
 
+    /// Whether a spilled bundle can do without a spillslot: its value
+    /// is rematerialized wherever it is needed in a register, so the
+    /// slot would only be written, never read. That is the case when
+    /// the bundle has no uses of its own, and its vreg never reads or
+    /// writes the slot otherwise: it must not be passed as a block
+    /// argument, or need a stack location for any of its operands.
+    fn can_spill_without_slot(&self, bundle: LiveBundleIndex) -> bool {
+        let spillset = self.ctx.bundles[bundle].spillset;
+        if !self.ctx.spillsets[spillset].remat {
+            return false;
+        }
+        let ranges = &self.ctx.bundles[bundle].ranges;
+        if ranges
+            .iter()
+            .any(|entry| !self.ctx.ranges[entry.index].uses.is_empty())
+        {
+            return false;
+        }
+        let Some(entry) = ranges.first() else {
+            return false;
+        };
+        let vreg = self.ctx.ranges[entry.index].vreg;
+        if self.ctx.vregs[vreg].blockparam.is_valid() {
+            return false;
+        }
+        let outs = self
+            .ctx
+            .blockparam_outs
+            .partition_point(|out| out.from_vreg < vreg);
+        if self
+            .ctx
+            .blockparam_outs
+            .get(outs)
+            .map_or(false, |out| out.from_vreg == vreg)
+        {
+            return false;
+        }
+        self.ctx.vregs[vreg].ranges.iter().all(|entry| {
+            let range = &self.ctx.ranges[entry.index];
+            self.ctx.bundles[range.bundle].spillset == spillset
+                && range.uses.iter().all(|u| match u.operand.constraint() {
+                    OperandConstraint::Stack | OperandConstraint::FixedStack(_) => false,
+                    OperandConstraint::FixedReg(preg) => !self.ctx.pregs[preg.index()].is_stack,
+                    _ => true,
+                })
+        })
+    }
+
     pub fn spillslot_can_fit_spillset(
         &mut self,
         spillslot: SpillSlotIndex,
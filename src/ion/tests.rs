@@ -0,0 +1,1175 @@
+use crate::checker::Checker;
+use crate::test_function::{op, p, FlatCostModel, RealFunction};
+use crate::OperandConstraint::{self, *};
+use crate::OperandKind::*;
+use crate::{
+    run, run_incremental, run_with_ctx, validate_function, Algorithm, Allocation, Block, Ctx, Edit,
+    Function, Inst, InstRange, InvalidEnv, InvalidOperand, MachineEnv, Operand, OperandGroup,
+    Output, PReg, PRegSet, ProgPoint, RegAllocError, RegClass, RegallocOptions, SpillSlot,
+    StackLoc, VReg,
+};
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[test]
+fn test_wide_regs_do_not_overlap_narrow_regs() {
+    let mach_env = wide_mach_env();
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, WideReg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Def, 2, Reg)],
+        /* 3. */ vec![op(Def, 3, WideReg)],
+        /* 4. */ vec![op(Def, 4, Reg)],
+        /* 5. */
+        vec![
+            op(Use, 0, WideReg),
+            op(Use, 1, Reg),
+            op(Use, 2, Reg),
+            op(Use, 3, WideReg),
+            op(Use, 4, Reg),
+        ],
+    ]);
+    let output = run_and_check(&f, &mach_env);
+    let allocs = output.inst_allocs(Inst::new(5));
+    for (i, a) in allocs.iter().enumerate() {
+        let a = a.as_reg().unwrap();
+        let op = f.inst_operands(Inst::new(5))[i];
+        let is_wide = mach_env.wide_regs().contains(a);
+        assert_eq!(is_wide, op.constraint() == WideReg);
+        for b in &allocs[i + 1..] {
+            assert!(!mach_env.regs_overlap(a, b.as_reg().unwrap()));
+        }
+    }
+}
+
+#[test]
+fn test_wide_regs_under_pressure() {
+    // Three wide and three narrow values live at once need nine units,
+    // but there are only eight: something must be spilled.
+    let mach_env = wide_mach_env();
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, WideReg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Def, 2, WideReg)],
+        /* 3. */ vec![op(Def, 3, Reg)],
+        /* 4. */ vec![op(Def, 4, WideReg)],
+        /* 5. */ vec![op(Def, 5, Reg)],
+        /* 6. */ vec![op(Use, 0, WideReg), op(Use, 1, Reg)],
+        /* 7. */ vec![op(Use, 2, WideReg), op(Use, 3, Reg)],
+        /* 8. */ vec![op(Use, 4, WideReg), op(Use, 5, Reg)],
+        /* 9. */
+        vec![
+            op(Use, 0, Any),
+            op(Use, 1, Any),
+            op(Use, 2, Any),
+            op(Use, 3, Any),
+            op(Use, 4, Any),
+            op(Use, 5, Any),
+        ],
+    ]);
+    run_and_check(&f, &mach_env);
+}
+
+#[test]
+fn test_wide_reg_clobbers_units() {
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, WideReg)],
+        /* 2. */ vec![],
+        /* 3. */ vec![op(Use, 0, Reg), op(Use, 1, WideReg)],
+    ]);
+    // Clobber every wide register, and so every unit too: both values
+    // must survive the clobber on the stack.
+    f.clobbers[2] = mach_env.wide_regs();
+    run_and_check(&f, &mach_env);
+}
+
+#[test]
+fn test_wide_reg_partially_overlapping_moves() {
+    // At inst 2, v0 must move from p9 (p2:p3) to p8 (p0:p1) while v1
+    // moves from p0 to p2: each destination overlaps the other's source.
+    let mach_env = wide_mach_env();
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, FixedReg(p(9)))],
+        /* 1. */ vec![op(Def, 1, FixedReg(p(0)))],
+        /* 2. */ vec![op(Use, 0, FixedReg(p(8))), op(Use, 1, FixedReg(p(2)))],
+        /* 3. */ vec![op(Use, 0, WideReg), op(Use, 1, Reg)],
+    ]);
+    let output = run_and_check(&f, &mach_env);
+    // Neither move can be done directly, so both go through the stack.
+    assert!(output.edits.iter().all(
+        |(_, edit)| matches!(edit, Edit::Move { from, to } if from.is_stack() || to.is_stack())
+    ));
+}
+
+#[test]
+fn test_remat_replaces_reloads() {
+    // Ten values live at once across the narrow registers: some must be
+    // spilled, and the rematerializable ones should never be reloaded.
+    let mach_env = wide_mach_env();
+    let mut insts: Vec<Vec<Operand>> = (0..10).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.extend((0..10).rev().map(|i| vec![op(Use, i, Reg)]));
+    let mut f = RealFunction::single_block(insts);
+    f.remat_vregs = (0..10).collect();
+    let output = run_and_check(&f, &mach_env);
+    assert!(output.stats.remat_count > 0);
+    for (_, edit) in &output.edits {
+        if let Edit::Move { from, to } = edit {
+            assert!(!(from.is_stack() && to.is_reg()));
+        }
+    }
+}
+
+#[test]
+fn test_fixed_stack_locations() {
+    // More stack arguments than registers, one of them also needed in a
+    // register, and a result returned in a stack location.
+    let mach_env = wide_mach_env();
+    let loc = |i| FixedStack(StackLoc::new(i));
+    let mut insts: Vec<Vec<Operand>> = (0..10).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.push((0..10).map(|i| op(Use, i, loc(i))).collect());
+    insts.last_mut().unwrap().push(op(Use, 0, Reg));
+    insts.last_mut().unwrap().push(op(Def, 10, loc(0)));
+    insts.push(vec![op(Use, 10, Reg), op(Use, 5, Reg)]);
+    let f = RealFunction::single_block(insts);
+    let output = run_and_check(&f, &mach_env);
+    let allocs = output.inst_allocs(Inst::new(10));
+    for i in 0..10 {
+        assert_eq!(allocs[i], Allocation::fixed_stack(StackLoc::new(i)));
+    }
+    assert_eq!(allocs[11], Allocation::fixed_stack(StackLoc::new(0)));
+}
+
+#[test]
+fn test_reg_set_constraints() {
+    // Several values need the two-register set {p1, p6}, two of them at
+    // once, while other values are live in other registers.
+    let mut mach_env = wide_mach_env();
+    mach_env.reg_sets = vec![PRegSet::empty().with(p(1)).with(p(6))];
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, RegSet(0))],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Def, 2, Reg)],
+        /* 3. */ vec![op(Def, 3, RegSet(0))],
+        /* 4. */ vec![op(Use, 0, RegSet(0)), op(Use, 3, RegSet(0))],
+        /* 5. */ vec![op(Def, 4, RegSet(0)), op(Use, 1, Reg), op(Use, 2, Reg)],
+        /* 6. */ vec![op(Use, 4, RegSet(0)), op(Use, 0, Reg), op(Use, 3, Any)],
+    ]);
+    let output = run_and_check(&f, &mach_env);
+    for inst in 0..f.num_insts() {
+        let inst = Inst::new(inst);
+        for (op, alloc) in f.inst_operands(inst).iter().zip(output.inst_allocs(inst)) {
+            if op.constraint() == RegSet(0) {
+                assert!(mach_env.reg_sets[0].contains(alloc.as_reg().unwrap()));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "large-pregs")]
+#[test]
+fn test_limit_of_large_register_file() {
+    // Every register is in use at once, by one value limited to all
+    // 256 of them and by 255 values without a limit.
+    let num_regs = PReg::MAX + 1;
+    let mut preferred_regs_by_class = [PRegSet::empty(); RegClass::COUNT];
+    preferred_regs_by_class[RegClass::Int as usize] = (0..num_regs).map(p).collect();
+    let mach_env = MachineEnv {
+        preferred_regs_by_class,
+        non_preferred_regs_by_class: [PRegSet::empty(); RegClass::COUNT],
+        scratch_by_class: [None; RegClass::COUNT],
+        fixed_stack_slots: vec![],
+        reg_units: vec![],
+        reg_sets: vec![],
+    };
+    let last = num_regs - 1;
+    let mut insts: Vec<_> = (0..last).map(|v| vec![op(Def, v, Reg)]).collect();
+    insts.push(vec![op(Def, last, Limit(num_regs))]);
+    insts.push((0..num_regs).map(|v| op(Use, v, Limit(num_regs))).collect());
+    let f = RealFunction::single_block(insts);
+    for output in [
+        run_and_check(&f, &mach_env),
+        run_linear_scan_and_check(&f, &mach_env),
+    ] {
+        assert_eq!(output.num_spillslots, 0);
+        assert!(output
+            .inst_allocs(Inst::new(num_regs))
+            .iter()
+            .all(|a| a.is_reg()));
+    }
+}
+
+#[test]
+fn test_custom_cost_model() {
+    let mach_env = wide_mach_env();
+    let mut insts: Vec<Vec<Operand>> = (0..10).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.extend((0..10).rev().map(|i| vec![op(Use, i, Reg)]));
+    let mut f = RealFunction::single_block(insts);
+    f.cost_model = Some(FlatCostModel::default());
+    run_and_check(&f, &mach_env);
+    assert!(f.cost_model.unwrap().calls.get() > 0);
+}
+
+#[test]
+fn test_block_frequency() {
+    // Every edit is in the only block, so each costs its frequency.
+    let mach_env = wide_mach_env();
+    let mut insts: Vec<Vec<Operand>> = (0..10).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.extend((0..10).rev().map(|i| vec![op(Use, i, Reg)]));
+    let mut f = RealFunction::single_block(insts);
+    let output = run_and_check(&f, &mach_env);
+    assert!(output.stats.edits_count > 0);
+    assert_eq!(output.stats.edits_dynamic_cost, output.stats.edits_count);
+    f.block_frequency = Some(3.0);
+    let output = run_and_check(&f, &mach_env);
+    assert_eq!(
+        output.stats.edits_dynamic_cost,
+        3 * output.stats.edits_count
+    );
+}
+
+#[test]
+fn test_vreg_hints() {
+    // v0 and v1 get their hinted registers; v2's hint is not
+    // allocatable, and v3's is a wide register, so both are ignored.
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Def, 2, Reg)],
+        /* 3. */ vec![op(Def, 3, Reg)],
+        /* 4. */
+        vec![
+            op(Use, 0, Reg),
+            op(Use, 1, Reg),
+            op(Use, 2, Reg),
+            op(Use, 3, Reg),
+        ],
+    ]);
+    f.vreg_hints = vec![(0, p(5)), (1, p(3)), (2, p(40)), (3, p(9))];
+    let output = run_and_check(&f, &mach_env);
+    assert_eq!(output.inst_allocs(Inst::new(0)), [Allocation::reg(p(5))]);
+    assert_eq!(output.inst_allocs(Inst::new(1)), [Allocation::reg(p(3))]);
+    assert_eq!(
+        output.inst_allocs(Inst::new(4))[..2],
+        [Allocation::reg(p(5)), Allocation::reg(p(3))]
+    );
+    assert_eq!(output.stats.hints_satisfied, 4);
+}
+
+#[test]
+fn test_reuse_either() {
+    // v1 dies at inst 2 and v0 doesn't, so v2 takes over v1's register
+    // and no copy is needed for the tie.
+    let mach_env = wide_mach_env();
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */
+        vec![
+            op(Def, 2, ReuseEither(1, 2)),
+            op(Use, 0, Reg),
+            op(Use, 1, Reg),
+        ],
+        /* 3. */ vec![op(Use, 0, Reg), op(Use, 2, Reg)],
+    ]);
+    let output = run_and_check(&f, &mach_env);
+    let allocs = output.inst_allocs(Inst::new(2));
+    assert_eq!(allocs[0], allocs[2]);
+    assert_ne!(allocs[0], allocs[1]);
+    assert!(output.edits.is_empty());
+}
+
+#[test]
+fn test_operand_groups() {
+    // v2 may only share a register with v0, and v3 only with v1, even
+    // though all four are in registers at inst 2 and both inputs die
+    // there.
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */
+        vec![
+            op(Def, 2, Reg),
+            op(Def, 3, Reg),
+            op(Use, 0, Reg),
+            op(Use, 1, Reg),
+        ],
+        /* 3. */ vec![op(Use, 2, Reg), op(Use, 3, Reg)],
+    ]);
+    f.operand_groups = vec![
+        vec![],
+        vec![],
+        vec![OperandGroup::new(&[0], &[2]), OperandGroup::new(&[1], &[3])],
+    ];
+    let output = run_and_check(&f, &mach_env);
+    let allocs = output.inst_allocs(Inst::new(2));
+    assert_ne!(allocs[0], allocs[3]);
+    assert_ne!(allocs[1], allocs[2]);
+}
+
+#[test]
+fn test_linear_scan_reuses_registers() {
+    // Twenty values, but never more than two live at once: each gets a
+    // register as soon as the one before last has ended.
+    let mach_env = wide_mach_env();
+    let mut insts = vec![vec![op(Def, 0, Reg)]];
+    insts.extend((1..20).map(|i| vec![op(Def, i, Reg), op(Use, i - 1, Reg)]));
+    insts.push(vec![op(Use, 19, Reg)]);
+    let f = RealFunction::single_block(insts);
+    let output = run_linear_scan_and_check(&f, &mach_env);
+    assert_eq!(output.num_spillslots, 0);
+    assert!(output.edits.is_empty());
+    assert_eq!(output.stats.evict_bundle_count, 0);
+}
+
+#[test]
+fn test_linear_scan_evicts_rest_of_bundle() {
+    // v0 is live across an instruction that needs all eight narrow
+    // registers. It is used the latest, so it keeps its register up to
+    // there, is spilled around the instruction, and is reloaded for its
+    // last use.
+    let mach_env = wide_mach_env();
+    let mut insts = vec![vec![op(Def, 0, Reg)]];
+    insts.extend((1..9).map(|i| vec![op(Def, i, Reg)]));
+    insts.push((1..9).map(|i| op(Use, i, Reg)).collect());
+    insts.push(vec![op(Use, 0, Reg)]);
+    let f = RealFunction::single_block(insts);
+    let output = run_linear_scan_and_check(&f, &mach_env);
+    let reg = output.inst_allocs(Inst::new(0))[0];
+    assert!(reg.is_reg());
+    let [(_, Edit::Move { from, to: slot }), (_, Edit::Move { from: reload, to })] =
+        output.edits[..]
+    else {
+        panic!("unexpected edits {:?}", output.edits);
+    };
+    assert_eq!(from, reg);
+    assert!(slot.is_stack());
+    assert_eq!(reload, slot);
+    assert!(to.is_reg());
+    assert_eq!(output.inst_allocs(Inst::new(10)), [to]);
+    assert_eq!(output.stats.evict_bundle_count, 1);
+}
+
+#[test]
+fn test_linear_scan_evicts_for_fixed_use() {
+    // v0 is hinted to p0, which v1 then needs at its fixed use. v0
+    // keeps p0 until v1 is defined, then moves to another register
+    // rather than to the stack.
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Use, 1, FixedReg(p(0)))],
+        /* 3. */ vec![op(Use, 0, Reg)],
+    ]);
+    f.vreg_hints = vec![(0, p(0))];
+    let output = run_linear_scan_and_check(&f, &mach_env);
+    assert_eq!(output.inst_allocs(Inst::new(0)), [Allocation::reg(p(0))]);
+    assert_eq!(output.inst_allocs(Inst::new(2)), [Allocation::reg(p(0))]);
+    assert_ne!(output.inst_allocs(Inst::new(3)), [Allocation::reg(p(0))]);
+    assert_eq!(output.num_spillslots, 0);
+}
+
+#[test]
+fn test_fuel() {
+    // With no fuel at all, bundles that don't fit are split into
+    // minimal bundles, and the result is still valid.
+    let mach_env = wide_mach_env();
+    let mut insts: Vec<_> = (0..10).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.extend((0..10).map(|i| vec![op(Use, i, Reg), op(Use, (i + 5) % 10, Reg)]));
+    let f = RealFunction::single_block(insts);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.fuel = Some(0);
+    let output = run(&f, &mach_env, &options).unwrap();
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&output);
+    checker.run().unwrap();
+    assert_eq!(output.stats.fuel_exhausted, 1);
+    assert!(output.stats.fuel_minimal_splits > 0);
+}
+
+#[test]
+fn test_ion_with_fallback() {
+    // With enough fuel, Ion's result is kept; without any under
+    // register pressure, Ion gives up and Fastalloc allocates instead,
+    // in the same `Ctx`.
+    let mach_env = wide_mach_env();
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::IonWithFallback;
+    let mut ctx = Ctx::default();
+    for (num_values, fuel, algorithm) in [(4, 100, Algorithm::Ion), (10, 0, Algorithm::Fastalloc)] {
+        let mut insts: Vec<_> = (0..num_values).map(|i| vec![op(Def, i, Reg)]).collect();
+        insts.extend(
+            (0..num_values).map(|i| vec![op(Use, i, Reg), op(Use, (i + 2) % num_values, Reg)]),
+        );
+        let f = RealFunction::single_block(insts);
+        options.fuel = Some(fuel);
+        let output = run_with_ctx(&f, &mach_env, &options, &mut ctx).unwrap();
+        let mut checker = Checker::new(&f, &mach_env);
+        checker.prepare(output);
+        checker.run().unwrap();
+        assert_eq!(output.algorithm, algorithm);
+    }
+}
+
+#[test]
+fn test_fallback_unsupported() {
+    // Fastalloc doesn't support `WideReg`, so it rejects the function,
+    // and Ion doesn't fall back to it even once out of fuel.
+    let mach_env = wide_mach_env();
+    let mut insts: Vec<_> = (0..10).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.extend((0..10).map(|i| vec![op(Use, i, Reg), op(Use, (i + 2) % 10, Reg)]));
+    insts[0] = vec![op(Def, 0, WideReg)];
+    let f = RealFunction::single_block(insts);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Fastalloc;
+    assert!(matches!(
+        run(&f, &mach_env, &options),
+        Err(RegAllocError::Operand(
+            _,
+            0,
+            InvalidOperand::Unsupported(Algorithm::Fastalloc)
+        ))
+    ));
+    options.algorithm = Algorithm::IonWithFallback;
+    options.fuel = Some(0);
+    let output = run(&f, &mach_env, &options).unwrap();
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&output);
+    checker.run().unwrap();
+    assert_eq!(output.algorithm, Algorithm::Ion);
+}
+
+#[test]
+fn test_too_many_live_regs() {
+    // Nine values used in registers at once, with eight registers.
+    let mach_env = wide_mach_env();
+    let mut insts: Vec<_> = (0..9).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.push((0..9).map(|i| op(Use, i, Reg)).collect());
+    let f = RealFunction::single_block(insts);
+    for algorithm in [Algorithm::Ion, Algorithm::Fastalloc, Algorithm::LinearScan] {
+        let mut options = RegallocOptions::default();
+        options.algorithm = algorithm;
+        let err = run(&f, &mach_env, &options).unwrap_err();
+        let RegAllocError::TooManyLiveRegs {
+            point,
+            class,
+            ref vregs,
+            ..
+        } = err
+        else {
+            panic!("unexpected error {:?}", err);
+        };
+        assert_eq!(point.inst(), Inst::new(9));
+        assert_eq!(class, RegClass::Int);
+        assert!(!vregs.is_empty());
+        assert!(err
+            .to_string()
+            .starts_with("too many live Int registers before inst9"));
+    }
+}
+
+#[test]
+fn test_validate_function() {
+    let mach_env = wide_mach_env();
+    let validate = |insts| {
+        validate_function(
+            &RealFunction::single_block(insts),
+            &mach_env,
+            Algorithm::Ion,
+        )
+    };
+    assert!(validate(vec![
+        vec![op(Def, 0, Reg)],
+        vec![op(Def, 1, Reuse(1)), op(Use, 0, Reg)],
+    ])
+    .is_ok());
+    let invalid = |insts, inst, operand, why| match validate(insts) {
+        Err(RegAllocError::Operand(i, o, w)) => {
+            assert_eq!((i, o, w), (Inst::new(inst), operand, why))
+        }
+        other => panic!("unexpected result {:?}", other),
+    };
+    invalid(
+        vec![vec![op(Def, 0, Reg), op(Def, 1, Reuse(0))]],
+        0,
+        1,
+        InvalidOperand::ReuseIndex(0),
+    );
+    invalid(
+        vec![vec![op(Def, 0, Reg), op(Def, 1, Reuse(2))]],
+        0,
+        1,
+        InvalidOperand::ReuseIndex(2),
+    );
+    invalid(
+        vec![vec![op(Def, 0, Reg)], vec![op(Use, 0, FixedReg(p(20)))]],
+        1,
+        0,
+        InvalidOperand::FixedRegNotInEnv(p(20)),
+    );
+    invalid(
+        vec![vec![op(Def, 0, RegSet(0))]],
+        0,
+        0,
+        InvalidOperand::RegSetIndex(0),
+    );
+    invalid(
+        vec![
+            vec![op(Def, 0, Reg)],
+            vec![
+                op(Def, 1, Reuse(1)),
+                op(Use, 0, FixedStack(StackLoc::new(0))),
+            ],
+        ],
+        1,
+        0,
+        InvalidOperand::ReuseFixedStack(1),
+    );
+    invalid(
+        vec![
+            vec![op(Def, 0, WideReg)],
+            vec![op(Def, 1, Reuse(1)), op(Use, 0, WideReg)],
+        ],
+        1,
+        0,
+        InvalidOperand::ReuseWide(1),
+    );
+    invalid(
+        vec![
+            vec![op(Def, 0, Reg)],
+            vec![op(Def, 1, Reg)],
+            vec![
+                op(Def, 2, ReuseEither(2, 3)),
+                op(Def, 3, ReuseEither(2, 3)),
+                op(Use, 0, Reg),
+                op(Use, 1, Reg),
+            ],
+        ],
+        2,
+        1,
+        InvalidOperand::MultipleReuseEither,
+    );
+
+    // Two uses may share a fixed stack location only if they are of the
+    // same vreg.
+    let loc = StackLoc::new(0);
+    invalid(
+        vec![
+            vec![op(Def, 0, Reg)],
+            vec![op(Def, 1, Reg)],
+            vec![op(Use, 0, FixedStack(loc)), op(Use, 1, FixedStack(loc))],
+        ],
+        2,
+        1,
+        InvalidOperand::FixedStackConflict(loc),
+    );
+    invalid(
+        vec![vec![
+            op(Def, 0, FixedStack(loc)),
+            op(Def, 1, FixedStack(loc)),
+        ]],
+        0,
+        1,
+        InvalidOperand::FixedStackConflict(loc),
+    );
+    assert!(validate(vec![
+        vec![op(Def, 0, Reg)],
+        vec![
+            op(Def, 1, FixedStack(loc)),
+            op(Use, 0, FixedStack(loc)),
+            op(Use, 0, FixedStack(loc)),
+        ],
+    ])
+    .is_ok());
+
+    let float_use = Operand::reg_use(VReg::new(0, RegClass::Float));
+    assert!(matches!(
+        validate(vec![vec![op(Def, 0, Reg)], vec![float_use]]),
+        Err(RegAllocError::ClassMismatch(_, RegClass::Int))
+    ));
+
+    // Fastalloc supports neither wide registers nor limits.
+    let f = RealFunction::single_block(vec![vec![op(Def, 0, WideReg)]]);
+    assert!(validate_function(&f, &mach_env, Algorithm::Ion).is_ok());
+    assert!(matches!(
+        validate_function(&f, &mach_env, Algorithm::Fastalloc),
+        Err(RegAllocError::Operand(
+            _,
+            0,
+            InvalidOperand::Unsupported(Algorithm::Fastalloc)
+        ))
+    ));
+    let f = RealFunction::single_block(vec![vec![op(Def, 0, FixedReg(p(8)))]]);
+    assert!(validate_function(&f, &mach_env, Algorithm::Ion).is_ok());
+    assert!(matches!(
+        validate_function(&f, &mach_env, Algorithm::Fastalloc),
+        Err(RegAllocError::Operand(
+            _,
+            0,
+            InvalidOperand::Unsupported(Algorithm::Fastalloc)
+        ))
+    ));
+
+    // The scratch register can't also be allocatable; and the check is
+    // done by `run` when asked for.
+    let mut mach_env = wide_mach_env();
+    mach_env.scratch_by_class[RegClass::Int as usize] = Some(p(0));
+    let mut options = RegallocOptions::default();
+    options.validate_function = true;
+    let f = RealFunction::single_block(vec![vec![op(Def, 0, Reg)]]);
+    assert!(matches!(
+        run(&f, &mach_env, &options),
+        Err(RegAllocError::Env(InvalidEnv::ScratchRegAllocatable(_)))
+    ));
+
+    // Nor can it be a unit of an allocatable wide register.
+    mach_env.preferred_regs_by_class[RegClass::Int as usize].remove(p(0));
+    assert!(matches!(
+        run(&f, &mach_env, &options),
+        Err(RegAllocError::Env(InvalidEnv::ScratchRegOverlap(preg))) if preg == p(8)
+    ));
+
+    // Wide registers must be allocatable and made of units of their class.
+    let env_error = |mach_env: &MachineEnv| match validate_function(&f, mach_env, Algorithm::Ion) {
+        Err(RegAllocError::Env(why)) => why,
+        other => panic!("unexpected result {:?}", other),
+    };
+    let mut mach_env = wide_mach_env();
+    mach_env
+        .reg_units
+        .push((p(12), PRegSet::empty().with(p(0))));
+    assert_eq!(
+        env_error(&mach_env),
+        InvalidEnv::WideRegNotAllocatable(p(12))
+    );
+    let mut mach_env = wide_mach_env();
+    let float = PReg::new(0, RegClass::Float);
+    mach_env.reg_units[0].1.add(float);
+    assert_eq!(env_error(&mach_env), InvalidEnv::WrongClass(float));
+}
+
+#[test]
+fn test_checker_trace() {
+    // v0's register is overwritten by a move of v1 before v0 is used.
+    let mach_env = wide_mach_env();
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Use, 0, Reg), op(Use, 1, Reg)],
+    ]);
+    let r = |i| Allocation::reg(p(i));
+    let output = Output {
+        allocs: vec![r(0), r(1), r(0), r(1)],
+        inst_alloc_offsets: vec![0, 1, 2, 4],
+        edits: vec![(
+            ProgPoint::before(Inst::new(2)),
+            Edit::Move {
+                from: r(1),
+                to: r(0),
+            },
+        )],
+        ..Output::default()
+    };
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&output);
+    let errors = checker.run().unwrap_err();
+    let traces: Vec<_> = errors.iter().map(|e| e.trace()).collect();
+    assert_eq!(
+        traces,
+        [Some("block0:\n  inst1: def v1 into p1i\n  move p1i -> p0i")]
+    );
+    assert_eq!(
+        errors.to_string(),
+        "inst2: operand (Use: v0i reg) in p0i: expected v0, but it holds v1\n    \
+         block0:\n      inst1: def v1 into p1i\n      move p1i -> p0i"
+    );
+}
+
+#[test]
+fn test_checker_clobbers() {
+    // v0 is left in p0 across inst 1, which clobbers p0, and v1 is
+    // defined into p1, which it also clobbers.
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Use, 0, Reg)],
+    ]);
+    f.clobbers[1] = PRegSet::empty().with(p(0)).with(p(1));
+    let r = |i| Allocation::reg(p(i));
+    let output = Output {
+        allocs: vec![r(0), r(1), r(0)],
+        inst_alloc_offsets: vec![0, 1, 2, 3],
+        ..Output::default()
+    };
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&output);
+    let errors = checker.run().unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "inst1: operand (Def: v1i reg) in p1i: the instruction clobbers p1i\n\
+         inst2: operand (Use: v0i reg) in p0i: expected v0, but inst1 clobbered it\n    \
+         block0:\n      inst1: clobbers p0i"
+    );
+}
+
+#[test]
+fn test_checker_spillslots() {
+    // Every class takes two slots, so an odd slot is misaligned, and
+    // slot 4 is past the end.
+    let mach_env = wide_mach_env();
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg), op(Def, 1, Reg)],
+        /* 1. */ vec![op(Use, 0, Any), op(Use, 1, Any)],
+    ]);
+    let r = |i| Allocation::reg(p(i));
+    let s = |i| Allocation::stack(SpillSlot::new(i));
+    let output = Output {
+        num_spillslots: 4,
+        allocs: vec![r(0), r(1), s(1), s(4)],
+        inst_alloc_offsets: vec![0, 2, 4],
+        edits: vec![
+            (
+                ProgPoint::before(Inst::new(1)),
+                Edit::Move {
+                    from: r(0),
+                    to: s(1),
+                },
+            ),
+            (
+                ProgPoint::before(Inst::new(1)),
+                Edit::Move {
+                    from: r(1),
+                    to: s(4),
+                },
+            ),
+        ],
+        ..Output::default()
+    };
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&output);
+    let errors = checker.run().unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "stack1 at progpoint1-pre is not aligned to the 2 slots of class Int\n\
+         stack4 at progpoint1-pre is outside the 4 spillslots\n\
+         stack1 at progpoint1-pre is not aligned to the 2 slots of class Int\n\
+         stack4 at progpoint1-pre is outside the 4 spillslots"
+    );
+}
+
+#[test]
+fn test_checker_scratch() {
+    // v0 is saved in the scratch register across inst 1.
+    let mut mach_env = wide_mach_env();
+    mach_env.scratch_by_class[RegClass::Int as usize] = Some(p(12));
+    let f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Use, 0, Reg), op(Use, 1, Reg)],
+    ]);
+    let r = |i| Allocation::reg(p(i));
+    let output = Output {
+        allocs: vec![r(0), r(0), r(1), r(0)],
+        inst_alloc_offsets: vec![0, 1, 2, 4],
+        edits: vec![
+            (
+                ProgPoint::before(Inst::new(1)),
+                Edit::Move {
+                    from: r(0),
+                    to: r(12),
+                },
+            ),
+            (
+                ProgPoint::before(Inst::new(2)),
+                Edit::Move {
+                    from: r(12),
+                    to: r(1),
+                },
+            ),
+        ],
+        ..Output::default()
+    };
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(&output);
+    let errors = checker.run().unwrap_err();
+    assert_eq!(
+        errors.to_string(),
+        "scratch register p12i read at progpoint2-pre was written before an instruction"
+    );
+}
+
+#[test]
+fn test_incremental() {
+    // Loosening v1's use at inst 2 only re-allocates the one block,
+    // without falling back to a full allocation.
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Use, 0, Reg), op(Use, 1, FixedReg(p(4)))],
+        /* 3. */ vec![op(Use, 1, Reg)],
+    ]);
+    let previous = run_and_check(&f, &mach_env);
+    f.operands[3] = op(Use, 1, Any);
+    let changed = InstRange::new(Inst::new(2), Inst::new(3));
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    let mut ctx = Ctx::default();
+    let output = run_incremental(&f, &mach_env, &options, &mut ctx, &previous, &[changed]).unwrap();
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(output);
+    checker.run().unwrap();
+    assert_eq!(output.stats.incremental_blocks, 1);
+    assert_eq!(output.stats.incremental_fallbacks, 0);
+}
+
+#[test]
+fn test_incremental_debug_locations() {
+    // v0 stays in p3 when the block is re-allocated, so its debug
+    // location is kept, even within the block.
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, FixedReg(p(3)))],
+        /* 1. */ vec![op(Def, 1, Reg)],
+        /* 2. */ vec![op(Use, 1, Reg)],
+        /* 3. */ vec![op(Use, 0, FixedReg(p(3)))],
+    ]);
+    f.debug_value_labels = vec![(VReg::new(0, RegClass::Int), Inst::new(1), Inst::new(4), 7)];
+    let previous = run_and_check(&f, &mach_env);
+    let location = (
+        7,
+        ProgPoint::before(Inst::new(1)),
+        ProgPoint::after(Inst::new(3)),
+        Allocation::reg(p(3)),
+    );
+    assert_eq!(previous.debug_locations, vec![location]);
+
+    f.operands[2] = op(Use, 1, Any);
+    let changed = InstRange::new(Inst::new(2), Inst::new(3));
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.run_checker = true;
+    let mut ctx = Ctx::default();
+    let output = run_incremental(&f, &mach_env, &options, &mut ctx, &previous, &[changed]).unwrap();
+    assert_eq!(output.stats.incremental_fallbacks, 0);
+    assert_eq!(output.debug_locations, vec![location]);
+}
+
+#[test]
+fn test_incremental_algorithm() {
+    // The output reports the algorithm that re-allocated the changed
+    // block, not the one that produced the previous output.
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Use, 0, Reg)],
+    ]);
+    let mut options = RegallocOptions::default();
+    options.algorithm = Algorithm::Fastalloc;
+    options.run_checker = true;
+    let previous = run(&f, &mach_env, &options).unwrap();
+    assert_eq!(previous.algorithm, Algorithm::Fastalloc);
+
+    f.operands[1] = op(Use, 0, Any);
+    let changed = InstRange::new(Inst::new(1), Inst::new(2));
+    options.algorithm = Algorithm::Ion;
+    let mut ctx = Ctx::default();
+    let output = run_incremental(&f, &mach_env, &options, &mut ctx, &previous, &[changed]).unwrap();
+    assert_eq!(output.algorithm, Algorithm::Ion);
+    assert_eq!(output.stats.incremental_fallbacks, 0);
+}
+
+#[test]
+fn test_run_checker() {
+    let mach_env = wide_mach_env();
+    let mut f = RealFunction::single_block(vec![
+        /* 0. */ vec![op(Def, 0, Reg)],
+        /* 1. */ vec![op(Def, 1, WideReg)],
+        /* 2. */ vec![op(Use, 0, Reg), op(Use, 1, FixedReg(p(9)))],
+        /* 3. */ vec![op(Use, 1, Reg)],
+    ]);
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.run_checker = true;
+    let mut output = run(&f, &mach_env, &options).unwrap();
+
+    // The same goes for incremental allocation.
+    f.operands[3] = op(Use, 1, Any);
+    let changed = InstRange::new(Inst::new(2), Inst::new(3));
+    let mut ctx = Ctx::default();
+    run_incremental(&f, &mach_env, &options, &mut ctx, &output, &[changed]).unwrap();
+
+    // A broken allocation is reported as an error.
+    let offset = output.inst_alloc_offsets[3] as usize;
+    let wrong = if output.allocs[offset] == Allocation::reg(p(10)) {
+        p(11)
+    } else {
+        p(10)
+    };
+    output.allocs[offset] = Allocation::reg(wrong);
+    assert!(matches!(
+        crate::check_output(&f, &mach_env, &output),
+        Err(RegAllocError::Checker(_))
+    ));
+}
+
+#[test]
+fn test_remat_skips_spill_store() {
+    // When every reload of a spilled value is a rematerialization, its
+    // value is never stored and needs no spillslot.
+    let mach_env = wide_mach_env();
+    let mut insts: Vec<Vec<Operand>> = (0..10).map(|i| vec![op(Def, i, Reg)]).collect();
+    insts.extend((0..10).rev().map(|i| vec![op(Use, i, Reg)]));
+    let mut f = RealFunction::single_block(insts);
+    f.remat_vregs = (0..10).collect();
+    let output = run_and_check(&f, &mach_env);
+    assert!(output.stats.remat_count > 0);
+    assert_eq!(output.num_spillslots, 0);
+    for (_, edit) in &output.edits {
+        if let Edit::Move { to, .. } = edit {
+            assert!(!to.is_stack());
+        }
+    }
+}
+
+fn run_and_check(f: &RealFunction, mach_env: &MachineEnv) -> Output {
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::Ion;
+    let output = run(f, mach_env, &options).unwrap();
+    let mut checker = Checker::new(f, mach_env);
+    checker.prepare(&output);
+    checker.run().unwrap();
+    output
+}
+
+fn run_linear_scan_and_check(f: &RealFunction, mach_env: &MachineEnv) -> Output {
+    let mut options = RegallocOptions::default();
+    options.validate_ssa = true;
+    options.algorithm = Algorithm::LinearScan;
+    let output = run(f, mach_env, &options).unwrap();
+    let mut checker = Checker::new(f, mach_env);
+    checker.prepare(&output);
+    checker.run().unwrap();
+    output
+}
+
+/// Eight narrow registers `p0..p7` and four wide registers `p8..p11`,
+/// each made of a pair of narrow ones.
+fn wide_mach_env() -> MachineEnv {
+    let mut preferred_regs_by_class = [PRegSet::empty(); RegClass::COUNT];
+    preferred_regs_by_class[RegClass::Int as usize] =
+        (0..12).map(|no| PReg::new(no, RegClass::Int)).collect();
+    MachineEnv {
+        preferred_regs_by_class,
+        non_preferred_regs_by_class: [PRegSet::empty(); RegClass::COUNT],
+        scratch_by_class: [None; RegClass::COUNT],
+        fixed_stack_slots: vec![],
+        reg_units: (0..4)
+            .map(|i| {
+                (
+                    PReg::new(8 + i, RegClass::Int),
+                    PRegSet::empty()
+                        .with(PReg::new(2 * i, RegClass::Int))
+                        .with(PReg::new(2 * i + 1, RegClass::Int)),
+                )
+            })
+            .collect(),
+        reg_sets: vec![],
+    }
+}
+
+#[test]
+fn test_large_function() {
+    // Tens of thousands of blocks, vregs and instructions, which is
+    // still quick in debug builds.
+    let num_blocks = 1 << 14;
+    let f = LargeFunction::new(num_blocks);
+    let mach_env = wide_mach_env();
+    for algorithm in [Algorithm::Ion, Algorithm::Fastalloc, Algorithm::LinearScan] {
+        let options = RegallocOptions {
+            algorithm,
+            ..RegallocOptions::default()
+        };
+        let output = run(&f, &mach_env, &options).unwrap();
+        assert_eq!(output.inst_alloc_offsets.len(), f.num_insts());
+        for inst in 0..f.num_insts() {
+            let inst = Inst::new(inst);
+            for (op, alloc) in f.inst_operands(inst).iter().zip(output.inst_allocs(inst)) {
+                if op.constraint() == OperandConstraint::Reg {
+                    assert!(alloc.is_reg());
+                } else {
+                    assert!(alloc.is_some());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_incremental_large_function() {
+    extern crate std;
+    use std::time::{Duration, Instant};
+
+    // Changing one block in the middle of a long chain re-allocates
+    // only that block, and takes less time than allocating the whole
+    // function again.
+    let num_blocks = 2000;
+    let mut f = LargeFunction::new(num_blocks);
+    let mach_env = wide_mach_env();
+    let options = RegallocOptions::default();
+    let previous = run(&f, &mach_env, &options).unwrap();
+    let b = num_blocks / 2;
+    let inst = 3 * b + 1;
+    let (start, _) = f.operand_ranges[inst];
+    f.operands[start as usize] = Operand::reg_def(VReg::new(2 * b + 1, RegClass::Int));
+    let changed = InstRange::new(Inst::new(inst), Inst::new(inst + 1));
+
+    let mut ctx = Ctx::default();
+    let mut full = Duration::MAX;
+    let mut incremental = Duration::MAX;
+    for _ in 0..3 {
+        let start = Instant::now();
+        run_with_ctx(&f, &mach_env, &options, &mut ctx).unwrap();
+        full = full.min(start.elapsed());
+        let start = Instant::now();
+        run_incremental(&f, &mach_env, &options, &mut ctx, &previous, &[changed]).unwrap();
+        incremental = incremental.min(start.elapsed());
+    }
+    assert!(
+        incremental < full,
+        "incremental took {:?}, full took {:?}",
+        incremental,
+        full
+    );
+
+    let output = run_incremental(&f, &mach_env, &options, &mut ctx, &previous, &[changed]).unwrap();
+    assert_eq!(output.stats.incremental_blocks, 1);
+    assert_eq!(output.stats.incremental_fallbacks, 0);
+    let mut checker = Checker::new(&f, &mach_env);
+    checker.prepare(output);
+    checker.run().unwrap();
+}
+
+/// A straight-line chain of blocks, each defining two vregs and
+/// passing the second to its successor.
+struct LargeFunction {
+    operands: Vec<Operand>,
+    operand_ranges: Vec<(u32, u32)>,
+    succs: Vec<[Block; 1]>,
+    preds: Vec<[Block; 1]>,
+    num_blocks: usize,
+}
+
+impl LargeFunction {
+    fn new(num_blocks: usize) -> Self {
+        let mut f = LargeFunction {
+            operands: vec![],
+            operand_ranges: vec![],
+            succs: vec![],
+            preds: vec![],
+            num_blocks,
+        };
+        for b in 0..num_blocks {
+            let mut inst = |ops: &[Operand]| {
+                let start = f.operands.len() as u32;
+                f.operands.extend_from_slice(ops);
+                f.operand_ranges.push((start, f.operands.len() as u32));
+            };
+            let v = |i| VReg::new(i, RegClass::Int);
+            if b == 0 {
+                inst(&[Operand::reg_def(v(0))]);
+            } else {
+                inst(&[Operand::reg_def(v(2 * b)), Operand::reg_use(v(2 * b - 1))]);
+            }
+            inst(&[Operand::any_def(v(2 * b + 1)), Operand::reg_use(v(2 * b))]);
+            if b + 1 < num_blocks {
+                inst(&[]);
+            } else {
+                inst(&[Operand::reg_use(v(2 * b + 1))]);
+            }
+            f.succs.push([Block::new(b + 1)]);
+            f.preds.push([Block::new(b.wrapping_sub(1))]);
+        }
+        f
+    }
+}
+
+impl Function for LargeFunction {
+    fn num_insts(&self) -> usize {
+        self.operand_ranges.len()
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    fn entry_block(&self) -> Block {
+        Block::new(0)
+    }
+
+    fn block_insns(&self, block: Block) -> InstRange {
+        InstRange::new(
+            Inst::new(3 * block.index()),
+            Inst::new(3 * block.index() + 3),
+        )
+    }
+
+    fn block_succs(&self, block: Block) -> &[Block] {
+        if block.index() + 1 < self.num_blocks {
+            &self.succs[block.index()]
+        } else {
+            &[]
+        }
+    }
+
+    fn block_preds(&self, block: Block) -> &[Block] {
+        if block.index() > 0 {
+            &self.preds[block.index()]
+        } else {
+            &[]
+        }
+    }
+
+    fn block_params(&self, _block: Block) -> &[VReg] {
+        &[]
+    }
+
+    fn is_ret(&self, insn: Inst) -> bool {
+        insn.index() == self.num_insts() - 1
+    }
+
+    fn is_branch(&self, insn: Inst) -> bool {
+        insn.index() % 3 == 2 && !self.is_ret(insn)
+    }
+
+    fn branch_blockparams(&self, _block: Block, _insn: Inst, _succ_idx: usize) -> &[VReg] {
+        &[]
+    }
+
+    fn inst_operands(&self, insn: Inst) -> &[Operand] {
+        let (start, end) = self.operand_ranges[insn.index()];
+        &self.operands[start as usize..end as usize]
+    }
+
+    fn inst_clobbers(&self, _insn: Inst) -> PRegSet {
+        PRegSet::empty()
+    }
+
+    fn num_vregs(&self) -> usize {
+        2 * self.num_blocks
+    }
+
+    fn spillslot_size(&self, _regclass: RegClass) -> usize {
+        1
+    }
+}
@@ -46,11 +46,14 @@ type FxHashSet<V> = hashbrown::HashSet<V, BuildHasherDefault<FxHasher>>;
 pub(crate) mod cfg;
 pub(crate) mod domtree;
 pub(crate) mod fastalloc;
+pub(crate) mod incremental;
 pub mod indexset;
 pub(crate) mod ion;
 pub(crate) mod moves;
+pub(crate) mod peephole;
 pub(crate) mod postorder;
 pub mod ssa;
+pub(crate) mod validate;
 
 #[macro_use]
 mod index;
@@ -58,9 +61,13 @@ mod index;
 pub use self::ion::data_structures::Ctx;
 use alloc::vec::Vec;
 pub use index::{Block, Inst, InstRange};
+pub use validate::validate_function;
 
 pub mod checker;
 
+#[cfg(test)]
+mod test_function;
+
 #[cfg(feature = "fuzzing")]
 pub mod fuzzing;
 
@@ -77,18 +84,93 @@ use serde::{Deserialize, Serialize};
 /// class; i.e., they are disjoint.
 ///
 /// For tight bit-packing throughout our data structures, we support
-/// only three classes, "int", "float" and "vector". Usually two will
-/// be enough on modern machines, as they have one class of general-purpose
+/// only a small, fixed number of classes. Usually two will be enough
+/// on modern machines, as they have one class of general-purpose
 /// integer registers of machine width (e.g. 64 bits), and another
 /// class of float/vector registers used both for FP and for vector
 /// operations. Additionally for machines with totally separate vector
-/// registers a third class is provided.
+/// registers a third class is provided, and a fourth class with no
+/// predefined meaning, e.g. for predicate or mask registers. The
+/// `more-reg-classes` feature provides four more such classes, for
+/// eight in total, at the cost of one more bit in the packed
+/// encodings of `PReg` and `VReg`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub enum RegClass {
     Int = 0,
     Float = 1,
     Vector = 2,
+    Class3 = 3,
+    #[cfg(feature = "more-reg-classes")]
+    Class4 = 4,
+    #[cfg(feature = "more-reg-classes")]
+    Class5 = 5,
+    #[cfg(feature = "more-reg-classes")]
+    Class6 = 6,
+    #[cfg(feature = "more-reg-classes")]
+    Class7 = 7,
+}
+
+impl RegClass {
+    /// The number of bits used to encode a class.
+    #[cfg(not(feature = "more-reg-classes"))]
+    pub const BITS: usize = 2;
+    #[cfg(feature = "more-reg-classes")]
+    pub const BITS: usize = 3;
+
+    /// The number of register classes.
+    pub const COUNT: usize = 1 << Self::BITS;
+
+    /// All register classes, in index order.
+    #[cfg(not(feature = "more-reg-classes"))]
+    pub const ALL: [RegClass; Self::COUNT] = [
+        RegClass::Int,
+        RegClass::Float,
+        RegClass::Vector,
+        RegClass::Class3,
+    ];
+    #[cfg(feature = "more-reg-classes")]
+    pub const ALL: [RegClass; Self::COUNT] = [
+        RegClass::Int,
+        RegClass::Float,
+        RegClass::Vector,
+        RegClass::Class3,
+        RegClass::Class4,
+        RegClass::Class5,
+        RegClass::Class6,
+        RegClass::Class7,
+    ];
+
+    /// Get the index of this class, in `0..RegClass::COUNT`.
+    #[inline(always)]
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Construct a class from the value returned from `.index()`.
+    #[inline(always)]
+    pub const fn from_index(index: usize) -> Self {
+        Self::ALL[index]
+    }
+
+    /// A short name for the class, used as a suffix when printing
+    /// registers.
+    const fn suffix(self) -> &'static str {
+        match self {
+            RegClass::Int => "i",
+            RegClass::Float => "f",
+            RegClass::Vector => "v",
+            RegClass::Class3 => "c3",
+            #[cfg(feature = "more-reg-classes")]
+            RegClass::Class4 => "c4",
+            #[cfg(feature = "more-reg-classes")]
+            RegClass::Class5 => "c5",
+            #[cfg(feature = "more-reg-classes")]
+            RegClass::Class6 => "c6",
+            #[cfg(feature = "more-reg-classes")]
+            RegClass::Class7 => "c7",
+        }
+    }
 }
 
 /// A physical register. Contains a physical register number and a class.
@@ -98,31 +180,45 @@ pub enum RegClass {
 /// register 0 is different than Float register 0.
 ///
 /// Because of bit-packed encodings throughout the implementation,
-/// `hw_enc` must fit in 6 bits, i.e., at most 64 registers per class.
+/// `hw_enc` must fit in `PReg::MAX_BITS` bits. By default this is 6
+/// bits, i.e., at most 64 registers per class; the `large-pregs`
+/// feature raises this to 8 bits, i.e., 256 registers per class, at
+/// the cost of a larger `PReg` and `PRegSet`.
 ///
 /// The value returned by `index()`, in contrast, is in a single index
 /// space shared by all classes, in order to enable uniform reasoning
-/// about physical registers. This is done by putting the class bit at
-/// the MSB, or equivalently, declaring that indices 0..=63 are the 64
-/// integer registers and indices 64..=127 are the 64 float registers.
+/// about physical registers. This is done by putting the class bits at
+/// the MSB, or equivalently, declaring that (with the default
+/// encoding) indices 0..=63 are the 64 integer registers and indices
+/// 64..=127 are the 64 float registers.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct PReg {
-    bits: u8,
+    bits: PRegBits,
 }
 
+/// The packed representation of a `PReg`.
+#[cfg(not(any(feature = "large-pregs", feature = "more-reg-classes")))]
+type PRegBits = u8;
+#[cfg(any(feature = "large-pregs", feature = "more-reg-classes"))]
+type PRegBits = u16;
+
 impl PReg {
+    #[cfg(not(feature = "large-pregs"))]
     pub const MAX_BITS: usize = 6;
+    #[cfg(feature = "large-pregs")]
+    pub const MAX_BITS: usize = 8;
     pub const MAX: usize = (1 << Self::MAX_BITS) - 1;
-    pub const NUM_INDEX: usize = 1 << (Self::MAX_BITS + 2); // including RegClass bits
-    pub const INVALID: u8 = ((RegClass::Int as u8) << Self::MAX_BITS) | (Self::MAX as u8);
+    pub const NUM_INDEX: usize = 1 << (Self::MAX_BITS + RegClass::BITS); // including RegClass bits
+    pub const INVALID: PRegBits =
+        ((RegClass::Int as PRegBits) << Self::MAX_BITS) | (Self::MAX as PRegBits);
 
-    /// Create a new PReg. The `hw_enc` range is 6 bits.
+    /// Create a new PReg. The `hw_enc` range is `PReg::MAX_BITS` bits.
     #[inline(always)]
     pub const fn new(hw_enc: usize, class: RegClass) -> Self {
         debug_assert!(hw_enc <= PReg::MAX);
         PReg {
-            bits: ((class as u8) << Self::MAX_BITS) | (hw_enc as u8),
+            bits: ((class as PRegBits) << Self::MAX_BITS) | (hw_enc as PRegBits),
         }
     }
 
@@ -135,12 +231,7 @@ impl PReg {
     /// The register class.
     #[inline(always)]
     pub const fn class(self) -> RegClass {
-        match (self.bits >> Self::MAX_BITS) & 0b11 {
-            0 => RegClass::Int,
-            1 => RegClass::Float,
-            2 => RegClass::Vector,
-            _ => unreachable!(),
-        }
+        RegClass::from_index(self.bits as usize >> Self::MAX_BITS)
     }
 
     /// Get an index into the (not necessarily contiguous) index space of
@@ -155,7 +246,7 @@ impl PReg {
     #[inline(always)]
     pub const fn from_index(index: usize) -> Self {
         PReg {
-            bits: (index & (Self::NUM_INDEX - 1)) as u8,
+            bits: (index & (Self::NUM_INDEX - 1)) as PRegBits,
         }
     }
 
@@ -199,12 +290,7 @@ impl core::fmt::Debug for PReg {
 
 impl core::fmt::Display for PReg {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-        let class = match self.class() {
-            RegClass::Int => "i",
-            RegClass::Float => "f",
-            RegClass::Vector => "v",
-        };
-        write!(f, "p{}{}", self.hw_enc(), class)
+        write!(f, "p{}{}", self.hw_enc(), self.class().suffix())
     }
 }
 
@@ -229,6 +315,9 @@ impl PRegSet {
     /// Length of the internal bit array.
     const LEN: usize = (PReg::NUM_INDEX + Self::BITS - 1) / Self::BITS;
 
+    /// Number of elements of the internal bit array covering one class.
+    const CLASS_LEN: usize = Self::LEN / RegClass::COUNT;
+
     /// Create an empty set.
     pub const fn empty() -> Self {
         Self {
@@ -291,7 +380,15 @@ impl PRegSet {
     }
 
     pub fn is_empty(&self, regclass: RegClass) -> bool {
-        self.bits[regclass as usize] == 0
+        let start = regclass as usize * Self::CLASS_LEN;
+        self.bits[start..start + Self::CLASS_LEN]
+            .iter()
+            .all(|&bits| bits == 0)
+    }
+
+    /// Returns whether the set contains no registers of any class.
+    pub fn is_empty_set(&self) -> bool {
+        self.bits.iter().all(|&bits| bits == 0)
     }
 
     /// Returns the number of register in this set.
@@ -426,31 +523,26 @@ pub struct VReg {
 }
 
 impl VReg {
-    pub const MAX_BITS: usize = 30;
+    pub const MAX_BITS: usize = 32 - RegClass::BITS;
     pub const MAX: usize = (1 << Self::MAX_BITS) - 1;
 
     #[inline(always)]
     pub const fn new(virt_reg: usize, class: RegClass) -> Self {
         debug_assert!(virt_reg <= VReg::MAX);
         VReg {
-            bits: ((virt_reg as u32) << 2) | (class as u8 as u32),
+            bits: ((virt_reg as u32) << RegClass::BITS) | (class as u8 as u32),
         }
     }
 
     #[inline(always)]
     pub const fn vreg(self) -> usize {
-        let vreg = (self.bits >> 2) as usize;
+        let vreg = (self.bits >> RegClass::BITS) as usize;
         vreg
     }
 
     #[inline(always)]
     pub const fn class(self) -> RegClass {
-        match self.bits & 0b11 {
-            0 => RegClass::Int,
-            1 => RegClass::Float,
-            2 => RegClass::Vector,
-            _ => unreachable!(),
-        }
+        RegClass::from_index(self.bits as usize & (RegClass::COUNT - 1))
     }
 
     #[inline(always)]
@@ -505,8 +597,9 @@ pub struct SpillSlot {
 }
 
 impl SpillSlot {
-    /// The maximum spillslot index.
-    pub const MAX: usize = (1 << 24) - 1;
+    /// The maximum spillslot index. This is the full width of the
+    /// index field in an `Allocation`.
+    pub const MAX: usize = (1 << 28) - 1;
 
     /// Create a new SpillSlot.
     #[inline(always)]
@@ -518,7 +611,7 @@ impl SpillSlot {
     /// Get the spillslot index for this spillslot.
     #[inline(always)]
     pub fn index(self) -> usize {
-        (self.bits & 0x00ffffff) as usize
+        (self.bits & 0x0fffffff) as usize
     }
 
     /// Get the spillslot `offset` slots away.
@@ -552,6 +645,44 @@ impl core::fmt::Display for SpillSlot {
     }
 }
 
+/// A client-defined location on the stack, such as an incoming or
+/// outgoing stack argument, that an operand can be constrained to
+/// with `OperandConstraint::FixedStack`.
+///
+/// These locations are in a namespace separate from `SpillSlot`s:
+/// the allocator never assigns them on its own, and only moves a
+/// value into or out of one around an instruction that names it. It
+/// is up to the client to map each index to an actual stack offset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct StackLoc {
+    bits: u32,
+}
+
+impl StackLoc {
+    /// The maximum stack location index.
+    pub const MAX: usize = (1 << 20) - 1;
+
+    /// Create a new StackLoc.
+    #[inline(always)]
+    pub fn new(index: usize) -> Self {
+        debug_assert!(index <= Self::MAX);
+        StackLoc { bits: index as u32 }
+    }
+
+    /// Get the index of this stack location.
+    #[inline(always)]
+    pub fn index(self) -> usize {
+        self.bits as usize
+    }
+}
+
+impl core::fmt::Display for StackLoc {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "fstack{}", self.index())
+    }
+}
+
 /// An `OperandConstraint` specifies where a vreg's value must be
 /// placed at a particular reference to that vreg via an
 /// `Operand`. The constraint may be loose -- "any register of a given
@@ -582,6 +713,43 @@ pub enum OperandConstraint {
     /// register between `0 ..= n-1`. Due to encoding constraints, `n` must be a
     /// power of two and below 2^16.
     Limit(usize),
+    /// Operand must be in a wide register: one that is made up of
+    /// several register units, as described by
+    /// `MachineEnv::reg_units` (e.g. an even/odd register pair).
+    ///
+    /// A vreg that is named by any `WideReg` operand is wide for its
+    /// whole lifetime: it is only ever assigned wide registers (or
+    /// spillslots), and it is never assigned a register that is not
+    /// wide.
+    WideReg,
+    /// Operand must be in a specific client-defined stack location.
+    ///
+    /// The allocator copies the value into the location just before
+    /// the instruction (for uses) or out of it just after the
+    /// instruction (for defs). The same location may not be named by
+    /// two different vregs' uses, or two defs, in one instruction,
+    /// and a use with this constraint may not be the input of a
+    /// `Reuse` constraint.
+    FixedStack(StackLoc),
+    /// Operand must be in one of a set of registers.
+    ///
+    /// The contained `usize` is an index into `MachineEnv::reg_sets`,
+    /// which holds the allowed registers; all of them must be
+    /// allocatable registers of the operand's class. If the same vreg
+    /// is used with several such constraints at one program point,
+    /// their sets must have a register in common.
+    RegSet(usize),
+    /// On defs only: reuse the register of either of two uses, e.g.
+    /// for the inputs of a commutative two-address instruction.
+    ///
+    /// Both uses must be valid inputs for a `Reuse` constraint. The
+    /// allocator picks one, preferring an input that dies at the
+    /// instruction, and the def is allocated the same register as that
+    /// input (and not as the other one, unless both are the same
+    /// vreg), so the client can tell which one it picked by comparing
+    /// their allocations. An instruction may have at most one def with
+    /// this constraint.
+    ReuseEither(usize, usize),
 }
 
 impl core::fmt::Display for OperandConstraint {
@@ -593,6 +761,10 @@ impl core::fmt::Display for OperandConstraint {
             Self::FixedReg(preg) => write!(f, "fixed({preg})"),
             Self::Reuse(idx) => write!(f, "reuse({idx})"),
             Self::Limit(max) => write!(f, "limit(0..={})", max - 1),
+            Self::WideReg => write!(f, "wide"),
+            Self::FixedStack(loc) => write!(f, "fixed({loc})"),
+            Self::RegSet(idx) => write!(f, "regset({idx})"),
+            Self::ReuseEither(a, b) => write!(f, "reuse({a}|{b})"),
         }
     }
 }
@@ -658,11 +830,17 @@ pub enum OperandPos {
 pub struct Operand {
     /// Bit-pack into 64 bits.
     ///
-    /// unused:21 constraint:7 kind:1 pos:1 class:2 vreg:32
+    /// unused:3 payload:20 constraint:7 kind:1 pos:1 class:c vreg:v
     ///
     /// where `constraint` is an `OperandConstraint`, `kind` is an
     /// `OperandKind`, `pos` is an `OperandPos`, `class` is a
-    /// `RegClass`, and `vreg` is a vreg index.
+    /// `RegClass` of `c = RegClass::BITS` bits, `vreg` is a vreg index
+    /// of `v = VReg::MAX_BITS` bits (so that `c + v = 32`), and
+    /// `payload` holds the constraint's data that doesn't fit in
+    /// `constraint` (zero otherwise): the `StackLoc` index of a
+    /// `FixedStack` constraint, the set index of a `RegSet` constraint,
+    /// the two input indices of a `ReuseEither` constraint (five bits
+    /// each), or the `hw_enc` bits above the low six of a `FixedReg`.
     ///
     /// The constraints are encoded as follows:
     /// - 1xxxxxx => FixedReg(preg)
@@ -671,16 +849,20 @@ pub struct Operand {
     /// - 0000000 => Any
     /// - 0000001 => Reg
     /// - 0000010 => Stack
+    /// - 0000011 => WideReg
+    /// - 0000100 => FixedStack(stack_loc)
+    /// - 0000101 => RegSet(index)
+    /// - 0000110 => ReuseEither(index, index)
     /// - _ => Unused for now
     bits: u64,
 }
 
 impl Operand {
-    const VREG_BITS: usize = 32;
+    const VREG_BITS: usize = VReg::MAX_BITS;
     const VREG_SHIFT: usize = 0;
     const VREG_MASK: u64 = (1 << Self::VREG_BITS) - 1;
 
-    const CLASS_BITS: usize = 2;
+    const CLASS_BITS: usize = RegClass::BITS;
     const CLASS_SHIFT: usize = Self::VREG_SHIFT + Self::VREG_BITS;
     const CLASS_MASK: u64 = (1 << Self::CLASS_BITS) - 1;
 
@@ -696,7 +878,11 @@ impl Operand {
     const CONSTRAINT_SHIFT: usize = Self::KIND_SHIFT + Self::KIND_BITS;
     const CONSTRAINT_MASK: u64 = (1 << Self::CONSTRAINT_BITS) - 1;
 
-    const TOTAL_BITS: usize = Self::CONSTRAINT_SHIFT + Self::CONSTRAINT_BITS;
+    const PAYLOAD_BITS: usize = 20;
+    const PAYLOAD_SHIFT: usize = Self::CONSTRAINT_SHIFT + Self::CONSTRAINT_BITS;
+    const PAYLOAD_MASK: u64 = (1 << Self::PAYLOAD_BITS) - 1;
+
+    const TOTAL_BITS: usize = Self::PAYLOAD_SHIFT + Self::PAYLOAD_BITS;
 
     /// Construct a new operand.
     #[inline(always)]
@@ -706,18 +892,38 @@ impl Operand {
         kind: OperandKind,
         pos: OperandPos,
     ) -> Self {
+        let mut payload_field = 0;
         let constraint_field = match constraint {
             OperandConstraint::Any => 0,
             OperandConstraint::Reg => 1,
             OperandConstraint::Stack => 2,
+            OperandConstraint::WideReg => 3,
+            OperandConstraint::FixedStack(loc) => {
+                payload_field = loc.index() as u64;
+                4
+            }
+            OperandConstraint::RegSet(index) => {
+                assert!(
+                    index as u64 <= Self::PAYLOAD_MASK,
+                    "register set index is too large"
+                );
+                payload_field = index as u64;
+                5
+            }
             OperandConstraint::FixedReg(preg) => {
                 debug_assert_eq!(preg.class(), vreg.class());
-                0b1000000 | preg.hw_enc() as u64
+                payload_field = (preg.hw_enc() >> 6) as u64;
+                0b1000000 | (preg.hw_enc() & 0b111111) as u64
             }
             OperandConstraint::Reuse(which) => {
                 debug_assert!(which <= 0b11111);
                 0b0100000 | which as u64
             }
+            OperandConstraint::ReuseEither(a, b) => {
+                debug_assert!(a <= 0b11111 && b <= 0b11111);
+                payload_field = (a | (b << 5)) as u64;
+                6
+            }
             OperandConstraint::Limit(max) => {
                 assert!(max.is_power_of_two());
                 assert!(
@@ -737,7 +943,8 @@ impl Operand {
                 | (class_field << Self::CLASS_SHIFT)
                 | (pos_field << Self::POS_SHIFT)
                 | (kind_field << Self::KIND_SHIFT)
-                | (constraint_field << Self::CONSTRAINT_SHIFT),
+                | (constraint_field << Self::CONSTRAINT_SHIFT)
+                | (payload_field << Self::PAYLOAD_SHIFT),
         }
     }
 
@@ -842,6 +1049,48 @@ impl Operand {
         )
     }
 
+    /// Create an `Operand` that designates a def of a vreg that must
+    /// reuse the register assigned to either the `a`th or the `b`th
+    /// input to the instruction, as the allocator sees fit (see
+    /// `OperandConstraint::ReuseEither`). Both inputs must be
+    /// constrained to a register.
+    #[inline(always)]
+    pub fn reg_reuse_either_def(vreg: VReg, a: usize, b: usize) -> Self {
+        Operand::new(
+            vreg,
+            OperandConstraint::ReuseEither(a, b),
+            OperandKind::Def,
+            OperandPos::Late,
+        )
+    }
+
+    /// Create an `Operand` that designates a use of a VReg that must
+    /// be in a wide register (see `OperandConstraint::WideReg`), and
+    /// that is used at the "before" point.
+    #[inline(always)]
+    pub fn reg_wide_use(vreg: VReg) -> Self {
+        Operand::new(
+            vreg,
+            OperandConstraint::WideReg,
+            OperandKind::Use,
+            OperandPos::Early,
+        )
+    }
+
+    /// Create an `Operand` that designates a definition of a VReg
+    /// that must be in a wide register (see
+    /// `OperandConstraint::WideReg`), and that occurs at the "after"
+    /// point.
+    #[inline(always)]
+    pub fn reg_wide_def(vreg: VReg) -> Self {
+        Operand::new(
+            vreg,
+            OperandConstraint::WideReg,
+            OperandKind::Def,
+            OperandPos::Late,
+        )
+    }
+
     /// Create an `Operand` that designates a use of a vreg and
     /// ensures that it is placed in the given, fixed PReg at the
     /// use. It is guaranteed that the `Allocation` resulting for this
@@ -892,6 +1141,32 @@ impl Operand {
         )
     }
 
+    /// Create an `Operand` that designates a use of a vreg that must
+    /// be in one of the registers of `MachineEnv::reg_sets[set]`, and
+    /// that is used at the "before" point.
+    #[inline(always)]
+    pub fn reg_set_use(vreg: VReg, set: usize) -> Self {
+        Operand::new(
+            vreg,
+            OperandConstraint::RegSet(set),
+            OperandKind::Use,
+            OperandPos::Early,
+        )
+    }
+
+    /// Create an `Operand` that designates a def of a vreg that must
+    /// be in one of the registers of `MachineEnv::reg_sets[set]`, and
+    /// that occurs at the "after" point.
+    #[inline(always)]
+    pub fn reg_set_def(vreg: VReg, set: usize) -> Self {
+        Operand::new(
+            vreg,
+            OperandConstraint::RegSet(set),
+            OperandKind::Def,
+            OperandPos::Late,
+        )
+    }
+
     /// Create an `Operand` that designates a use of a vreg and places
     /// no constraints on its location (i.e., it can be allocated into
     /// either a register or on the stack).
@@ -918,6 +1193,34 @@ impl Operand {
         )
     }
 
+    /// Create an `Operand` that designates a use of a vreg and
+    /// ensures that it is placed in the given client-defined stack
+    /// location at the use. It is guaranteed that the `Allocation`
+    /// resulting for this operand will be `loc`.
+    #[inline(always)]
+    pub fn stack_fixed_use(vreg: VReg, loc: StackLoc) -> Self {
+        Operand::new(
+            vreg,
+            OperandConstraint::FixedStack(loc),
+            OperandKind::Use,
+            OperandPos::Early,
+        )
+    }
+
+    /// Create an `Operand` that designates a def of a vreg and
+    /// ensures that it is placed in the given client-defined stack
+    /// location at the def. It is guaranteed that the `Allocation`
+    /// resulting for this operand will be `loc`.
+    #[inline(always)]
+    pub fn stack_fixed_def(vreg: VReg, loc: StackLoc) -> Self {
+        Operand::new(
+            vreg,
+            OperandConstraint::FixedStack(loc),
+            OperandKind::Def,
+            OperandPos::Late,
+        )
+    }
+
     /// Create an `Operand` that always results in an assignment to the
     /// given fixed `preg`, *without* tracking liveranges in that
     /// `preg`. Must only be used for non-allocatable registers.
@@ -945,12 +1248,7 @@ impl Operand {
     #[inline(always)]
     pub fn class(self) -> RegClass {
         let class_field = (self.bits >> Self::CLASS_SHIFT) & Self::CLASS_MASK;
-        match class_field {
-            0 => RegClass::Int,
-            1 => RegClass::Float,
-            2 => RegClass::Vector,
-            _ => unreachable!(),
-        }
+        RegClass::from_index(class_field as usize)
     }
 
     /// Get the "kind" of this operand: a definition (write) or a use
@@ -985,8 +1283,10 @@ impl Operand {
     pub fn constraint(self) -> OperandConstraint {
         let constraint_field =
             ((self.bits >> Self::CONSTRAINT_SHIFT) & Self::CONSTRAINT_MASK) as usize;
+        let payload_field = ((self.bits >> Self::PAYLOAD_SHIFT) & Self::PAYLOAD_MASK) as usize;
         if constraint_field & 0b1000000 != 0 {
-            OperandConstraint::FixedReg(PReg::new(constraint_field & 0b0111111, self.class()))
+            let hw_enc = (payload_field << 6) | (constraint_field & 0b0111111);
+            OperandConstraint::FixedReg(PReg::new(hw_enc, self.class()))
         } else if constraint_field & 0b0100000 != 0 {
             OperandConstraint::Reuse(constraint_field & 0b0011111)
         } else if constraint_field & 0b0010000 != 0 {
@@ -996,6 +1296,10 @@ impl Operand {
                 0 => OperandConstraint::Any,
                 1 => OperandConstraint::Reg,
                 2 => OperandConstraint::Stack,
+                3 => OperandConstraint::WideReg,
+                4 => OperandConstraint::FixedStack(StackLoc::new(payload_field)),
+                5 => OperandConstraint::RegSet(payload_field),
+                6 => OperandConstraint::ReuseEither(payload_field & 0b11111, payload_field >> 5),
                 _ => unreachable!(),
             }
         }
@@ -1050,16 +1354,102 @@ impl core::fmt::Display for Operand {
             f,
             ": {}{} {}",
             self.vreg(),
-            match self.class() {
-                RegClass::Int => "i",
-                RegClass::Float => "f",
-                RegClass::Vector => "v",
-            },
+            self.class().suffix(),
             self.constraint()
         )
     }
 }
 
+/// A group of operands of one instruction: the defs in the group may
+/// be allocated the same register as the uses in the group.
+///
+/// Normally a `Late` def may share a register with any `Early` use,
+/// as the instruction reads all of its inputs before writing any
+/// output. An instruction with several results may write some of them
+/// before it has read all of its inputs; rather than making such a
+/// def `Early`, which keeps it apart from every use, the client can
+/// name the uses it may share a register with in a group (see
+/// `Function::inst_operand_groups`). A def that appears in any group
+/// is kept apart from every `Early` use of the instruction that is not
+/// in one of its groups.
+///
+/// Only `Late` defs without a `Reuse` or `ReuseEither` constraint are
+/// affected: other defs are already kept apart from, or tied to, the
+/// uses. Operand indices must be below 64, and a use that is kept
+/// apart from a def must not be constrained to the same fixed register
+/// as any `Late` def of the instruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct OperandGroup {
+    defs: u64,
+    uses: u64,
+}
+
+impl OperandGroup {
+    /// Create a group from the indices of its defs and uses in the
+    /// instruction's operand list.
+    pub fn new(defs: &[usize], uses: &[usize]) -> Self {
+        let mask = |indices: &[usize]| {
+            indices.iter().fold(0u64, |mask, &idx| {
+                debug_assert!(idx < 64);
+                mask | (1 << idx)
+            })
+        };
+        OperandGroup {
+            defs: mask(defs),
+            uses: mask(uses),
+        }
+    }
+
+    /// Is the operand at `idx` one of the defs in this group?
+    #[inline(always)]
+    pub fn contains_def(&self, idx: usize) -> bool {
+        idx < 64 && self.defs & (1 << idx) != 0
+    }
+
+    /// Is the operand at `idx` one of the uses in this group?
+    #[inline(always)]
+    pub fn contains_use(&self, idx: usize) -> bool {
+        idx < 64 && self.uses & (1 << idx) != 0
+    }
+
+    /// Must the def at index `def` be kept apart from the use at index
+    /// `use_idx`, given the instruction's `groups`?
+    pub(crate) fn separates(
+        groups: &[OperandGroup],
+        operands: &[Operand],
+        def: usize,
+        use_idx: usize,
+    ) -> bool {
+        let (d, u) = (operands[def], operands[use_idx]);
+        let grouped_def = d.kind() == OperandKind::Def
+            && d.pos() == OperandPos::Late
+            && !matches!(
+                d.constraint(),
+                OperandConstraint::Reuse(_) | OperandConstraint::ReuseEither(..)
+            )
+            && groups.iter().any(|g| g.contains_def(def));
+        grouped_def
+            && u.kind() == OperandKind::Use
+            && u.pos() == OperandPos::Early
+            && u.as_fixed_nonallocatable().is_none()
+            && !groups
+                .iter()
+                .any(|g| g.contains_def(def) && g.contains_use(use_idx))
+    }
+
+    /// Must some def of the instruction be kept apart from the use at
+    /// index `use_idx`, given the instruction's `groups`?
+    pub(crate) fn separates_use(
+        groups: &[OperandGroup],
+        operands: &[Operand],
+        use_idx: usize,
+    ) -> bool {
+        !groups.is_empty()
+            && (0..operands.len()).any(|def| Self::separates(groups, operands, def, use_idx))
+    }
+}
+
 /// An Allocation represents the end result of regalloc for an
 /// Operand.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -1083,6 +1473,7 @@ impl core::fmt::Display for Allocation {
             AllocationKind::None => write!(f, "none"),
             AllocationKind::Reg => write!(f, "{}", self.as_reg().unwrap()),
             AllocationKind::Stack => write!(f, "{}", self.as_stack().unwrap()),
+            AllocationKind::FixedStack => write!(f, "{}", self.as_fixed_stack().unwrap()),
         }
     }
 }
@@ -1116,13 +1507,21 @@ impl Allocation {
         Allocation::new(AllocationKind::Stack, slot.bits as usize)
     }
 
-    /// Get the allocation's "kind": none, register, or stack (spillslot).
+    /// Create an allocation into a client-defined stack location.
+    #[inline(always)]
+    pub fn fixed_stack(loc: StackLoc) -> Allocation {
+        Allocation::new(AllocationKind::FixedStack, loc.index())
+    }
+
+    /// Get the allocation's "kind": none, register, stack (spillslot),
+    /// or fixed stack location.
     #[inline(always)]
     pub fn kind(self) -> AllocationKind {
         match (self.bits >> 29) & 7 {
             0 => AllocationKind::None,
             1 => AllocationKind::Reg,
             2 => AllocationKind::Stack,
+            3 => AllocationKind::FixedStack,
             _ => unreachable!(),
         }
     }
@@ -1151,6 +1550,12 @@ impl Allocation {
         self.kind() == AllocationKind::Stack
     }
 
+    /// Is the allocation a client-defined stack location?
+    #[inline(always)]
+    pub fn is_fixed_stack(self) -> bool {
+        self.kind() == AllocationKind::FixedStack
+    }
+
     /// Get the index of the spillslot or register. If register, this
     /// is an index that can be used by `PReg::from_index()`.
     #[inline(always)]
@@ -1180,6 +1585,16 @@ impl Allocation {
         }
     }
 
+    /// Get the allocation as a client-defined stack location, if any.
+    #[inline(always)]
+    pub fn as_fixed_stack(self) -> Option<StackLoc> {
+        if self.kind() == AllocationKind::FixedStack {
+            Some(StackLoc::new(self.index()))
+        } else {
+            None
+        }
+    }
+
     /// Get the raw bits for the packed encoding of this allocation.
     #[inline(always)]
     pub fn bits(self) -> u32 {
@@ -1194,8 +1609,8 @@ impl Allocation {
     }
 }
 
-/// An allocation is one of two "kinds" (or "none"): register or
-/// spillslot/stack.
+/// An allocation is one of three "kinds" (or "none"): register,
+/// spillslot/stack, or client-defined stack location.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u8)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
@@ -1203,6 +1618,7 @@ pub enum AllocationKind {
     None = 0,
     Reg = 1,
     Stack = 2,
+    FixedStack = 3,
 }
 
 /// A trait defined by the regalloc client to provide access to its
@@ -1257,8 +1673,21 @@ pub trait Function {
     // --------------------------
 
     /// Get the Operands for an instruction.
+    ///
+    /// Unlike the numbers of vregs, blocks and instructions, which are
+    /// only limited by their 32-bit indices, the number of operands of
+    /// a single instruction is limited to 2^16: `Ion` and `LinearScan`
+    /// keep each operand's index in 16 bits to keep their per-use data
+    /// small, and fail with `RegAllocError::TooManyOperands` otherwise.
     fn inst_operands(&self, insn: Inst) -> &[Operand];
 
+    /// Get the operand groups of an instruction, which state which of
+    /// its defs may share a register with which of its uses (see
+    /// `OperandGroup`). Most instructions don't need any.
+    fn inst_operand_groups(&self, _insn: Inst) -> &[OperandGroup] {
+        &[]
+    }
+
     /// Get the clobbers for an instruction; these are the registers
     /// that, after the instruction has executed, hold values that are
     /// arbitrary, separately from the usual outputs to the
@@ -1335,6 +1764,56 @@ pub trait Function {
         false
     }
 
+    /// Can the value of the given vreg be recomputed, rather than
+    /// reloaded from its spillslot, wherever it is live? This is
+    /// typically true for constants and for addresses computed from
+    /// registers that are always available, and must only be true for
+    /// vregs defined by an instruction (not block parameters).
+    ///
+    /// If so, the allocator may emit an `Edit::Remat` in place of a
+    /// reload `Edit::Move`, leaving out the spill store (and the
+    /// spillslot) when nothing else reads the value from the stack, and
+    /// will prefer to spill such vregs over others (see
+    /// `CostModel::remat_spill_weight`).
+    fn is_rematerializable(&self, _vreg: VReg) -> bool {
+        false
+    }
+
+    /// A register the client would like `vreg` to be allocated to,
+    /// e.g. because that avoids a move around a call or a return.
+    /// Unlike a `FixedReg` constraint, this is only a preference: the
+    /// allocator tries the hinted register first, but is free to pick
+    /// another one (or to spill) instead.
+    ///
+    /// Hints of another class than `vreg`, or naming registers that
+    /// are not allocatable, are ignored.
+    fn vreg_hint(&self, _vreg: VReg) -> Option<PReg> {
+        None
+    }
+
+    // ----------
+    // Cost model
+    // ----------
+
+    /// How often `block` is expected to run, relative to the other
+    /// blocks of the function, e.g. from profile data. The allocator
+    /// prefers to place splits and moves in blocks that run less often.
+    ///
+    /// If `None` (the default), the frequency is estimated from the
+    /// block's loop depth: each level of loop nesting multiplies it by
+    /// four, up to a depth of ten.
+    fn block_frequency(&self, _block: Block) -> Option<f32> {
+        None
+    }
+
+    /// The cost model that guides the Ion allocator's choice of which
+    /// values to keep in registers. The default estimates how often
+    /// each block runs from its loop depth; clients with profile data
+    /// can provide their own.
+    fn cost_model(&self) -> &dyn CostModel {
+        &DefaultCostModel
+    }
+
     // -----------
     // Misc config
     // -----------
@@ -1354,6 +1833,77 @@ pub trait Function {
     }
 }
 
+/// The costs that the Ion allocator weighs when choosing which values
+/// to spill and in what order to allocate them. See
+/// `Function::cost_model`.
+pub trait CostModel {
+    /// The spill weight of an operand: how costly it is for its value
+    /// not to be in a register at that point. Values with the greatest
+    /// total weight per instruction are the last to be spilled.
+    ///
+    /// `block` is the block of the operand's instruction, and
+    /// `frequency` is that block's frequency (see
+    /// `Function::block_frequency`). `is_def` is true for defs, which
+    /// need a register to be written into before any spill.
+    fn operand_weight(
+        &self,
+        block: Block,
+        frequency: f32,
+        constraint: OperandConstraint,
+        is_def: bool,
+    ) -> f32;
+
+    /// The contribution to a bundle's allocation priority of one of its
+    /// live ranges, from `from` up to (not including) `to`. Bundles
+    /// are allocated in order of decreasing total priority.
+    fn range_priority(&self, from: ProgPoint, to: ProgPoint) -> u32;
+
+    /// The spill weight of a bundle whose value can be rematerialized
+    /// (see `Function::is_rematerializable`), given the weight it
+    /// would have if spilling it meant a store and reloads.
+    ///
+    /// The default halves it: rematerializing typically re-executes
+    /// one cheap instruction in place of each reload, which costs no
+    /// memory access, and there is no store, so such a value is the
+    /// first to give up its register.
+    fn remat_spill_weight(&self, weight: u32) -> u32 {
+        weight / 2
+    }
+}
+
+/// The default cost model, which weighs operands by the frequency of
+/// their block and prioritizes bundles by the number of instructions
+/// they cover.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultCostModel;
+
+impl CostModel for DefaultCostModel {
+    fn operand_weight(
+        &self,
+        _block: Block,
+        frequency: f32,
+        constraint: OperandConstraint,
+        is_def: bool,
+    ) -> f32 {
+        // With estimated frequencies, a bonus of 1000 outside loops,
+        // 4000 for one loop level, 16000 for two loop levels, etc.
+        let hot_bonus: f32 = 1000.0 * frequency;
+        let def_bonus: f32 = if is_def { 2000.0 } else { 0.0 };
+        let constraint_bonus: f32 = match constraint {
+            OperandConstraint::Any => 1000.0,
+            OperandConstraint::Reg
+            | OperandConstraint::WideReg
+            | OperandConstraint::FixedReg(_) => 2000.0,
+            _ => 0.0,
+        };
+        hot_bonus + def_bonus + constraint_bonus
+    }
+
+    fn range_priority(&self, from: ProgPoint, to: ProgPoint) -> u32 {
+        (to.inst().index() - from.inst().index()) as u32
+    }
+}
+
 /// A position before or after an instruction at which we can make an
 /// edit.
 ///
@@ -1480,6 +2030,12 @@ pub enum Edit {
     /// are the same if the vreg changes; this allows proper metadata
     /// tracking even when moves are elided.
     Move { from: Allocation, to: Allocation },
+
+    /// Recompute the value of `vreg` into the register `to`, e.g. by
+    /// re-executing the instruction that defined it. Only generated
+    /// for vregs for which `Function::is_rematerializable` returns
+    /// true, in place of a reload from a spillslot.
+    Remat { vreg: VReg, to: Allocation },
 }
 
 /// Wrapper around either an original instruction or an inserted edit.
@@ -1534,7 +2090,7 @@ pub struct MachineEnv {
     ///
     /// If an explicit scratch register is provided in `scratch_by_class` then
     /// it must not appear in this list.
-    pub preferred_regs_by_class: [PRegSet; 3],
+    pub preferred_regs_by_class: [PRegSet; RegClass::COUNT],
 
     /// Non-preferred physical registers for each class. These are the
     /// registers that will be allocated if a preferred register is
@@ -1543,7 +2099,7 @@ pub struct MachineEnv {
     ///
     /// If an explicit scratch register is provided in `scratch_by_class` then
     /// it must not appear in this list.
-    pub non_preferred_regs_by_class: [PRegSet; 3],
+    pub non_preferred_regs_by_class: [PRegSet; RegClass::COUNT],
 
     /// Optional dedicated scratch register per class. This is needed to perform
     /// moves between registers when cyclic move patterns occur. The
@@ -1560,7 +2116,7 @@ pub struct MachineEnv {
     /// If a scratch register is not provided then the register allocator will
     /// automatically allocate one as needed, spilling a value to the stack if
     /// necessary.
-    pub scratch_by_class: [Option<PReg>; 3],
+    pub scratch_by_class: [Option<PReg>; RegClass::COUNT],
 
     /// Some `PReg`s can be designated as locations on the stack rather than
     /// actual registers. These can be used to tell the register allocator about
@@ -1568,7 +2124,62 @@ pub struct MachineEnv {
     ///
     /// `PReg`s in this list cannot be used as an allocatable or scratch
     /// register.
+    ///
+    /// Each of these uses up a `PReg` encoding in its class; for larger
+    /// numbers of stack locations, prefer `OperandConstraint::FixedStack`.
     pub fixed_stack_slots: Vec<PReg>,
+
+    /// Registers that overlap other registers of the same class. Each
+    /// entry names a wide register and the set of "register units" it
+    /// is made up of: e.g. an ARM32 D-register and the two
+    /// S-registers it aliases, or an RV32 even/odd GPR pair and its
+    /// two halves. Registers not listed here are their own single
+    /// unit. Two registers overlap if they share any unit.
+    ///
+    /// Wide registers must also be listed as preferred or
+    /// non-preferred registers, and are only assigned to vregs that
+    /// require them (see `OperandConstraint::WideReg`). The spillslot
+    /// size of the class must be large enough to hold a wide value,
+    /// and a wide register must be able to carry a value of its class
+    /// that is not wide, as the allocator may use one as a scratch
+    /// register when resolving moves. Registers that overlap a
+    /// dedicated scratch register must not be allocatable, and vregs
+    /// connected by a `Reuse` constraint or a block parameter must
+    /// agree on whether they are wide.
+    ///
+    /// Wide registers are not yet supported by the fastalloc
+    /// algorithm, which only allocates the remaining registers and
+    /// rejects `WideReg` operands, and fixed constraints that name a
+    /// wide register, with `InvalidOperand::Unsupported`.
+    pub reg_units: Vec<(PReg, PRegSet)>,
+
+    /// Register sets named by `OperandConstraint::RegSet` operands,
+    /// e.g. the registers encodable in a compressed instruction. Each
+    /// operand names its set by index into this table, which keeps
+    /// the set itself out of the `Operand` encoding.
+    pub reg_sets: Vec<PRegSet>,
+}
+
+impl MachineEnv {
+    /// Returns the set of wide registers, i.e. those that are made up
+    /// of more than one register unit.
+    pub fn wide_regs(&self) -> PRegSet {
+        self.reg_units.iter().map(|&(preg, _)| preg).collect()
+    }
+
+    /// Returns the register units that `preg` occupies.
+    pub fn units_of(&self, preg: PReg) -> PRegSet {
+        self.reg_units
+            .iter()
+            .find(|&&(wide, _)| wide == preg)
+            .map(|&(_, units)| units)
+            .unwrap_or(PRegSet::empty().with(preg))
+    }
+
+    /// Returns whether the two registers share any register unit.
+    pub fn regs_overlap(&self, a: PReg, b: PReg) -> bool {
+        a == b || !(self.units_of(a) & self.units_of(b)).is_empty_set()
+    }
 }
 
 /// The output of the register allocator.
@@ -1599,6 +2210,13 @@ pub struct Output {
 
     /// Internal stats from the allocator.
     pub stats: ion::Stats,
+
+    /// The algorithm that produced this output: with
+    /// `Algorithm::IonWithFallback`, either `Ion` or `Fastalloc`. After
+    /// `run_incremental`, the one that re-allocated the changed blocks,
+    /// or `Fastalloc` if it did so for any of them, whatever produced
+    /// the previous output.
+    pub algorithm: Algorithm,
 }
 
 impl Output {
@@ -1648,16 +2266,13 @@ pub enum RegAllocError {
     /// illegal use. `inst` may be `Inst::invalid()` if this concerns
     /// a block param.
     SSA(VReg, Inst),
-    /// Invalid basic block: does not end in branch/ret, or contains a
-    /// branch/ret in the middle, or the VReg ids do not start at zero
-    /// or aren't numbered sequentially.
-    BB(Block),
-    /// Invalid branch: operand count does not match sum of block
-    /// params of successor blocks, or the block ids do not start at
-    /// zero or aren't numbered sequentially.
-    Branch(Inst),
+    /// Invalid basic block, for the given reason.
+    BB(Block, InvalidBlock),
+    /// Invalid branch: the number of arguments it passes to the given
+    /// successor block does not match that block's parameters.
+    Branch(Inst, Block),
     /// A VReg is live-in on entry; this is not allowed.
-    EntryLivein,
+    EntryLivein(VReg),
     /// A branch has non-blockparam arg(s) and at least one of the
     /// successor blocks has more than one predecessor, forcing
     /// edge-moves before this branch. This is disallowed because it
@@ -1666,15 +2281,318 @@ pub enum RegAllocError {
     DisallowedBranchArg(Inst),
     /// Too many pinned VRegs + Reg-constrained Operands are live at
     /// once, making allocation impossible.
-    TooManyLiveRegs,
+    TooManyLiveRegs {
+        /// Where the allocator ran out of registers.
+        point: ProgPoint,
+        /// The class of the registers that ran out.
+        class: RegClass,
+        /// The VRegs that needed a register of `class` at `point`,
+        /// starting with the one that didn't get one. This may be
+        /// incomplete.
+        vregs: Vec<VReg>,
+        /// Registers of `class` that were taken by fixed-register
+        /// constraints at `point`. This may be incomplete.
+        fixed: Vec<PReg>,
+    },
     /// Too many operands on a single instruction (beyond limit of
-    /// 2^16 - 1).
-    TooManyOperands,
+    /// 2^16 - 1); see `Function::inst_operands`.
+    TooManyOperands(Inst),
+    /// The first block is a predecessor of the second, but the second
+    /// is not a successor of the first, or the other way around.
+    PredSuccMismatch(Block, Block),
+    /// A VReg is mentioned with a different class than the given one
+    /// elsewhere.
+    ClassMismatch(VReg, RegClass),
+    /// The operand at the given index of the given instruction is
+    /// invalid, for the given reason.
+    Operand(Inst, usize, InvalidOperand),
+    /// The `MachineEnv` is invalid, for the given reason.
+    Env(InvalidEnv),
+    /// The checker, run because of `RegallocOptions::run_checker`,
+    /// found errors in the allocation.
+    #[cfg_attr(feature = "enable-serde", serde(skip))]
+    Checker(checker::CheckerErrors),
+}
+
+/// Why a block is invalid; see [`RegAllocError::BB`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub enum InvalidBlock {
+    /// The block does not exist: the entry block or a successor is
+    /// out of range.
+    OutOfRange,
+    /// The given last instruction of the block is neither a branch nor
+    /// a return.
+    NoTerminator(Inst),
+    /// The given branch or return is not the last instruction of the
+    /// block.
+    EarlyTerminator(Inst),
+    /// The block is the entry block and has block params.
+    EntryBlockParams,
+    /// The block has no instructions, or they don't start right after
+    /// those of the previous block; or this is the last block and
+    /// there are instructions after its own.
+    InstRange,
+    /// The given block param and an argument passed to it disagree on
+    /// whether they are wide (see `OperandConstraint::WideReg`).
+    WideParam(VReg),
+}
+
+/// Why an operand is invalid; see [`RegAllocError::Operand`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub enum InvalidOperand {
+    /// The operand's VReg is not below `Function::num_vregs`.
+    VRegOutOfRange,
+    /// The operand is fixed to the given register, which is not an
+    /// allocatable register or fixed stack slot of its class.
+    FixedRegNotInEnv(PReg),
+    /// The operand is a fixed non-allocatable register, but the given
+    /// register is allocatable.
+    NonallocatableRegAllocatable(PReg),
+    /// The operand has a `Reuse` or `ReuseEither` constraint with the
+    /// given index, but it isn't a def, or the index doesn't refer to
+    /// a use of the same class.
+    ReuseIndex(usize),
+    /// The given `Limit` isn't a power of two, or there is no
+    /// allocatable register of the operand's class below it.
+    Limit(usize),
+    /// The given `RegSet` index is not below the length of
+    /// `MachineEnv::reg_sets`.
+    RegSetIndex(usize),
+    /// The operand's constraint isn't supported by the given
+    /// algorithm: `Fastalloc` supports neither `Limit` nor `WideReg`,
+    /// nor a `FixedReg` that names a wide register.
+    Unsupported(Algorithm),
+    /// The operand has a `Reuse` or `ReuseEither` constraint with the
+    /// given index, and only one of its vreg and that input's is wide
+    /// (see `OperandConstraint::WideReg`).
+    ReuseWide(usize),
+    /// The operand has a `Reuse` or `ReuseEither` constraint with the
+    /// given index, and that input has a `FixedStack` constraint.
+    ReuseFixedStack(usize),
+    /// The operand is a second def with a `ReuseEither` constraint in
+    /// its instruction.
+    MultipleReuseEither,
+    /// The operand has a `FixedStack` constraint with the given
+    /// location, which another operand of the instruction also names:
+    /// both defs, or uses of different vregs.
+    FixedStackConflict(StackLoc),
+}
+
+/// Why a `MachineEnv` is invalid; see [`RegAllocError::Env`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub enum InvalidEnv {
+    /// The register is listed for a class other than its own, or as a
+    /// unit of a wide register of another class.
+    WrongClass(PReg),
+    /// The scratch register is also allocatable.
+    ScratchRegAllocatable(PReg),
+    /// The fixed stack slot is also allocatable.
+    FixedStackSlotAllocatable(PReg),
+    /// The register set with the given index contains a register that
+    /// is not allocatable.
+    RegSetNotAllocatable(usize, PReg),
+    /// The wide register is not allocatable.
+    WideRegNotAllocatable(PReg),
+    /// The allocatable register overlaps the scratch register of its
+    /// class.
+    ScratchRegOverlap(PReg),
+}
+
+impl core::fmt::Display for InvalidBlock {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            InvalidBlock::OutOfRange => write!(f, "block index is out of range"),
+            InvalidBlock::NoTerminator(inst) => write!(
+                f,
+                "last instruction inst{} is not a branch or return",
+                inst.index()
+            ),
+            InvalidBlock::EarlyTerminator(inst) => write!(
+                f,
+                "inst{} is a branch or return but not the last instruction",
+                inst.index()
+            ),
+            InvalidBlock::EntryBlockParams => write!(f, "entry block has block params"),
+            InvalidBlock::InstRange => write!(
+                f,
+                "instructions are empty or don't follow those of the previous block"
+            ),
+            InvalidBlock::WideParam(vreg) => write!(
+                f,
+                "block param {} and an argument passed to it disagree on being wide",
+                vreg
+            ),
+        }
+    }
+}
+
+impl core::fmt::Display for InvalidOperand {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            InvalidOperand::VRegOutOfRange => write!(f, "vreg is out of range"),
+            InvalidOperand::FixedRegNotInEnv(preg) => write!(
+                f,
+                "fixed register {} is not an allocatable register or fixed stack slot of its class",
+                preg
+            ),
+            InvalidOperand::NonallocatableRegAllocatable(preg) => {
+                write!(f, "fixed non-allocatable register {} is allocatable", preg)
+            }
+            InvalidOperand::ReuseIndex(i) => {
+                write!(
+                    f,
+                    "reuse of operand {} is not a def reusing a use of its class",
+                    i
+                )
+            }
+            InvalidOperand::Limit(limit) => write!(
+                f,
+                "limit {} is not a power of two above an allocatable register",
+                limit
+            ),
+            InvalidOperand::RegSetIndex(i) => write!(f, "register set {} is out of range", i),
+            InvalidOperand::Unsupported(algorithm) => {
+                write!(f, "constraint is not supported by {:?}", algorithm)
+            }
+            InvalidOperand::ReuseWide(i) => {
+                write!(f, "reuse of operand {} disagrees with it on being wide", i)
+            }
+            InvalidOperand::ReuseFixedStack(i) => {
+                write!(f, "reuse of operand {} in a fixed stack location", i)
+            }
+            InvalidOperand::MultipleReuseEither => {
+                write!(f, "instruction has more than one ReuseEither def")
+            }
+            InvalidOperand::FixedStackConflict(loc) => write!(
+                f,
+                "fixed stack location {} is named by another operand",
+                loc.index()
+            ),
+        }
+    }
+}
+
+impl core::fmt::Display for InvalidEnv {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            InvalidEnv::WrongClass(preg) => {
+                write!(f, "register {} is listed for another class", preg)
+            }
+            InvalidEnv::ScratchRegAllocatable(preg) => {
+                write!(f, "scratch register {} is allocatable", preg)
+            }
+            InvalidEnv::FixedStackSlotAllocatable(preg) => {
+                write!(f, "fixed stack slot {} is allocatable", preg)
+            }
+            InvalidEnv::RegSetNotAllocatable(i, preg) => write!(
+                f,
+                "register set {} contains register {}, which is not allocatable",
+                i, preg
+            ),
+            InvalidEnv::WideRegNotAllocatable(preg) => {
+                write!(f, "wide register {} is not allocatable", preg)
+            }
+            InvalidEnv::ScratchRegOverlap(preg) => write!(
+                f,
+                "allocatable register {} overlaps the scratch register",
+                preg
+            ),
+        }
+    }
 }
 
 impl core::fmt::Display for RegAllocError {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            RegAllocError::CritEdge(from, to) => write!(
+                f,
+                "critical edge from block{} to block{} is not split",
+                from.index(),
+                to.index()
+            ),
+            RegAllocError::SSA(vreg, inst) if inst.is_valid() => write!(
+                f,
+                "invalid SSA for {} at inst{}: defined more than once or used where its def does not dominate",
+                vreg,
+                inst.index()
+            ),
+            RegAllocError::SSA(vreg, _) => write!(
+                f,
+                "invalid SSA for block param {}: defined more than once",
+                vreg
+            ),
+            RegAllocError::BB(block, why) => write!(f, "invalid block{}: {}", block.index(), why),
+            RegAllocError::Branch(inst, succ) => write!(
+                f,
+                "branch inst{} passes the wrong number of args to block{}",
+                inst.index(),
+                succ.index()
+            ),
+            RegAllocError::EntryLivein(vreg) => write!(f, "{} is live-in on entry", vreg),
+            RegAllocError::DisallowedBranchArg(inst) => write!(
+                f,
+                "branch inst{} has non-blockparam args but a successor with more than one predecessor",
+                inst.index()
+            ),
+            RegAllocError::TooManyLiveRegs {
+                point,
+                class,
+                vregs,
+                fixed,
+            } => {
+                let pos = match point.pos() {
+                    InstPosition::Before => "before",
+                    InstPosition::After => "after",
+                };
+                write!(
+                    f,
+                    "too many live {:?} registers {} inst{}",
+                    class,
+                    pos,
+                    point.inst().index()
+                )?;
+                for (i, vreg) in vregs.iter().enumerate() {
+                    let sep = if i == 0 { "; vregs: " } else { ", " };
+                    write!(f, "{}{}", sep, vreg)?;
+                }
+                for (i, preg) in fixed.iter().enumerate() {
+                    let sep = if i == 0 { "; fixed: " } else { ", " };
+                    write!(f, "{}{}", sep, preg)?;
+                }
+                Ok(())
+            }
+            RegAllocError::TooManyOperands(inst) => write!(
+                f,
+                "inst{} has too many operands (at most {} are allowed)",
+                inst.index(),
+                u16::MAX
+            ),
+            RegAllocError::PredSuccMismatch(pred, succ) => write!(
+                f,
+                "block{} and block{} disagree on whether there is an edge between them",
+                pred.index(),
+                succ.index()
+            ),
+            RegAllocError::ClassMismatch(vreg, class) => write!(
+                f,
+                "{} has class {:?}, but {:?} elsewhere",
+                vreg,
+                vreg.class(),
+                class
+            ),
+            RegAllocError::Operand(inst, i, why) => write!(
+                f,
+                "invalid operand {} of inst{}: {}",
+                i,
+                inst.index(),
+                why
+            ),
+            RegAllocError::Env(why) => write!(f, "invalid machine env: {}", why),
+            RegAllocError::Checker(errors) => write!(f, "checker failed:\n{}", errors),
+        }
     }
 }
 
@@ -1687,13 +2605,23 @@ pub fn run<F: Function>(
     options: &RegallocOptions,
 ) -> Result<Output, RegAllocError> {
     match options.algorithm {
-        Algorithm::Ion => {
+        Algorithm::Ion | Algorithm::LinearScan | Algorithm::IonWithFallback => {
             let mut ctx = Ctx::default();
             run_with_ctx(func, env, options, &mut ctx)?;
             Ok(ctx.output)
         }
         Algorithm::Fastalloc => {
-            fastalloc::run(func, env, options.verbose_log, options.validate_ssa)
+            if options.validate_function {
+                validate_function(func, env, options.algorithm)?;
+            }
+            let mut output = fastalloc::run(func, env, options.verbose_log, options.validate_ssa)?;
+            if options.copy_propagation {
+                peephole::run(func, env, &mut output);
+            }
+            if options.run_checker {
+                check_output(func, env, &output)?;
+            }
+            Ok(output)
         }
     }
 }
@@ -1707,20 +2635,128 @@ pub fn run_with_ctx<'a, F: Function>(
     options: &RegallocOptions,
     ctx: &'a mut Ctx,
 ) -> Result<&'a Output, RegAllocError> {
+    if options.validate_function {
+        validate_function(func, env, options.algorithm)?;
+    }
     match options.algorithm {
-        Algorithm::Ion => ion::run(func, env, ctx, options.verbose_log, options.validate_ssa)?,
+        Algorithm::Ion => ion::run(
+            func,
+            env,
+            ctx,
+            options.verbose_log,
+            options.validate_ssa,
+            options.fuel,
+        )?,
+        Algorithm::LinearScan => ion::linear_scan::run(
+            func,
+            env,
+            ctx,
+            options.verbose_log,
+            options.validate_ssa,
+            options.fuel,
+        )?,
         Algorithm::Fastalloc => {
             ctx.output = fastalloc::run(func, env, options.verbose_log, options.validate_ssa)?
         }
+        Algorithm::IonWithFallback if fastalloc::unsupported_operand(func, env).is_some() => {
+            // Fastalloc can't allocate this function, so Ion has to
+            // finish on its own.
+            ion::run(
+                func,
+                env,
+                ctx,
+                options.verbose_log,
+                options.validate_ssa,
+                options.fuel,
+            )?
+        }
+        Algorithm::IonWithFallback => {
+            let done = match ion::run_or_give_up(
+                func,
+                env,
+                ctx,
+                options.verbose_log,
+                options.validate_ssa,
+                options.fuel,
+            ) {
+                Ok(done) => done,
+                Err(RegAllocError::TooManyLiveRegs { .. }) => false,
+                Err(e) => return Err(e),
+            };
+            if !done {
+                ctx.output = fastalloc::run(func, env, options.verbose_log, options.validate_ssa)?;
+            }
+        }
+    }
+    if options.copy_propagation {
+        peephole::run(func, env, &mut ctx.output);
+    }
+    if options.run_checker {
+        check_output(func, env, &ctx.output)?;
     }
     Ok(&ctx.output)
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+/// Run the checker over `output`, an allocation of `func`.
+pub(crate) fn check_output<F: Function>(
+    func: &F,
+    env: &MachineEnv,
+    output: &Output,
+) -> Result<(), RegAllocError> {
+    let mut checker = checker::Checker::new(func, env);
+    checker.prepare(output);
+    checker.run().map_err(RegAllocError::Checker)
+}
+
+/// Re-allocate a function after some of its instructions changed,
+/// starting from the output of a previous allocation.
+///
+/// `previous` must be the output of allocating `func` before the
+/// instructions in `changed` were modified, and nothing else may have
+/// changed since: the blocks and their parameters, the CFG and the
+/// numbering of instructions must be the same, as must everything
+/// about the instructions outside of `changed`.
+///
+/// Only the blocks containing a changed instruction are re-allocated.
+/// Everywhere else, the allocations and edits of `previous` are kept,
+/// and the values live into and out of the re-allocated blocks stay
+/// where `previous` had them; moves to connect the two may be added at
+/// the start of a block that follows the re-allocated blocks, or at
+/// the end of one that precedes them. The re-allocated blocks use new
+/// spillslots, after those of `previous`, and have no
+/// `Output::debug_locations`.
+///
+/// The changed blocks must be entered through a single block per set
+/// of changed blocks connected by CFG edges, and the values live
+/// across their boundaries must be where the re-allocation can name
+/// them. When this isn't the case, the whole function is allocated
+/// again instead, which is counted in `Stats::incremental_fallbacks`.
+///
+/// Return value points to `ctx.output`, as with `run_with_ctx`.
+pub fn run_incremental<'a, F: Function>(
+    func: &F,
+    env: &MachineEnv,
+    options: &RegallocOptions,
+    ctx: &'a mut Ctx,
+    previous: &Output,
+    changed: &[InstRange],
+) -> Result<&'a Output, RegAllocError> {
+    incremental::run(func, env, options, ctx, previous, changed)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub enum Algorithm {
     #[default]
     Ion,
     Fastalloc,
+    LinearScan,
+    /// `Ion`, but if it fails with `RegAllocError::TooManyLiveRegs` or
+    /// runs out of `RegallocOptions::fuel`, `Fastalloc` instead, in
+    /// the same `Ctx`. `Output::algorithm` says which one it was. A
+    /// function with constraints that `Fastalloc` doesn't support is
+    /// always allocated by `Ion`, as with `Algorithm::Ion`.
+    IonWithFallback,
 }
 
 /// Options for allocation.
@@ -1732,8 +2768,34 @@ pub struct RegallocOptions {
     /// Run the SSA validator before allocating registers.
     pub validate_ssa: bool,
 
+    /// Run [`validate_function`] before allocating registers, which
+    /// includes the checks of `validate_ssa`.
+    pub validate_function: bool,
+
     /// The register allocation algorithm to be used.
     pub algorithm: Algorithm,
+
+    /// Run the checker over the output after allocating registers,
+    /// failing with `RegAllocError::Checker` if it finds any errors.
+    /// This needs nothing beyond the `Output` itself, so it can be
+    /// enabled for some functions and not others.
+    pub run_checker: bool,
+
+    /// Run a copy-propagation pass over the allocator's output, which
+    /// removes moves whose destination is read only once more by having
+    /// that read take the move's source instead, and moves whose
+    /// destination is never read.
+    pub copy_propagation: bool,
+
+    /// A limit on the number of steps the allocation loop of `Ion` and
+    /// `LinearScan` takes: one per attempt at allocating a bundle.
+    /// Once it is used up, bundles that don't fit into a register are
+    /// no longer split normally or allowed to evict others; they are
+    /// split into one minimal bundle per register use instead, with the
+    /// rest of the value spilled, which bounds the remaining work. The
+    /// result is still valid, and the fallback is recorded in
+    /// `Stats::fuel_exhausted`. `None` means no limit.
+    pub fuel: Option<usize>,
 }
 
 pub(crate) trait VecExt<T> {
@@ -1828,7 +2890,10 @@ unsafe impl allocator_api2::alloc::Allocator for Bump {
 
 #[cfg(test)]
 mod tests {
-    use super::{PReg, PRegSet, RegClass::*};
+    use super::{
+        Operand, OperandConstraint, OperandGroup, OperandKind, OperandPos, PReg, PRegSet, RegClass,
+        RegClass::*, VReg,
+    };
 
     #[test]
     fn preg_set_len() {
@@ -1859,9 +2924,85 @@ mod tests {
         assert_eq!(set.max_preg(), Some(PReg::new(4, Int)));
     }
 
+    #[test]
+    fn operand_fixed_reg_encoding() {
+        for class in RegClass::ALL {
+            let vreg = VReg::new(1, class);
+            for hw_enc in 0..=PReg::MAX {
+                let preg = PReg::new(hw_enc, class);
+                let op = Operand::reg_fixed_use(vreg, preg);
+                assert_eq!(op.constraint(), OperandConstraint::FixedReg(preg));
+                assert_eq!(op.vreg(), vreg);
+            }
+        }
+    }
+
+    #[test]
+    fn operand_reg_set_encoding() {
+        let vreg = VReg::new(VReg::MAX, RegClass::Float);
+        for set in [0, 1, (1 << 20) - 1] {
+            let op = Operand::reg_set_def(vreg, set);
+            assert_eq!(op.constraint(), OperandConstraint::RegSet(set));
+            assert_eq!(op.vreg(), vreg);
+            assert_eq!(op.kind(), OperandKind::Def);
+        }
+    }
+
+    #[test]
+    fn operand_reuse_either_encoding() {
+        let vreg = VReg::new(VReg::MAX, RegClass::Int);
+        for (a, b) in [(0, 1), (2, 0), (31, 30)] {
+            let op = Operand::reg_reuse_either_def(vreg, a, b);
+            assert_eq!(op.constraint(), OperandConstraint::ReuseEither(a, b));
+            assert_eq!(op.vreg(), vreg);
+            assert_eq!(op.pos(), OperandPos::Late);
+        }
+    }
+
+    #[test]
+    fn operand_group_separates() {
+        let v = |n| VReg::new(n, Int);
+        let operands = [
+            Operand::reg_def(v(0)),
+            Operand::reg_def(v(1)),
+            Operand::reg_use(v(2)),
+            Operand::reg_use(v(3)),
+        ];
+        let groups = [OperandGroup::new(&[0], &[2])];
+        assert!(!OperandGroup::separates(&groups, &operands, 0, 2));
+        assert!(OperandGroup::separates(&groups, &operands, 0, 3));
+        // v1 is not in a group, so it may share with either use.
+        assert!(!OperandGroup::separates(&groups, &operands, 1, 3));
+        assert!(OperandGroup::separates_use(&groups, &operands, 3));
+        assert!(!OperandGroup::separates_use(&groups, &operands, 2));
+        assert!(!OperandGroup::separates_use(&[], &operands, 3));
+    }
+
+    #[test]
+    fn vreg_class_encoding() {
+        for class in RegClass::ALL {
+            for index in [0, 1, VReg::MAX] {
+                let vreg = VReg::new(index, class);
+                assert_eq!(vreg.vreg(), index);
+                assert_eq!(vreg.class(), class);
+            }
+            assert_eq!(PReg::new(PReg::MAX, class).class(), class);
+        }
+    }
+
+    #[test]
+    fn preg_set_is_empty() {
+        for class in RegClass::ALL {
+            let set = PRegSet::empty().with(PReg::new(PReg::MAX, class));
+            for other in RegClass::ALL {
+                assert_eq!(set.is_empty(other), other != class);
+            }
+        }
+    }
+
     #[test]
     fn preg_set_new_up_to() {
-        for class in [Int, Float, Vector] {
+        for class in RegClass::ALL {
             let p0 = PReg::new(0, class);
             let p1 = PReg::new(1, class);
             let p2 = PReg::new(2, class);
@@ -1885,7 +3026,7 @@ mod tests {
                 assert!(set.contains(p2));
                 assert!(!set.contains(p3));
             }
-            for i in 1..64 {
+            for i in 1..=PReg::MAX {
                 let mut set = PRegSet::empty();
                 set.add_up_to(PReg::new(i, class));
                 assert!(set.contains(p0));
@@ -3,7 +3,7 @@
  * exception. See `LICENSE` for details.
  */
 
-use crate::{ion::data_structures::u64_key, Allocation, PReg};
+use crate::{ion::data_structures::u64_key, Allocation, PReg, PRegSet};
 use core::fmt::Debug;
 use smallvec::{smallvec, SmallVec};
 
@@ -232,6 +232,16 @@ impl<T: Clone + Copy + Default + PartialEq> ParallelMoves<T> {
 }
 
 impl<T> MoveVecWithScratch<T> {
+    /// Appends moves to be performed, in order, after all of the
+    /// resolved moves.
+    pub fn extend<I: IntoIterator<Item = (Allocation, Allocation, T)>>(&mut self, moves: I) {
+        match self {
+            MoveVecWithScratch::NoScratch(vec) | MoveVecWithScratch::Scratch(vec) => {
+                vec.extend(moves)
+            }
+        }
+    }
+
     /// Fills in the scratch space, if needed, with the given
     /// register/allocation and returns a final list of moves. The
     /// scratch register must not occur anywhere in the parallel-move
@@ -319,6 +329,14 @@ where
     /// move sequence. Provided by caller and statically chosen. This is
     /// a very last-ditch option, so static choice is OK.
     pub borrowed_scratch_reg: PReg,
+    /// Registers that share a register unit with `borrowed_scratch_reg`
+    /// (see `MachineEnv::reg_units`): moves that read or write them
+    /// also see the borrowed register's value.
+    pub borrowed_scratch_aliases: PRegSet,
+    /// Registers among `borrowed_scratch_aliases` that hold values of
+    /// their own, which the borrowed register doesn't: if it is
+    /// borrowed, these are saved and restored along with it.
+    pub borrowed_scratch_occupants: PRegSet,
 }
 
 impl<GetReg, GetStackSlot, IsStackAlloc> MoveAndScratchResolver<GetReg, GetStackSlot, IsStackAlloc>
@@ -370,6 +388,29 @@ where
             (reg, Some(save))
         };
 
+        // Stackslots into which we save the registers occupying parts
+        // of the scratch reg, if we stole it.
+        let occupant_saves: SmallVec<[(Allocation, Allocation); 2]> = if save_slot.is_some() {
+            self.borrowed_scratch_occupants
+                .into_iter()
+                .map(|preg| (Allocation::reg(preg), (self.get_stackslot)()))
+                .collect()
+        } else {
+            smallvec![]
+        };
+        let save = |result: &mut MoveVec<T>, save_slot: Allocation| {
+            result.push((scratch_reg, save_slot, T::default()));
+            for &(reg, slot) in &occupant_saves {
+                result.push((reg, slot, T::default()));
+            }
+        };
+        let restore = |result: &mut MoveVec<T>, save_slot: Allocation| {
+            result.push((save_slot, scratch_reg, T::default()));
+            for &(reg, slot) in &occupant_saves {
+                result.push((slot, reg, T::default()));
+            }
+        };
+
         // Mutually exclusive flags for whether either scratch_reg or
         // save_slot need to be restored from the other. Initially,
         // scratch_reg has a value we should preserve and save_slot
@@ -377,6 +418,19 @@ where
         let mut scratch_dirty = false;
         let mut save_dirty = true;
 
+        // Registers that are only partly overwritten when the scratch
+        // register is, if we stole it.
+        let scratch_aliases = if save_slot.is_some() {
+            self.borrowed_scratch_aliases
+        } else {
+            PRegSet::empty()
+        };
+        let is_scratch_alias = |alloc: Allocation| {
+            alloc
+                .as_reg()
+                .map_or(false, |r| scratch_aliases.contains(r))
+        };
+
         let mut result = smallvec![];
         for &(src, dst, data) in &moves {
             // Do we have a stack-to-stack move? If so, resolve.
@@ -393,7 +447,7 @@ where
                     // to do it again.
                     if save_dirty {
                         debug_assert!(!scratch_dirty);
-                        result.push((scratch_reg, save_slot, T::default()));
+                        save(&mut result, save_slot);
                         save_dirty = false;
                     }
                 }
@@ -421,10 +475,18 @@ where
                     // allocated too.
                     debug_assert!(!save_dirty);
                     let save_slot = save_slot.expect("move source should not be a free register");
-                    result.push((save_slot, scratch_reg, T::default()));
+                    restore(&mut result, save_slot);
+                    scratch_dirty = false;
+                }
+                if (is_scratch_alias(src) || is_scratch_alias(dst)) && scratch_dirty {
+                    // Reading or writing a register that overlaps the
+                    // scratch register: restore it first, so that the
+                    // overlapping part holds its real value.
+                    debug_assert!(!save_dirty);
+                    restore(&mut result, save_slot.unwrap());
                     scratch_dirty = false;
                 }
-                if dst == scratch_reg {
+                if dst == scratch_reg || is_scratch_alias(dst) {
                     // We are writing something to the scratch register
                     // so it doesn't matter what was there before. We
                     // can avoid restoring it, but we will need to save
@@ -441,7 +503,7 @@ where
         if let Some(save_slot) = save_slot {
             if scratch_dirty {
                 debug_assert!(!save_dirty);
-                result.push((save_slot, scratch_reg, T::default()));
+                restore(&mut result, save_slot);
             }
         }
 
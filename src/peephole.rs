@@ -0,0 +1,502 @@
+/*
+ * Released under the terms of the Apache 2.0 license with LLVM
+ * exception. See `LICENSE` for details.
+ */
+
+//! Post-allocation copy propagation.
+//!
+//! This is an optional pass over a finished `Output` (see
+//! `RegallocOptions::copy_propagation`). It looks for moves `r1 <- r2`
+//! whose destination is read only once before it dies, and makes that
+//! read take `r2` instead:
+//!
+//! - `r1 <- r2; r3 <- r1` becomes `r3 <- r2`;
+//! - `r1 <- r2; inst(r1)` becomes `inst(r2)`, if the operand's
+//!   constraint allows `r2`.
+//!
+//! Moves whose destination is never read at all are removed too. Both
+//! rely on a liveness analysis over allocations (rather than vregs),
+//! and are local to a block: a move is only changed if the read of its
+//! destination and the point where the destination dies are in the
+//! same block as the move, within a bounded distance.
+
+use crate::{
+    Allocation, Block, Edit, Function, FxHashMap, FxHashSet, Inst, MachineEnv, OperandConstraint,
+    OperandGroup, OperandKind, OperandPos, Output, PReg, PRegSet, ProgPoint,
+};
+use alloc::vec::Vec;
+use smallvec::SmallVec;
+
+/// How many events past a move we look for the read of its destination
+/// and for the point where the destination dies.
+const MAX_SCAN: usize = 64;
+
+/// Something that happens in a block: an edit (by its index in
+/// `Output::edits`) or an instruction.
+#[derive(Clone, Copy, Debug)]
+enum Event {
+    Edit(usize),
+    Inst(Inst),
+}
+
+/// How an event touches an allocation.
+#[derive(Clone, Copy, Debug, Default)]
+struct Access {
+    /// The event reads (some of) the allocation.
+    read: bool,
+    /// The event overwrites some of the allocation.
+    written: bool,
+    /// The event overwrites all of the allocation.
+    killed: bool,
+}
+
+struct CopyProp<'a, F: Function> {
+    func: &'a F,
+    env: &'a MachineEnv,
+    /// The register units of each wide register.
+    wide_units: FxHashMap<PReg, PRegSet>,
+    /// All the register units that hold some labeled value at some
+    /// point, according to `Output::debug_locations`.
+    debug_units: FxHashSet<Allocation>,
+    /// The allocations that are live on exit from each block, as
+    /// register units and stack slots.
+    live_out: Vec<FxHashSet<Allocation>>,
+    /// Whether each edit has been removed.
+    removed: Vec<bool>,
+}
+
+/// Runs copy propagation over `output`, which must be an allocation
+/// of `func` on `env`.
+pub(crate) fn run<F: Function>(func: &F, env: &MachineEnv, output: &mut Output) {
+    let mut pass = CopyProp {
+        func,
+        env,
+        wide_units: env.reg_units.iter().cloned().collect(),
+        debug_units: FxHashSet::default(),
+        live_out: Vec::new(),
+        removed: alloc::vec![false; output.edits.len()],
+    };
+    for &(_, _, _, alloc) in &output.debug_locations {
+        let units = pass.units(alloc);
+        pass.debug_units.extend(units);
+    }
+
+    let events: Vec<Vec<Event>> = (0..func.num_blocks())
+        .map(|block| pass.block_events(output, Block::new(block)))
+        .collect();
+    pass.compute_liveness(output, &events);
+
+    for (block, events) in events.iter().enumerate() {
+        for idx in 0..events.len() {
+            pass.propagate(output, Block::new(block), events, idx);
+        }
+    }
+
+    // A propagation may remove both the move and the one it feeds.
+    let removed_count = pass.removed.iter().filter(|&&removed| removed).count();
+    let mut removed = pass.removed.into_iter();
+    output.edits.retain(|_| !removed.next().unwrap());
+    output.stats.copy_prop_moves_removed += removed_count;
+}
+
+impl<'a, F: Function> CopyProp<'a, F> {
+    /// The allocations making up `alloc`: its register units if it is
+    /// a wide register, or else `alloc` itself.
+    fn units(&self, alloc: Allocation) -> SmallVec<[Allocation; 4]> {
+        match alloc.as_reg().and_then(|preg| self.wide_units.get(&preg)) {
+            Some(units) => units.into_iter().map(Allocation::reg).collect(),
+            None => smallvec::smallvec![alloc],
+        }
+    }
+
+    /// Whether `a` and `b` share a register unit or stack slot.
+    fn overlaps(&self, a: Allocation, b: Allocation) -> bool {
+        a == b || {
+            let b_units = self.units(b);
+            self.units(a).iter().any(|unit| b_units.contains(unit))
+        }
+    }
+
+    /// Whether `alloc` is in memory: a spillslot, a client-defined
+    /// stack location, or a register that represents a fixed stack
+    /// slot.
+    fn is_stack(&self, alloc: Allocation) -> bool {
+        match alloc.as_reg() {
+            Some(preg) => self.env.fixed_stack_slots.contains(&preg),
+            None => alloc.is_stack() || alloc.is_fixed_stack(),
+        }
+    }
+
+    /// The edits and instructions of `block`, in execution order.
+    fn block_events(&self, output: &Output, block: Block) -> Vec<Event> {
+        let insts = self.func.block_insns(block);
+        let mut edit = output
+            .edits
+            .partition_point(|&(pos, _)| pos < ProgPoint::before(insts.first()));
+        let mut events = Vec::new();
+        for inst in insts.iter() {
+            while edit < output.edits.len() && output.edits[edit].0 <= ProgPoint::before(inst) {
+                events.push(Event::Edit(edit));
+                edit += 1;
+            }
+            events.push(Event::Inst(inst));
+            while edit < output.edits.len() && output.edits[edit].0 <= ProgPoint::after(inst) {
+                events.push(Event::Edit(edit));
+                edit += 1;
+            }
+        }
+        events
+    }
+
+    /// Calls `read` with each allocation that `event` reads, and
+    /// `write` with each one that it writes. An instruction's reads all
+    /// happen before its writes.
+    fn for_each_access(
+        &self,
+        output: &Output,
+        event: Event,
+        mut read: impl FnMut(Allocation),
+        mut write: impl FnMut(Allocation),
+    ) {
+        match event {
+            Event::Edit(idx) if self.removed[idx] => {}
+            Event::Edit(idx) => match output.edits[idx].1 {
+                Edit::Move { from, to } => {
+                    read(from);
+                    write(to);
+                }
+                Edit::Remat { to, .. } => write(to),
+            },
+            Event::Inst(inst) => {
+                let operands = self.func.inst_operands(inst);
+                let allocs = output.inst_allocs(inst);
+                for (op, &alloc) in operands.iter().zip(allocs) {
+                    if op.kind() == OperandKind::Use && !alloc.is_none() {
+                        read(alloc);
+                    }
+                }
+                for (op, &alloc) in operands.iter().zip(allocs) {
+                    if alloc.is_none() {
+                        continue;
+                    }
+                    // A client-defined stack location may be
+                    // overwritten by the instruction that reads it.
+                    if op.kind() == OperandKind::Def || alloc.is_fixed_stack() {
+                        write(alloc);
+                    }
+                }
+                for preg in self.func.inst_clobbers(inst) {
+                    write(Allocation::reg(preg));
+                }
+                // The dedicated scratch registers may be clobbered by
+                // any instruction.
+                for preg in self.env.scratch_by_class.iter().flatten() {
+                    write(Allocation::reg(*preg));
+                }
+            }
+        }
+    }
+
+    /// How `event` touches `alloc`.
+    fn access(&self, output: &Output, event: Event, alloc: Allocation) -> Access {
+        let mut access = Access::default();
+        let units = self.units(alloc);
+        let mut written = |a: Allocation| {
+            let written = self.units(a);
+            access.written |= units.iter().any(|unit| written.contains(unit));
+            access.killed |= units.iter().all(|unit| written.contains(unit));
+        };
+        let mut read = false;
+        self.for_each_access(
+            output,
+            event,
+            |a| read |= self.overlaps(a, alloc),
+            &mut written,
+        );
+        access.read = read;
+        access
+    }
+
+    /// Computes the allocations live on exit from each block, with a
+    /// backward dataflow analysis to a fixpoint. An allocation is also
+    /// live where `Output::debug_locations` says it holds a labeled
+    /// value, as a debugger may read it there.
+    fn compute_liveness(&mut self, output: &Output, events: &[Vec<Event>]) {
+        let num_blocks = self.func.num_blocks();
+        let block_debug_locations: Vec<Vec<(ProgPoint, ProgPoint, Allocation)>> = (0..num_blocks)
+            .map(|block| {
+                let insts = self.func.block_insns(Block::new(block));
+                let (start, end) = (
+                    ProgPoint::before(insts.first()),
+                    ProgPoint::after(insts.last()),
+                );
+                output
+                    .debug_locations
+                    .iter()
+                    .filter(|&&(_, from, to, _)| from <= end && start < to)
+                    .map(|&(_, from, to, alloc)| (from, to, alloc))
+                    .collect()
+            })
+            .collect();
+        let mut live_in: Vec<FxHashSet<Allocation>> = alloc::vec![FxHashSet::default(); num_blocks];
+        self.live_out = alloc::vec![FxHashSet::default(); num_blocks];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in (0..num_blocks).rev() {
+                let mut live = FxHashSet::default();
+                for &succ in self.func.block_succs(Block::new(block)) {
+                    live.extend(live_in[succ.index()].iter().copied());
+                }
+                self.live_out[block] = live.clone();
+                let debug_reads = |live: &mut FxHashSet<Allocation>, point: ProgPoint| {
+                    for &(from, to, alloc) in &block_debug_locations[block] {
+                        if from <= point && point < to {
+                            live.extend(self.units(alloc));
+                        }
+                    }
+                };
+                for &event in events[block].iter().rev() {
+                    let (before, after) = match event {
+                        // A labeled value need only be in its location
+                        // at some time during the moves at a point, so
+                        // a move into it doesn't end the read there.
+                        Event::Edit(idx) => (Some(output.edits[idx].0), output.edits[idx].0),
+                        Event::Inst(inst) => {
+                            (Some(ProgPoint::before(inst)), ProgPoint::after(inst))
+                        }
+                    };
+                    debug_reads(&mut live, after);
+                    let mut reads = SmallVec::<[Allocation; 8]>::new();
+                    let mut writes = SmallVec::<[Allocation; 8]>::new();
+                    self.for_each_access(
+                        output,
+                        event,
+                        |a| reads.extend(self.units(a)),
+                        |a| writes.extend(self.units(a)),
+                    );
+                    for unit in writes {
+                        live.remove(&unit);
+                    }
+                    live.extend(reads);
+                    if let Some(before) = before {
+                        debug_reads(&mut live, before);
+                    }
+                }
+                if live != live_in[block] {
+                    live_in[block] = live;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// Finds the first event after `events[idx]` in the block, within
+    /// `MAX_SCAN` events, that reads `alloc`. Returns `Ok(None)` if
+    /// `alloc` is dead after `events[idx]`, and `Err(())` if neither
+    /// can be shown.
+    fn next_read(
+        &self,
+        output: &Output,
+        block: Block,
+        events: &[Event],
+        idx: usize,
+        alloc: Allocation,
+    ) -> Result<Option<usize>, ()> {
+        for next in (idx + 1..events.len()).take(MAX_SCAN) {
+            let access = self.access(output, events[next], alloc);
+            if access.read {
+                return Ok(Some(next));
+            } else if access.killed {
+                return Ok(None);
+            } else if access.written {
+                return Err(());
+            }
+        }
+        if idx + 1 + MAX_SCAN < events.len() {
+            return Err(());
+        }
+        let live_out = &self.live_out[block.index()];
+        if self.units(alloc).iter().any(|unit| live_out.contains(unit)) {
+            Err(())
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Whether a labeled value may be in `alloc` anywhere from `pos` to
+    /// the end of `block`, so that removing a write to it at `pos` could
+    /// change what a debugger sees.
+    fn in_debug_locations(
+        &self,
+        output: &Output,
+        block: Block,
+        alloc: Allocation,
+        pos: ProgPoint,
+    ) -> bool {
+        let end = ProgPoint::after(self.func.block_insns(block).last());
+        self.units(alloc)
+            .iter()
+            .any(|unit| self.debug_units.contains(unit))
+            && output
+                .debug_locations
+                .iter()
+                .any(|&(_, start, stop, a)| start <= end && pos < stop && self.overlaps(a, alloc))
+    }
+
+    /// Whether the operands of `inst` that read `from` can read `to`
+    /// instead, with the constraint of each still met.
+    fn can_rewrite_uses(
+        &self,
+        output: &Output,
+        inst: Inst,
+        from: Allocation,
+        to: Allocation,
+    ) -> bool {
+        let operands = self.func.inst_operands(inst);
+        let groups = self.func.inst_operand_groups(inst);
+        let allocs = output.inst_allocs(inst);
+        let wide = |alloc: Allocation| {
+            alloc
+                .as_reg()
+                .map_or(false, |preg| self.wide_units.contains_key(&preg))
+        };
+        let mut late = false;
+        for (idx, (op, &alloc)) in operands.iter().zip(allocs).enumerate() {
+            if op.kind() != OperandKind::Use || !self.overlaps(alloc, from) {
+                continue;
+            }
+            if alloc != from {
+                return false;
+            }
+            let allowed = match op.constraint() {
+                OperandConstraint::Any => true,
+                OperandConstraint::Reg => {
+                    to.is_reg() && !self.is_stack(to) && wide(to) == wide(from)
+                }
+                _ => false,
+            };
+            let reused = operands.iter().any(|op| match op.constraint() {
+                OperandConstraint::Reuse(i) => i == idx,
+                OperandConstraint::ReuseEither(a, b) => a == idx || b == idx,
+                _ => false,
+            });
+            if !allowed || reused {
+                return false;
+            }
+            // A use kept apart from the defs must not share a
+            // register with them either, as if it were late.
+            late |=
+                op.pos() == OperandPos::Late || OperandGroup::separates_use(groups, operands, idx);
+        }
+        // `to` must still hold its value when the operands read it.
+        let early_def = operands.iter().zip(allocs).any(|(op, &alloc)| {
+            op.kind() == OperandKind::Def
+                && op.pos() == OperandPos::Early
+                && self.overlaps(alloc, to)
+        });
+        let late_write = || {
+            let mut written = false;
+            self.for_each_access(
+                output,
+                Event::Inst(inst),
+                |_| {},
+                |alloc| written |= self.overlaps(alloc, to),
+            );
+            written
+        };
+        !early_def && !(late && late_write())
+    }
+
+    /// Tries to propagate the move at `events[idx]`, if it is one, into
+    /// the next read of its destination, removing the move if so.
+    fn propagate(&mut self, output: &mut Output, block: Block, events: &[Event], idx: usize) {
+        let Event::Edit(edit) = events[idx] else {
+            return;
+        };
+        let (pos, Edit::Move { from, to }) = output.edits[edit] else {
+            return;
+        };
+        if self.removed[edit] || from == to {
+            return;
+        }
+
+        let Ok(read) = self.next_read(output, block, events, idx, to) else {
+            return;
+        };
+        if self.in_debug_locations(output, block, to, pos) {
+            return;
+        }
+        let Some(read) = read else {
+            // `to` is never read: the move is dead.
+            trace!("copy propagation: removing dead move {} -> {}", from, to);
+            self.removed[edit] = true;
+            return;
+        };
+
+        // `from` must still hold the moved value at the read, and `to`
+        // must die right after it.
+        if events[idx + 1..read]
+            .iter()
+            .any(|&event| self.access(output, event, from).written)
+        {
+            return;
+        }
+        if !self.access(output, events[read], to).killed
+            && self.next_read(output, block, events, read, to) != Ok(None)
+        {
+            return;
+        }
+
+        match events[read] {
+            Event::Edit(read_edit) => {
+                let Edit::Move {
+                    from: read_from,
+                    to: dest,
+                } = output.edits[read_edit].1
+                else {
+                    return;
+                };
+                if read_from != to || (self.is_stack(from) && self.is_stack(dest)) {
+                    return;
+                }
+                if dest == from {
+                    // `dest` already holds the value.
+                    self.removed[read_edit] = true;
+                } else if self.overlaps(dest, from) {
+                    return;
+                } else {
+                    output.edits[read_edit].1 = Edit::Move { from, to: dest };
+                }
+                trace!(
+                    "copy propagation: {} -> {} -> {} becomes {} -> {}",
+                    from,
+                    to,
+                    dest,
+                    from,
+                    dest
+                );
+            }
+            Event::Inst(inst) => {
+                if !self.can_rewrite_uses(output, inst, to, from) {
+                    return;
+                }
+                let start = output.inst_alloc_offsets[inst.index()] as usize;
+                let ops = self.func.inst_operands(inst);
+                for (op_idx, op) in ops.iter().enumerate() {
+                    let alloc = &mut output.allocs[start + op_idx];
+                    if op.kind() == OperandKind::Use && *alloc == to {
+                        *alloc = from;
+                    }
+                }
+                trace!(
+                    "copy propagation: {} now reads {} instead of {}",
+                    inst.index(),
+                    from,
+                    to
+                );
+            }
+        }
+        self.removed[edit] = true;
+    }
+}
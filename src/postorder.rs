@@ -5,7 +5,7 @@
 
 //! Fast postorder computation.
 
-use crate::{Block, RegAllocError, VecExt};
+use crate::{Block, InvalidBlock, RegAllocError, VecExt};
 use alloc::vec::Vec;
 use smallvec::{smallvec, SmallVec};
 
@@ -28,7 +28,7 @@ pub fn calculate<'a, SuccFn: Fn(Block) -> &'a [Block]>(
 
     let entry_visit = visited
         .get_mut(entry.index())
-        .ok_or(RegAllocError::BB(entry))?;
+        .ok_or(RegAllocError::BB(entry, InvalidBlock::OutOfRange))?;
     *entry_visit = true;
     stack.push(State {
         block: entry,
@@ -40,7 +40,7 @@ pub fn calculate<'a, SuccFn: Fn(Block) -> &'a [Block]>(
         if let Some(&succ) = state.succs.next() {
             let succ_visit = visited
                 .get_mut(succ.index())
-                .ok_or(RegAllocError::BB(succ))?;
+                .ok_or(RegAllocError::BB(succ, InvalidBlock::OutOfRange))?;
             if !*succ_visit {
                 *succ_visit = true;
                 stack.push(State {
@@ -111,12 +111,10 @@ impl SerializableFunction {
                 .collect(),
             num_vregs: func.num_vregs(),
             debug_value_labels: func.debug_value_labels().to_vec(),
-            spillslot_size: [
-                func.spillslot_size(RegClass::Int),
-                func.spillslot_size(RegClass::Float),
-                func.spillslot_size(RegClass::Vector),
-            ]
-            .to_vec(),
+            spillslot_size: RegClass::ALL
+                .iter()
+                .map(|&class| func.spillslot_size(class))
+                .collect(),
             multi_spillslot_named_by_last_slot: func.multi_spillslot_named_by_last_slot(),
             allow_multiple_vreg_defs: func.allow_multiple_vreg_defs(),
         }
@@ -202,21 +200,14 @@ impl fmt::Debug for SerializableFunction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{{\n")?;
         write!(f, "  machine_env: {:#?}\n", self.machine_env())?;
-        write!(
-            f,
-            "  spillslot_size(Int): {}\n",
-            self.spillslot_size(RegClass::Int)
-        )?;
-        write!(
-            f,
-            "  spillslot_size(Float): {}\n",
-            self.spillslot_size(RegClass::Float)
-        )?;
-        write!(
-            f,
-            "  spillslot_size(Vector): {}\n",
-            self.spillslot_size(RegClass::Vector)
-        )?;
+        for class in RegClass::ALL {
+            write!(
+                f,
+                "  spillslot_size({:?}): {}\n",
+                class,
+                self.spillslot_size(class)
+            )?;
+        }
         write!(
             f,
             "  multi_spillslot_named_by_last_slot: {}\n",
@@ -8,7 +8,7 @@
 use alloc::vec;
 
 use crate::cfg::CFGInfo;
-use crate::{Block, Function, FxHashSet, Inst, OperandKind, RegAllocError, VReg};
+use crate::{Block, Function, FxHashSet, Inst, InvalidBlock, OperandKind, RegAllocError, VReg};
 
 pub fn validate_ssa<F: Function>(f: &F, cfginfo: &CFGInfo) -> Result<(), RegAllocError> {
     // For every block param and inst def, check that this is the only def.
@@ -102,7 +102,7 @@ pub fn validate_ssa<F: Function>(f: &F, cfginfo: &CFGInfo) -> Result<(), RegAllo
             if insn == insns.last() {
                 if !(f.is_branch(insn) || f.is_ret(insn)) {
                     trace!("block {:?} is not terminated by a branch or ret!", block);
-                    return Err(RegAllocError::BB(block));
+                    return Err(RegAllocError::BB(block, InvalidBlock::NoTerminator(insn)));
                 }
                 if f.is_branch(insn) {
                     for (i, &succ) in f.block_succs(block).iter().enumerate() {
@@ -114,14 +114,17 @@ pub fn validate_ssa<F: Function>(f: &F, cfginfo: &CFGInfo) -> Result<(), RegAllo
                                 blockparams_out.len(),
                                 blockparams_in.len()
                             );
-                            return Err(RegAllocError::Branch(insn));
+                            return Err(RegAllocError::Branch(insn, succ));
                         }
                     }
                 }
             } else {
                 if f.is_branch(insn) || f.is_ret(insn) {
                     trace!("Block terminator found in the middle of a block");
-                    return Err(RegAllocError::BB(block));
+                    return Err(RegAllocError::BB(
+                        block,
+                        InvalidBlock::EarlyTerminator(insn),
+                    ));
                 }
             }
         }
@@ -131,7 +134,10 @@ pub fn validate_ssa<F: Function>(f: &F, cfginfo: &CFGInfo) -> Result<(), RegAllo
     // undefined what their value would be.
     if f.block_params(f.entry_block()).len() > 0 {
         trace!("Entry block contains block args");
-        return Err(RegAllocError::BB(f.entry_block()));
+        return Err(RegAllocError::BB(
+            f.entry_block(),
+            InvalidBlock::EntryBlockParams,
+        ));
     }
 
     Ok(())
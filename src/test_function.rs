@@ -0,0 +1,254 @@
+//! A function built up by hand, shared by the allocators' unit tests.
+
+use crate::OperandKind::{self, *};
+use crate::{
+    Block, CostModel, DefaultCostModel, Function, Inst, InstRange, Operand, OperandConstraint,
+    OperandGroup, OperandPos, PReg, PRegSet, ProgPoint, RegClass, VReg,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+pub(crate) fn op(kind: OperandKind, vreg_num: usize, constraint: OperandConstraint) -> Operand {
+    Operand::new(
+        VReg::new(vreg_num, RegClass::Int),
+        constraint,
+        kind,
+        match kind {
+            Use => OperandPos::Early,
+            Def => OperandPos::Late,
+        },
+    )
+}
+
+pub(crate) fn p(hw_enc: usize) -> PReg {
+    PReg::new(hw_enc, RegClass::Int)
+}
+
+/// A cost model that weighs all operands and ranges alike, and counts
+/// how often it is consulted.
+#[derive(Default)]
+pub(crate) struct FlatCostModel {
+    pub(crate) calls: Cell<usize>,
+}
+
+impl CostModel for FlatCostModel {
+    fn operand_weight(&self, _: Block, _: f32, _: OperandConstraint, _: bool) -> f32 {
+        self.calls.set(self.calls.get() + 1);
+        1.0
+    }
+
+    fn range_priority(&self, _: ProgPoint, _: ProgPoint) -> u32 {
+        self.calls.set(self.calls.get() + 1);
+        1
+    }
+}
+
+pub(crate) struct BlockBuildInfo {
+    pub(crate) insts: Vec<Vec<Operand>>,
+}
+
+#[derive(Default)]
+pub(crate) struct RealFunction {
+    pub(crate) blocks: Vec<RealBlock>,
+    pub(crate) insts: Vec<RealInst>,
+    pub(crate) operands: Vec<Operand>,
+    pub(crate) operand_ranges: Vec<(usize, usize)>,
+    pub(crate) inst_ranges: Vec<(usize, usize)>,
+    pub(crate) num_vregs: usize,
+    pub(crate) clobbers: Vec<PRegSet>,
+    pub(crate) remat_vregs: Vec<usize>,
+    pub(crate) cost_model: Option<FlatCostModel>,
+    pub(crate) block_frequency: Option<f32>,
+    pub(crate) debug_value_labels: Vec<(VReg, Inst, Inst, u32)>,
+    pub(crate) vreg_hints: Vec<(usize, PReg)>,
+    pub(crate) operand_groups: Vec<Vec<OperandGroup>>,
+}
+
+pub(crate) struct RealBlock {
+    pub(crate) params: Vec<VReg>,
+    pub(crate) preds: Vec<Block>,
+    pub(crate) succs: Vec<Block>,
+}
+
+pub(crate) struct RealInst {
+    pub(crate) inst: Inst,
+    pub(crate) kind: RealInstKind,
+}
+
+impl RealInst {
+    fn is_branch(&self) -> bool {
+        match self.kind {
+            RealInstKind::Branch(_, _) => true,
+            _ => false,
+        }
+    }
+
+    fn is_ret(&self) -> bool {
+        match self.kind {
+            RealInstKind::Ret => true,
+            _ => false,
+        }
+    }
+}
+
+pub(crate) enum RealInstKind {
+    Normal,
+    Branch(Block, Vec<VReg>),
+    Ret,
+}
+
+impl RealFunction {
+    pub(crate) fn new(blocks: Vec<BlockBuildInfo>) -> Self {
+        assert!(blocks.len() <= 2, "Just for testing purposes");
+        let mut f = Self::default();
+        let mut max_vreg_num_seen = 0;
+        for block in blocks.iter() {
+            f.blocks.push(RealBlock {
+                params: vec![],
+                preds: vec![],
+                succs: vec![],
+            });
+            let start_inst_idx = f.insts.len();
+            for inst in block.insts.iter() {
+                f.insts.push(RealInst {
+                    inst: Inst::new(f.insts.len()),
+                    kind: RealInstKind::Normal,
+                });
+                let start_op_idx = f.operands.len();
+                for op in inst.iter() {
+                    max_vreg_num_seen = max_vreg_num_seen.max(op.vreg().vreg());
+                    f.operands.push(*op);
+                }
+                f.operand_ranges.push((start_op_idx, f.operands.len()));
+                f.clobbers.push(PRegSet::empty());
+            }
+            if !block.insts.is_empty() {
+                f.insts.last_mut().unwrap().kind = RealInstKind::Ret;
+            }
+            f.inst_ranges.push((start_inst_idx, f.insts.len()));
+        }
+        f.num_vregs = max_vreg_num_seen + 1;
+        f
+    }
+
+    /// A single block of `insts`, followed by an empty return.
+    pub(crate) fn single_block(mut insts: Vec<Vec<Operand>>) -> Self {
+        insts.push(vec![]);
+        Self::new(vec![BlockBuildInfo { insts }])
+    }
+}
+
+impl Function for RealFunction {
+    fn num_insts(&self) -> usize {
+        self.insts.len()
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn block_insns(&self, block: crate::Block) -> crate::InstRange {
+        let (start, end) = self.inst_ranges[block.index()];
+        if start != end {
+            InstRange::new(
+                self.insts[start].inst,
+                Inst::new(self.insts[end - 1].inst.index() + 1),
+            )
+        } else {
+            InstRange::new(Inst::new(0), Inst::new(0))
+        }
+    }
+
+    fn allow_multiple_vreg_defs(&self) -> bool {
+        false
+    }
+
+    fn block_params(&self, block: crate::Block) -> &[VReg] {
+        &self.blocks[block.index()].params
+    }
+
+    fn block_preds(&self, block: crate::Block) -> &[crate::Block] {
+        &self.blocks[block.index()].preds
+    }
+
+    fn block_succs(&self, block: Block) -> &[Block] {
+        &self.blocks[block.index()].succs
+    }
+
+    fn debug_value_labels(&self) -> &[(VReg, Inst, Inst, u32)] {
+        &self.debug_value_labels
+    }
+
+    fn entry_block(&self) -> Block {
+        Block::new(0)
+    }
+
+    fn inst_clobbers(&self, insn: Inst) -> crate::PRegSet {
+        self.clobbers[insn.index()]
+    }
+
+    fn inst_operands(&self, insn: Inst) -> &[Operand] {
+        let (start, end) = self.operand_ranges[insn.index()];
+        &self.operands[start..end]
+    }
+
+    fn inst_operand_groups(&self, insn: Inst) -> &[OperandGroup] {
+        self.operand_groups
+            .get(insn.index())
+            .map_or(&[], |groups| &groups[..])
+    }
+
+    fn is_branch(&self, insn: Inst) -> bool {
+        self.insts[insn.index()].is_branch()
+    }
+
+    fn is_ret(&self, insn: Inst) -> bool {
+        self.insts[insn.index()].is_ret()
+    }
+
+    fn is_rematerializable(&self, vreg: VReg) -> bool {
+        self.remat_vregs.contains(&vreg.vreg())
+    }
+
+    fn block_frequency(&self, _block: Block) -> Option<f32> {
+        self.block_frequency
+    }
+
+    fn cost_model(&self) -> &dyn CostModel {
+        match &self.cost_model {
+            Some(model) => model,
+            None => &DefaultCostModel,
+        }
+    }
+
+    fn multi_spillslot_named_by_last_slot(&self) -> bool {
+        false
+    }
+
+    fn num_vregs(&self) -> usize {
+        self.num_vregs
+    }
+
+    fn vreg_hint(&self, vreg: VReg) -> Option<PReg> {
+        self.vreg_hints
+            .iter()
+            .find(|&&(v, _)| v == vreg.vreg())
+            .map(|&(_, preg)| preg)
+    }
+
+    fn spillslot_size(&self, regclass: crate::RegClass) -> usize {
+        match regclass {
+            RegClass::Int => 2,
+            RegClass::Float => 4,
+            _ => 8,
+        }
+    }
+
+    fn branch_blockparams(&self, _block: Block, insn: Inst, _succ_idx: usize) -> &[VReg] {
+        match &self.insts[insn.index()].kind {
+            RealInstKind::Branch(_, args) => args,
+            _ => &[],
+        }
+    }
+}
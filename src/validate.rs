@@ -0,0 +1,280 @@
+/*
+ * Released under the terms of the Apache 2.0 license with LLVM
+ * exception. See `LICENSE` for details.
+ */
+
+//! Validation of a `Function` and `MachineEnv` before allocation.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::cfg::CFGInfo;
+use crate::ssa::validate_ssa;
+use crate::{
+    Algorithm, Block, Function, Inst, InvalidBlock, InvalidEnv, InvalidOperand, MachineEnv,
+    Operand, OperandConstraint, OperandKind, PRegSet, RegAllocError, RegClass, VReg,
+};
+
+/// Check that `func` and `env` satisfy everything the allocators
+/// assume about them, returning the first problem found.
+///
+/// This covers the checks done with `RegallocOptions::validate_ssa`,
+/// and also the consistency of the CFG, the operands' constraints and
+/// register classes, and the `MachineEnv`, and that `algorithm`
+/// supports every constraint. Without it, most of these mistakes are
+/// only caught by a panic or a debug assertion somewhere in the
+/// allocator, if at all.
+pub fn validate_function<F: Function>(
+    func: &F,
+    env: &MachineEnv,
+    algorithm: Algorithm,
+) -> Result<(), RegAllocError> {
+    validate_env(env)?;
+    validate_cfg(func)?;
+
+    // A vreg is wide if any of its operands has a `WideReg` constraint.
+    let mut wide = vec![false; func.num_vregs()];
+    for inst in 0..func.num_insts() {
+        for op in func.inst_operands(Inst::new(inst)) {
+            if op.constraint() == OperandConstraint::WideReg {
+                if let Some(wide) = wide.get_mut(op.vreg().vreg()) {
+                    *wide = true;
+                }
+            }
+        }
+    }
+    let is_wide = |vreg: VReg| wide.get(vreg.vreg()).copied().unwrap_or(false);
+
+    // Every mention of a vreg must agree on its class.
+    let mut classes: Vec<Option<RegClass>> = vec![None; func.num_vregs()];
+    let mut observe = |vreg: VReg, inst: Inst| {
+        let class = classes
+            .get_mut(vreg.vreg())
+            .ok_or(RegAllocError::SSA(vreg, inst))?;
+        match *class {
+            Some(class) if class != vreg.class() => Err(RegAllocError::ClassMismatch(vreg, class)),
+            _ => {
+                *class = Some(vreg.class());
+                Ok(())
+            }
+        }
+    };
+    for block in 0..func.num_blocks() {
+        let block = Block::new(block);
+        for &param in func.block_params(block) {
+            observe(param, Inst::invalid())?;
+        }
+        let last = func.block_insns(block).last();
+        if func.is_branch(last) {
+            for (i, &succ) in func.block_succs(block).iter().enumerate() {
+                let args = func.branch_blockparams(block, last, i);
+                for &arg in args {
+                    observe(arg, last)?;
+                }
+                for (&arg, &param) in args.iter().zip(func.block_params(succ)) {
+                    if is_wide(arg) != is_wide(param) {
+                        return Err(RegAllocError::BB(succ, InvalidBlock::WideParam(param)));
+                    }
+                }
+            }
+        }
+    }
+
+    let allocatable = PRegSet::from(env);
+    for inst in 0..func.num_insts() {
+        let inst = Inst::new(inst);
+        let operands = func.inst_operands(inst);
+        let mut reuse_either = false;
+        for (i, &op) in operands.iter().enumerate() {
+            let invalid = |why| Err(RegAllocError::Operand(inst, i, why));
+            let check_reuse = |input: usize| {
+                if !is_reuse_input(op, operands, input) {
+                    return invalid(InvalidOperand::ReuseIndex(input));
+                }
+                let input_op = operands[input];
+                if let OperandConstraint::FixedStack(_) = input_op.constraint() {
+                    return invalid(InvalidOperand::ReuseFixedStack(input));
+                }
+                if is_wide(input_op.vreg()) != is_wide(op.vreg()) {
+                    return invalid(InvalidOperand::ReuseWide(input));
+                }
+                Ok(())
+            };
+            if let Some(preg) = op.as_fixed_nonallocatable() {
+                if allocatable.contains(preg) {
+                    return invalid(InvalidOperand::NonallocatableRegAllocatable(preg));
+                }
+                continue;
+            }
+            if op.vreg().vreg() >= func.num_vregs() {
+                return invalid(InvalidOperand::VRegOutOfRange);
+            }
+            observe(op.vreg(), inst)?;
+            match op.constraint() {
+                OperandConstraint::FixedReg(preg)
+                    if algorithm == Algorithm::Fastalloc && env.wide_regs().contains(preg) =>
+                {
+                    return invalid(InvalidOperand::Unsupported(algorithm));
+                }
+                OperandConstraint::FixedReg(preg) => {
+                    if preg.class() != op.class()
+                        || !(allocatable.contains(preg) || env.fixed_stack_slots.contains(&preg))
+                    {
+                        return invalid(InvalidOperand::FixedRegNotInEnv(preg));
+                    }
+                }
+                OperandConstraint::Reuse(input) => check_reuse(input)?,
+                OperandConstraint::ReuseEither(a, b) => {
+                    if reuse_either {
+                        return invalid(InvalidOperand::MultipleReuseEither);
+                    }
+                    reuse_either = true;
+                    check_reuse(a)?;
+                    check_reuse(b)?;
+                }
+                OperandConstraint::FixedStack(loc) => {
+                    let conflicts = |other: &Operand| {
+                        other.constraint() == OperandConstraint::FixedStack(loc)
+                            && match (op.kind(), other.kind()) {
+                                (OperandKind::Def, OperandKind::Def) => true,
+                                (OperandKind::Use, OperandKind::Use) => op.vreg() != other.vreg(),
+                                _ => false,
+                            }
+                    };
+                    if operands[..i].iter().any(conflicts) {
+                        return invalid(InvalidOperand::FixedStackConflict(loc));
+                    }
+                }
+                OperandConstraint::Limit(_) | OperandConstraint::WideReg
+                    if algorithm == Algorithm::Fastalloc =>
+                {
+                    return invalid(InvalidOperand::Unsupported(algorithm));
+                }
+                OperandConstraint::Limit(limit) => {
+                    let class = op.class() as usize;
+                    let regs =
+                        env.preferred_regs_by_class[class] | env.non_preferred_regs_by_class[class];
+                    if !limit.is_power_of_two()
+                        || !regs.into_iter().any(|preg| preg.hw_enc() < limit)
+                    {
+                        return invalid(InvalidOperand::Limit(limit));
+                    }
+                }
+                OperandConstraint::RegSet(set) => {
+                    if set >= env.reg_sets.len() {
+                        return invalid(InvalidOperand::RegSetIndex(set));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let cfginfo = CFGInfo::new(func)?;
+    validate_ssa(func, &cfginfo)
+}
+
+/// Whether operand `input` of an instruction with `operands` can be
+/// the input of a `Reuse` constraint on `def`.
+fn is_reuse_input(def: Operand, operands: &[Operand], input: usize) -> bool {
+    def.kind() == OperandKind::Def
+        && operands.get(input).map_or(false, |&op| {
+            op.kind() == OperandKind::Use
+                && op.class() == def.class()
+                && op.as_fixed_nonallocatable().is_none()
+        })
+}
+
+fn validate_env(env: &MachineEnv) -> Result<(), RegAllocError> {
+    let allocatable = PRegSet::from(env);
+    for class in RegClass::ALL {
+        let regs = env.preferred_regs_by_class[class as usize]
+            | env.non_preferred_regs_by_class[class as usize];
+        if let Some(preg) = regs.into_iter().find(|preg| preg.class() != class) {
+            return Err(RegAllocError::Env(InvalidEnv::WrongClass(preg)));
+        }
+        if let Some(scratch) = env.scratch_by_class[class as usize] {
+            if scratch.class() != class {
+                return Err(RegAllocError::Env(InvalidEnv::WrongClass(scratch)));
+            }
+            if allocatable.contains(scratch) {
+                return Err(RegAllocError::Env(InvalidEnv::ScratchRegAllocatable(
+                    scratch,
+                )));
+            }
+            if let Some(preg) = regs
+                .into_iter()
+                .find(|&preg| env.regs_overlap(preg, scratch))
+            {
+                return Err(RegAllocError::Env(InvalidEnv::ScratchRegOverlap(preg)));
+            }
+        }
+    }
+    for &(wide, units) in &env.reg_units {
+        if !allocatable.contains(wide) {
+            return Err(RegAllocError::Env(InvalidEnv::WideRegNotAllocatable(wide)));
+        }
+        if let Some(unit) = units.into_iter().find(|unit| unit.class() != wide.class()) {
+            return Err(RegAllocError::Env(InvalidEnv::WrongClass(unit)));
+        }
+    }
+    for &preg in &env.fixed_stack_slots {
+        if allocatable.contains(preg) {
+            return Err(RegAllocError::Env(InvalidEnv::FixedStackSlotAllocatable(
+                preg,
+            )));
+        }
+    }
+    for (i, set) in env.reg_sets.iter().enumerate() {
+        if let Some(preg) = set.into_iter().find(|&preg| !allocatable.contains(preg)) {
+            return Err(RegAllocError::Env(InvalidEnv::RegSetNotAllocatable(
+                i, preg,
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_cfg<F: Function>(func: &F) -> Result<(), RegAllocError> {
+    let num_blocks = func.num_blocks();
+    let in_range = |block: Block| {
+        if block.index() < num_blocks {
+            Ok(())
+        } else {
+            Err(RegAllocError::BB(block, InvalidBlock::OutOfRange))
+        }
+    };
+    in_range(func.entry_block())?;
+
+    // The blocks' instructions must follow each other, without gaps,
+    // and cover all instructions.
+    let mut next = Inst::new(0);
+    for block in 0..num_blocks {
+        let block = Block::new(block);
+        let insns = func.block_insns(block);
+        if insns.len() == 0 || insns.first() != next {
+            return Err(RegAllocError::BB(block, InvalidBlock::InstRange));
+        }
+        next = insns.last().next();
+
+        for &succ in func.block_succs(block) {
+            in_range(succ)?;
+            if !func.block_preds(succ).contains(&block) {
+                return Err(RegAllocError::PredSuccMismatch(block, succ));
+            }
+        }
+        for &pred in func.block_preds(block) {
+            in_range(pred)?;
+            if !func.block_succs(pred).contains(&block) {
+                return Err(RegAllocError::PredSuccMismatch(pred, block));
+            }
+        }
+    }
+    if next.index() != func.num_insts() {
+        return Err(RegAllocError::BB(
+            Block::new(num_blocks - 1),
+            InvalidBlock::InstRange,
+        ));
+    }
+    Ok(())
+}